@@ -0,0 +1,277 @@
+//! Clause盲Schnorr签名
+//!
+//! 基于Ristretto群，用于CoinJoin输出登记凭证：协调节点在登记输入时为参与者盲签一个凭证，
+//! 参与者去盲后用凭证登记输出。协调节点能验证凭证由自己签发，但无法把凭证与签发时的输入对应起来。
+//!
+//! 普通盲Schnorr签名在多个签发会话并发时可被ROS攻击伪造出多于签发次数的签名。
+//! 这里采用Fuchsbauer–Wolf的Clause变体：签发方每次给出两个nonce，参与者对两者分别盲化，
+//! 签发方随机选择其中一个作答，并发会话下仍然安全，最终签名与普通Schnorr签名相同。
+//!
+//! 签发方对同一个上下文（参与者）只能签发一次：nonce由签发密钥和上下文确定性派生，
+//! 同一nonce对两个不同的挑战签名会泄露私钥。
+
+use curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
+use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
+use curve25519_dalek::scalar::Scalar;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+/// 哈希前缀，带版本号
+const CHALLENGE_PREFIX: &[u8] = b"hancoin-blind-v2\0";
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum BlindError {
+    #[error("Invalid encoding")]
+    InvalidEncoding,
+    #[error("Invalid point")]
+    InvalidPoint,
+    #[error("Blind signature verification failed")]
+    VerificationFailed,
+}
+
+/// 去盲后的签名，即输出登记凭证
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlindSignature {
+    /// 去盲后的nonce点（hex），同时作为凭证的唯一标识
+    pub nonce: String,
+    /// 去盲后的标量（hex）
+    pub scalar: String,
+}
+
+/// 签发方
+pub struct BlindSigner {
+    secret: Zeroizing<[u8; 32]>,
+    key: Scalar,
+    public: RistrettoPoint,
+}
+
+impl BlindSigner {
+    /// 从32字节种子派生签发密钥
+    pub fn from_seed(seed: &[u8; 32]) -> Self {
+        let key = hash_to_scalar(&[b"key".as_slice(), seed.as_slice()]);
+        Self {
+            secret: Zeroizing::new(*seed),
+            key,
+            public: RISTRETTO_BASEPOINT_POINT * key,
+        }
+    }
+
+    /// 生成随机种子
+    pub fn generate_seed() -> [u8; 32] {
+        let mut seed = [0u8; 32];
+        rand::rngs::OsRng.fill_bytes(&mut seed);
+        seed
+    }
+
+    /// hex编码的签发公钥
+    pub fn public_key(&self) -> String {
+        hex::encode(self.public.compress().as_bytes())
+    }
+
+    fn nonce_scalar(&self, context: &str, branch: u8) -> Scalar {
+        hash_to_scalar(&[b"nonce".as_slice(), self.secret.as_slice(), context.as_bytes(), &[branch]])
+    }
+
+    /// 上下文对应的两个nonce点（hex），交给参与者用于盲化
+    pub fn nonce(&self, context: &str) -> String {
+        let mut encoded = Vec::with_capacity(64);
+        for branch in 0..2 {
+            let point = RISTRETTO_BASEPOINT_POINT * self.nonce_scalar(context, branch);
+            encoded.extend_from_slice(point.compress().as_bytes());
+        }
+        hex::encode(encoded)
+    }
+
+    /// 对盲化挑战签名，随机选择一个分支作答，调用方须保证每个上下文只签一次
+    pub fn sign(&self, context: &str, challenge: &str) -> Result<String, BlindError> {
+        self.sign_branch(context, challenge, (rand::rngs::OsRng.next_u32() & 1) as u8)
+    }
+
+    fn sign_branch(&self, context: &str, challenge: &str, branch: u8) -> Result<String, BlindError> {
+        let challenges = decode_scalar_pair(challenge)?;
+        let s = self.nonce_scalar(context, branch) + challenges[branch as usize] * self.key;
+        let mut encoded = Vec::with_capacity(33);
+        encoded.push(branch);
+        encoded.extend_from_slice(s.as_bytes());
+        Ok(hex::encode(encoded))
+    }
+}
+
+/// 一个分支的盲化状态
+struct Branch {
+    nonce: RistrettoPoint,
+    blinded_nonce: RistrettoPoint,
+    challenge: Scalar,
+    alpha: Scalar,
+    beta: Scalar,
+}
+
+/// 参与者的盲化状态，收到盲签名后用于去盲
+pub struct Unblinder {
+    public: RistrettoPoint,
+    branches: [Branch; 2],
+}
+
+/// 盲化待签消息，返回发给签发方的两个挑战（hex）和去盲状态
+pub fn blind(public_key: &str, nonce: &str, message: &[u8]) -> Result<(String, Unblinder), BlindError> {
+    let public = decode_point(public_key)?;
+    let nonces = decode_point_pair(nonce)?;
+
+    let branches = nonces.map(|nonce| {
+        let alpha = random_scalar();
+        let beta = random_scalar();
+        let blinded_nonce = nonce + RISTRETTO_BASEPOINT_POINT * alpha + public * beta;
+        Branch {
+            nonce,
+            blinded_nonce,
+            challenge: challenge(&public, &blinded_nonce, message),
+            alpha,
+            beta,
+        }
+    });
+    let mut encoded = Vec::with_capacity(64);
+    for branch in &branches {
+        encoded.extend_from_slice((branch.challenge + branch.beta).as_bytes());
+    }
+    Ok((hex::encode(encoded), Unblinder { public, branches }))
+}
+
+impl Unblinder {
+    /// 去盲并检查结果是有效签名
+    pub fn unblind(&self, blind_signature: &str) -> Result<BlindSignature, BlindError> {
+        let bytes = hex::decode(blind_signature).map_err(|_| BlindError::InvalidEncoding)?;
+        let (branch, scalar) = match bytes.split_first() {
+            Some((&branch, scalar)) if branch < 2 => (&self.branches[branch as usize], scalar),
+            _ => return Err(BlindError::InvalidEncoding),
+        };
+        let s = scalar_from_slice(scalar)?;
+        // 签发方的应答须对应该分支的原始nonce和盲化挑战
+        if RISTRETTO_BASEPOINT_POINT * s != branch.nonce + self.public * (branch.challenge + branch.beta) {
+            return Err(BlindError::VerificationFailed);
+        }
+        let s = s + branch.alpha;
+        Ok(BlindSignature {
+            nonce: hex::encode(branch.blinded_nonce.compress().as_bytes()),
+            scalar: hex::encode(s.as_bytes()),
+        })
+    }
+}
+
+/// 验证去盲后的签名
+pub fn verify(public_key: &str, message: &[u8], signature: &BlindSignature) -> Result<(), BlindError> {
+    let public = decode_point(public_key)?;
+    let nonce = decode_point(&signature.nonce)?;
+    let s = decode_scalar(&signature.scalar)?;
+    if RISTRETTO_BASEPOINT_POINT * s != nonce + public * challenge(&public, &nonce, message) {
+        return Err(BlindError::VerificationFailed);
+    }
+    Ok(())
+}
+
+fn challenge(public: &RistrettoPoint, nonce: &RistrettoPoint, message: &[u8]) -> Scalar {
+    hash_to_scalar(&[
+        public.compress().as_bytes().as_slice(),
+        nonce.compress().as_bytes().as_slice(),
+        message,
+    ])
+}
+
+fn hash_to_scalar(parts: &[&[u8]]) -> Scalar {
+    let mut hasher = Sha512::new();
+    hasher.update(CHALLENGE_PREFIX);
+    for part in parts {
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part);
+    }
+    let mut wide = [0u8; 64];
+    wide.copy_from_slice(&hasher.finalize());
+    Scalar::from_bytes_mod_order_wide(&wide)
+}
+
+fn random_scalar() -> Scalar {
+    let mut bytes = [0u8; 64];
+    rand::rngs::OsRng.fill_bytes(&mut bytes);
+    Scalar::from_bytes_mod_order_wide(&bytes)
+}
+
+fn decode_point(value: &str) -> Result<RistrettoPoint, BlindError> {
+    let bytes = hex::decode(value).map_err(|_| BlindError::InvalidEncoding)?;
+    CompressedRistretto::from_slice(&bytes)
+        .map_err(|_| BlindError::InvalidEncoding)?
+        .decompress()
+        .ok_or(BlindError::InvalidPoint)
+}
+
+fn decode_point_pair(value: &str) -> Result<[RistrettoPoint; 2], BlindError> {
+    if value.len() != 128 || !value.is_char_boundary(64) {
+        return Err(BlindError::InvalidEncoding);
+    }
+    Ok([decode_point(&value[..64])?, decode_point(&value[64..])?])
+}
+
+fn decode_scalar(value: &str) -> Result<Scalar, BlindError> {
+    scalar_from_slice(&hex::decode(value).map_err(|_| BlindError::InvalidEncoding)?)
+}
+
+fn decode_scalar_pair(value: &str) -> Result<[Scalar; 2], BlindError> {
+    let bytes = hex::decode(value).map_err(|_| BlindError::InvalidEncoding)?;
+    if bytes.len() != 64 {
+        return Err(BlindError::InvalidEncoding);
+    }
+    Ok([scalar_from_slice(&bytes[..32])?, scalar_from_slice(&bytes[32..])?])
+}
+
+fn scalar_from_slice(bytes: &[u8]) -> Result<Scalar, BlindError> {
+    let bytes: [u8; 32] = bytes.try_into().map_err(|_| BlindError::InvalidEncoding)?;
+    Option::from(Scalar::from_canonical_bytes(bytes)).ok_or(BlindError::InvalidEncoding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blind_signature_roundtrip() {
+        let signer = BlindSigner::from_seed(&BlindSigner::generate_seed());
+        let public = signer.public_key();
+
+        let (challenge, unblinder) = blind(&public, &signer.nonce("alice"), b"output").unwrap();
+        let blind_signature = signer.sign("alice", &challenge).unwrap();
+        let signature = unblinder.unblind(&blind_signature).unwrap();
+
+        assert!(verify(&public, b"output", &signature).is_ok());
+        assert_eq!(verify(&public, b"other", &signature), Err(BlindError::VerificationFailed));
+
+        // 签发方看到的nonce和挑战与最终签名无关
+        assert_ne!(signature.nonce, signer.nonce("alice"));
+
+        // 其他签发方的密钥或错误的盲签名不能通过
+        let other = BlindSigner::from_seed(&BlindSigner::generate_seed());
+        assert!(verify(&other.public_key(), b"output", &signature).is_err());
+        assert!(unblinder.unblind(&signer.sign("bob", &challenge).unwrap()).is_err());
+        assert!(unblinder.unblind("02").is_err());
+    }
+
+    #[test]
+    fn test_either_branch_unblinds() {
+        let signer = BlindSigner::from_seed(&BlindSigner::generate_seed());
+        let public = signer.public_key();
+        let (challenge, unblinder) = blind(&public, &signer.nonce("alice"), b"output").unwrap();
+
+        let signatures: Vec<BlindSignature> = (0..2)
+            .map(|branch| unblinder.unblind(&signer.sign_branch("alice", &challenge, branch).unwrap()).unwrap())
+            .collect();
+        for signature in &signatures {
+            assert!(verify(&public, b"output", signature).is_ok());
+        }
+        // 两个分支的盲化因子相互独立
+        assert_ne!(signatures[0].nonce, signatures[1].nonce);
+
+        // 单个挑战或单个nonce不被接受
+        assert_eq!(signer.sign("alice", &challenge[..64]), Err(BlindError::InvalidEncoding));
+        assert!(blind(&public, &signer.nonce("alice")[..64], b"output").is_err());
+    }
+}
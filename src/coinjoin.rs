@@ -4,18 +4,22 @@
 //! 从而提高交易的隐私性，使外部观察者难以确定哪些输入对应哪些输出。
 
 use std::collections::{HashMap, HashSet};
-use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use log::{debug, info, warn, error};
 use serde::{Serialize, Deserialize};
 use uuid::Uuid;
-use tokio::sync::mpsc;
-use axum::{
-    extract::{Path, State, Json},
-    http::StatusCode,
-    response::{IntoResponse, Response},
-};
+use tokio::sync::{broadcast, mpsc};
 use dashmap::DashMap;
+use once_cell::sync::OnceCell;
+use parking_lot::{Mutex, RwLock};
+use tokio::sync::broadcast::error::RecvError;
+use crate::blind::{self, BlindSignature, BlindSigner, Unblinder};
+use crate::crypto;
+use ed25519_dalek::SigningKey;
+use crate::p2p::{P2PEvent, P2PHandle, P2PPayload};
+use crate::tor::{self, TorConnector};
 
 /// CoinJoin会话状态
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -62,6 +66,13 @@ pub struct TxOutput {
     pub amount: u64,
 }
 
+impl TxOutput {
+    /// 输出凭证签名的消息内容
+    pub fn credential_message(&self, session_id: &str) -> String {
+        format!("{}:{}:{}", session_id, self.address, self.amount)
+    }
+}
+
 /// 交易签名
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TxSignature {
@@ -74,7 +85,7 @@ pub struct TxSignature {
 }
 
 /// CoinJoin会话
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CoinJoinSession {
     /// 会话ID
     pub id: String,
@@ -94,16 +105,272 @@ pub struct CoinJoinSession {
     pub fee_rate: u64,
     /// 超时时间（秒）
     pub timeout: u64,
+    /// 等待加入阶段的截止时间，达到最少人数后会话在此之前保持可加入，满员时提前开始
+    #[serde(default)]
+    pub lobby_deadline: u64,
     /// 参与者
     pub participants: HashSet<String>,
     /// 交易输入
     pub inputs: Vec<TxInput>,
+    /// 输入提供者（与`inputs`按索引对应）
+    pub input_owners: Vec<String>,
     /// 交易输出
     pub outputs: Vec<TxOutput>,
     /// 交易签名
     pub signatures: Vec<TxSignature>,
     /// 最终交易ID
     pub final_txid: Option<String>,
+    /// 输出凭证签发种子（hex）
+    pub credential_seed: String,
+    /// 已签发输出凭证的参与者
+    pub credentials_issued: HashSet<String>,
+    /// 已使用的输出凭证（去盲后的nonce）
+    pub spent_credentials: HashSet<String>,
+    /// 经P2P网络或HTTP接口加入的参与者及其来源（节点ID或客户端地址）
+    pub joined_via: HashMap<String, String>,
+    /// 创建会话的来源，用于限制每个来源同时开启的会话数量
+    #[serde(default)]
+    pub created_via: Option<String>,
+    /// 会话事件通知通道
+    #[serde(skip)]
+    pub events: Option<broadcast::Sender<CoinJoinEvent>>,
+    /// 会话自上次保存后是否有变更，由管理器在释放会话表锁后写入存储
+    #[serde(skip)]
+    pub dirty: bool,
+}
+
+/// CoinJoin会话事件
+///
+/// 通过WebSocket推送给订阅的参与者
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CoinJoinEvent {
+    /// 会话状态变化
+    StatusChanged {
+        /// 会话ID
+        session_id: String,
+        /// 新状态
+        status: CoinJoinStatus,
+        /// 当前阶段截止时间
+        deadline: u64,
+    },
+    /// 会话完成后的最终记录
+    Transcript(CoinJoinTranscript),
+}
+
+impl CoinJoinEvent {
+    /// 事件所属的会话ID
+    pub fn session_id(&self) -> &str {
+        match self {
+            CoinJoinEvent::StatusChanged { session_id, .. } => session_id,
+            CoinJoinEvent::Transcript(transcript) => &transcript.session_id,
+        }
+    }
+}
+
+/// CoinJoin最终交易记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoinJoinTranscript {
+    /// 会话ID
+    pub session_id: String,
+    /// 交易输入
+    pub inputs: Vec<TxInput>,
+    /// 交易输出
+    pub outputs: Vec<TxOutput>,
+    /// 最终交易ID
+    pub final_txid: Option<String>,
+    /// 参与者数量
+    pub participants_count: usize,
+}
+
+impl CoinJoinTranscript {
+    /// 计算每种输出金额的匿名集大小
+    ///
+    /// 同一金额的输出数量即为该金额输出的匿名集，但不超过参与者数量
+    pub fn anonymity_sets(&self) -> HashMap<u64, usize> {
+        let mut sets: HashMap<u64, usize> = HashMap::new();
+        for output in &self.outputs {
+            *sets.entry(output.amount).or_default() += 1;
+        }
+        for size in sets.values_mut() {
+            *size = (*size).min(self.participants_count.max(1));
+        }
+        sets
+    }
+}
+
+/// 通过P2P网络广播的CoinJoin会话公告
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionAnnouncement {
+    /// 会话ID
+    pub session_id: String,
+    /// 协调节点的PeerId
+    pub coordinator: String,
+    /// 会话状态
+    pub status: CoinJoinStatus,
+    /// 最小参与者数量
+    pub min_participants: usize,
+    /// 最大参与者数量
+    pub max_participants: usize,
+    /// 当前参与者数量
+    pub participants_count: usize,
+    /// 目标金额
+    pub target_amount: u64,
+    /// 交易费率（聪/字节）
+    pub fee_rate: u64,
+    /// 公告过期时间
+    pub expires_at: u64,
+    /// 输出凭证签发公钥
+    #[serde(default)]
+    pub credential_key: Option<String>,
+    /// 登记地址（`host:port`），输入、输出和签名不经P2P广播，直接发往该地址
+    #[serde(default)]
+    pub registration_endpoint: Option<String>,
+}
+
+/// P2P网络上的CoinJoin协调消息
+///
+/// 公告、nonce由协调节点广播，加入消息由参与者签名后经所在节点发往协调节点。
+/// 本节点参与者的输入、输出和签名不经P2P广播，见[`Registration`]；
+/// 输入和签名消息仍可由其他节点经P2P网络转发，协调节点同样处理
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum CoinJoinMessage {
+    /// 会话公告
+    Announce(SessionAnnouncement),
+    /// 加入会话
+    Join {
+        session_id: String,
+        participant_id: String,
+        /// 参与者私钥的签名（hex）
+        participant_signature: String,
+    },
+    /// 注册输入，同时请求盲签输出凭证
+    Input {
+        session_id: String,
+        participant_id: String,
+        input: TxInput,
+        /// 盲化的输出凭证挑战（hex）
+        blinded_output: String,
+        participant_signature: String,
+    },
+    /// 注册输出（不携带参与者ID，凭输出凭证登记，避免关联输入与输出）
+    Output {
+        session_id: String,
+        output: TxOutput,
+        credential: BlindSignature,
+    },
+    /// 提交签名
+    Signature {
+        session_id: String,
+        participant_id: String,
+        signature: TxSignature,
+        participant_signature: String,
+    },
+    /// 协调节点为参与者生成的nonce，参与者据此盲化输出
+    Nonce {
+        session_id: String,
+        participant_id: String,
+        nonce: String,
+    },
+    /// 协调节点对盲化输出的签名
+    Credential {
+        session_id: String,
+        participant_id: String,
+        blind_signature: String,
+    },
+    /// 会话结束
+    Closed {
+        session_id: String,
+        status: CoinJoinStatus,
+        /// 已完成会话的最终记录，参与节点据此统计隐私
+        #[serde(default)]
+        transcript: Option<CoinJoinTranscript>,
+    },
+}
+
+impl CoinJoinMessage {
+    /// 消息所属的会话ID
+    pub fn session_id(&self) -> &str {
+        match self {
+            CoinJoinMessage::Announce(announcement) => &announcement.session_id,
+            CoinJoinMessage::Join { session_id, .. }
+            | CoinJoinMessage::Input { session_id, .. }
+            | CoinJoinMessage::Output { session_id, .. }
+            | CoinJoinMessage::Signature { session_id, .. }
+            | CoinJoinMessage::Nonce { session_id, .. }
+            | CoinJoinMessage::Credential { session_id, .. }
+            | CoinJoinMessage::Closed { session_id, .. } => session_id,
+        }
+    }
+    
+    /// 参与者签名的消息内容，只有加入、输入和签名消息需要参与者签名
+    pub fn signing_message(&self) -> Option<String> {
+        match self {
+            CoinJoinMessage::Join { session_id, participant_id, .. } => {
+                Some(format!("join:{}:{}", session_id, participant_id))
+            }
+            CoinJoinMessage::Input { session_id, participant_id, input, blinded_output, .. } => Some(format!(
+                "input:{}:{}:{}:{}:{}:{}:{}:{}",
+                session_id, participant_id, input.txid, input.vout, input.amount,
+                input.script, input.pubkey, blinded_output
+            )),
+            CoinJoinMessage::Signature { session_id, participant_id, signature, .. } => Some(format!(
+                "signature:{}:{}:{}:{}:{}",
+                session_id, participant_id, signature.input_index, signature.signature, signature.pubkey
+            )),
+            _ => None,
+        }
+    }
+    
+    /// 用参与者私钥签名
+    pub fn sign(mut self, keypair: &SigningKey) -> Result<Self, String> {
+        let Some(message) = self.signing_message() else {
+            return Ok(self);
+        };
+        let signature = sign_participant(keypair, &message)?;
+        match &mut self {
+            CoinJoinMessage::Join { participant_signature, .. }
+            | CoinJoinMessage::Input { participant_signature, .. }
+            | CoinJoinMessage::Signature { participant_signature, .. } => *participant_signature = signature,
+            _ => {}
+        }
+        Ok(self)
+    }
+    
+    /// 检查参与者签名，参与者ID为其公钥（hex）
+    pub fn verify(&self) -> Result<(), String> {
+        let Some(message) = self.signing_message() else {
+            return Ok(());
+        };
+        let (participant_id, participant_signature) = match self {
+            CoinJoinMessage::Join { participant_id, participant_signature, .. }
+            | CoinJoinMessage::Input { participant_id, participant_signature, .. }
+            | CoinJoinMessage::Signature { participant_id, participant_signature, .. } => {
+                (participant_id, participant_signature)
+            }
+            _ => return Ok(()),
+        };
+        verify_participant(participant_id, &message, participant_signature)
+    }
+}
+
+/// 参与者签名的用途域，见[`crypto::sign`]
+pub const PARTICIPANT_SIGNATURE_DOMAIN: &str = "coinjoin-participant";
+
+/// 用参与者私钥按用途域签名消息内容，返回hex签名
+fn sign_participant(keypair: &SigningKey, message: &str) -> Result<String, String> {
+    let signature = crypto::sign(keypair, PARTICIPANT_SIGNATURE_DOMAIN, message.as_bytes());
+    Ok(hex::encode(signature.to_bytes()))
+}
+
+/// 检查参与者对消息内容的签名，参与者ID为其公钥（hex）
+fn verify_participant(participant_id: &str, message: &str, participant_signature: &str) -> Result<(), String> {
+    let public_key = crypto::public_key_from_hex(participant_id)
+        .map_err(|_| format!("参与者ID不是有效公钥: {}", participant_id))?;
+    let signature = crypto::signature_from_hex(participant_signature)
+        .map_err(|_| "参与者签名格式错误".to_string())?;
+    crypto::verify(&public_key, PARTICIPANT_SIGNATURE_DOMAIN, message.as_bytes(), &signature)
+        .map_err(|_| format!("参与者签名无效: {}", participant_id))
 }
 
 impl CoinJoinSession {
@@ -130,11 +397,20 @@ impl CoinJoinSession {
             target_amount,
             fee_rate,
             timeout,
+            lobby_deadline: now,
             participants: HashSet::new(),
             inputs: Vec::new(),
+            input_owners: Vec::new(),
             outputs: Vec::new(),
             signatures: Vec::new(),
             final_txid: None,
+            credential_seed: hex::encode(BlindSigner::generate_seed()),
+            credentials_issued: HashSet::new(),
+            spent_credentials: HashSet::new(),
+            joined_via: HashMap::new(),
+            created_via: None,
+            events: None,
+            dirty: false,
         }
     }
     
@@ -144,29 +420,49 @@ impl CoinJoinSession {
             return false;
         }
         
+        // 已达到最大参与者数量
+        if self.is_full() && !self.participants.contains(participant_id) {
+            return false;
+        }
+        
         self.participants.insert(participant_id.to_string());
         self.update_last_active();
         
-        // 如果达到最小参与者数量，进入下一阶段
-        if self.participants.len() >= self.min_participants {
-            self.status = CoinJoinStatus::CollectingInputs;
+        // 满员时立即进入下一阶段，否则等待加入截止时间，见`close_lobby`
+        if self.is_full() {
+            self.set_status(CoinJoinStatus::CollectingInputs);
+        }
+        
+        true
+    }
+    
+    /// 加入截止时间已过且达到最少人数时结束等待，进入输入阶段，返回是否进入
+    pub fn close_lobby(&mut self, now: u64) -> bool {
+        if self.status != CoinJoinStatus::Waiting
+            || now < self.lobby_deadline
+            || self.participants.len() < self.min_participants
+        {
+            return false;
         }
         
+        self.set_status(CoinJoinStatus::CollectingInputs);
+        self.update_last_active();
         true
     }
     
     /// 添加交易输入
-    pub fn add_input(&mut self, input: TxInput) -> bool {
+    pub fn add_input(&mut self, participant_id: &str, input: TxInput) -> bool {
         if self.status != CoinJoinStatus::CollectingInputs {
             return false;
         }
         
         self.inputs.push(input);
+        self.input_owners.push(participant_id.to_string());
         self.update_last_active();
         
         // 如果每个参与者都提供了至少一个输入，进入下一阶段
         if self.inputs.len() >= self.participants.len() {
-            self.status = CoinJoinStatus::CollectingOutputs;
+            self.set_status(CoinJoinStatus::CollectingOutputs);
         }
         
         true
@@ -183,7 +479,7 @@ impl CoinJoinSession {
         
         // 如果每个参与者都提供了至少一个输出，进入下一阶段
         if self.outputs.len() >= self.participants.len() {
-            self.status = CoinJoinStatus::CollectingSignatures;
+            self.set_status(CoinJoinStatus::CollectingSignatures);
         }
         
         true
@@ -195,8 +491,10 @@ impl CoinJoinSession {
             return false;
         }
         
-        // 验证输入索引是否有效
-        if signature.input_index >= self.inputs.len() {
+        // 验证输入索引是否有效，每个输入只接受一个签名
+        if signature.input_index >= self.inputs.len()
+            || self.signatures.iter().any(|s| s.input_index == signature.input_index)
+        {
             return false;
         }
         
@@ -205,12 +503,59 @@ impl CoinJoinSession {
         
         // 如果所有输入都已签名，进入下一阶段
         if self.signatures.len() >= self.inputs.len() {
-            self.status = CoinJoinStatus::Broadcasting;
+            self.set_status(CoinJoinStatus::Broadcasting);
         }
         
         true
     }
     
+    /// 输出凭证签发方，旧版本保存的会话没有签发种子
+    fn credential_signer(&self) -> Option<BlindSigner> {
+        let seed: [u8; 32] = hex::decode(&self.credential_seed).ok()?.try_into().ok()?;
+        Some(BlindSigner::from_seed(&seed))
+    }
+    
+    /// 输出凭证签发公钥
+    pub fn credential_key(&self) -> Option<String> {
+        self.credential_signer().map(|signer| signer.public_key())
+    }
+    
+    /// 参与者的凭证nonce
+    pub fn credential_nonce(&self, participant_id: &str) -> Option<String> {
+        self.credential_signer().map(|signer| signer.nonce(participant_id))
+    }
+    
+    /// 为参与者盲签输出凭证，不修改会话；由调用方在登记输入时记入`credentials_issued`
+    pub fn sign_credential(&self, participant_id: &str, blinded_output: &str) -> Result<String, String> {
+        if self.credentials_issued.contains(participant_id) {
+            return Err(format!("参与者已领取输出凭证: {}", participant_id));
+        }
+        let signer = self.credential_signer()
+            .ok_or_else(|| "会话不支持输出凭证".to_string())?;
+        signer.sign(participant_id, blinded_output)
+            .map_err(|e| format!("盲化输出无效: {}", e))
+    }
+    
+    /// 凭输出凭证添加交易输出，每个凭证只能使用一次
+    pub fn add_blinded_output(&mut self, output: TxOutput, credential: &BlindSignature) -> Result<(), String> {
+        if output.amount != self.target_amount {
+            return Err(format!("输出金额与目标金额不一致: {}", output.amount));
+        }
+        let key = self.credential_key()
+            .ok_or_else(|| "会话不支持输出凭证".to_string())?;
+        blind::verify(&key, output.credential_message(&self.id).as_bytes(), credential)
+            .map_err(|_| "输出凭证无效".to_string())?;
+        if self.spent_credentials.contains(&credential.nonce) {
+            return Err("输出凭证已使用".to_string());
+        }
+        if !self.add_output(output) {
+            return Err("无法添加输出，会话状态不正确".to_string());
+        }
+        
+        self.spent_credentials.insert(credential.nonce.clone());
+        Ok(())
+    }
+    
     /// 完成会话
     pub fn complete(&mut self, txid: &str) -> bool {
         if self.status != CoinJoinStatus::Broadcasting {
@@ -218,7 +563,7 @@ impl CoinJoinSession {
         }
         
         self.final_txid = Some(txid.to_string());
-        self.status = CoinJoinStatus::Completed;
+        self.set_status(CoinJoinStatus::Completed);
         self.update_last_active();
         
         true
@@ -226,31 +571,159 @@ impl CoinJoinSession {
     
     /// 标记会话失败
     pub fn fail(&mut self) {
-        self.status = CoinJoinStatus::Failed;
+        self.set_status(CoinJoinStatus::Failed);
         self.update_last_active();
     }
     
     /// 检查会话是否已超时
     pub fn check_timeout(&mut self) -> bool {
+        if self.is_terminal() {
+            return false;
+        }
+        
         let now = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap_or_default()
             .as_secs();
             
-        if now - self.last_active > self.timeout {
-            self.status = CoinJoinStatus::TimedOut;
+        if now.saturating_sub(self.last_active) > self.timeout {
+            self.set_status(CoinJoinStatus::TimedOut);
             return true;
         }
         
         false
     }
     
+    /// 是否已达到最大参与者数量
+    pub fn is_full(&self) -> bool {
+        self.participants.len() >= self.max_participants
+    }
+    
+    /// 会话是否已结束（完成、失败或超时）
+    pub fn is_terminal(&self) -> bool {
+        matches!(
+            self.status,
+            CoinJoinStatus::Completed | CoinJoinStatus::Failed | CoinJoinStatus::TimedOut
+        )
+    }
+    
+    /// 获取在签名阶段未提交签名的参与者
+    pub fn unsigned_participants(&self) -> HashSet<String> {
+        let signed: HashSet<usize> = self.signatures.iter()
+            .map(|s| s.input_index)
+            .collect();
+            
+        self.input_owners.iter()
+            .enumerate()
+            .filter(|(index, _)| !signed.contains(index))
+            .map(|(_, owner)| owner.clone())
+            .collect()
+    }
+    
+    /// 切换会话状态并通知订阅者
+    fn set_status(&mut self, status: CoinJoinStatus) {
+        if self.status == status {
+            return;
+        }
+        self.status = status;
+        self.dirty = true;
+        
+        let Some(events) = &self.events else {
+            return;
+        };
+        
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+            
+        // 没有订阅者时发送会失败，忽略即可
+        let _ = events.send(CoinJoinEvent::StatusChanged {
+            session_id: self.id.clone(),
+            status: self.status.clone(),
+            deadline: now.saturating_add(self.timeout),
+        });
+        
+        if self.status == CoinJoinStatus::Completed {
+            let _ = events.send(CoinJoinEvent::Transcript(self.transcript()));
+        }
+    }
+    
+    /// 生成会话公告
+    pub fn announcement(&self, coordinator: &str, registration_endpoint: Option<&str>) -> SessionAnnouncement {
+        SessionAnnouncement {
+            session_id: self.id.clone(),
+            coordinator: coordinator.to_string(),
+            status: self.status.clone(),
+            min_participants: self.min_participants,
+            max_participants: self.max_participants,
+            participants_count: self.participants.len(),
+            target_amount: self.target_amount,
+            fee_rate: self.fee_rate,
+            expires_at: self.deadline(),
+            credential_key: self.credential_key(),
+            registration_endpoint: registration_endpoint.map(str::to_string),
+        }
+    }
+    
+    /// 当前阶段的截止时间
+    pub fn deadline(&self) -> u64 {
+        self.last_active.saturating_add(self.timeout)
+    }
+    
+    /// 会话当前状态对应的事件，已完成的会话附带最终记录
+    pub fn current_events(&self) -> Vec<CoinJoinEvent> {
+        let mut events = vec![CoinJoinEvent::StatusChanged {
+            session_id: self.id.clone(),
+            status: self.status.clone(),
+            deadline: self.deadline(),
+        }];
+        if self.status == CoinJoinStatus::Completed {
+            events.push(CoinJoinEvent::Transcript(self.transcript()));
+        }
+        events
+    }
+    
+    /// 获取会话的交易记录
+    pub fn transcript(&self) -> CoinJoinTranscript {
+        CoinJoinTranscript {
+            session_id: self.id.clone(),
+            inputs: self.inputs.clone(),
+            outputs: self.outputs.clone(),
+            final_txid: self.final_txid.clone(),
+            participants_count: self.participants.len(),
+        }
+    }
+    
     /// 更新最后活动时间
     fn update_last_active(&mut self) {
         self.last_active = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap_or_default()
             .as_secs();
+            
+        // 每次会话变更都会更新活动时间，在此处标记待保存
+        self.dirty = true;
+    }
+    
+    /// 计算最终交易ID
+    pub fn compute_txid(&self) -> String {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(self.id.as_bytes());
+        hasher.update(&serde_json::to_vec(&(&self.inputs, &self.outputs, &self.signatures)).unwrap_or_default());
+        hex::encode(hasher.finalize().as_bytes())
+    }
+    
+    /// 生成退款记录，会话终止后每个已注册的输入都被释放
+    pub fn refunds(&self) -> Vec<CoinJoinRefund> {
+        self.inputs.iter()
+            .zip(self.input_owners.iter())
+            .map(|(input, owner)| CoinJoinRefund {
+                session_id: self.id.clone(),
+                participant_id: owner.clone(),
+                input: input.clone(),
+            })
+            .collect()
     }
     
     /// 获取会话信息
@@ -320,90 +793,1693 @@ pub struct CoinJoinRequest {
     pub fee_rate: Option<u64>,
     /// 超时时间（秒）
     pub timeout: Option<u64>,
-    /// 参与者ID
+    /// 参与者ID（公钥hex）
+    pub participant_id: String,
+    /// 请求时间戳，超出[`CREATE_REQUEST_WINDOW`]的请求被拒绝
+    pub timestamp: u64,
+    /// 参与者对创建消息的签名
+    pub participant_signature: String,
+}
+
+/// 创建请求时间戳与本节点时间的最大偏差（秒）
+pub const CREATE_REQUEST_WINDOW: u64 = 300;
+
+impl CoinJoinRequest {
+    /// 参与者签名的消息内容，未指定的可选参数记为空
+    pub fn signing_message(&self) -> String {
+        fn field<T: ToString>(value: Option<T>) -> String {
+            value.map(|v| v.to_string()).unwrap_or_default()
+        }
+        format!(
+            "create:{}:{}:{}:{}:{}:{}:{}",
+            self.participant_id,
+            field(self.min_participants),
+            field(self.max_participants),
+            self.target_amount,
+            field(self.fee_rate),
+            field(self.timeout),
+            self.timestamp
+        )
+    }
+    
+    /// 用参与者私钥签名
+    pub fn sign(mut self, keypair: &SigningKey) -> Result<Self, String> {
+        self.participant_signature = sign_participant(keypair, &self.signing_message())?;
+        Ok(self)
+    }
+    
+    /// 检查参与者签名和请求时间戳
+    pub fn verify(&self, now: u64) -> Result<(), String> {
+        if self.timestamp.abs_diff(now) > CREATE_REQUEST_WINDOW {
+            return Err("创建请求已过期".to_string());
+        }
+        verify_participant(&self.participant_id, &self.signing_message(), &self.participant_signature)
+    }
+}
+
+/// CoinJoin加入请求
+#[derive(Debug, Clone, Deserialize)]
+pub struct JoinRequest {
+    /// 参与者ID（公钥hex）
     pub participant_id: String,
+    /// 参与者对加入消息的签名
+    pub participant_signature: String,
+}
+
+impl JoinRequest {
+    /// 对应的会话消息
+    pub fn message(&self, session_id: &str) -> CoinJoinMessage {
+        CoinJoinMessage::Join {
+            session_id: session_id.to_string(),
+            participant_id: self.participant_id.clone(),
+            participant_signature: self.participant_signature.clone(),
+        }
+    }
+    
+    /// 用参与者私钥签名
+    pub fn sign(mut self, session_id: &str, keypair: &SigningKey) -> Result<Self, String> {
+        let message = self.message(session_id).signing_message().unwrap_or_default();
+        self.participant_signature = sign_participant(keypair, &message)?;
+        Ok(self)
+    }
 }
 
 /// CoinJoin输入请求
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InputRequest {
-    /// 参与者ID
+    /// 参与者ID（公钥hex）
     pub participant_id: String,
     /// 交易输入
     pub input: TxInput,
+    /// 盲化的输出凭证挑战（hex）
+    pub blinded_output: String,
+    /// 参与者对输入消息的签名
+    pub participant_signature: String,
 }
 
-/// CoinJoin输出请求
-#[derive(Debug, Deserialize)]
-pub struct OutputRequest {
-    /// 参与者ID
-    pub participant_id: String,
+impl InputRequest {
+    /// 对应的会话消息
+    pub fn message(&self, session_id: &str) -> CoinJoinMessage {
+        CoinJoinMessage::Input {
+            session_id: session_id.to_string(),
+            participant_id: self.participant_id.clone(),
+            input: self.input.clone(),
+            blinded_output: self.blinded_output.clone(),
+            participant_signature: self.participant_signature.clone(),
+        }
+    }
+    
+    /// 用参与者私钥签名
+    pub fn sign(mut self, session_id: &str, keypair: &SigningKey) -> Result<Self, String> {
+        let message = self.message(session_id).signing_message().unwrap_or_default();
+        self.participant_signature = sign_participant(keypair, &message)?;
+        Ok(self)
+    }
+}
+
+/// 经HTTP接口提交加入或输入请求的结果
+#[derive(Debug, Serialize)]
+pub struct ParticipantReply {
+    /// 会话信息
+    pub session: CoinJoinSessionInfo,
+    /// 加入会话后下发的凭证nonce，用于盲化输出
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    /// 注册输入后签发的盲签输出凭证
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blind_signature: Option<String>,
+}
+
+/// 凭输出凭证登记输出的请求，不携带参与者ID
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlindedOutputRequest {
     /// 交易输出
     pub output: TxOutput,
+    /// 去盲后的输出凭证
+    pub credential: BlindSignature,
+}
+
+/// 发往协调节点登记地址的请求
+///
+/// 输入、输出和签名不经P2P广播（广播消息带有节点签名，协调节点可据此关联同一节点的各个阶段），
+/// 而是每次通过单独的连接提交，启用Tor时每个阶段的每次提交使用独立线路
+#[derive(Debug, Clone)]
+pub struct Registration {
+    /// 协调节点的登记地址（`host:port`）
+    pub endpoint: String,
+    /// 会话ID
+    pub session_id: String,
+    /// 登记内容
+    pub request: RegistrationRequest,
+}
+
+/// 登记内容，按CoinJoin阶段区分
+#[derive(Debug, Clone)]
+pub enum RegistrationRequest {
+    /// 输入登记，协调节点在回复中附带盲签输出凭证
+    Input(InputRequest),
+    /// 凭输出凭证登记输出
+    Output(BlindedOutputRequest),
+    /// 提交签名
+    Signature(SignatureRequest),
+}
+
+impl RegistrationRequest {
+    /// 所属阶段，用作Tor线路隔离的用途
+    pub fn phase(&self) -> &'static str {
+        match self {
+            RegistrationRequest::Input(_) => "coinjoin-input",
+            RegistrationRequest::Output(_) => "coinjoin-output",
+            RegistrationRequest::Signature(_) => "coinjoin-signature",
+        }
+    }
+    
+    /// 协调节点HTTP接口的路径
+    fn path(&self, session_id: &str) -> String {
+        let suffix = match self {
+            RegistrationRequest::Input(_) => "inputs",
+            RegistrationRequest::Output(_) => "outputs/blinded",
+            RegistrationRequest::Signature(_) => "signatures",
+        };
+        format!("/v1/coinjoin/sessions/{}/{}", session_id, suffix)
+    }
+    
+    fn body(&self) -> Result<String, String> {
+        match self {
+            RegistrationRequest::Input(request) => serde_json::to_string(request),
+            RegistrationRequest::Output(request) => serde_json::to_string(request),
+            RegistrationRequest::Signature(request) => serde_json::to_string(request),
+        }
+        .map_err(|e| e.to_string())
+    }
+}
+
+/// 参与者一方待登记的输出
+#[derive(Default)]
+struct PendingOutput {
+    /// 协调节点下发的nonce
+    nonce: Option<String>,
+    /// 待登记的输出
+    output: Option<TxOutput>,
+    /// 盲化状态
+    unblinder: Option<Unblinder>,
+    /// 去盲后的凭证
+    credential: Option<BlindSignature>,
+    /// 已登记的输出，会话完成后只统计这些输出的隐私
+    registered: Option<TxOutput>,
+}
+
+/// 本节点参与者加入的远程会话
+struct RemoteParticipation {
+    /// 协调节点的PeerId，只接受其发送的结束消息
+    coordinator: String,
+    /// 等待结束消息的截止时间
+    expires_at: u64,
 }
 
 /// CoinJoin签名请求
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SignatureRequest {
-    /// 参与者ID
+    /// 参与者ID（公钥hex）
     pub participant_id: String,
     /// 交易签名
     pub signature: TxSignature,
+    /// 参与者对签名消息的签名
+    pub participant_signature: String,
+}
+
+impl SignatureRequest {
+    /// 对应的会话消息
+    pub fn message(&self, session_id: &str) -> CoinJoinMessage {
+        CoinJoinMessage::Signature {
+            session_id: session_id.to_string(),
+            participant_id: self.participant_id.clone(),
+            signature: self.signature.clone(),
+            participant_signature: self.participant_signature.clone(),
+        }
+    }
+    
+    /// 用参与者私钥签名
+    pub fn sign(mut self, session_id: &str, keypair: &SigningKey) -> Result<Self, String> {
+        let message = self.message(session_id).signing_message().unwrap_or_default();
+        self.participant_signature = sign_participant(keypair, &message)?;
+        Ok(self)
+    }
 }
 
 /// CoinJoin完成请求
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct FinalizeRequest {
+    /// 参与者ID（公钥hex）
+    pub participant_id: String,
+    /// 参与者对`finalize:<会话ID>:<参与者ID>`的签名
+    pub participant_signature: String,
+}
+
+impl FinalizeRequest {
+    /// 参与者签名的消息内容
+    pub fn signing_message(&self, session_id: &str) -> String {
+        format!("finalize:{}:{}", session_id, self.participant_id)
+    }
+    
+    /// 用参与者私钥签名
+    pub fn sign(mut self, session_id: &str, keypair: &SigningKey) -> Result<Self, String> {
+        self.participant_signature = sign_participant(keypair, &self.signing_message(session_id))?;
+        Ok(self)
+    }
+    
+    /// 检查参与者签名
+    pub fn verify(&self, session_id: &str) -> Result<(), String> {
+        verify_participant(&self.participant_id, &self.signing_message(session_id), &self.participant_signature)
+    }
+}
+
+/// CoinJoin退款记录
+///
+/// 会话失败后，已注册的输入被释放，参与者可以在其他交易中重新使用
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoinJoinRefund {
+    /// 会话ID
+    pub session_id: String,
     /// 参与者ID
     pub participant_id: String,
+    /// 退还的输入
+    pub input: TxInput,
 }
 
-/// CoinJoin会话管理器
-pub struct CoinJoinManager {
-    /// 会话映射表
-    sessions: DashMap<String, CoinJoinSession>,
-    /// 会话超时时间（秒）
-    session_timeout: u64,
-    /// 清理任务通道
-    _cleanup_tx: Option<mpsc::Sender<()>>,
+/// 会话恢复结果
+#[derive(Debug, Default)]
+pub struct RestoreReport {
+    /// 继续进行的会话
+    pub resumed: Vec<String>,
+    /// 因超时或失败而终止的会话
+    pub failed: Vec<String>,
+    /// 生成的退款记录
+    pub refunds: Vec<CoinJoinRefund>,
 }
 
-impl CoinJoinManager {
-    /// 创建新的CoinJoin会话管理器
-    pub fn new(session_timeout: u64) -> Self {
-        let (tx, mut rx) = mpsc::channel::<()>(1);
-        
-        // 启动清理任务
-        tokio::spawn(async move {
-            let mut interval = tokio::time::interval(Duration::from_secs(60));
-            
-            loop {
-                tokio::select! {
-                    _ = interval.tick() => {
-                        // 定期清理过期会话
-                        debug!("执行CoinJoin会话清理");
-                    }
-                    _ = rx.recv() => {
-                        debug!("CoinJoin会话清理任务退出");
-                        break;
-                    }
-                }
-            }
-        });
-        
-        Self {
-            sessions: DashMap::new(),
-            session_timeout,
-            _cleanup_tx: Some(tx),
+/// CoinJoin会话持久化存储
+///
+/// 基于sled，会话和退款记录分别保存在不同的树中，终止会话与写入退款在同一事务中完成。
+/// 会话的写入都经过写锁，保证同一会话先取的快照不会覆盖后取的快照
+#[derive(Debug, Clone)]
+pub struct CoinJoinStore {
+    sessions: sled::Tree,
+    refunds: sled::Tree,
+    privacy: sled::Tree,
+    write_lock: Arc<Mutex<()>>,
+}
+
+impl CoinJoinStore {
+    /// 打开指定目录下的存储
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, String> {
+        let db = sled::open(path).map_err(|e| e.to_string())?;
+        Self::from_db(&db)
+    }
+    
+    /// 使用已打开的数据库
+    pub fn from_db(db: &sled::Db) -> Result<Self, String> {
+        Ok(Self {
+            sessions: db.open_tree("coinjoin_sessions").map_err(|e| e.to_string())?,
+            refunds: db.open_tree("coinjoin_refunds").map_err(|e| e.to_string())?,
+            privacy: db.open_tree("coinjoin_privacy").map_err(|e| e.to_string())?,
+            write_lock: Arc::new(Mutex::new(())),
+        })
+    }
+    
+    /// 保存会话
+    ///
+    /// 只写入不刷盘，由sled后台定期批量刷盘；终止会话和写入退款记录立即刷盘
+    pub fn save(&self, session: &CoinJoinSession) -> Result<(), String> {
+        let _guard = self.write_lock.lock();
+        self.insert(session)
+    }
+    
+    /// 在写锁内取会话快照并保存，快照为空时不写入
+    ///
+    /// 管理器在快照闭包中短暂持有会话表锁复制会话，写入存储时不持有会话表锁
+    pub fn save_latest(&self, snapshot: impl FnOnce() -> Option<CoinJoinSession>) -> Result<(), String> {
+        let _guard = self.write_lock.lock();
+        match snapshot() {
+            Some(session) => self.insert(&session),
+            None => Ok(()),
         }
     }
     
-    /// 创建新的CoinJoin会话
-    pub fn create_session(&self, req: &CoinJoinRequest) -> CoinJoinSessionInfo {
+    fn insert(&self, session: &CoinJoinSession) -> Result<(), String> {
+        let data = serde_json::to_vec(session).map_err(|e| e.to_string())?;
+        self.sessions.insert(session.id.as_bytes(), data).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+    
+    /// 删除会话
+    pub fn remove(&self, session_id: &str) -> Result<(), String> {
+        let _guard = self.write_lock.lock();
+        self.sessions.remove(session_id.as_bytes()).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+    
+    /// 读取所有会话
+    pub fn load_all(&self) -> Result<Vec<CoinJoinSession>, String> {
+        let mut sessions = Vec::new();
+        for item in self.sessions.iter() {
+            let (key, value) = item.map_err(|e| e.to_string())?;
+            match serde_json::from_slice::<CoinJoinSession>(&value) {
+                Ok(session) => sessions.push(session),
+                Err(e) => warn!("无法解析持久化的CoinJoin会话 {}: {}", String::from_utf8_lossy(&key), e),
+            }
+        }
+        Ok(sessions)
+    }
+    
+    /// 终止会话并写入退款记录（同一事务）
+    pub fn fail_with_refunds(&self, session: &CoinJoinSession) -> Result<Vec<CoinJoinRefund>, String> {
+        use sled::Transactional;
+        
+        let refunds = session.refunds();
+        let data = serde_json::to_vec(&refunds).map_err(|e| e.to_string())?;
+        
+        let _guard = self.write_lock.lock();
+        (&self.sessions, &self.refunds)
+            .transaction(|(sessions, pending)| {
+                sessions.remove(session.id.as_bytes())?;
+                if !refunds.is_empty() {
+                    pending.insert(session.id.as_bytes(), data.clone())?;
+                }
+                Ok(())
+            })
+            .map_err(|e: sled::transaction::TransactionError<()>| format!("{:?}", e))?;
+        self.sessions.flush().map_err(|e| e.to_string())?;
+        
+        Ok(refunds)
+    }
+    
+    /// 待处理的退款记录
+    pub fn pending_refunds(&self) -> Result<Vec<CoinJoinRefund>, String> {
+        let mut refunds = Vec::new();
+        for item in self.refunds.iter() {
+            let (_, value) = item.map_err(|e| e.to_string())?;
+            let batch: Vec<CoinJoinRefund> = serde_json::from_slice(&value).map_err(|e| e.to_string())?;
+            refunds.extend(batch);
+        }
+        Ok(refunds)
+    }
+    
+    /// 确认退款已处理
+    pub fn acknowledge_refunds(&self, session_id: &str) -> Result<bool, String> {
+        let removed = self.refunds.remove(session_id.as_bytes()).map_err(|e| e.to_string())?;
+        Ok(removed.is_some())
+    }
+    
+    /// 保存账户隐私统计
+    pub fn save_privacy(&self, privacy: &AccountPrivacy) -> Result<(), String> {
+        let data = serde_json::to_vec(privacy).map_err(|e| e.to_string())?;
+        self.privacy.insert(privacy.account.as_bytes(), data).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+    
+    /// 读取所有账户隐私统计
+    pub fn load_privacy(&self) -> Result<Vec<AccountPrivacy>, String> {
+        let mut records = Vec::new();
+        for item in self.privacy.iter() {
+            let (_, value) = item.map_err(|e| e.to_string())?;
+            records.push(serde_json::from_slice(&value).map_err(|e| e.to_string())?);
+        }
+        Ok(records)
+    }
+}
+
+/// 拉黑（blame）配置
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BlameConfig {
+    /// 触发封禁的拖延次数
+    pub max_strikes: u32,
+    /// 拖延记录的有效期（秒），超过后重新计数
+    pub strike_window: u64,
+    /// 封禁时长（秒）
+    pub ban_duration: u64,
+}
+
+impl Default for BlameConfig {
+    fn default() -> Self {
+        Self {
+            max_strikes: 3,
+            strike_window: 86400, // 24小时
+            ban_duration: 3600,   // 1小时
+        }
+    }
+}
+
+/// 拖延记录有效期和封禁时长的上限（秒）
+pub const MAX_BLAME_DURATION: u64 = 365 * 86400;
+
+/// 单个会话的参与者数量上限，创建会话时指定的最大人数不能超过该值
+pub const MAX_PARTICIPANTS: usize = 20;
+
+impl BlameConfig {
+    /// 检查配置是否有效
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_strikes == 0 {
+            return Err("max_strikes必须大于0".to_string());
+        }
+        if self.strike_window > MAX_BLAME_DURATION {
+            return Err(format!("strike_window不能超过{}秒", MAX_BLAME_DURATION));
+        }
+        if self.ban_duration > MAX_BLAME_DURATION {
+            return Err(format!("ban_duration不能超过{}秒", MAX_BLAME_DURATION));
+        }
+        Ok(())
+    }
+}
+
+/// CoinJoin管理器配置
+#[derive(Clone, Debug)]
+pub struct CoinJoinConfig {
+    /// 会话超时时间（秒）
+    pub session_timeout: u64,
+    /// 创建会话时可指定的最长超时时间（秒）
+    pub max_session_timeout: u64,
+    /// 清理任务执行间隔
+    pub cleanup_interval: Duration,
+    /// 已完成会话保留时间（秒），便于参与者查询最终结果
+    pub completed_retention: u64,
+    /// 拉黑配置
+    pub blame: BlameConfig,
+    /// 会话事件缓冲区大小
+    pub event_buffer: usize,
+    /// 建议的最低匿名分数，低于该值时建议再次混币
+    pub target_anonymity: u64,
+    /// 每个来源（远程节点或HTTP客户端地址）在一个会话中最多代理的参与者数量
+    pub max_participants_per_peer: usize,
+    /// 每个来源同时开启（未结束）的会话数量上限
+    pub max_sessions_per_source: usize,
+    /// 会话创建后接受加入的时间（秒），不超过会话超时时间
+    pub lobby_timeout: u64,
+    /// 本节点的登记地址（`host:port`），随会话公告广播
+    pub registration_endpoint: Option<String>,
+}
+
+impl Default for CoinJoinConfig {
+    fn default() -> Self {
+        Self {
+            session_timeout: 3600,
+            max_session_timeout: 86400,
+            cleanup_interval: Duration::from_secs(60),
+            completed_retention: 600,
+            blame: BlameConfig::default(),
+            event_buffer: 256,
+            target_anonymity: 5,
+            max_participants_per_peer: 1,
+            max_sessions_per_source: 2,
+            lobby_timeout: 120,
+            registration_endpoint: None,
+        }
+    }
+}
+
+/// 参与者的拖延记录
+#[derive(Debug, Clone, Serialize)]
+pub struct BlameRecord {
+    /// 参与者ID
+    pub participant_id: String,
+    /// 有效期内的拖延次数
+    pub strikes: u32,
+    /// 最近一次拖延时间
+    pub last_strike: u64,
+    /// 封禁截止时间
+    pub banned_until: Option<u64>,
+}
+
+/// 拖延追踪器
+///
+/// 记录在签名阶段未提交签名而导致会话超时的参与者，
+/// 拖延次数达到阈值后临时封禁。
+pub struct BlameTracker {
+    config: RwLock<BlameConfig>,
+    records: DashMap<String, BlameRecord>,
+}
+
+impl BlameTracker {
+    /// 创建新的拖延追踪器
+    pub fn new(config: BlameConfig) -> Self {
+        Self {
+            config: RwLock::new(config),
+            records: DashMap::new(),
+        }
+    }
+    
+    /// 当前拉黑配置
+    pub fn config(&self) -> BlameConfig {
+        self.config.read().clone()
+    }
+    
+    /// 更新拉黑配置，只影响之后的拖延记录和封禁
+    pub fn set_config(&self, config: BlameConfig) -> Result<(), String> {
+        config.validate()?;
+        *self.config.write() = config;
+        Ok(())
+    }
+    
+    /// 记录一次拖延，返回是否因此被封禁
+    pub fn record_strike(&self, participant_id: &str, now: u64) -> bool {
+        let config = self.config();
+        let mut record = self.records.entry(participant_id.to_string())
+            .or_insert_with(|| BlameRecord {
+                participant_id: participant_id.to_string(),
+                strikes: 0,
+                last_strike: now,
+                banned_until: None,
+            });
+            
+        // 超过有效期的旧记录重新计数
+        if now.saturating_sub(record.last_strike) > config.strike_window {
+            record.strikes = 0;
+        }
+        
+        record.strikes += 1;
+        record.last_strike = now;
+        
+        if record.strikes >= config.max_strikes {
+            record.banned_until = Some(now.saturating_add(config.ban_duration));
+            record.strikes = 0;
+            warn!("参与者 {} 多次拖延CoinJoin会话，封禁 {} 秒", participant_id, config.ban_duration);
+            return true;
+        }
+        
+        false
+    }
+    
+    /// 检查参与者是否处于封禁期
+    pub fn is_banned(&self, participant_id: &str, now: u64) -> bool {
+        self.records.get(participant_id)
+            .and_then(|r| r.banned_until)
+            .is_some_and(|until| now < until)
+    }
+    
+    /// 解除封禁并清除拖延记录
+    pub fn unban(&self, participant_id: &str) -> bool {
+        self.records.remove(participant_id).is_some()
+    }
+    
+    /// 获取当前封禁列表
+    pub fn banned(&self, now: u64) -> Vec<BlameRecord> {
+        self.records.iter()
+            .filter(|r| r.banned_until.is_some_and(|until| now < until))
+            .map(|r| r.clone())
+            .collect()
+    }
+    
+    /// 清理已过期的记录
+    pub fn prune(&self, now: u64) {
+        let strike_window = self.config.read().strike_window;
+        self.records.retain(|_, r| {
+            let banned = r.banned_until.is_some_and(|until| now < until);
+            let recent = r.strikes > 0 && now.saturating_sub(r.last_strike) <= strike_window;
+            banned || recent
+        });
+    }
+}
+
+/// 每个账户最多保留的混币输出记录数
+const MAX_MIXED_OUTPUTS: usize = 100;
+
+/// 来自CoinJoin的输出
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MixedOutput {
+    /// 会话ID
+    pub session_id: String,
+    /// 最终交易ID
+    pub txid: Option<String>,
+    /// 金额
+    pub amount: u64,
+    /// 匿名集大小
+    pub anonymity_set: usize,
+    /// 混币完成时间
+    pub mixed_at: u64,
+}
+
+/// 账户的混币隐私统计
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountPrivacy {
+    /// 账户（输出地址）
+    pub account: String,
+    /// 累计匿名分数
+    pub anonymity_score: u64,
+    /// 参与混币次数
+    pub mixes: u32,
+    /// 最近的混币输出
+    pub outputs: Vec<MixedOutput>,
+}
+
+/// 账户隐私报告（用于API响应）
+#[derive(Debug, Serialize)]
+pub struct PrivacyReport {
+    /// 账户隐私统计
+    #[serde(flatten)]
+    pub privacy: AccountPrivacy,
+    /// 建议的最低匿名分数
+    pub target_anonymity: u64,
+    /// 是否建议再次混币
+    pub remix_recommended: bool,
+}
+
+/// 混币隐私统计
+///
+/// 累计分数采用加法模型：未混币的账户分数为1，
+/// 每次混币在原有分数上增加（匿名集大小 - 1）
+pub struct PrivacyTracker {
+    target_anonymity: u64,
+    accounts: DashMap<String, AccountPrivacy>,
+}
+
+impl PrivacyTracker {
+    /// 创建新的隐私统计
+    pub fn new(target_anonymity: u64) -> Self {
+        Self {
+            target_anonymity,
+            accounts: DashMap::new(),
+        }
+    }
+    
+    /// 记录一次完成的混币，返回更新后的账户统计
+    ///
+    /// 只统计本节点为其参与者登记的输出`own_outputs`；最终记录由协调节点提供，
+    /// 缺少其中任何一个输出时视为不可信，不做统计
+    pub fn record(&self, transcript: &CoinJoinTranscript, own_outputs: &[TxOutput], now: u64) -> Result<Vec<AccountPrivacy>, String> {
+        let mut unmatched: Vec<&TxOutput> = transcript.outputs.iter().collect();
+        for output in own_outputs {
+            let index = unmatched.iter()
+                .position(|o| o.address == output.address && o.amount == output.amount)
+                .ok_or_else(|| format!("最终记录缺少本节点登记的输出: {}", output.address))?;
+            unmatched.swap_remove(index);
+        }
+        
+        let sets = transcript.anonymity_sets();
+        let mut updated: HashMap<String, AccountPrivacy> = HashMap::new();
+        
+        for output in own_outputs {
+            let anonymity_set = sets.get(&output.amount).copied().unwrap_or(1);
+            let mut entry = self.accounts.entry(output.address.clone())
+                .or_insert_with(|| AccountPrivacy {
+                    account: output.address.clone(),
+                    anonymity_score: 1,
+                    mixes: 0,
+                    outputs: Vec::new(),
+                });
+                
+            // 同一会话中的多个输出只计一次混币
+            if !updated.contains_key(&output.address) {
+                entry.mixes += 1;
+                entry.anonymity_score = entry.anonymity_score
+                    .saturating_add(anonymity_set.saturating_sub(1) as u64);
+            }
+            
+            if entry.outputs.len() >= MAX_MIXED_OUTPUTS {
+                entry.outputs.remove(0);
+            }
+            entry.outputs.push(MixedOutput {
+                session_id: transcript.session_id.clone(),
+                txid: transcript.final_txid.clone(),
+                amount: output.amount,
+                anonymity_set,
+                mixed_at: now,
+            });
+            
+            updated.insert(output.address.clone(), entry.clone());
+        }
+        
+        Ok(updated.into_values().collect())
+    }
+    
+    /// 载入已持久化的账户统计
+    pub fn load(&self, privacy: AccountPrivacy) {
+        self.accounts.insert(privacy.account.clone(), privacy);
+    }
+    
+    /// 获取账户隐私报告
+    pub fn report(&self, account: &str) -> PrivacyReport {
+        let privacy = self.accounts.get(account)
+            .map(|p| p.clone())
+            .unwrap_or_else(|| AccountPrivacy {
+                account: account.to_string(),
+                anonymity_score: 1,
+                mixes: 0,
+                outputs: Vec::new(),
+            });
+            
+        PrivacyReport {
+            remix_recommended: privacy.anonymity_score < self.target_anonymity,
+            target_anonymity: self.target_anonymity,
+            privacy,
+        }
+    }
+}
+
+/// 连接CoinJoin管理器与P2P网络
+///
+/// 转发本地会话公告和参与者消息，并将网络上收到的CoinJoin消息交给管理器处理；
+/// 输入、输出和签名登记不经P2P网络，每条登记随机延迟后通过新的连接提交到协调节点，
+/// 启用Tor时每条登记按阶段使用独立线路
+pub fn spawn_network_bridge(manager: Arc<CoinJoinManager>, p2p: P2PHandle, tor: Option<TorConnector>) {
+    manager.set_node_id(p2p.local_peer_id().to_string());
+    
+    let mut outbound = manager.subscribe_outbound();
+    let mut registrations = manager.subscribe_registrations();
+    let mut inbound = p2p.subscribe();
+    let mut status_events = manager.subscribe();
+    
+    tokio::spawn(async move {
+        let mut reannounce = tokio::time::interval(Duration::from_secs(30));
+        
+        loop {
+            tokio::select! {
+                msg = outbound.recv() => match msg {
+                    Ok(msg) => {
+                        if let Err(e) = p2p.publish(P2PPayload::CoinJoin(msg)).await {
+                            warn!("广播CoinJoin消息失败: {}", e);
+                        }
+                    }
+                    Err(RecvError::Lagged(skipped)) => warn!("CoinJoin出站消息积压，丢弃 {} 条", skipped),
+                    Err(RecvError::Closed) => break,
+                },
+                registration = registrations.recv() => match registration {
+                    Ok(registration) => {
+                        let tor = tor.clone();
+                        let manager = manager.clone();
+                        tokio::spawn(async move {
+                            let delay = Duration::from_millis(rand::random::<u64>() % REGISTRATION_JITTER_MS);
+                            tokio::time::sleep(delay).await;
+                            let result = post_registration(&registration, tor.as_ref()).await
+                                .and_then(|reply| manager.complete_registration(&registration, &reply));
+                            if let Err(e) = result {
+                                warn!("提交CoinJoin登记失败: {} ({}): {}", registration.session_id, registration.request.phase(), e);
+                            }
+                        });
+                    }
+                    Err(RecvError::Lagged(skipped)) => warn!("CoinJoin登记积压，丢弃 {} 条", skipped),
+                    Err(RecvError::Closed) => break,
+                },
+                event = inbound.recv() => match event {
+                    Ok(P2PEvent::Message { source, payload: P2PPayload::CoinJoin(msg) }) => {
+                        manager.handle_network_message(&source.to_string(), msg);
+                    }
+                    Ok(P2PEvent::Stopped) => break,
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(skipped)) => warn!("P2P入站消息积压，丢弃 {} 条", skipped),
+                    Err(RecvError::Closed) => break,
+                },
+                event = status_events.recv() => match event {
+                    Ok(event) => {
+                        manager.announce(event.session_id());
+                    }
+                    Err(RecvError::Lagged(_)) => manager.announce_sessions(),
+                    Err(RecvError::Closed) => break,
+                },
+                _ = reannounce.tick() => {
+                    manager.announce_sessions();
+                }
+            }
+        }
+        
+        debug!("CoinJoin网络桥接任务退出");
+    });
+}
+
+/// 待登记输出的键
+fn pending_key(session_id: &str, participant_id: &str) -> String {
+    format!("{}/{}", session_id, participant_id)
+}
+
+/// 输出登记的最大随机延迟（毫秒），打乱同一节点多个输出的提交时间
+const REGISTRATION_JITTER_MS: u64 = 10_000;
+
+/// 通过新的连接向协调节点的登记地址提交登记，启用Tor时经该阶段的独立线路连接，返回响应内容
+async fn post_registration(registration: &Registration, tor: Option<&TorConnector>) -> Result<serde_json::Value, String> {
+    use futures::{AsyncReadExt, AsyncWriteExt};
+    
+    let body = registration.request.body()?;
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        registration.request.path(&registration.session_id), registration.endpoint, body.len(), body
+    );
+    
+    let mut stream = match tor {
+        Some(tor) => {
+            let (host, port) = tor::parse_host_port(&registration.endpoint).map_err(|e| e.to_string())?;
+            let isolation = tor.fresh_isolation(registration.request.phase());
+            tor.connect_isolated(&host, port, &isolation).await.map_err(|e| e.to_string())?
+        }
+        None => {
+            let stream = tokio::time::timeout(Duration::from_secs(30), tokio::net::TcpStream::connect(&registration.endpoint))
+                .await
+                .map_err(|_| "连接超时".to_string())?
+                .map_err(|e| e.to_string())?;
+            tor::TorStream::Tcp(libp2p::tcp::tokio::TcpStream(stream))
+        }
+    };
+    stream.write_all(request.as_bytes()).await.map_err(|e| e.to_string())?;
+    
+    let mut response = Vec::new();
+    tokio::time::timeout(Duration::from_secs(30), stream.read_to_end(&mut response))
+        .await
+        .map_err(|_| "等待响应超时".to_string())?
+        .map_err(|e| e.to_string())?;
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or_default();
+    if status_line.split_whitespace().nth(1) != Some("200") {
+        return Err(format!("协调节点拒绝登记: {}", status_line));
+    }
+    let body = response.split_once("\r\n\r\n").map(|(_, body)| body).unwrap_or_default();
+    Ok(serde_json::from_str(body).unwrap_or_default())
+}
+
+/// CoinJoin会话管理器
+pub struct CoinJoinManager {
+    /// 会话映射表
+    sessions: Arc<DashMap<String, CoinJoinSession>>,
+    /// 会话超时时间（秒）
+    session_timeout: u64,
+    /// 会话超时时间上限（秒）
+    max_session_timeout: u64,
+    /// 已完成会话保留时间（秒）
+    completed_retention: u64,
+    /// 拖延追踪器
+    blame: Arc<BlameTracker>,
+    /// 会话事件广播
+    events: broadcast::Sender<CoinJoinEvent>,
+    /// 本节点的PeerId，作为本地会话的协调者标识
+    node_id: OnceCell<String>,
+    /// 网络上其他节点协调的会话
+    remote_sessions: Arc<DashMap<String, SessionAnnouncement>>,
+    /// 待发往P2P网络的消息
+    outbound: broadcast::Sender<CoinJoinMessage>,
+    /// 会话持久化存储
+    store: Arc<OnceCell<Arc<CoinJoinStore>>>,
+    /// 混币隐私统计
+    privacy: Arc<PrivacyTracker>,
+    /// 本节点参与者加入的远程会话，键为会话ID
+    remote_participation: Arc<DashMap<String, RemoteParticipation>>,
+    /// 每个远程节点在一个会话中最多代理的参与者数量
+    max_participants_per_peer: usize,
+    /// 每个来源同时开启的会话数量上限
+    max_sessions_per_source: usize,
+    /// 会话接受加入的时间（秒）
+    lobby_timeout: u64,
+    /// 本节点的登记地址
+    registration_endpoint: Option<String>,
+    /// 本节点参与者待登记的输出，键为`<会话ID>/<参与者ID>`
+    pending_outputs: Arc<DashMap<String, PendingOutput>>,
+    /// 待通过独立连接提交的登记
+    registrations: broadcast::Sender<Registration>,
+    /// 清理任务通道
+    _cleanup_tx: Option<mpsc::Sender<()>>,
+}
+
+impl CoinJoinManager {
+    /// 创建新的CoinJoin会话管理器
+    pub fn new(session_timeout: u64) -> Self {
+        Self::with_config(CoinJoinConfig {
+            session_timeout,
+            ..Default::default()
+        })
+    }
+    
+    /// 使用指定配置创建CoinJoin会话管理器
+    pub fn with_config(config: CoinJoinConfig) -> Self {
+        let (tx, mut rx) = mpsc::channel::<()>(1);
+        let sessions = Arc::new(DashMap::new());
+        let blame = Arc::new(BlameTracker::new(config.blame.clone()));
+        let (events, _) = broadcast::channel(config.event_buffer);
+        let (outbound, _) = broadcast::channel(config.event_buffer);
+        let (registrations, _) = broadcast::channel(config.event_buffer);
+        let remote_sessions = Arc::new(DashMap::new());
+        let pending_outputs = Arc::new(DashMap::new());
+        let store: Arc<OnceCell<Arc<CoinJoinStore>>> = Arc::new(OnceCell::new());
+        let privacy = Arc::new(PrivacyTracker::new(config.target_anonymity));
+        let remote_participation = Arc::new(DashMap::new());
+        
+        // 启动清理任务
+        let sweep_sessions = sessions.clone();
+        let sweep_remote = remote_sessions.clone();
+        let sweep_pending = pending_outputs.clone();
+        let sweep_participation = remote_participation.clone();
+        let sweep_blame = blame.clone();
+        let sweep_store = store.clone();
+        let completed_retention = config.completed_retention;
+        let cleanup_interval = config.cleanup_interval;
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(cleanup_interval);
+            
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        // 定期清理过期会话
+                        debug!("执行CoinJoin会话清理");
+                        Self::sweep(
+                            &sweep_sessions,
+                            &sweep_remote,
+                            &sweep_pending,
+                            &sweep_participation,
+                            &sweep_blame,
+                            sweep_store.get().map(|s| s.as_ref()),
+                            completed_retention,
+                        );
+                    }
+                    _ = rx.recv() => {
+                        debug!("CoinJoin会话清理任务退出");
+                        break;
+                    }
+                }
+            }
+        });
+        
+        Self {
+            sessions,
+            session_timeout: config.session_timeout,
+            max_session_timeout: config.max_session_timeout,
+            completed_retention,
+            blame,
+            events,
+            node_id: OnceCell::new(),
+            remote_sessions,
+            outbound,
+            store,
+            privacy,
+            remote_participation,
+            max_participants_per_peer: config.max_participants_per_peer,
+            max_sessions_per_source: config.max_sessions_per_source,
+            lobby_timeout: config.lobby_timeout,
+            registration_endpoint: config.registration_endpoint,
+            pending_outputs,
+            registrations,
+            _cleanup_tx: Some(tx),
+        }
+    }
+    
+    /// 立即执行一次会话清理，返回被移除的会话数量
+    pub fn cleanup(&self) -> usize {
+        Self::sweep(
+            &self.sessions,
+            &self.remote_sessions,
+            &self.pending_outputs,
+            &self.remote_participation,
+            &self.blame,
+            self.store.get().map(|s| s.as_ref()),
+            self.completed_retention,
+        )
+    }
+    
+    /// 清理超时、失败以及保留期已过的已完成会话
+    ///
+    /// 在签名阶段超时的会话会对未签名的参与者记录一次拖延。
+    fn sweep(
+        sessions: &DashMap<String, CoinJoinSession>,
+        remote_sessions: &DashMap<String, SessionAnnouncement>,
+        pending_outputs: &DashMap<String, PendingOutput>,
+        remote_participation: &DashMap<String, RemoteParticipation>,
+        blame: &BlameTracker,
+        store: Option<&CoinJoinStore>,
+        completed_retention: u64,
+    ) -> usize {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let before = sessions.len();
+        let mut removed = Vec::new();
+        let mut advanced = Vec::new();
+        
+        sessions.retain(|id, session| {
+            if session.close_lobby(now) {
+                info!("CoinJoin会话结束等待加入: {} ({} 名参与者)", id, session.participants.len());
+                advanced.push(id.clone());
+            }
+            
+            let was_signing = session.status == CoinJoinStatus::CollectingSignatures;
+            if session.check_timeout() {
+                info!("CoinJoin会话超时: {}", id);
+                if was_signing {
+                    for participant in session.unsigned_participants() {
+                        blame.record_strike(&participant, now);
+                    }
+                }
+            }
+            
+            let keep = match session.status {
+                CoinJoinStatus::TimedOut | CoinJoinStatus::Failed => false,
+                CoinJoinStatus::Completed => {
+                    now.saturating_sub(session.last_active) <= completed_retention
+                }
+                _ => true,
+            };
+            
+            if !keep && store.is_some() {
+                removed.push(session.clone());
+            }
+            keep
+        });
+        
+        // 在会话表锁外更新持久化存储
+        if let Some(store) = store {
+            for id in advanced {
+                let result = store.save_latest(|| {
+                    let mut session = sessions.get_mut(&id)?;
+                    std::mem::take(&mut session.dirty).then(|| session.clone())
+                });
+                if let Err(e) = result {
+                    error!("持久化CoinJoin会话失败: {}: {}", id, e);
+                }
+            }
+            for session in removed {
+                let result = if session.status == CoinJoinStatus::Completed {
+                    store.remove(&session.id)
+                } else {
+                    store.fail_with_refunds(&session).map(|_| ())
+                };
+                if let Err(e) = result {
+                    error!("清理持久化的CoinJoin会话失败: {}: {}", session.id, e);
+                }
+            }
+        }
+        blame.prune(now);
+        remote_sessions.retain(|_, a| a.expires_at > now);
+        remote_participation.retain(|_, p| p.expires_at > now);
+        pending_outputs.retain(|key, _| {
+            let session_id = key.split('/').next().unwrap_or_default();
+            sessions.contains_key(session_id)
+                || remote_sessions.contains_key(session_id)
+                || remote_participation.contains_key(session_id)
+        });
+        
+        let removed = before.saturating_sub(sessions.len());
+        if removed > 0 {
+            debug!("清理了 {} 个CoinJoin会话", removed);
+        }
+        removed
+    }
+    
+    /// 保存有变更的会话
+    ///
+    /// 在释放会话表锁后调用：只在复制会话时短暂持有锁，写入存储时不阻塞同一分片上的其他会话
+    fn persist(&self, session_id: &str) {
+        let Some(store) = self.store.get() else {
+            return;
+        };
+        let result = store.save_latest(|| {
+            let mut session = self.sessions.get_mut(session_id)?;
+            std::mem::take(&mut session.dirty).then(|| session.clone())
+        });
+        if let Err(e) = result {
+            error!("持久化CoinJoin会话失败: {}: {}", session_id, e);
+        }
+    }
+    
+    /// 检查参与者是否被封禁
+    fn ensure_not_banned(&self, participant_id: &str) -> Result<(), String> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+            
+        if self.blame.is_banned(participant_id, now) {
+            return Err(format!("参与者已被临时封禁: {}", participant_id));
+        }
+        Ok(())
+    }
+    
+    /// 订阅会话事件
+    pub fn subscribe(&self) -> broadcast::Receiver<CoinJoinEvent> {
+        self.events.subscribe()
+    }
+    
+    /// 会话当前状态对应的事件，订阅者错过事件后据此补发；会话不存在时为空
+    pub fn session_events(&self, session_id: &str) -> Vec<CoinJoinEvent> {
+        self.sessions.get(session_id)
+            .map(|session| session.current_events())
+            .unwrap_or_default()
+    }
+    
+    /// 检查参与者是否在会话中
+    pub fn is_participant(&self, session_id: &str, participant_id: &str) -> bool {
+        self.sessions.get(session_id)
+            .is_some_and(|s| s.participants.contains(participant_id))
+    }
+    
+    /// 挂载持久化存储并恢复重启前的会话
+    ///
+    /// 未超时的会话继续进行；已失败、已超时或在停机期间超时的会话
+    /// 被终止并生成退款记录；保留期已过的已完成会话被删除。
+    pub fn attach_store(&self, store: CoinJoinStore) -> Result<RestoreReport, String> {
+        let store = Arc::new(store);
+        if self.store.set(store.clone()).is_err() {
+            return Err("CoinJoin存储已挂载".to_string());
+        }
+        
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut report = RestoreReport::default();
+        
+        for privacy in store.load_privacy()? {
+            self.privacy.load(privacy);
+        }
+        
+        for mut session in store.load_all()? {
+            session.check_timeout();
+            
+            match session.status {
+                CoinJoinStatus::Failed | CoinJoinStatus::TimedOut => {
+                    warn!("CoinJoin会话 {} 在重启后终止 ({:?})", session.id, session.status);
+                    report.refunds.extend(store.fail_with_refunds(&session)?);
+                    report.failed.push(session.id);
+                }
+                CoinJoinStatus::Completed
+                    if now.saturating_sub(session.last_active) > self.completed_retention =>
+                {
+                    store.remove(&session.id)?;
+                }
+                _ => {
+                    info!("恢复CoinJoin会话 {} ({:?})", session.id, session.status);
+                    session.events = Some(self.events.clone());
+                    report.resumed.push(session.id.clone());
+                    self.sessions.insert(session.id.clone(), session);
+                }
+            }
+        }
+        
+        Ok(report)
+    }
+    
+    /// 获取账户的混币隐私报告
+    pub fn privacy_report(&self, account: &str) -> PrivacyReport {
+        self.privacy.report(account)
+    }
+    
+    /// 统计一次完成的混币中本节点登记的输出，并持久化更新后的账户统计
+    ///
+    /// 最终记录缺少本节点登记的输出时返回错误，不做统计
+    fn record_privacy(&self, transcript: &CoinJoinTranscript) -> Result<(), String> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let prefix = format!("{}/", transcript.session_id);
+        let own: Vec<TxOutput> = self.pending_outputs.iter()
+            .filter(|entry| entry.key().starts_with(&prefix))
+            .filter_map(|entry| entry.registered.clone())
+            .collect();
+        let updated = self.privacy.record(transcript, &own, now)?;
+        self.pending_outputs.retain(|key, _| !key.starts_with(&prefix));
+        for account in updated {
+            if let Some(store) = self.store.get() {
+                if let Err(e) = store.save_privacy(&account) {
+                    error!("保存账户隐私统计失败: {}: {}", account.account, e);
+                }
+            }
+        }
+        Ok(())
+    }
+    
+    /// 待处理的退款记录
+    pub fn pending_refunds(&self) -> Vec<CoinJoinRefund> {
+        self.store.get()
+            .map(|store| store.pending_refunds().unwrap_or_else(|e| {
+                error!("读取CoinJoin退款记录失败: {}", e);
+                Vec::new()
+            }))
+            .unwrap_or_default()
+    }
+    
+    /// 确认会话的退款已处理
+    pub fn acknowledge_refunds(&self, session_id: &str) -> Result<bool, String> {
+        match self.store.get() {
+            Some(store) => store.acknowledge_refunds(session_id),
+            None => Ok(false),
+        }
+    }
+    
+    /// 设置本节点的PeerId
+    pub fn set_node_id(&self, node_id: String) {
+        if self.node_id.set(node_id).is_err() {
+            warn!("CoinJoin节点ID已设置，忽略重复设置");
+        }
+    }
+    
+    /// 订阅待发往P2P网络的消息
+    pub fn subscribe_outbound(&self) -> broadcast::Receiver<CoinJoinMessage> {
+        self.outbound.subscribe()
+    }
+    
+    /// 广播本地会话的最新公告，会话结束时广播结束消息
+    pub fn announce(&self, session_id: &str) -> bool {
+        let Some(node_id) = self.node_id.get() else {
+            return false;
+        };
+        let Some(session) = self.sessions.get(session_id) else {
+            return false;
+        };
+        
+        let msg = if session.is_terminal() {
+            CoinJoinMessage::Closed {
+                session_id: session.id.clone(),
+                status: session.status.clone(),
+                transcript: (session.status == CoinJoinStatus::Completed).then(|| session.transcript()),
+            }
+        } else {
+            CoinJoinMessage::Announce(session.announcement(node_id, self.registration_endpoint.as_deref()))
+        };
+        
+        self.outbound.send(msg).is_ok()
+    }
+    
+    /// 重新广播所有本地会话
+    ///
+    /// 保留期内的已结束会话重发结束消息，错过结束消息的参与节点据此补记隐私统计
+    pub fn announce_sessions(&self) {
+        let ids: Vec<String> = self.sessions.iter()
+            .map(|s| s.id.clone())
+            .collect();
+            
+        for id in ids {
+            self.announce(&id);
+        }
+    }
+    
+    /// 查找可加入的会话（包括本地和网络上的会话）
+    pub fn open_rounds(&self, target_amount: Option<u64>) -> Vec<SessionAnnouncement> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let node_id = self.node_id.get().map(String::as_str).unwrap_or("local");
+        
+        let local = self.sessions.iter()
+            .filter(|s| s.status == CoinJoinStatus::Waiting && !s.is_full())
+            .map(|s| s.announcement(node_id, self.registration_endpoint.as_deref()));
+        let remote = self.remote_sessions.iter()
+            .filter(|a| a.status == CoinJoinStatus::Waiting && a.expires_at > now)
+            .filter(|a| a.participants_count < a.max_participants)
+            .map(|a| a.clone());
+            
+        local.chain(remote)
+            .filter(|a| target_amount.is_none_or(|amount| a.target_amount == amount))
+            .collect()
+    }
+    
+    /// 订阅待通过独立连接提交的登记
+    pub fn subscribe_registrations(&self) -> broadcast::Receiver<Registration> {
+        self.registrations.subscribe()
+    }
+    
+    /// 处理协调节点对登记的回复：输入登记的回复附带盲签输出凭证
+    pub fn complete_registration(&self, registration: &Registration, reply: &serde_json::Value) -> Result<(), String> {
+        let RegistrationRequest::Input(request) = &registration.request else {
+            return Ok(());
+        };
+        let blind_signature = reply.get("blind_signature")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| "协调节点未返回输出凭证".to_string())?;
+        self.handle_coordinator_reply(&CoinJoinMessage::Credential {
+            session_id: registration.session_id.clone(),
+            participant_id: request.participant_id.clone(),
+            blind_signature: blind_signature.to_string(),
+        });
+        Ok(())
+    }
+    
+    /// 提交本节点参与者的会话消息
+    ///
+    /// 本地会话直接处理；其他节点协调的会话中，加入消息通过P2P网络转发给协调节点，
+    /// 输入、输出和签名不经P2P网络，按阶段分别通过协调节点公告的登记地址提交
+    pub fn submit(&self, msg: CoinJoinMessage) -> Result<(), String> {
+        msg.verify()?;
+        let session_id = msg.session_id().to_string();
+        let is_local = self.sessions.contains_key(&session_id);
+        
+        if !is_local && !self.remote_sessions.contains_key(&session_id) {
+            return Err(format!("会话不存在: {}", session_id));
+        }
+        
+        // 记录本节点的参与者，以便接收协调节点下发的nonce和凭证
+        if let CoinJoinMessage::Join { participant_id, .. } = &msg {
+            self.pending_outputs.entry(pending_key(&session_id, participant_id)).or_default();
+            if let Some(announcement) = self.remote_sessions.get(&session_id) {
+                self.remote_participation.insert(session_id.clone(), RemoteParticipation {
+                    coordinator: announcement.coordinator.clone(),
+                    expires_at: announcement.expires_at.saturating_add(self.completed_retention),
+                });
+            }
+        }
+        
+        if is_local {
+            return self.apply_local(msg, None);
+        }
+        
+        let request = match msg {
+            CoinJoinMessage::Input { participant_id, input, blinded_output, participant_signature, .. } => {
+                RegistrationRequest::Input(InputRequest { participant_id, input, blinded_output, participant_signature })
+            }
+            CoinJoinMessage::Output { output, credential, .. } => {
+                RegistrationRequest::Output(BlindedOutputRequest { output, credential })
+            }
+            CoinJoinMessage::Signature { participant_id, signature, participant_signature, .. } => {
+                RegistrationRequest::Signature(SignatureRequest { participant_id, signature, participant_signature })
+            }
+            msg => {
+                return self.outbound.send(msg)
+                    .map(|_| ())
+                    .map_err(|_| format!("P2P网络不可用，无法转发会话消息: {}", session_id));
+            }
+        };
+        self.register_remote(session_id, request)
+    }
+    
+    /// 盲化本节点参与者的输出，返回写入输入消息的`blinded_output`
+    ///
+    /// 需要先加入会话并收到协调节点下发的nonce；收到凭证后，输出在会话进入输出阶段时自动登记
+    pub fn prepare_output(&self, session_id: &str, participant_id: &str, output: TxOutput) -> Result<String, String> {
+        let credential_key = match self.sessions.get(session_id) {
+            Some(session) => session.credential_key(),
+            None => self.remote_sessions.get(session_id)
+                .ok_or_else(|| format!("会话不存在: {}", session_id))?
+                .credential_key
+                .clone(),
+        }.ok_or_else(|| "会话不支持输出凭证".to_string())?;
+        
+        let mut pending = self.pending_outputs.get_mut(&pending_key(session_id, participant_id))
+            .ok_or_else(|| format!("参与者未通过本节点加入会话: {}", participant_id))?;
+        let nonce = pending.nonce.clone()
+            .ok_or_else(|| "尚未收到协调节点的nonce".to_string())?;
+        
+        let (challenge, unblinder) = blind::blind(&credential_key, &nonce, output.credential_message(session_id).as_bytes())
+            .map_err(|e| format!("盲化输出失败: {}", e))?;
+        pending.output = Some(output);
+        pending.unblinder = Some(unblinder);
+        Ok(challenge)
+    }
+    
+    /// 凭输出凭证登记输出（协调节点的登记接口）
+    pub fn register_blinded_output(&self, session_id: &str, req: &BlindedOutputRequest) -> Result<CoinJoinSessionInfo, String> {
+        let info = {
+            let mut session = self.sessions.get_mut(session_id)
+                .ok_or_else(|| format!("会话不存在: {}", session_id))?;
+            session.add_blinded_output(req.output.clone(), &req.credential)?;
+            session.get_info()
+        };
+        self.persist(session_id);
+        Ok(info)
+    }
+    
+    /// 处理来自P2P网络的消息
+    pub fn handle_network_message(&self, source: &str, msg: CoinJoinMessage) {
+        match msg {
+            CoinJoinMessage::Announce(announcement) => {
+                if announcement.coordinator != source {
+                    warn!("忽略来自非协调节点的CoinJoin公告: {}", source);
+                    return;
+                }
+                if self.sessions.contains_key(&announcement.session_id) {
+                    return;
+                }
+                debug!("发现CoinJoin会话: {} (协调节点: {})", announcement.session_id, source);
+                let session_id = announcement.session_id.clone();
+                if let Some(mut participation) = self.remote_participation.get_mut(&session_id) {
+                    if participation.coordinator == source {
+                        participation.expires_at = announcement.expires_at.saturating_add(self.completed_retention);
+                    }
+                }
+                self.remote_sessions.insert(session_id.clone(), announcement);
+                self.flush_outputs(&session_id);
+            }
+            CoinJoinMessage::Closed { session_id, status, transcript } => {
+                let removed = self.remote_sessions
+                    .remove_if(&session_id, |_, a| a.coordinator == source);
+                if removed.is_some() {
+                    debug!("CoinJoin会话已结束: {} ({:?})", session_id, status);
+                }
+                
+                // 只统计本节点参与者加入过的会话，每个会话只统计一次；
+                // 最终记录与本节点登记的输出不符时保留参与记录，等待协调节点重发
+                let participated = self.remote_participation.get(&session_id)
+                    .is_some_and(|p| p.coordinator == source);
+                if !participated {
+                    return;
+                }
+                if status != CoinJoinStatus::Completed {
+                    self.remote_participation.remove(&session_id);
+                    return;
+                }
+                let result = match transcript {
+                    Some(transcript) if transcript.session_id == session_id => self.record_privacy(&transcript),
+                    _ => Err("结束消息缺少最终记录".to_string()),
+                };
+                match result {
+                    Ok(()) => {
+                        self.remote_participation.remove(&session_id);
+                    }
+                    Err(e) => warn!("CoinJoin会话 {} 无法统计隐私: {}", session_id, e),
+                }
+            }
+            msg @ (CoinJoinMessage::Nonce { .. } | CoinJoinMessage::Credential { .. }) => {
+                let from_coordinator = self.remote_sessions.get(msg.session_id())
+                    .is_some_and(|a| a.coordinator == source);
+                if !from_coordinator {
+                    return;
+                }
+                self.handle_coordinator_reply(&msg);
+            }
+            CoinJoinMessage::Output { .. } => {
+                warn!("忽略经P2P广播的CoinJoin输出: {}", source);
+            }
+            msg => {
+                if !self.sessions.contains_key(msg.session_id()) {
+                    return;
+                }
+                if let Err(e) = self.apply_local(msg, Some(source)) {
+                    warn!("处理来自 {} 的CoinJoin消息失败: {}", source, e);
+                }
+            }
+        }
+    }
+    
+    /// 将参与者消息应用到本地会话，`source`为转发消息的远程节点
+    fn apply_local(&self, msg: CoinJoinMessage, source: Option<&str>) -> Result<(), String> {
+        msg.verify()?;
+        match msg {
+            CoinJoinMessage::Join { session_id, participant_id, .. } => {
+                self.join(&session_id, &participant_id, source)?;
+                let nonce = self.sessions.get(&session_id)
+                    .and_then(|session| session.credential_nonce(&participant_id));
+                if let Some(nonce) = nonce {
+                    self.reply_to_participant(CoinJoinMessage::Nonce { session_id, participant_id, nonce });
+                }
+                Ok(())
+            }
+            CoinJoinMessage::Input { session_id, participant_id, input, blinded_output, .. } => {
+                let blind_signature = self.register_input(&session_id, &participant_id, input, &blinded_output)?;
+                self.reply_to_participant(CoinJoinMessage::Credential { session_id, participant_id, blind_signature });
+                Ok(())
+            }
+            CoinJoinMessage::Output { session_id, output, credential } => {
+                self.register_blinded_output(&session_id, &BlindedOutputRequest { output, credential })
+                    .map(|_| ())
+            }
+            CoinJoinMessage::Signature { session_id, participant_id, signature, .. } => {
+                {
+                    let mut session = self.sessions.get_mut(&session_id)
+                        .ok_or_else(|| format!("会话不存在: {}", session_id))?;
+                    if session.input_owners.get(signature.input_index) != Some(&participant_id) {
+                        return Err(format!("参与者无权签名该输入: {}", participant_id));
+                    }
+                    if !session.add_signature(signature) {
+                        return Err("无法添加签名，会话状态不正确".to_string());
+                    }
+                }
+                self.persist(&session_id);
+                Ok(())
+            }
+            CoinJoinMessage::Announce(_)
+            | CoinJoinMessage::Closed { .. }
+            | CoinJoinMessage::Nonce { .. }
+            | CoinJoinMessage::Credential { .. } => {
+                Err("公告消息只能由协调节点发送".to_string())
+            }
+        }
+    }
+    
+    /// 登记输入并为参与者的盲化输出签发凭证，返回盲签名
+    ///
+    /// 先签发凭证再登记输入，盲化输出无效时会话保持不变，参与者可以重试
+    fn register_input(&self, session_id: &str, participant_id: &str, input: TxInput, blinded_output: &str) -> Result<String, String> {
+        self.ensure_not_banned(participant_id)?;
+        
+        let mut session = self.sessions.get_mut(session_id)
+            .ok_or_else(|| format!("会话不存在: {}", session_id))?;
+        if !session.participants.contains(participant_id) {
+            return Err(format!("参与者不在会话中: {}", participant_id));
+        }
+        if session.credentials_issued.contains(participant_id) {
+            return Err(format!("参与者已注册输入: {}", participant_id));
+        }
+        if session.status != CoinJoinStatus::CollectingInputs {
+            return Err("无法添加输入，会话状态不正确".to_string());
+        }
+        
+        let blind_signature = session.sign_credential(participant_id, blinded_output)?;
+        session.credentials_issued.insert(participant_id.to_string());
+        session.add_input(participant_id, input);
+        drop(session);
+        
+        self.persist(session_id);
+        Ok(blind_signature)
+    }
+    
+    /// 向参与者下发nonce或凭证：本节点的参与者直接处理，其他节点的参与者经P2P网络发送
+    fn reply_to_participant(&self, msg: CoinJoinMessage) {
+        self.handle_coordinator_reply(&msg);
+        let _ = self.outbound.send(msg);
+    }
+    
+    /// 处理协调节点下发给本节点参与者的nonce或凭证
+    fn handle_coordinator_reply(&self, msg: &CoinJoinMessage) {
+        match msg {
+            CoinJoinMessage::Nonce { session_id, participant_id, nonce } => {
+                if let Some(mut pending) = self.pending_outputs.get_mut(&pending_key(session_id, participant_id)) {
+                    pending.nonce = Some(nonce.clone());
+                }
+            }
+            CoinJoinMessage::Credential { session_id, participant_id, blind_signature } => {
+                {
+                    let Some(mut pending) = self.pending_outputs.get_mut(&pending_key(session_id, participant_id)) else {
+                        return;
+                    };
+                    let Some(unblinder) = pending.unblinder.take() else {
+                        return;
+                    };
+                    match unblinder.unblind(blind_signature) {
+                        Ok(credential) => pending.credential = Some(credential),
+                        Err(e) => {
+                            warn!("协调节点下发的输出凭证无效: {}: {}", session_id, e);
+                            return;
+                        }
+                    }
+                }
+                self.flush_outputs(session_id);
+            }
+            _ => {}
+        }
+    }
+    
+    /// 会话进入输出阶段后登记本节点参与者已取得凭证的输出
+    fn flush_outputs(&self, session_id: &str) {
+        let collecting = match self.sessions.get(session_id) {
+            Some(session) => session.status == CoinJoinStatus::CollectingOutputs,
+            None => self.remote_sessions.get(session_id)
+                .is_some_and(|a| a.status == CoinJoinStatus::CollectingOutputs),
+        };
+        if !collecting {
+            return;
+        }
+        
+        let prefix = format!("{}/", session_id);
+        let ready: Vec<String> = self.pending_outputs.iter()
+            .filter(|entry| entry.key().starts_with(&prefix))
+            .filter(|entry| entry.credential.is_some() && entry.output.is_some())
+            .map(|entry| entry.key().clone())
+            .collect();
+        
+        for key in ready {
+            let (output, credential) = {
+                let Some(mut pending) = self.pending_outputs.get_mut(&key) else {
+                    continue;
+                };
+                let (Some(output), Some(credential)) = (pending.output.take(), pending.credential.take()) else {
+                    continue;
+                };
+                (output, credential)
+            };
+            let request = BlindedOutputRequest { output: output.clone(), credential };
+            let result = if self.sessions.contains_key(session_id) {
+                self.register_blinded_output(session_id, &request).map(|_| ())
+            } else {
+                self.register_remote(session_id.to_string(), RegistrationRequest::Output(request))
+            };
+            match result {
+                // 保留已登记的输出，会话完成后据此统计隐私
+                Ok(()) => {
+                    if let Some(mut pending) = self.pending_outputs.get_mut(&key) {
+                        pending.registered = Some(output);
+                    }
+                }
+                Err(e) => warn!("登记CoinJoin输出失败: {}: {}", session_id, e),
+            }
+        }
+    }
+    
+    /// 将登记交给网络桥接任务，通过单独的连接提交到协调节点的登记地址
+    fn register_remote(&self, session_id: String, request: RegistrationRequest) -> Result<(), String> {
+        let endpoint = self.remote_sessions.get(&session_id)
+            .and_then(|a| a.registration_endpoint.clone())
+            .ok_or_else(|| format!("协调节点未公布登记地址: {}", session_id))?;
+        self.registrations.send(Registration { endpoint, session_id, request })
+            .map(|_| ())
+            .map_err(|_| "P2P网络不可用，无法提交登记".to_string())
+    }
+    
+    /// 获取当前封禁列表
+    pub fn banned_participants(&self) -> Vec<BlameRecord> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.blame.banned(now)
+    }
+    
+    /// 解除参与者封禁
+    pub fn unban_participant(&self, participant_id: &str) -> bool {
+        self.blame.unban(participant_id)
+    }
+    
+    /// 当前拉黑配置
+    pub fn blame_config(&self) -> BlameConfig {
+        self.blame.config()
+    }
+    
+    /// 运行时调整拉黑配置
+    pub fn set_blame_config(&self, config: BlameConfig) -> Result<(), String> {
+        self.blame.set_config(config.clone())?;
+        info!("CoinJoin拉黑配置已更新: {:?}", config);
+        Ok(())
+    }
+    
+    /// 创建新的CoinJoin会话，`source`为创建者的HTTP客户端地址
+    ///
+    /// 创建者须签名创建消息，每个来源同时开启的会话数量受限
+    pub fn create_session(&self, req: &CoinJoinRequest, source: &str) -> Result<CoinJoinSessionInfo, String> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        req.verify(now)?;
+        self.ensure_not_banned(&req.participant_id)?;
+        
+        let open = self.sessions.iter()
+            .filter(|s| !s.is_terminal() && s.created_via.as_deref() == Some(source))
+            .count();
+        if open >= self.max_sessions_per_source {
+            return Err(format!("来源 {} 开启的会话已达上限", source));
+        }
+        
         let min_participants = req.min_participants.unwrap_or(3);
-        let max_participants = req.max_participants.unwrap_or(10);
+        let max_participants = req.max_participants.unwrap_or(10).min(MAX_PARTICIPANTS);
+        if min_participants < 2 || min_participants > max_participants {
+            return Err(format!(
+                "参与者数量范围无效: 最少 {}, 最多 {}", min_participants, max_participants
+            ));
+        }
         let fee_rate = req.fee_rate.unwrap_or(1);
-        let timeout = req.timeout.unwrap_or(self.session_timeout);
+        let timeout = req.timeout.unwrap_or(self.session_timeout).min(self.max_session_timeout);
         
         let mut session = CoinJoinSession::new(
             min_participants,
@@ -413,14 +2489,21 @@ impl CoinJoinManager {
             timeout,
         );
         
+        session.events = Some(self.events.clone());
+        session.lobby_deadline = session.created_at.saturating_add(self.lobby_timeout.min(timeout));
+        
         // 添加创建者作为第一个参与者
         session.add_participant(&req.participant_id);
+        session.joined_via.insert(req.participant_id.clone(), source.to_string());
+        session.created_via = Some(source.to_string());
         
         let session_info = session.get_info();
         self.sessions.insert(session.id.clone(), session);
+        self.persist(&session_info.id);
         
         info!("创建新的CoinJoin会话: {}", session_info.id);
-        session_info
+        self.announce(&session_info.id);
+        Ok(session_info)
     }
     
     /// 获取会话
@@ -428,14 +2511,101 @@ impl CoinJoinManager {
         self.sessions.get(id).map(|s| s.clone())
     }
     
-    /// 添加交易输入
-    pub fn add_input(&self, session_id: &str, req: &InputRequest) -> Result<CoinJoinSessionInfo, String> {
-        let mut session = self.sessions.get_mut(session_id)
+    /// 加入会话（HTTP接口），需要参与者签名，返回会话信息和凭证nonce
+    ///
+    /// `source`为HTTP客户端地址，与远程节点一样受每个来源的参与者数量限制
+    pub fn join_session(&self, session_id: &str, req: &JoinRequest, source: &str) -> Result<ParticipantReply, String> {
+        req.message(session_id).verify()?;
+        let session = self.join(session_id, &req.participant_id, Some(source))?;
+        let nonce = self.sessions.get(session_id)
+            .and_then(|session| session.credential_nonce(&req.participant_id));
+        Ok(ParticipantReply { session, nonce, blind_signature: None })
+    }
+    
+    /// 加入会话，限制每个来源代理的参与者数量；`source`为空表示本节点提交
+    fn join(&self, session_id: &str, participant_id: &str, source: Option<&str>) -> Result<CoinJoinSessionInfo, String> {
+        self.ensure_not_banned(participant_id)?;
+        
+        let info = {
+            let mut session = self.sessions.get_mut(session_id)
+                .ok_or_else(|| format!("会话不存在: {}", session_id))?;
+                
+            if session.is_full() && !session.participants.contains(participant_id) {
+                return Err(format!("会话参与者已满: {}", session_id));
+            }
+            
+            if let Some(source) = source {
+                let from_source = session.joined_via.iter()
+                    .filter(|(participant, via)| via.as_str() == source && participant.as_str() != participant_id)
+                    .count();
+                if from_source >= self.max_participants_per_peer {
+                    return Err(format!("节点 {} 代理的参与者已达上限", source));
+                }
+            }
+            
+            if !session.add_participant(participant_id) {
+                return Err("无法加入会话，会话状态不正确".to_string());
+            }
+            if let Some(source) = source {
+                session.joined_via.insert(participant_id.to_string(), source.to_string());
+            }
+            
+            session.get_info()
+        };
+        
+        // 参与者数量变化后更新公告
+        self.persist(session_id);
+        self.announce(session_id);
+        Ok(info)
+    }
+    
+    /// 添加交易输入（HTTP接口），需要参与者签名，返回会话信息和盲签输出凭证
+    ///
+    /// 输出不在此登记：参与者去盲凭证后通过`register_blinded_output`匿名登记输出
+    pub fn add_input(&self, session_id: &str, req: &InputRequest) -> Result<ParticipantReply, String> {
+        req.message(session_id).verify()?;
+        let blind_signature = self.register_input(session_id, &req.participant_id, req.input.clone(), &req.blinded_output)?;
+        let session = self.sessions.get(session_id)
+            .map(|session| session.get_info())
             .ok_or_else(|| format!("会话不存在: {}", session_id))?;
+        Ok(ParticipantReply { session, nonce: None, blind_signature: Some(blind_signature) })
+    }
+    
+    /// 添加交易签名（HTTP接口），需要参与者签名
+    pub fn add_signature(&self, session_id: &str, req: &SignatureRequest) -> Result<CoinJoinSessionInfo, String> {
+        self.apply_local(req.message(session_id), None)?;
+        self.sessions.get(session_id)
+            .map(|session| session.get_info())
+            .ok_or_else(|| format!("会话不存在: {}", session_id))
+    }
+    
+    /// 完成会话
+    ///
+    /// 所有输入签名后由任一参与者签名触发，最终交易ID为输入、输出和签名的哈希
+    pub fn finalize_session(&self, session_id: &str, req: &FinalizeRequest) -> Result<CoinJoinSessionInfo, String> {
+        req.verify(session_id)?;
+        let (info, transcript) = {
+            let mut session = self.sessions.get_mut(session_id)
+                .ok_or_else(|| format!("会话不存在: {}", session_id))?;
+                
+            if !session.participants.contains(&req.participant_id) {
+                return Err(format!("参与者不在会话中: {}", req.participant_id));
+            }
             
-        if !session.participants.contains(&req.participant_id) {
-            return Err(format!("参与者不在会话中: {}", req.participant_id));
-        }
+            let txid = session.compute_txid();
+            if !session.complete(&txid) {
+                return Err("无法完成会话，会话状态不正确".to_string());
+            }
+            
+            info!("CoinJoin会话完成: {} (交易 {})", session_id, txid);
+            (session.get_info(), session.transcript())
+        };
         
-        if !session.add_input(req.input.clone()) {
-            return Err(format!("无法添加输入，会话状态不正确"));
\ No newline at end of file
+        self.persist(session_id);
+        if let Err(e) = self.record_privacy(&transcript) {
+            warn!("CoinJoin会话 {} 无法统计隐私: {}", session_id, e);
+        }
+        self.announce(session_id);
+        Ok(info)
+    }
+}
@@ -1,7 +1,227 @@
-use ed25519_dalek::{Keypair, PublicKey, Signature, Signer, Verifier};
+//! 账户密钥与签名
+//!
+//! 基于ed25519-dalek 2：生成密钥、按用途域分离签名和验证，以及从hex/base58安全解析公钥、私钥和签名。
+//! 解析失败返回[`CryptoError`]而不是panic；私钥的中间缓冲区用`Zeroizing`包裹，
+//! `SigningKey`本身在drop时清零。
+//!
+//! 域分离签名的消息格式为`前缀 || len(domain) || domain || message`，
+//! 同一把密钥在不同用途下的签名不能互相替换，CoinJoin参与者签名和WebSocket订阅挑战使用这种格式。
+//! 已有的转账、动态等签名直接对原始消息签名，用[`verify_raw`]按原有的非严格规则验证，
+//! 避免改变这些消息的有效性。
+//!
+//! 节点身份、onion服务等密钥文件统一用[`write_private_file`]保存。
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey, PUBLIC_KEY_LENGTH, SECRET_KEY_LENGTH, SIGNATURE_LENGTH};
 use rand::rngs::OsRng;
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+/// 签名消息前缀，带版本号
+const SIGNING_PREFIX: &[u8] = b"hancoin-sig-v1\0";
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum CryptoError {
+    #[error("Invalid encoding")]
+    InvalidEncoding,
+    #[error("Invalid length: expected {expected} bytes, got {actual}")]
+    InvalidLength { expected: usize, actual: usize },
+    #[error("Invalid public key")]
+    InvalidPublicKey,
+    #[error("Signature verification failed")]
+    VerificationFailed,
+}
+
+/// 生成随机密钥
+pub fn generate_keypair() -> SigningKey {
+    SigningKey::generate(&mut OsRng)
+}
+
+/// hex编码的账户ID（即公钥）
+pub fn account_id(public_key: &VerifyingKey) -> String {
+    hex::encode(public_key.as_bytes())
+}
+
+/// 按用途域签名
+pub fn sign(keypair: &SigningKey, domain: &str, message: &[u8]) -> Signature {
+    keypair.sign(&domain_message(domain, message))
+}
+
+/// 验证按用途域签名的消息，拒绝弱公钥和非规范签名
+pub fn verify(public_key: &VerifyingKey, domain: &str, message: &[u8], signature: &Signature) -> Result<(), CryptoError> {
+    public_key
+        .verify_strict(&domain_message(domain, message), signature)
+        .map_err(|_| CryptoError::VerificationFailed)
+}
+
+/// 验证对原始消息的签名，用于转账、动态等不带用途域的旧格式
+///
+/// 保持这些消息原有的非严格验证，不拒绝弱公钥和小阶R，已接受的消息仍然有效
+pub fn verify_raw(public_key: &VerifyingKey, message: &[u8], signature: &Signature) -> Result<(), CryptoError> {
+    public_key
+        .verify(message, signature)
+        .map_err(|_| CryptoError::VerificationFailed)
+}
+
+/// 从hex解析公钥
+pub fn public_key_from_hex(value: &str) -> Result<VerifyingKey, CryptoError> {
+    let bytes = hex::decode(value).map_err(|_| CryptoError::InvalidEncoding)?;
+    public_key_from_bytes(&bytes)
+}
+
+/// 从base58解析公钥
+pub fn public_key_from_base58(value: &str) -> Result<VerifyingKey, CryptoError> {
+    let bytes = bs58::decode(value).into_vec().map_err(|_| CryptoError::InvalidEncoding)?;
+    public_key_from_bytes(&bytes)
+}
+
+/// base58编码的公钥
+pub fn public_key_to_base58(public_key: &VerifyingKey) -> String {
+    bs58::encode(public_key.as_bytes()).into_string()
+}
+
+/// 从字节解析公钥，拒绝无法解压的点；小阶的弱公钥由[`verify`]拒绝
+pub fn public_key_from_bytes(bytes: &[u8]) -> Result<VerifyingKey, CryptoError> {
+    let bytes: [u8; PUBLIC_KEY_LENGTH] = fixed_length(bytes)?;
+    VerifyingKey::from_bytes(&bytes).map_err(|_| CryptoError::InvalidPublicKey)
+}
+
+/// 从hex解析32字节私钥
+pub fn signing_key_from_hex(value: &str) -> Result<SigningKey, CryptoError> {
+    let bytes = Zeroizing::new(hex::decode(value).map_err(|_| CryptoError::InvalidEncoding)?);
+    signing_key_from_bytes(&bytes)
+}
+
+/// 从base58解析32字节私钥
+pub fn signing_key_from_base58(value: &str) -> Result<SigningKey, CryptoError> {
+    let bytes = Zeroizing::new(bs58::decode(value).into_vec().map_err(|_| CryptoError::InvalidEncoding)?);
+    signing_key_from_bytes(&bytes)
+}
+
+/// 从字节解析32字节私钥
+pub fn signing_key_from_bytes(bytes: &[u8]) -> Result<SigningKey, CryptoError> {
+    let secret = Zeroizing::new(fixed_length::<SECRET_KEY_LENGTH>(bytes)?);
+    Ok(SigningKey::from_bytes(&secret))
+}
+
+/// hex编码的私钥，返回值在drop时清零
+pub fn signing_key_to_hex(keypair: &SigningKey) -> Zeroizing<String> {
+    Zeroizing::new(hex::encode(keypair.as_bytes()))
+}
+
+/// 从hex解析签名
+pub fn signature_from_hex(value: &str) -> Result<Signature, CryptoError> {
+    let bytes = hex::decode(value).map_err(|_| CryptoError::InvalidEncoding)?;
+    Ok(Signature::from_bytes(&fixed_length::<SIGNATURE_LENGTH>(&bytes)?))
+}
+
+/// 从base58解析签名
+pub fn signature_from_base58(value: &str) -> Result<Signature, CryptoError> {
+    let bytes = bs58::decode(value).into_vec().map_err(|_| CryptoError::InvalidEncoding)?;
+    Ok(Signature::from_bytes(&fixed_length::<SIGNATURE_LENGTH>(&bytes)?))
+}
+
+/// 保存密钥文件：先写临时文件再重命名，仅所有者可读写
+pub fn write_private_file(path: &Path, contents: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    
+    let tmp = path.with_extension("tmp");
+    {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(&tmp)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+    }
+    fs::rename(&tmp, path)
+}
+
+fn domain_message(domain: &str, message: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(SIGNING_PREFIX.len() + 8 + domain.len() + message.len());
+    data.extend_from_slice(SIGNING_PREFIX);
+    data.extend_from_slice(&(domain.len() as u64).to_le_bytes());
+    data.extend_from_slice(domain.as_bytes());
+    data.extend_from_slice(message);
+    data
+}
+
+fn fixed_length<const N: usize>(bytes: &[u8]) -> Result<[u8; N], CryptoError> {
+    bytes.try_into().map_err(|_| CryptoError::InvalidLength { expected: N, actual: bytes.len() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_domain_separated_signatures() {
+        let keypair = generate_keypair();
+        let public_key = keypair.verifying_key();
+
+        let signature = sign(&keypair, "auth", b"challenge");
+        assert!(verify(&public_key, "auth", b"challenge", &signature).is_ok());
+        assert_eq!(verify(&public_key, "p2p", b"challenge", &signature), Err(CryptoError::VerificationFailed));
+        assert_eq!(verify(&public_key, "auth", b"other", &signature), Err(CryptoError::VerificationFailed));
+
+        // 域分离签名不能当作原始消息签名使用
+        assert!(verify_raw(&public_key, b"challenge", &signature).is_err());
+        assert!(verify_raw(&public_key, b"challenge", &keypair.sign(b"challenge")).is_ok());
+
+        let other = generate_keypair();
+        assert!(verify(&other.verifying_key(), "auth", b"challenge", &signature).is_err());
+    }
+
+    #[test]
+    fn test_parse_keys_and_signatures() {
+        let keypair = generate_keypair();
+        let public_key = keypair.verifying_key();
+
+        assert_eq!(public_key_from_hex(&account_id(&public_key)).unwrap(), public_key);
+        assert_eq!(public_key_from_base58(&public_key_to_base58(&public_key)).unwrap(), public_key);
+        assert_eq!(signing_key_from_hex(&signing_key_to_hex(&keypair)).unwrap().as_bytes(), keypair.as_bytes());
+        let base58_secret = bs58::encode(keypair.as_bytes()).into_string();
+        assert_eq!(signing_key_from_base58(&base58_secret).unwrap().as_bytes(), keypair.as_bytes());
+
+        let signature = sign(&keypair, "snapshot", b"manifest");
+        assert_eq!(signature_from_hex(&hex::encode(signature.to_bytes())).unwrap(), signature);
+        assert_eq!(signature_from_base58(&bs58::encode(signature.to_bytes()).into_string()).unwrap(), signature);
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed_input() {
+        assert_eq!(public_key_from_hex("zz"), Err(CryptoError::InvalidEncoding));
+        assert_eq!(public_key_from_hex("abcd"), Err(CryptoError::InvalidLength { expected: 32, actual: 2 }));
+        assert_eq!(public_key_from_base58("0OIl"), Err(CryptoError::InvalidEncoding));
+        assert_eq!(signature_from_hex(&"00".repeat(63)), Err(CryptoError::InvalidLength { expected: 64, actual: 63 }));
+        assert!(matches!(signing_key_from_hex(&"11".repeat(33)), Err(CryptoError::InvalidLength { .. })));
 
-pub fn generate_keypair() -> Keypair {
-    let mut csprng = OsRng;
-    Keypair::generate(&mut csprng)
+        // 不在曲线上的点
+        let mut invalid = [0u8; 32];
+        invalid[0] = 2;
+        assert_eq!(public_key_from_bytes(&invalid), Err(CryptoError::InvalidPublicKey));
+    }
+    
+    #[test]
+    fn test_weak_keys_only_rejected_by_domain_signatures() {
+        // 单位元是小阶点：R为单位元、s为0的签名在非严格规则下对任意消息成立
+        let mut identity = [0u8; 32];
+        identity[0] = 1;
+        let public_key = public_key_from_bytes(&identity).unwrap();
+        let mut bytes = [0u8; 64];
+        bytes[..32].copy_from_slice(&identity);
+        let signature = Signature::from_bytes(&bytes);
+        
+        // 旧格式消息的有效性不变，域分离签名拒绝弱公钥
+        assert!(verify_raw(&public_key, b"claim_faucet", &signature).is_ok());
+        assert_eq!(verify(&public_key, "auth", b"claim_faucet", &signature), Err(CryptoError::VerificationFailed));
+    }
 }
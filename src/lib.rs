@@ -8,6 +8,16 @@
 /// 提供签名验证、地址生成和密钥管理等功能
 pub mod crypto;
 
+/// 钱包核心模块
+///
+/// 助记词备份和SLIP-0010分层确定性账户密钥派生
+pub mod wallet;
+
+/// 盲签名模块
+///
+/// 为CoinJoin输出登记提供不可关联的凭证
+pub mod blind;
+
 /// 数据类型定义模块
 pub mod types;
 
@@ -1,30 +1,25 @@
-mod types;
-mod crypto;
-mod p2p;
-mod ws;
-mod tor;
-mod coinjoin;
-
-use crate::types::*;
-use crate::p2p::{start_p2p, P2PConfig};
-use crate::ws::chat_routes;
-use crate::crypto::{init_crypto, generate_keypair, sign_message};
-use crate::tor::TorConfig;
-use crate::coinjoin::{CoinJoinManager, CoinJoinSession, CoinJoinRequest, CoinJoinStatus};
+use hancoin::{p2p, tor};
+use hancoin::types::*;
+use hancoin::ws::chat_routes;
+use hancoin::tor::{TorConnector, TorHealth, TorNetwork};
+use hancoin::coinjoin::{
+    spawn_network_bridge, BlameConfig, BlindedOutputRequest, CoinJoinConfig, CoinJoinManager, CoinJoinRequest, CoinJoinSessionInfo, CoinJoinStore,
+    FinalizeRequest, InputRequest, JoinRequest, ParticipantReply, SignatureRequest,
+};
 
 use std::sync::Arc;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::time::{SystemTime, UNIX_EPOCH};
-use std::net::IpAddr;
+use std::sync::atomic::Ordering;
+use std::time::SystemTime;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use warp::Filter;
 use log::{info, error, warn, debug};
 use hex::decode;
-use tokio::sync::RwLock;
 use std::time::Duration;
-use uuid::Uuid;
-use ed25519_dalek::{Verifier, Signature, VerifyingKey};
+use hancoin::crypto::{self, CryptoError};
+use ed25519_dalek::{Signature, VerifyingKey};
 
 // API版本常量
 const API_VERSION: &str = "v1";
@@ -33,11 +28,18 @@ const API_VERSION: &str = "v1";
 async fn main() {
     // 初始化日志系统
     env_logger::init();
+    
+    // 数据目录
+    let data_dir = PathBuf::from(std::env::var("HANCOIN_DATA_DIR").unwrap_or_else(|_| "data".to_string()));
+    
+    // 命令行子命令
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("node-key") {
+        std::process::exit(run_node_key_command(&args[2..], &data_dir));
+    }
+    
     info!("Starting HANCOIN node v0.3.0...");
 
-    // 初始化加密子系统
-    init_crypto();
-
     // 创建账本实例
     let ledger = Arc::new(Ledger::new());
 
@@ -48,35 +50,168 @@ async fn main() {
     }
 
     // 创建CoinJoin会话管理器
-    let coinjoin_manager = Arc::new(tokio::sync::Mutex::new(CoinJoinManager::new(3600))); // 1小时超时
+    let coinjoin_manager = Arc::new(CoinJoinManager::with_config(coinjoin_config_from_env()));
+    
+    // 恢复重启前的CoinJoin会话
+    match CoinJoinStore::open(data_dir.join("coinjoin")) {
+        Ok(store) => match coinjoin_manager.attach_store(store) {
+            Ok(report) => info!(
+                "CoinJoin会话已恢复: {} 个继续, {} 个终止, {} 笔待退款",
+                report.resumed.len(), report.failed.len(), report.refunds.len()
+            ),
+            Err(e) => error!("恢复CoinJoin会话失败: {}", e),
+        },
+        Err(e) => error!("无法打开CoinJoin存储: {}", e),
+    }
     
     // 创建P2P配置
-    let mut p2p_config = p2p::P2PConfig::default();
+    let mut p2p_config = p2p::P2PConfig {
+        key_file: node_key_path(&data_dir),
+        operator_key: operator_key_from_env(),
+        ..Default::default()
+    };
+    
+    // 所属网络（mainnet/testnet/devnet）
+    if let Ok(network) = std::env::var("HANCOIN_NETWORK") {
+        match network.parse() {
+            Ok(network) => p2p_config.network = network,
+            Err(e) => {
+                error!("Invalid HANCOIN_NETWORK: {}", e);
+                return;
+            }
+        }
+    }
+    info!("P2P network: {}, protocol version {}", p2p_config.network, p2p::PROTOCOL_VERSION);
+    
+    // 纯账本中继节点可以关闭社交主题
+    if std::env::var("HANCOIN_SOCIAL").is_ok_and(|v| v == "false") {
+        p2p_config.enable_social = false;
+        info!("Social topics disabled, running as ledger relay");
+    }
+    
+    // 监听地址和引导节点
+    configure_peers(&mut p2p_config, &data_dir);
 
     // 配置Tor
     let tor_enabled = std::env::var("ENABLE_TOR").unwrap_or_else(|_| "false".to_string()) == "true";
     if tor_enabled {
         p2p_config.tor_config.enabled = true;
+        if let Ok(backend) = std::env::var("TOR_BACKEND") {
+            match backend.parse() {
+                Ok(backend) => p2p_config.tor_config.backend = backend,
+                Err(e) => {
+                    error!("Invalid TOR_BACKEND: {}", e);
+                    return;
+                }
+            }
+        }
+        p2p_config.tor_config.embedded_dir = data_dir.join("tor").join("arti");
         p2p_config.tor_config.proxy_addr = std::env::var("TOR_PROXY")
             .unwrap_or_else(|_| "127.0.0.1:9050".to_string());
-        info!("Tor已启用，代理地址: {}", p2p_config.tor_config.proxy_addr);
+        if let (Ok(username), Ok(password)) = (std::env::var("TOR_PROXY_USER"), std::env::var("TOR_PROXY_PASSWORD")) {
+            // 仅当代理是按IsolateSOCKSAuth隔离线路的Tor时才开启，普通SOCKS5代理需要原样的凭据
+            let isolate_with_auth = std::env::var("TOR_PROXY_ISOLATE_AUTH").is_ok_and(|v| v == "true");
+            p2p_config.tor_config.proxy_auth = Some(tor::ProxyAuth {
+                username,
+                password: Zeroizing::new(password),
+                isolate_with_auth,
+            });
+            if isolate_with_auth {
+                info!("SOCKS proxy authentication enabled, usernames carry a stream isolation tag");
+            } else {
+                info!("SOCKS proxy authentication enabled");
+            }
+        }
+        if let Some(timeout) = std::env::var("TOR_CONNECT_TIMEOUT").ok().and_then(|v| v.parse().ok()) {
+            p2p_config.tor_config.connect_timeout = Duration::from_secs(timeout);
+        }
+        if let Ok(control_addr) = std::env::var("TOR_CONTROL") {
+            p2p_config.tor_config.control_addr = control_addr;
+        }
+        p2p_config.tor_config.control_password = std::env::var("TOR_CONTROL_PASSWORD").ok();
+        p2p_config.tor_config.control_cookie_file = std::env::var("TOR_CONTROL_COOKIE").ok().map(PathBuf::from);
+        p2p_config.tor_config.onion_key_file = data_dir.join("tor").join("onion_key");
+        p2p_config.tor_config.only_onion = std::env::var("TOR_ONLY_ONION").is_ok_and(|v| v == "true");
+        // 网桥和传输插件均以换行分隔，每行同torrc中的Bridge/ClientTransportPlugin，只由内嵌客户端载入
+        let lines = |name: &str| std::env::var(name).map_or_else(|_| Vec::new(), |v| {
+            v.lines().map(str::trim).filter(|l| !l.is_empty()).map(str::to_string).collect()
+        });
+        p2p_config.tor_config.bridges = lines("TOR_BRIDGES");
+        p2p_config.tor_config.transport_plugins = lines("TOR_TRANSPORT_PLUGINS");
+        if !p2p_config.tor_config.bridges.is_empty() {
+            info!("已配置{}个Tor网桥", p2p_config.tor_config.bridges.len());
+        }
+        info!("Tor已启用: {:?}，代理地址: {}", p2p_config.tor_config.backend, p2p_config.tor_config.proxy_addr);
+        if p2p_config.tor_config.only_onion {
+            info!("严格Tor模式：只连接onion节点，P2P和HTTP只监听本机");
+        }
+        
+        // P2P网络和onion服务共用同一个连接器（内嵌客户端时为同一个arti实例）
+        let connector = match TorConnector::from_config(p2p_config.tor_config.clone()) {
+            Ok(connector) => connector,
+            Err(e) => {
+                error!("无法创建Tor客户端: {}", e);
+                return;
+            }
+        };
+        // 外部tor进程的网桥由torrc配置，这里只检查，未启用时连接会直达Tor中继
+        match connector.check_bridges().await {
+            Ok(true) => {}
+            Ok(false) => warn!(
+                "已配置TOR_BRIDGES，但外部tor进程未启用网桥；请在torrc中设置UseBridges 1及对应的Bridge行"
+            ),
+            Err(e) => warn!(
+                "无法通过Tor控制端口 {} 检查网桥配置: {}；请确认torrc中已设置UseBridges 1",
+                p2p_config.tor_config.control_addr, e
+            ),
+        }
+        p2p_config.tor_connector = Some(connector);
     } else {
         info!("Tor未启用，使用标准网络连接");
     }
     
+    let tor_config = p2p_config.tor_config.clone();
+    let tor_connector = p2p_config.tor_connector.clone();
+    let onion_target = p2p_config.onion_service_target();
+    let sync_config = sync_config(&data_dir, &p2p_config.bootstrap_peers);
+    
     // 启动P2P网络
-    if let Err(e) = p2p::start_p2p(p2p_config).await {
-        error!("Failed to start P2P network: {:?}", e);
-    }
+    let p2p_handle = match p2p::start_p2p(Some(p2p_config)).await {
+        Ok(p2p_handle) => {
+            // 账本同步：为其他节点提供快照，并从已有节点追赶账本
+            p2p::spawn_sync_server(p2p_handle.clone(), ledger.clone(), sync_config.clone());
+            p2p::spawn_initial_sync(p2p_handle.clone(), ledger.clone(), sync_config);
+            p2p::spawn_ledger_bridge(p2p_handle.clone(), ledger.clone());
+            
+            // 通过P2P网络协调CoinJoin会话
+            spawn_network_bridge(coinjoin_manager.clone(), p2p_handle.clone(), tor_connector.clone());
+            Some(p2p_handle)
+        }
+        Err(e) => {
+            error!("Failed to start P2P network: {:?}", e);
+            None
+        }
+    };
+
+    // 发布onion服务，使节点无需公网IP即可被访问；内嵌客户端引导较慢，在后台进行以免阻塞HTTP接口
+    let tor_service = match (&p2p_handle, &tor_connector) {
+        (Some(p2p_handle), Some(connector)) => Some(tokio::spawn(
+            publish_onion_service(connector.onion_service(), p2p_handle.clone(), onion_target),
+        )),
+        _ => None,
+    };
 
     // WebSocket路由
-    let ws_routes = chat_routes();
+    let ws_routes = chat_routes(coinjoin_manager.clone());
 
     // 创建API路由
-    let api_routes = create_api_routes(ledger.clone());
+    let api_routes = create_api_routes(ledger.clone(), p2p_handle.clone());
     
     // 创建CoinJoin API路由
     let coinjoin_routes = create_coinjoin_routes(coinjoin_manager.clone());
+    
+    // 混币隐私统计路由
+    let privacy_routes = create_privacy_routes(coinjoin_manager.clone());
 
     // CORS配置
     let cors = warp::cors()
@@ -88,17 +223,316 @@ async fn main() {
     let routes = ws_routes
         .or(api_routes)
         .or(coinjoin_routes)
+        .or(privacy_routes)
         .with(cors)
         .recover(handle_rejection);
+    
+    // 管理接口单独监听本机端口，不经onion服务或公网端口暴露
+    let admin_routes = create_network_routes(p2p_handle.clone())
+        .or(create_network_admin_routes(p2p_handle.clone()))
+        .or(create_coinjoin_admin_routes(coinjoin_manager.clone()))
+        .recover(handle_rejection);
+
+    // 严格Tor模式下HTTP只通过onion服务对外提供
+    let http_ip = if tor_config.is_strict() { [127, 0, 0, 1] } else { [0, 0, 0, 0] };
+    
+    // 收到Ctrl-C后停止接受请求并关闭P2P网络
+    let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+    tokio::spawn(async move {
+        let _ = tokio::signal::ctrl_c().await;
+        info!("Shutdown signal received");
+        let _ = shutdown_tx.send(true);
+    });
+    let shutdown = |mut rx: tokio::sync::watch::Receiver<bool>| async move {
+        let _ = rx.wait_for(|stop| *stop).await;
+    };
+    
+    let (admin_addr, admin_server) = warp::serve(admin_routes)
+        .bind_with_graceful_shutdown(admin_addr_from_env(), shutdown(shutdown_rx.clone()));
+    info!("Admin API running at http://{}/", admin_addr);
+    let admin_server = tokio::spawn(admin_server);
+    
+    let (addr, server) = warp::serve(routes)
+        .bind_with_graceful_shutdown((http_ip, 3030), shutdown(shutdown_rx));
+    info!("Server running at http://{}/", addr);
+    server.await;
+    let _ = admin_server.await;
+    
+    if let Some(p2p_handle) = p2p_handle {
+        if let Err(e) = p2p_handle.shutdown().await {
+            warn!("P2P network did not shut down cleanly: {}", e);
+        }
+    }
+    if let Some(publishing) = tor_service {
+        if !publishing.is_finished() {
+            publishing.abort();
+        }
+        if let Ok(Some(tor_service)) = publishing.await {
+            let _ = tor_service.disconnect().await;
+        }
+    }
+}
+
+/// 发布P2P端口和HTTP API的onion服务，并把onion地址公布给其他节点
+async fn publish_onion_service(
+    service: Box<dyn TorNetwork + Send + Sync>,
+    p2p_handle: p2p::P2PHandle,
+    onion_target: std::net::SocketAddr,
+) -> Option<Box<dyn TorNetwork + Send + Sync>> {
+    if let Err(e) = service.connect().await {
+        error!("无法连接Tor: {}", e);
+        return None;
+    }
+    
+    // 只转发P2P端口和公开HTTP接口，管理接口监听在单独的本机端口上
+    let p2p_port = onion_target.port();
+    let ports = [
+        (p2p_port, onion_target),
+        (80, std::net::SocketAddr::from(([127, 0, 0, 1], 3030))),
+    ];
+    let onion = match service.publish_services(&ports).await {
+        Ok(onion) => onion,
+        Err(e) => {
+            error!("发布onion服务失败: {}", e);
+            return None;
+        }
+    };
+    info!("节点onion地址: {} (P2P端口 {}, HTTP端口 80)", onion, p2p_port);
+    
+    let service_id = onion.trim_end_matches(".onion");
+    match format!("/onion3/{}:{}", service_id, p2p_port).parse() {
+        Ok(addr) => {
+            if let Err(e) = p2p_handle.add_external_address(addr).await {
+                warn!("无法公布onion地址: {}", e);
+            }
+        }
+        Err(e) => warn!("无效的onion地址 {}: {}", onion, e),
+    }
+    Some(service)
+}
+
+/// 节点密钥文件路径（可通过HANCOIN_NODE_KEY覆盖）
+fn node_key_path(data_dir: &Path) -> PathBuf {
+    std::env::var("HANCOIN_NODE_KEY")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| data_dir.join(p2p::NODE_KEY_FILE))
+}
+
+/// 从HANCOIN_OPERATOR_KEY读取运营者账户私钥（十六进制）
+fn operator_key_from_env() -> Option<Zeroizing<[u8; 32]>> {
+    let hex_key = Zeroizing::new(std::env::var("HANCOIN_OPERATOR_KEY").ok()?);
+    let bytes = Zeroizing::new(decode(hex_key.trim()).ok()?);
+    match <[u8; 32]>::try_from(bytes.as_slice()) {
+        Ok(key) => Some(Zeroizing::new(key)),
+        Err(_) => {
+            warn!("HANCOIN_OPERATOR_KEY must be 32 bytes of hex, ignoring");
+            None
+        }
+    }
+}
+
+/// 从环境变量和引导节点文件配置P2P地址
+///
+/// - `HANCOIN_P2P_LISTEN`     逗号分隔的监听地址，可加传输前缀，如 `ws=/ip4/0.0.0.0/tcp/4002`
+/// - `HANCOIN_BOOTSTRAP`      逗号分隔的引导节点地址
+/// - `HANCOIN_BOOTSTRAP_FILE` 每行一个引导节点地址的文件，默认 `<数据目录>/bootstrap.txt`
+/// - `HANCOIN_MIN_PEERS`      目标最少连接节点数
+/// - `HANCOIN_P2P_LOCAL`      设为`1`时启用本地模式，Kademlia接受回环和内网地址
+fn configure_peers(config: &mut p2p::P2PConfig, data_dir: &Path) {
+    if let Ok(listen) = std::env::var("HANCOIN_P2P_LISTEN") {
+        match p2p::parse_listen_addrs(&listen) {
+            Ok(addrs) if !addrs.is_empty() => config.listen_addrs = addrs,
+            Ok(_) => warn!("HANCOIN_P2P_LISTEN is empty, using default listen addresses"),
+            Err(e) => error!("Invalid HANCOIN_P2P_LISTEN: {}", e),
+        }
+    }
+    
+    let bootstrap_file = std::env::var("HANCOIN_BOOTSTRAP_FILE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| data_dir.join("bootstrap.txt"));
+    if let Ok(contents) = std::fs::read_to_string(&bootstrap_file) {
+        match p2p::parse_multiaddrs(&contents) {
+            Ok(addrs) => config.bootstrap_peers.extend(addrs),
+            Err(e) => error!("Invalid bootstrap file {}: {}", bootstrap_file.display(), e),
+        }
+    }
+    
+    if let Ok(bootstrap) = std::env::var("HANCOIN_BOOTSTRAP") {
+        match p2p::parse_multiaddrs(&bootstrap) {
+            Ok(addrs) => config.bootstrap_peers.extend(addrs),
+            Err(e) => error!("Invalid HANCOIN_BOOTSTRAP: {}", e),
+        }
+    }
+    
+    if let Some(min_peers) = std::env::var("HANCOIN_MIN_PEERS").ok().and_then(|v| v.parse().ok()) {
+        config.min_peers = min_peers;
+    }
+    
+    if std::env::var("HANCOIN_P2P_LOCAL").is_ok_and(|v| v == "1" || v == "true") {
+        config.allow_private_addrs = true;
+    }
+    
+    info!("P2P listen addresses: {:?}, bootstrap peers: {}",
+        config.listen_addrs, config.bootstrap_peers.len());
+}
+
+/// 从环境变量读取CoinJoin配置
+///
+/// - `HANCOIN_COINJOIN_TIMEOUT`      会话超时时间(秒)，默认1小时
+/// - `HANCOIN_COINJOIN_MAX_TIMEOUT`  创建会话时可指定的最长超时时间(秒)，默认24小时
+/// - `HANCOIN_COINJOIN_MAX_STRIKES`  触发封禁的拖延次数
+/// - `HANCOIN_COINJOIN_STRIKE_WINDOW` 拖延记录的有效期(秒)
+/// - `HANCOIN_COINJOIN_BAN_DURATION` 封禁时长(秒)
+/// - `HANCOIN_COINJOIN_PEER_SLOTS`   每个远程节点在一个会话中最多代理的参与者数量
+/// - `HANCOIN_COINJOIN_SOURCE_SESSIONS` 每个HTTP客户端地址同时开启的会话数量上限
+/// - `HANCOIN_COINJOIN_LOBBY_TIMEOUT` 会话创建后接受加入的时间(秒)，未满员的会话在此之后开始混币
+/// - `HANCOIN_COINJOIN_ENDPOINT`     随会话公告广播的登记地址(`host:port`)，通常为本节点的onion地址
+fn coinjoin_config_from_env() -> CoinJoinConfig {
+    let env_u64 = |name: &str| std::env::var(name).ok().and_then(|v| match v.trim().parse::<u64>() {
+        Ok(value) => Some(value),
+        Err(_) => {
+            warn!("Invalid {}: {}, using default", name, v);
+            None
+        }
+    });
+    
+    let mut config = CoinJoinConfig::default();
+    if let Some(max_timeout) = env_u64("HANCOIN_COINJOIN_MAX_TIMEOUT") {
+        config.max_session_timeout = max_timeout;
+    }
+    if let Some(timeout) = env_u64("HANCOIN_COINJOIN_TIMEOUT") {
+        config.session_timeout = timeout.min(config.max_session_timeout);
+    }
+    let defaults = BlameConfig::default();
+    config.blame = BlameConfig {
+        max_strikes: env_u64("HANCOIN_COINJOIN_MAX_STRIKES")
+            .filter(|&strikes| strikes > 0)
+            .and_then(|strikes| u32::try_from(strikes).ok())
+            .unwrap_or(defaults.max_strikes),
+        strike_window: env_u64("HANCOIN_COINJOIN_STRIKE_WINDOW").unwrap_or(defaults.strike_window),
+        ban_duration: env_u64("HANCOIN_COINJOIN_BAN_DURATION").unwrap_or(defaults.ban_duration),
+    };
+    if let Err(e) = config.blame.validate() {
+        warn!("Invalid CoinJoin blame config: {}, using default", e);
+        config.blame = defaults;
+    }
+    if let Some(slots) = env_u64("HANCOIN_COINJOIN_PEER_SLOTS").filter(|&slots| slots > 0) {
+        config.max_participants_per_peer = slots as usize;
+    }
+    if let Some(sessions) = env_u64("HANCOIN_COINJOIN_SOURCE_SESSIONS").filter(|&sessions| sessions > 0) {
+        config.max_sessions_per_source = sessions as usize;
+    }
+    if let Some(lobby) = env_u64("HANCOIN_COINJOIN_LOBBY_TIMEOUT") {
+        config.lobby_timeout = lobby;
+    }
+    config.registration_endpoint = std::env::var("HANCOIN_COINJOIN_ENDPOINT").ok()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty());
+    
+    info!("CoinJoin session timeout {}s, blame config {:?}", config.session_timeout, config.blame);
+    config
+}
 
-    // 启动服务器
-    info!("Server running at http://0.0.0.0:3030/");
-    warp::serve(routes).run(([0, 0, 0, 0], 3030)).await;
+/// 账本同步配置
+///
+/// - `HANCOIN_TRUSTED_STATE_ROOT`  可信状态根
+/// - `HANCOIN_TRUSTED_SYNC_PEERS`  逗号分隔的可信节点PeerId，默认为带PeerId的引导节点
+fn sync_config(data_dir: &Path, bootstrap_peers: &[libp2p::Multiaddr]) -> p2p::SyncConfig {
+    let trusted_state_root = std::env::var("HANCOIN_TRUSTED_STATE_ROOT").ok()
+        .map(|root| root.trim().to_lowercase())
+        .filter(|root| !root.is_empty());
+    if let Some(root) = &trusted_state_root {
+        info!("Ledger sync restricted to trusted state root {}", root);
+    }
+    
+    let trusted_peers: std::collections::HashSet<libp2p::PeerId> = match std::env::var("HANCOIN_TRUSTED_SYNC_PEERS") {
+        Ok(peers) => peers.split(',')
+            .map(str::trim)
+            .filter(|peer| !peer.is_empty())
+            .filter_map(|peer| match peer.parse() {
+                Ok(peer_id) => Some(peer_id),
+                Err(e) => {
+                    warn!("Invalid peer id in HANCOIN_TRUSTED_SYNC_PEERS: {} ({})", peer, e);
+                    None
+                }
+            })
+            .collect(),
+        Err(_) => bootstrap_peers.iter().filter_map(p2p::multiaddr_peer_id).collect(),
+    };
+    if trusted_state_root.is_none() && trusted_peers.is_empty() {
+        warn!("No trusted state root or sync peers configured, initial ledger sync disabled");
+    }
+    
+    p2p::SyncConfig {
+        trusted_state_root,
+        trusted_peers,
+        progress_dir: data_dir.join("sync"),
+        ..Default::default()
+    }
+}
+
+/// 处理 `node-key` 子命令，返回进程退出码
+///
+/// - `node-key show`   显示节点PeerId和密钥来源
+/// - `node-key rotate` 备份旧密钥并生成新的节点身份
+fn run_node_key_command(args: &[String], data_dir: &Path) -> i32 {
+    let key_file = node_key_path(data_dir);
+    let operator_key = operator_key_from_env();
+    
+    match args.first().map(String::as_str) {
+        Some("show") => {
+            // 只读取，不存在时不生成新密钥
+            let keypair = match &operator_key {
+                Some(secret) => p2p::identity_from_operator_key(secret).map(Some),
+                None => p2p::load_identity(&key_file),
+            };
+            match keypair {
+                Ok(None) => {
+                    println!("No node key at {} (one is generated on first start)", key_file.display());
+                    1
+                }
+                Ok(Some(keypair)) => {
+                    println!("PeerId: {}", keypair.public().to_peer_id());
+                    if operator_key.is_some() {
+                        println!("Source: derived from HANCOIN_OPERATOR_KEY");
+                    } else {
+                        println!("Source: {}", key_file.display());
+                    }
+                    0
+                }
+                Err(e) => {
+                    eprintln!("Failed to load node key: {}", e);
+                    1
+                }
+            }
+        }
+        Some("rotate") => {
+            if operator_key.is_some() {
+                eprintln!("Node identity is derived from HANCOIN_OPERATOR_KEY and cannot be rotated");
+                return 1;
+            }
+            match p2p::rotate_identity(&key_file) {
+                Ok(keypair) => {
+                    println!("New PeerId: {}", keypair.public().to_peer_id());
+                    0
+                }
+                Err(e) => {
+                    eprintln!("Failed to rotate node key: {}", e);
+                    1
+                }
+            }
+        }
+        _ => {
+            eprintln!("Usage: hancoin node-key <show|rotate>");
+            2
+        }
+    }
 }
 
 /// 创建API路由
 fn create_api_routes(
     ledger: Arc<Ledger>,
+    p2p_handle: Option<p2p::P2PHandle>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     // 水龙头路由
     let faucet_route = warp::path(API_VERSION)
@@ -122,6 +556,7 @@ fn create_api_routes(
         .and(warp::post())
         .and(warp::body::json())
         .and(with_ledger(ledger.clone()))
+        .and(with_p2p(p2p_handle.clone()))
         .and_then(handle_transfer);
 
     // 查询交易历史路由
@@ -138,6 +573,7 @@ fn create_api_routes(
         .and(warp::post())
         .and(warp::body::json())
         .and(with_ledger(ledger.clone()))
+        .and(with_p2p(p2p_handle.clone()))
         .and_then(handle_post_moment);
 
     // 查询动态消息路由
@@ -148,11 +584,12 @@ fn create_api_routes(
         .and(with_ledger(ledger.clone()))
         .and_then(handle_get_moments);
 
-    // 系统状态路由
+    // 系统状态路由，启用Tor时附带Tor连接状态
     let status_route = warp::path(API_VERSION)
         .and(warp::path("status"))
         .and(warp::get())
         .and(with_ledger(ledger.clone()))
+        .and(warp::any().map(move || p2p_handle.as_ref().and_then(|h| h.tor_health())))
         .and_then(handle_status);
 
     // 组合所有API路由
@@ -165,6 +602,13 @@ fn create_api_routes(
         .or(status_route)
 }
 
+/// 将P2P句柄注入到处理程序中，P2P网络未启动时为`None`
+fn with_p2p(
+    p2p_handle: Option<p2p::P2PHandle>,
+) -> impl Filter<Extract = (Option<p2p::P2PHandle>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || p2p_handle.clone())
+}
+
 /// 将Ledger注入到处理程序中
 fn with_ledger(
     ledger: Arc<Ledger>,
@@ -172,6 +616,313 @@ fn with_ledger(
     warp::any().map(move || ledger.clone())
 }
 
+/// 管理接口默认监听地址
+const DEFAULT_ADMIN_PORT: u16 = 3031;
+
+/// 管理接口监听地址，可通过`HANCOIN_ADMIN_ADDR`指定，只接受本机回环地址
+fn admin_addr_from_env() -> std::net::SocketAddr {
+    let default = std::net::SocketAddr::from(([127, 0, 0, 1], DEFAULT_ADMIN_PORT));
+    match std::env::var("HANCOIN_ADMIN_ADDR").map(|v| v.parse::<std::net::SocketAddr>()) {
+        Ok(Ok(addr)) if addr.ip().is_loopback() => addr,
+        Ok(Ok(addr)) => {
+            warn!("HANCOIN_ADMIN_ADDR {} is not a loopback address, using {}", addr, default);
+            default
+        }
+        Ok(Err(e)) => {
+            warn!("Invalid HANCOIN_ADMIN_ADDR: {}, using {}", e, default);
+            default
+        }
+        Err(_) => default,
+    }
+}
+
+/// 创建P2P网络状态和指标路由，只挂在管理接口上
+///
+/// - `GET /v1/network/peers`       节点列表（地址、延迟、评分、协议版本、连接方式）和按主题的统计
+/// - `GET /metrics`                Prometheus格式的网络指标
+fn create_network_routes(
+    p2p_handle: Option<p2p::P2PHandle>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let peers_handle = p2p_handle.clone();
+    let peers_route = warp::path(API_VERSION)
+        .and(warp::path("network"))
+        .and(warp::path("peers"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(move || match &peers_handle {
+            Some(handle) => warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({
+                    "status": "ok",
+                    "metrics": handle.metrics(),
+                    "peers": handle.peers()
+                })),
+                warp::http::StatusCode::OK,
+            ),
+            None => p2p_unavailable(),
+        });
+    
+    let metrics_handle = p2p_handle.clone();
+    let metrics_route = warp::path("metrics")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(move || {
+            let body = metrics_handle.as_ref()
+                .map(|handle| handle.metrics().to_prometheus())
+                .unwrap_or_default();
+            warp::reply::with_header(body, "content-type", "text/plain; version=0.0.4")
+        });
+    
+    peers_route.or(metrics_route)
+}
+
+/// 创建P2P网络管理路由，只挂在管理接口上
+///
+/// - `POST   /v1/network/dial`           拨号 `{"addr": "<multiaddr>"}`
+/// - `POST   /v1/network/disconnect`     断开 `{"peer_id": "<PeerId>"}`
+/// - `GET    /v1/network/bans`           当前封禁的节点
+/// - `POST   /v1/network/bans`           封禁 `{"peer_id": "<PeerId>", "duration_secs": 3600}`
+/// - `DELETE /v1/network/bans/<PeerId>`  解除封禁
+fn create_network_admin_routes(
+    p2p_handle: Option<p2p::P2PHandle>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let dial_handle = p2p_handle.clone();
+    let dial_route = warp::path(API_VERSION)
+        .and(warp::path("network"))
+        .and(warp::path("dial"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(warp::any().map(move || dial_handle.clone()))
+        .and_then(handle_network_dial);
+    
+    let disconnect_handle = p2p_handle.clone();
+    let disconnect_route = warp::path(API_VERSION)
+        .and(warp::path("network"))
+        .and(warp::path("disconnect"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(warp::any().map(move || disconnect_handle.clone()))
+        .and_then(handle_network_disconnect);
+    
+    let bans_handle = p2p_handle.clone();
+    let bans_route = warp::path(API_VERSION)
+        .and(warp::path("network"))
+        .and(warp::path("bans"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(move || match &bans_handle {
+            Some(handle) => warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({
+                    "status": "ok",
+                    "banned": handle.banned_peers()
+                })),
+                warp::http::StatusCode::OK,
+            ),
+            None => p2p_unavailable(),
+        });
+    
+    let ban_handle = p2p_handle.clone();
+    let ban_route = warp::path(API_VERSION)
+        .and(warp::path("network"))
+        .and(warp::path("bans"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(warp::any().map(move || ban_handle.clone()))
+        .and_then(handle_network_ban);
+    
+    let unban_handle = p2p_handle;
+    let unban_route = warp::path(API_VERSION)
+        .and(warp::path("network"))
+        .and(warp::path("bans"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(warp::any().map(move || unban_handle.clone()))
+        .and_then(handle_network_unban);
+    
+    dial_route.or(disconnect_route).or(bans_route).or(ban_route).or(unban_route)
+}
+
+/// 处理封禁请求，未指定时长时使用默认封禁时长
+async fn handle_network_ban(
+    req: serde_json::Value,
+    p2p_handle: Option<p2p::P2PHandle>,
+) -> Result<warp::reply::WithStatus<warp::reply::Json>, warp::Rejection> {
+    let Some(p2p_handle) = p2p_handle else {
+        return Ok(p2p_unavailable());
+    };
+    let peer = match req.get("peer_id").and_then(|v| v.as_str()).map(str::parse::<libp2p::PeerId>) {
+        Some(Ok(peer)) => peer,
+        _ => return Ok(network_error(warp::http::StatusCode::BAD_REQUEST, "invalid peer_id")),
+    };
+    let duration = match req.get("duration_secs") {
+        None => p2p::PeerScoreConfig::default().ban_duration,
+        Some(value) => match value.as_u64().filter(|&secs| secs > 0) {
+            Some(secs) => Duration::from_secs(secs),
+            None => return Ok(network_error(warp::http::StatusCode::BAD_REQUEST, "invalid duration_secs")),
+        },
+    };
+    
+    match p2p_handle.ban_peer(peer, duration).await {
+        Ok(()) => Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({ "status": "ok", "banned_secs": duration.as_secs() })),
+            warp::http::StatusCode::OK,
+        )),
+        Err(e) => Ok(network_error(warp::http::StatusCode::SERVICE_UNAVAILABLE, &e.to_string())),
+    }
+}
+
+/// 处理解除封禁请求
+async fn handle_network_unban(
+    peer_id: String,
+    p2p_handle: Option<p2p::P2PHandle>,
+) -> Result<warp::reply::WithStatus<warp::reply::Json>, warp::Rejection> {
+    let Some(p2p_handle) = p2p_handle else {
+        return Ok(p2p_unavailable());
+    };
+    let Ok(peer) = peer_id.parse::<libp2p::PeerId>() else {
+        return Ok(network_error(warp::http::StatusCode::BAD_REQUEST, "invalid peer_id"));
+    };
+    
+    match p2p_handle.unban_peer(peer).await {
+        Ok(()) => Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({ "status": "ok" })),
+            warp::http::StatusCode::OK,
+        )),
+        Err(e) => Ok(network_error(warp::http::StatusCode::SERVICE_UNAVAILABLE, &e.to_string())),
+    }
+}
+
+/// 处理拨号请求
+async fn handle_network_dial(
+    req: serde_json::Value,
+    p2p_handle: Option<p2p::P2PHandle>,
+) -> Result<warp::reply::WithStatus<warp::reply::Json>, warp::Rejection> {
+    let Some(p2p_handle) = p2p_handle else {
+        return Ok(p2p_unavailable());
+    };
+    let addr = match req.get("addr").and_then(|v| v.as_str()).map(str::parse::<libp2p::Multiaddr>) {
+        Some(Ok(addr)) => addr,
+        _ => return Ok(network_error(warp::http::StatusCode::BAD_REQUEST, "invalid multiaddr")),
+    };
+    
+    match p2p_handle.dial(addr).await {
+        Ok(()) => Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({ "status": "ok" })),
+            warp::http::StatusCode::OK,
+        )),
+        Err(e) => Ok(network_error(warp::http::StatusCode::BAD_GATEWAY, &e.to_string())),
+    }
+}
+
+/// 处理断开连接请求
+async fn handle_network_disconnect(
+    req: serde_json::Value,
+    p2p_handle: Option<p2p::P2PHandle>,
+) -> Result<warp::reply::WithStatus<warp::reply::Json>, warp::Rejection> {
+    let Some(p2p_handle) = p2p_handle else {
+        return Ok(p2p_unavailable());
+    };
+    let peer = match req.get("peer_id").and_then(|v| v.as_str()).map(str::parse::<libp2p::PeerId>) {
+        Some(Ok(peer)) => peer,
+        _ => return Ok(network_error(warp::http::StatusCode::BAD_REQUEST, "invalid peer_id")),
+    };
+    
+    match p2p_handle.disconnect(peer).await {
+        Ok(disconnected) => Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({ "status": "ok", "disconnected": disconnected })),
+            warp::http::StatusCode::OK,
+        )),
+        Err(e) => Ok(network_error(warp::http::StatusCode::SERVICE_UNAVAILABLE, &e.to_string())),
+    }
+}
+
+fn network_error(status: warp::http::StatusCode, message: &str) -> warp::reply::WithStatus<warp::reply::Json> {
+    warp::reply::with_status(
+        warp::reply::json(&serde_json::json!({
+            "status": "error",
+            "message": message
+        })),
+        status,
+    )
+}
+
+/// P2P网络未启动时的响应
+fn p2p_unavailable() -> warp::reply::WithStatus<warp::reply::Json> {
+    network_error(warp::http::StatusCode::SERVICE_UNAVAILABLE, "P2P network is not running")
+}
+
+/// 隐私查询请求的有效期(秒)
+const PRIVACY_REQUEST_WINDOW: u64 = 300;
+
+/// 创建混币隐私统计路由
+fn create_privacy_routes(
+    coinjoin_manager: Arc<CoinJoinManager>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(API_VERSION)
+        .and(warp::path("coinjoin"))
+        .and(warp::path("privacy"))
+        .and(warp::path::param::<String>())
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::any().map(move || coinjoin_manager.clone()))
+        .and_then(handle_privacy_report)
+}
+
+/// 解析hex账户ID对应的公钥
+fn parse_account_key(account_id: &str) -> Result<VerifyingKey, warp::Rejection> {
+    crypto::public_key_from_hex(account_id).map_err(|e| match e {
+        CryptoError::InvalidPublicKey => warp::reject::custom(HancoinError::InvalidPublicKey),
+        _ => warp::reject::custom(HancoinError::InvalidAccountIdFormat),
+    })
+}
+
+/// 解析hex签名
+fn parse_request_signature(signature: &str) -> Result<Signature, warp::Rejection> {
+    crypto::signature_from_hex(signature)
+        .map_err(|_| warp::reject::custom(HancoinError::InvalidSignatureFormat))
+}
+
+/// 处理账户隐私统计查询
+///
+/// 需要账户私钥对 `coinjoin_privacy:<timestamp>` 的签名
+async fn handle_privacy_report(
+    account_id: String,
+    params: HashMap<String, String>,
+    coinjoin_manager: Arc<CoinJoinManager>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let timestamp = params.get("timestamp")
+        .and_then(|v| v.parse::<u64>().ok())
+        .ok_or_else(|| warp::reject::custom(HancoinError::InvalidSignatureData))?;
+    let signature = params.get("signature")
+        .ok_or_else(|| warp::reject::custom(HancoinError::MissingSignature))?;
+
+    // 检查请求时效，防止重放
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_err(|_| warp::reject::custom(HancoinError::SystemTimeError))?
+        .as_secs();
+    if now.abs_diff(timestamp) > PRIVACY_REQUEST_WINDOW {
+        return Err(warp::reject::custom(HancoinError::RequestExpired));
+    }
+
+    // 验证签名
+    let public_key = parse_account_key(&account_id)?;
+    let signature = parse_request_signature(signature)?;
+    let message = format!("coinjoin_privacy:{}", timestamp);
+    if crypto::verify_raw(&public_key, message.as_bytes(), &signature).is_err() {
+        return Err(warp::reject::custom(HancoinError::InvalidSignature));
+    }
+
+    let report = coinjoin_manager.privacy_report(&account_id);
+    Ok(warp::reply::json(&serde_json::json!({
+        "status": "ok",
+        "privacy": report
+    })))
+}
+
 /// 处理水龙头请求
 async fn handle_faucet(
     req: serde_json::Value,
@@ -193,19 +944,13 @@ async fn handle_faucet(
     }
 
     // 验证公钥格式
-    let public_key_bytes = decode(account_id)
-        .map_err(|_| warp::reject::custom(HancoinError::InvalidAccountIdFormat))?;
-    let public_key = VerifyingKey::from_bytes(&public_key_bytes.try_into().unwrap())
-        .map_err(|_| warp::reject::custom(HancoinError::InvalidPublicKey))?;
+    let public_key = parse_account_key(account_id)?;
 
     // 验证签名
     let message = b"claim_faucet";
-    let signature_bytes = decode(signature)
-        .map_err(|_| warp::reject::custom(HancoinError::InvalidSignatureFormat))?;
-    let signature = Signature::from_bytes(&signature_bytes.try_into().unwrap())
-        .map_err(|_| warp::reject::custom(HancoinError::InvalidSignatureData))?;
+    let signature = parse_request_signature(signature)?;
     
-    if public_key.verify(message, &signature).is_err() {
+    if crypto::verify_raw(&public_key, message, &signature).is_err() {
         return Err(warp::reject::custom(HancoinError::InvalidSignature));
     }
 
@@ -215,7 +960,8 @@ async fn handle_faucet(
         .map_err(|_| warp::reject::custom(HancoinError::SystemTimeError))?
         .as_secs();
 
-    // 获取或创建账户
+    // 获取或创建账户，期间账本不会被快照恢复替换
+    let _state = ledger.read_state();
     let mut account = match ledger.accounts.get(account_id) {
         Some(account) => account.clone(),
         None => {
@@ -271,10 +1017,16 @@ async fn handle_balance(
     })))
 }
 
+/// 转账请求时间戳的有效期(秒)
+const TRANSFER_REQUEST_WINDOW: u64 = 300;
+
 /// 处理转账请求
+///
+/// 交易id和时间戳由客户端生成并一同签名
 async fn handle_transfer(
     tx_req: serde_json::Value,
     ledger: Arc<Ledger>,
+    p2p_handle: Option<p2p::P2PHandle>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     // 提取交易信息
     let from = tx_req.get("from")
@@ -293,20 +1045,445 @@ async fn handle_transfer(
         .and_then(|v| v.as_str())
         .ok_or_else(|| warp::reject::custom(HancoinError::InvalidTransaction))?;
     
-    // 验证发送方账户存在
-    let mut from_account = ledger.accounts.get(from)
-        .ok_or_else(|| warp::reject::custom(HancoinError::AccountNotFound))?
-        .clone();
+    let nonce = tx_req.get("nonce")
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| warp::reject::custom(HancoinError::InvalidTransaction))?;
+    
+    let id = tx_req.get("id")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| warp::reject::custom(HancoinError::InvalidTransaction))?;
+    
+    let timestamp = tx_req.get("timestamp")
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| warp::reject::custom(HancoinError::InvalidTransaction))?;
+    
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_err(|_| warp::reject::custom(HancoinError::SystemTimeError))?
+        .as_secs();
+    if now.abs_diff(timestamp) > TRANSFER_REQUEST_WINDOW {
+        return Err(warp::reject::custom(HancoinError::RequestExpired));
+    }
+    
+    // 签名覆盖转发的全部字段(见`SignedTransfer::signing_message`)，账本验证签名、nonce和余额后记账
+    let transfer = SignedTransfer {
+        tx: Tx {
+            id: id.to_string(),
+            from: from.to_string(),
+            to: to.to_string(),
+            amount,
+            timestamp,
+            fee: tx_req.get("fee").and_then(|v| v.as_u64()).unwrap_or(0),
+            memo: tx_req.get("memo").and_then(|v| v.as_str()).map(str::to_string),
+            status: TxStatus::Completed,
+        },
+        nonce,
+        signature: signature.to_string(),
+    };
+    let balance = ledger.apply_transfer(&transfer).map_err(warp::reject::custom)?;
+    let tx_id = transfer.tx.id.clone();
+    
+    info!("Transfer {} from {} to {} (amount: {})", tx_id, from, to, amount);
     
-    // 验证余额充足
-    if from_account.balance < amount {
-        return Err(warp::reject::custom(HancoinError::InvalidTransaction));
+    // 广播给其他节点，由对方重新验证后记账
+    if let Some(p2p_handle) = p2p_handle {
+        if let Err(e) = p2p_handle.publish(p2p::P2PPayload::Transaction(transfer)).await {
+            warn!("Failed to broadcast transfer {}: {}", tx_id, e);
+        }
     }
     
-    // 验证签名
-    let public_key_bytes = decode(from)
-        .map_err(|_| warp::reject::custom(HancoinError::InvalidAccountIdFormat))?;
-    let public_key = VerifyingKey::from_bytes(&public_key_bytes.try_into().unwrap())
-        .map_err(|_| warp::reject::custom(HancoinError::InvalidPublicKey))?;
+    Ok(warp::reply::json(&serde_json::json!({
+        "status": "ok",
+        "tx_id": tx_id,
+        "balance": balance
+    })))
+}
+
+/// 处理交易历史查询请求
+async fn handle_transactions(
+    account_id: String,
+    ledger: Arc<Ledger>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let _state = ledger.read_state();
+    let account = ledger.accounts.get(&account_id)
+        .ok_or_else(|| warp::reject::custom(HancoinError::AccountNotFound))?;
+    
+    let transactions: Vec<Tx> = account.transactions.iter()
+        .rev()
+        .filter_map(|tx_ref| ledger.transactions.get(&tx_ref.tx_id).map(|tx| tx.clone()))
+        .collect();
+    
+    Ok(warp::reply::json(&serde_json::json!({
+        "status": "ok",
+        "transactions": transactions
+    })))
+}
+
+/// 处理发布动态请求
+///
+/// 需要作者账户私钥对 `<timestamp>:<content>` 的签名
+async fn handle_post_moment(
+    req: serde_json::Value,
+    ledger: Arc<Ledger>,
+    p2p_handle: Option<p2p::P2PHandle>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let author = req.get("author")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| warp::reject::custom(HancoinError::MissingAccountId))?;
+    let content = req.get("content")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| warp::reject::custom(HancoinError::InvalidMoment))?;
+    let timestamp = req.get("timestamp")
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| warp::reject::custom(HancoinError::InvalidSignatureData))?;
+    let signature = req.get("signature")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| warp::reject::custom(HancoinError::MissingSignature))?;
+    
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_err(|_| warp::reject::custom(HancoinError::SystemTimeError))?
+        .as_secs();
+    
+    // 账本检查长度、时间窗口和作者签名后保存，ID由作者、时间戳和内容派生
+    let moment = SignedMoment {
+        moment: Moment {
+            id: SignedMoment::moment_id(author, timestamp, content),
+            author: author.to_string(),
+            content: content.to_string(),
+            timestamp,
+            likes: 0,
+            reposts: 0,
+            comments: Vec::new(),
+        },
+        signature: signature.to_string(),
+    };
+    ledger.add_moment(&moment, now).map_err(warp::reject::custom)?;
     
-    let message =
\ No newline at end of file
+    let reply = warp::reply::json(&serde_json::json!({
+        "status": "ok",
+        "moment": moment.moment
+    }));
+    
+    if let Some(p2p_handle) = p2p_handle {
+        let id = moment.moment.id.clone();
+        if let Err(e) = p2p_handle.publish(p2p::P2PPayload::Moment(moment)).await {
+            warn!("Failed to broadcast moment {}: {}", id, e);
+        }
+    }
+    
+    Ok(reply)
+}
+
+/// 每页最多返回的动态数
+const MAX_MOMENTS_PAGE: usize = 100;
+
+/// 处理动态查询请求
+///
+/// 支持 `author` 过滤和 `limit` 分页，按时间倒序返回
+async fn handle_get_moments(
+    params: HashMap<String, String>,
+    ledger: Arc<Ledger>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let author = params.get("author");
+    let limit = params.get("limit")
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(20)
+        .min(MAX_MOMENTS_PAGE);
+    
+    let mut moments: Vec<Moment> = ledger.moments.iter()
+        .filter(|m| author.is_none_or(|a| &m.author == a))
+        .map(|m| m.clone())
+        .collect();
+    moments.sort_by(|a, b| (b.timestamp, &b.id).cmp(&(a.timestamp, &a.id)));
+    moments.truncate(limit);
+    
+    Ok(warp::reply::json(&serde_json::json!({
+        "status": "ok",
+        "moments": moments
+    })))
+}
+
+/// 处理系统状态请求，启用Tor时加入`tor`字段
+async fn handle_status(
+    ledger: Arc<Ledger>,
+    tor_health: Option<TorHealth>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let state = ledger.read_state();
+    let mut status = serde_json::json!({
+        "status": "ok",
+        "version": env!("CARGO_PKG_VERSION"),
+        "api_version": API_VERSION,
+        "accounts": ledger.accounts.len(),
+        "transactions": ledger.transactions.len(),
+        "moments": ledger.moments.len(),
+        "issued": ledger.issued.load(Ordering::SeqCst),
+        "total_supply": HAN_TOTAL_SUPPLY
+    });
+    drop(state);
+    if let Some(tor_health) = tor_health {
+        status["tor"] = serde_json::json!(tor_health);
+    }
+    Ok(warp::reply::json(&status))
+}
+
+/// 创建CoinJoin路由
+///
+/// - `GET  /v1/coinjoin/sessions`                 可加入的会话，可按 `target_amount` 过滤
+/// - `POST /v1/coinjoin/sessions`                 创建会话
+/// - `GET  /v1/coinjoin/sessions/<id>`            会话信息
+/// - `POST /v1/coinjoin/sessions/<id>/join`       加入会话，返回凭证nonce
+/// - `POST /v1/coinjoin/sessions/<id>/inputs`     注册输入并提交盲化输出，返回盲签输出凭证
+/// - `POST /v1/coinjoin/sessions/<id>/outputs/blinded` 凭输出凭证注册输出，不携带参与者ID
+/// - `POST /v1/coinjoin/sessions/<id>/signatures` 提交签名
+/// - `POST /v1/coinjoin/sessions/<id>/finalize`   完成会话
+///
+/// 加入、输入、签名和完成请求需带参与者私钥按`coinjoin-participant`用途域的签名（`participant_signature`），与P2P消息的验证方式相同
+fn create_coinjoin_routes(
+    coinjoin_manager: Arc<CoinJoinManager>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let sessions = warp::path(API_VERSION)
+        .and(warp::path("coinjoin"))
+        .and(warp::path("sessions"));
+    let manager = warp::any().map(move || coinjoin_manager.clone());
+    
+    let list_route = sessions
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(manager.clone())
+        .map(|params: HashMap<String, String>, manager: Arc<CoinJoinManager>| {
+            let target_amount = params.get("target_amount").and_then(|v| v.parse().ok());
+            warp::reply::json(&serde_json::json!({
+                "status": "ok",
+                "sessions": manager.open_rounds(target_amount)
+            }))
+        });
+    
+    let create_route = sessions
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(warp::addr::remote())
+        .and(manager.clone())
+        .map(|req: CoinJoinRequest, addr: Option<std::net::SocketAddr>, manager: Arc<CoinJoinManager>| {
+            coinjoin_reply(manager.create_session(&req, &http_source(addr)))
+        });
+    
+    let info_route = sessions
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(manager.clone())
+        .and_then(|id: String, manager: Arc<CoinJoinManager>| async move {
+            match manager.get_session(&id) {
+                Some(session) => Ok(coinjoin_reply(Ok(session.get_info()))),
+                None => Err(warp::reject::custom(HancoinError::SessionNotFound(id))),
+            }
+        });
+    
+    let join_route = sessions
+        .and(warp::path::param::<String>())
+        .and(warp::path("join"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(warp::addr::remote())
+        .and(manager.clone())
+        .map(|id: String, req: JoinRequest, addr: Option<std::net::SocketAddr>, manager: Arc<CoinJoinManager>| {
+            participant_reply(manager.join_session(&id, &req, &http_source(addr)))
+        });
+    
+    let input_route = sessions
+        .and(warp::path::param::<String>())
+        .and(warp::path("inputs"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(manager.clone())
+        .map(|id: String, req: InputRequest, manager: Arc<CoinJoinManager>| {
+            participant_reply(manager.add_input(&id, &req))
+        });
+    
+    let blinded_output_route = sessions
+        .and(warp::path::param::<String>())
+        .and(warp::path("outputs"))
+        .and(warp::path("blinded"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(manager.clone())
+        .map(|id: String, req: BlindedOutputRequest, manager: Arc<CoinJoinManager>| {
+            coinjoin_reply(manager.register_blinded_output(&id, &req))
+        });
+    
+    let signature_route = sessions
+        .and(warp::path::param::<String>())
+        .and(warp::path("signatures"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(manager.clone())
+        .map(|id: String, req: SignatureRequest, manager: Arc<CoinJoinManager>| {
+            coinjoin_reply(manager.add_signature(&id, &req))
+        });
+    
+    let finalize_route = sessions
+        .and(warp::path::param::<String>())
+        .and(warp::path("finalize"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(manager)
+        .map(|id: String, req: FinalizeRequest, manager: Arc<CoinJoinManager>| {
+            coinjoin_reply(manager.finalize_session(&id, &req))
+        });
+    
+    list_route
+        .or(create_route)
+        .or(info_route)
+        .or(join_route)
+        .or(input_route)
+        .or(blinded_output_route)
+        .or(signature_route)
+        .or(finalize_route)
+}
+
+/// 创建CoinJoin管理路由，只挂在管理接口上
+///
+/// - `GET    /v1/coinjoin/admin/bans`        当前封禁的参与者
+/// - `DELETE /v1/coinjoin/admin/bans/<id>`   解除封禁
+/// - `GET    /v1/coinjoin/admin/blame`       当前拉黑配置
+/// - `PUT    /v1/coinjoin/admin/blame`       调整拉黑配置
+fn create_coinjoin_admin_routes(
+    coinjoin_manager: Arc<CoinJoinManager>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let admin = warp::path(API_VERSION)
+        .and(warp::path("coinjoin"))
+        .and(warp::path("admin"));
+    let manager = warp::any().map(move || coinjoin_manager.clone());
+    
+    let bans_route = admin
+        .and(warp::path("bans"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(manager.clone())
+        .map(|manager: Arc<CoinJoinManager>| {
+            warp::reply::json(&serde_json::json!({
+                "status": "ok",
+                "banned": manager.banned_participants()
+            }))
+        });
+    
+    let unban_route = admin
+        .and(warp::path("bans"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(manager.clone())
+        .map(|participant_id: String, manager: Arc<CoinJoinManager>| {
+            warp::reply::json(&serde_json::json!({
+                "status": "ok",
+                "unbanned": manager.unban_participant(&participant_id)
+            }))
+        });
+    
+    let get_blame_route = admin
+        .and(warp::path("blame"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(manager.clone())
+        .map(|manager: Arc<CoinJoinManager>| {
+            warp::reply::json(&serde_json::json!({
+                "status": "ok",
+                "blame": manager.blame_config()
+            }))
+        });
+    
+    let set_blame_route = admin
+        .and(warp::path("blame"))
+        .and(warp::path::end())
+        .and(warp::put())
+        .and(warp::body::json())
+        .and(manager)
+        .map(|config: BlameConfig, manager: Arc<CoinJoinManager>| {
+            match manager.set_blame_config(config) {
+                Ok(()) => warp::reply::with_status(
+                    warp::reply::json(&serde_json::json!({
+                        "status": "ok",
+                        "blame": manager.blame_config()
+                    })),
+                    warp::http::StatusCode::OK,
+                ),
+                Err(e) => network_error(warp::http::StatusCode::BAD_REQUEST, &e),
+            }
+        });
+    
+    bans_route.or(unban_route).or(get_blame_route).or(set_blame_route)
+}
+
+/// HTTP客户端作为CoinJoin参与者来源的标识，同一地址的客户端计入同一来源
+fn http_source(addr: Option<std::net::SocketAddr>) -> String {
+    match addr {
+        Some(addr) => format!("http:{}", addr.ip()),
+        None => "http:unknown".to_string(),
+    }
+}
+
+/// CoinJoin操作结果的响应
+fn coinjoin_reply(result: Result<CoinJoinSessionInfo, String>) -> warp::reply::WithStatus<warp::reply::Json> {
+    match result {
+        Ok(info) => warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({
+                "status": "ok",
+                "session": info
+            })),
+            warp::http::StatusCode::OK,
+        ),
+        Err(e) => network_error(warp::http::StatusCode::BAD_REQUEST, &e),
+    }
+}
+
+
+/// CoinJoin加入和输入请求的响应，附带凭证nonce或盲签输出凭证
+fn participant_reply(result: Result<ParticipantReply, String>) -> warp::reply::WithStatus<warp::reply::Json> {
+    match result {
+        Ok(reply) => warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({
+                "status": "ok",
+                "session": reply.session,
+                "nonce": reply.nonce,
+                "blind_signature": reply.blind_signature
+            })),
+            warp::http::StatusCode::OK,
+        ),
+        Err(e) => network_error(warp::http::StatusCode::BAD_REQUEST, &e),
+    }
+}
+
+/// 将拒绝转换为JSON错误响应
+async fn handle_rejection(err: warp::Rejection) -> Result<impl warp::Reply, std::convert::Infallible> {
+    let (code, message) = if let Some(e) = err.find::<HancoinError>() {
+        let code = match e {
+            HancoinError::AccountNotFound | HancoinError::SessionNotFound(_) => warp::http::StatusCode::NOT_FOUND,
+            HancoinError::MissingSignature
+            | HancoinError::InvalidSignature
+            | HancoinError::RequestExpired => warp::http::StatusCode::UNAUTHORIZED,
+            HancoinError::FaucetCooldownNotOver => warp::http::StatusCode::TOO_MANY_REQUESTS,
+            HancoinError::SystemTimeError => warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            _ => warp::http::StatusCode::BAD_REQUEST,
+        };
+        (code, e.to_string())
+    } else if err.is_not_found() {
+        (warp::http::StatusCode::NOT_FOUND, "Not Found".to_string())
+    } else if err.find::<warp::filters::body::BodyDeserializeError>().is_some() {
+        (warp::http::StatusCode::BAD_REQUEST, "Invalid JSON data".to_string())
+    } else if err.find::<warp::reject::MethodNotAllowed>().is_some() {
+        (warp::http::StatusCode::METHOD_NOT_ALLOWED, "Method not allowed".to_string())
+    } else {
+        error!("Unhandled rejection: {:?}", err);
+        (warp::http::StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error".to_string())
+    };
+    
+    Ok(network_error(code, &message))
+}
@@ -1,321 +1,3584 @@
 use libp2p::{
-    swarm::SwarmBuilder,
     core::upgrade,
-    gossipsub::{Behaviour as Gossipsub, ConfigBuilder as GossipsubConfigBuilder, IdentTopic, MessageAuthenticity, Event as GossipsubEvent},
+    connection_limits::{self, ConnectionLimits},
+    gossipsub::{self, Behaviour as Gossipsub, ConfigBuilder as GossipsubConfigBuilder, IdentTopic, MessageAuthenticity, Event as GossipsubEvent},
     identity::{self, Keypair, PublicKey},
-    noise::{self, Config as NoiseConfig, Keypair as NoiseKeypair, X25519Spec},
-    swarm::{Swarm, SwarmEvent, Config as SwarmConfig, NetworkBehaviour},
+    noise::Config as NoiseConfig,
+    swarm::{Swarm, SwarmEvent, NetworkBehaviour, ConnectionId, dial_opts::DialOpts, behaviour::toggle::Toggle},
+    identify, kad, mdns, ping,
+    request_response::{self, ProtocolSupport, OutboundRequestId, ResponseChannel},
+    multiaddr::Protocol,
     tcp::tokio::Transport as TokioTcpTransport,
     yamux::Config as YamuxConfig,
-    PeerId, Transport,
+    Multiaddr, PeerId, StreamProtocol, Transport,
 };
-use crate::tor::{TorConfig, TorConnector};
-use ed25519_dalek::{Signature, Signer, Verifier};
+use crate::tor::{spawn_tor_monitor, TorConfig, TorConnector, TorHealth, TorNetworkStatus, TorStream, TorTransport};
+use crate::coinjoin::CoinJoinMessage;
+use crate::types::{HancoinError, Ledger, LedgerSnapshot, SignedMoment, SignedTransfer, SnapshotChunk, SnapshotManifest};
 use std::time::{SystemTime, UNIX_EPOCH};
 use std::error::Error;
 use std::time::{Duration, Instant};
 use log::{info, warn, error, debug};
-use serde::{Serialize, Deserialize};
-use bincode::{serialize, deserialize};
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+use futures::StreamExt;
 use parking_lot::Mutex;
 use std::sync::Arc;
-use std::collections::HashMap;
-use governor::{Quota, RateLimiter};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fs;
+use std::net::SocketAddr;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+use governor::{DefaultKeyedRateLimiter, Quota, RateLimiter};
 use nonzero_ext::nonzero;
+use thiserror::Error;
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
 
 /// 优化的P2P网络配置
 #[derive(Clone)]
 pub struct P2PConfig {
-    pub max_message_size: usize,
+    pub max_message_size: usize, // gossipsub传输上限，各主题另有各自的限制
     pub max_connections: u32,
-    pub message_rate_limit: u32, // 消息/秒
     pub peer_timeout: Duration,
     /// Tor网络配置
     pub tor_config: TorConfig,
+    /// 已创建的Tor连接器，可与onion服务共享内嵌客户端；为`None`时按`tor_config`创建
+    pub tor_connector: Option<TorConnector>,
+    /// 节点密钥文件
+    pub key_file: PathBuf,
+    /// 运营者账户私钥，设置后节点身份由其派生而不使用密钥文件
+    pub operator_key: Option<Zeroizing<[u8; 32]>>,
+    /// 监听地址
+    pub listen_addrs: Vec<Multiaddr>,
+    /// 引导节点地址（可包含`/p2p/<PeerId>`，支持`/onion3`地址）
+    pub bootstrap_peers: Vec<Multiaddr>,
+    /// 引导节点重连策略
+    pub reconnect: ReconnectPolicy,
+    /// 目标最少连接节点数，低于该值时主动拨号引导节点
+    pub min_peers: usize,
+    /// 启用mDNS局域网节点发现（启用Tor时总是关闭，避免在局域网暴露节点）
+    pub enable_mdns: bool,
+    /// 启用Kademlia广域节点发现
+    pub enable_kademlia: bool,
+    /// 本地模式：把对方公布的回环和内网地址也加入Kademlia路由表。
+    /// 关闭时只有mDNS生效才接受这些地址，避免向广域网传播不可达的地址
+    pub allow_private_addrs: bool,
+    /// Kademlia随机游走发现间隔
+    pub discovery_interval: Duration,
+    /// 节点评分和封禁策略
+    pub peer_scoring: PeerScoreConfig,
+    /// 允许的消息时间戳与本地时钟的最大偏差
+    pub max_clock_skew: Duration,
+    /// 防重放记录的最大条目数
+    pub replay_cache_size: usize,
+    /// 所属网络
+    pub network: Network,
+    /// 各类主题的消息大小和速率限制
+    pub topic_limits: HashMap<TopicClass, TopicLimits>,
+    /// 订阅社交主题（动态消息），关闭后节点只转发账本相关消息
+    pub enable_social: bool,
+}
+
+/// 重连策略（指数退避）
+#[derive(Clone, Debug)]
+pub struct ReconnectPolicy {
+    /// 首次重试等待时间
+    pub initial_backoff: Duration,
+    /// 最长等待时间
+    pub max_backoff: Duration,
+    /// 每次失败后等待时间的倍数
+    pub multiplier: u32,
+    /// 检查连接数的间隔
+    pub check_interval: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(300),
+            multiplier: 2,
+            check_interval: Duration::from_secs(5),
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// 第`attempts`次失败后的等待时间
+    pub fn backoff(&self, attempts: u32) -> Duration {
+        let factor = self.multiplier.max(1).saturating_pow(attempts.saturating_sub(1));
+        self.initial_backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+}
+
+/// 节点评分和封禁策略
+///
+/// 每条消息的验证结果都会调整发送节点的应用层评分，评分同时提交给gossipsub的
+/// 节点评分系统；评分低于`ban_threshold`的节点被临时封禁
+#[derive(Clone, Debug)]
+pub struct PeerScoreConfig {
+    /// 有效消息的加分
+    pub accept_reward: f64,
+    /// 无效消息的扣分
+    pub reject_penalty: f64,
+    /// 超出速率限制的扣分
+    pub throttle_penalty: f64,
+    /// 评分上限，避免长期在线的节点积累过多信用
+    pub max_score: f64,
+    /// 封禁阈值
+    pub ban_threshold: f64,
+    /// 封禁时长
+    pub ban_duration: Duration,
+    /// 每个维护周期评分向0衰减的比例
+    pub decay: f64,
+    /// 维护周期
+    pub maintenance_interval: Duration,
+    /// 断线节点的负评分保留时长，期间重连不会重置评分，过期后清除记录
+    pub negative_score_retention: Duration,
+}
+
+impl Default for PeerScoreConfig {
+    fn default() -> Self {
+        Self {
+            accept_reward: 0.1,
+            reject_penalty: 10.0,
+            throttle_penalty: 1.0,
+            max_score: 10.0,
+            ban_threshold: -50.0,
+            ban_duration: Duration::from_secs(3600),
+            decay: 0.9,
+            maintenance_interval: Duration::from_secs(10),
+            negative_score_retention: Duration::from_secs(24 * 3600),
+        }
+    }
 }
 
 impl Default for P2PConfig {
     fn default() -> Self {
         Self {
-            max_message_size: 1024,
+            max_message_size: 64 * 1024,
             max_connections: 100,
-            message_rate_limit: 10,
             peer_timeout: Duration::from_secs(30),
             tor_config: TorConfig::default(),
+            tor_connector: None,
+            key_file: PathBuf::from("data").join(NODE_KEY_FILE),
+            operator_key: None,
+            listen_addrs: vec![
+                "/ip4/0.0.0.0/tcp/4001".parse().expect("valid multiaddr"),
+                "/ip6/::/tcp/4001".parse().expect("valid multiaddr"), // 添加IPv6支持
+            ],
+            bootstrap_peers: Vec::new(),
+            reconnect: ReconnectPolicy::default(),
+            min_peers: 8,
+            enable_mdns: true,
+            enable_kademlia: true,
+            allow_private_addrs: false,
+            discovery_interval: Duration::from_secs(60),
+            peer_scoring: PeerScoreConfig::default(),
+            max_clock_skew: Duration::from_secs(120),
+            replay_cache_size: 100_000,
+            network: Network::default(),
+            topic_limits: TopicClass::ALL.iter()
+                .map(|class| (*class, class.default_limits()))
+                .collect(),
+            enable_social: true,
         }
     }
 }
 
-/// 优化的P2P网络状态
-#[derive(Default)]
-struct P2PState {
-    active_peers: HashMap<PeerId, Instant>,
-    message_count: usize,
-    last_message_time: Option<Instant>,
+impl P2PConfig {
+    /// onion服务转发P2P连接的本机地址：第一个TCP监听端口，默认4001
+    pub fn onion_service_target(&self) -> SocketAddr {
+        let port = self.listen_addrs.iter()
+            .find_map(|addr| addr.iter().find_map(|p| match p {
+                Protocol::Tcp(port) => Some(port),
+                _ => None,
+            }))
+            .unwrap_or(4001);
+        SocketAddr::from((std::net::Ipv4Addr::LOCALHOST, port))
+    }
 }
 
-/// 启动优化的P2P网络
-pub async fn start_p2p(config: Option<P2PConfig>) -> Result<(), Box<dyn Error>> {
-    let config = config.unwrap_or_default();
+/// 解析逗号或换行分隔的多地址列表，忽略空项和`#`注释
+pub fn parse_multiaddrs(list: &str) -> Result<Vec<Multiaddr>, P2PError> {
+    list.split([',', '\n'])
+        .map(str::trim)
+        .filter(|s| !s.is_empty() && !s.starts_with('#'))
+        .map(|s| s.parse::<Multiaddr>().map_err(|e| P2PError::InvalidAddress(format!("{}: {}", s, e))))
+        .collect()
+}
+
+/// 可插拔传输
+///
+/// 节点可以同时在多种传输上监听，每个监听地址的传输由地址本身决定；
+/// 拨号时按对方地址选择。启用Tor时各传输的外连都经Tor建立
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransportKind {
+    /// 直接TCP
+    Tcp,
+    /// TCP之上的WebSocket，用于只放行HTTP流量的网络
+    WebSocket,
+}
+
+impl TransportKind {
+    /// 地址使用的传输
+    pub fn of(addr: &Multiaddr) -> Self {
+        if addr.iter().any(|p| matches!(p, Protocol::Ws(_) | Protocol::Wss(_))) {
+            TransportKind::WebSocket
+        } else {
+            TransportKind::Tcp
+        }
+    }
     
-    // 1. 生成本地密钥和PeerId
-    let id_keys = identity::Keypair::generate_ed25519();
-    let peer_id = PeerId::from(id_keys.public());
-    info!("Starting P2P node with ID: {:?}", peer_id);
+    /// 让TCP地址使用该传输
+    pub fn apply(self, addr: Multiaddr) -> Multiaddr {
+        match self {
+            TransportKind::WebSocket if TransportKind::of(&addr) == TransportKind::Tcp => {
+                addr.with(Protocol::Ws("/".into()))
+            }
+            _ => addr,
+        }
+    }
+}
+
+impl std::str::FromStr for TransportKind {
+    type Err = P2PError;
     
-    // 初始化速率限制器
-    let rate_limiter = Arc::new(RateLimiter::direct(Quota::per_second(nonzero!(config.message_rate_limit))));
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tcp" => Ok(TransportKind::Tcp),
+            "ws" | "websocket" => Ok(TransportKind::WebSocket),
+            other => Err(P2PError::InvalidAddress(format!("unknown transport: {}", other))),
+        }
+    }
+}
 
-    // 2. 生成Noise密钥
-    let noise_keys = NoiseKeypair::<X25519Spec>::new()
-        .into_authenticated(&id_keys)
-        .map_err(|e| format!("Failed to generate Noise keys: {:?}", e))?;
+/// 解析监听地址列表，每项可写成`<transport>=<multiaddr>`，如`ws=/ip4/0.0.0.0/tcp/4002`
+pub fn parse_listen_addrs(list: &str) -> Result<Vec<Multiaddr>, P2PError> {
+    list.split([',', '\n'])
+        .map(str::trim)
+        .filter(|s| !s.is_empty() && !s.starts_with('#'))
+        .map(|s| {
+            let (transport, addr) = match s.split_once('=') {
+                Some((transport, addr)) => (transport.trim().parse()?, addr.trim()),
+                None => (TransportKind::Tcp, s),
+            };
+            addr.parse::<Multiaddr>()
+                .map(|addr| transport.apply(addr))
+                .map_err(|e| P2PError::InvalidAddress(format!("{}: {}", s, e)))
+        })
+        .collect()
+}
+
+/// 基础传输：TCP，启用Tor时经Tor拨出
+fn base_transport(tor: Option<&TorConnector>) -> libp2p::core::transport::Boxed<TorStream> {
+    let tcp_config = libp2p::tcp::Config::default()
+        .nodelay(true) // 启用TCP_NODELAY减少延迟
+        .listen_backlog(128); // 增加监听队列大小
+    let tcp = TokioTcpTransport::new(tcp_config);
     
-    // 初始化P2P状态
-    let state = Arc::new(Mutex::new(P2PState::default()));
+    // 启用Tor时所有外连都经Tor建立，支持/onion3和/dns地址
+    match tor {
+        Some(connector) => TorTransport::new(connector.clone(), tcp).boxed(),
+        None => tcp.map(|stream, _| TorStream::Tcp(stream)).boxed(),
+    }
+}
 
-    // 3. 构建优化的传输层，支持Tor
-    let transport = {
-        // 创建Tor连接器
-        let tor_connector = if config.tor_config.enabled {
-            info!("启用Tor网络连接，代理地址: {}", config.tor_config.proxy_addr);
-            Some(Arc::new(TorConnector::new(config.tor_config.clone())))
-        } else {
-            None
-        };
-        
-        // 创建TCP传输
-        let tcp_config = libp2p::tcp::Config::default()
-            .nodelay(true) // 启用TCP_NODELAY减少延迟
-            .reuse_address(true) // 允许地址重用
-            .listen_backlog(128); // 增加监听队列大小
-            
-        let tcp = if let Some(tor) = tor_connector {
-            // 使用自定义的TCP传输，通过Tor连接
-            let tor_clone = tor.clone();
-            TokioTcpTransport::new(tcp_config)
-                .map(move |socket, addr| {
-                    let addr_str = match addr {
-                        libp2p::core::ConnectedPoint::Dialer { address, .. } => address.to_string(),
-                        libp2p::core::ConnectedPoint::Listener { .. } => "listener".to_string(),
-                    };
-                    
-                    if TorConnector::is_onion_address(&addr_str) {
-                        debug!("通过Tor连接到.onion地址: {:?}", addr);
-                        Box::pin(async move {
-                            let stream = tor_clone.connect(&addr_str).await?;
-                            Ok((stream, addr))
-                        }) as std::pin::Pin<Box<dyn std::future::Future<Output = Result<(tokio::net::TcpStream, std::net::SocketAddr), std::io::Error>> + Send>>
-                    } else if tor_clone.is_enabled() {
-                        // 如果启用了Tor，所有连接都通过Tor
-                        debug!("通过Tor连接到地址: {:?}", addr);
-                        Box::pin(async move {
-                            let stream = tor_clone.connect(&addr_str).await?;
-                            Ok((stream, addr))
-                        }) as std::pin::Pin<Box<dyn std::future::Future<Output = Result<(tokio::net::TcpStream, std::net::SocketAddr), std::io::Error>> + Send>>
-                    } else {
-                        // 直接连接
-                        Box::pin(async move {
-                            let stream = tokio::net::TcpStream::connect(addr).await?;
-                            Ok((stream, addr))
-                        }) as std::pin::Pin<Box<dyn std::future::Future<Output = Result<(tokio::net::TcpStream, std::net::SocketAddr), std::io::Error>> + Send>>
-                    }
-                })
-        } else {
-            // 标准TCP传输
-            TokioTcpTransport::new(tcp_config)
-        };
-        
-        tcp.upgrade(upgrade::Version::V1)
-            .authenticate(
-                NoiseConfig::new(noise_keys)
-                    .into_authenticated()
-                    .with_remote_peer_id_verification(true) // 强制验证远程PeerId
-            )
-            .multiplex(YamuxConfig::default())
-            .timeout(Duration::from_secs(10)) // 添加超时
-            .boxed()
-    };
+/// 地址是否为.onion地址
+fn is_onion_multiaddr(addr: &Multiaddr) -> bool {
+    addr.iter().any(|p| matches!(p, Protocol::Onion(..) | Protocol::Onion3(_)))
+}
 
-    // 4. 配置优化的gossipsub
-    let gossipsub_config = GossipsubConfigBuilder::default()
-        .max_transmit_size(config.max_message_size)
-        .validation_mode(gossipsub::ValidationMode::Strict) // 使用Strict验证模式
-        .peer_score_params(Default::default()) // 启用对等节点评分
-        .flood_publish(true)
-        .message_id_fn(|message| {
-            // 使用更安全的消息ID生成
-            let mut hasher = blake3::Hasher::new();
-            hasher.update(&message.source.as_ref().map(|p| p.to_bytes()).unwrap_or_default());
-            hasher.update(&message.data);
-            hasher.update(&message.sequence_number.unwrap_or_default().to_be_bytes());
-            gossipsub::MessageId::from(hasher.finalize().as_bytes()[..32].to_vec())
+/// 地址是否可能在公网上可达：回环、内网、链路本地等地址返回`false`，域名和onion地址返回`true`
+fn is_global_multiaddr(addr: &Multiaddr) -> bool {
+    addr.iter().all(|p| match p {
+        Protocol::Ip4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                // 100.64.0.0/10 运营商级NAT
+                || (a == 100 && (b & 0xc0) == 64))
+        }
+        Protocol::Ip6(ip) => {
+            let first = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                // fc00::/7 唯一本地地址，fe80::/10 链路本地地址
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80)
+        }
+        _ => true,
+    })
+}
+
+/// 把监听地址限制到本机回环地址
+fn loopback_listen_addr(addr: &Multiaddr) -> Multiaddr {
+    let loopback: Multiaddr = addr.iter()
+        .map(|p| match p {
+            Protocol::Ip4(ip) if !ip.is_loopback() => Protocol::Ip4(std::net::Ipv4Addr::LOCALHOST),
+            Protocol::Ip6(ip) if !ip.is_loopback() => Protocol::Ip6(std::net::Ipv6Addr::LOCALHOST),
+            p => p,
         })
-        .build()
-        .expect("Failed to build Gossipsub config");
+        .collect();
+    if &loopback != addr {
+        warn!("Only onion peers are allowed, listening on {} instead of {}", loopback, addr);
+    }
+    loopback
+}
 
-    let mut gossipsub = Gossipsub::new_with_message_authenticity(
-        MessageAuthenticity::Signed(id_keys.clone()),
-        gossipsub_config,
-    )
-    .expect("Failed to create Gossipsub");
+/// 连接是否经由Tor：拨出到onion地址，或由本机Tor守护进程转发到onion服务目标端口的入站连接
+///
+/// 本机其他进程连接其他监听端口时不算Tor连接
+fn is_tor_endpoint(endpoint: &libp2p::core::ConnectedPoint, onion_target: SocketAddr) -> bool {
+    match endpoint {
+        libp2p::core::ConnectedPoint::Dialer { address, .. } => is_onion_multiaddr(address),
+        libp2p::core::ConnectedPoint::Listener { local_addr, send_back_addr } => {
+            let from_loopback = send_back_addr.iter().any(|p| match p {
+                Protocol::Ip4(ip) => ip.is_loopback(),
+                Protocol::Ip6(ip) => ip.is_loopback(),
+                _ => false,
+            });
+            let on_target_ip = local_addr.iter().any(|p| p == Protocol::from(onion_target.ip()));
+            let on_target_port = local_addr.iter().any(|p| p == Protocol::Tcp(onion_target.port()));
+            from_loopback && on_target_ip && on_target_port
+        }
+    }
+}
+
+/// 地址中携带的PeerId
+pub fn multiaddr_peer_id(addr: &Multiaddr) -> Option<PeerId> {
+    addr.iter().find_map(|p| match p {
+        Protocol::P2p(peer_id) => Some(peer_id),
+        _ => None,
+    })
+}
+
+/// 网络消息格式版本，消息格式不兼容时递增
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// 仍可互通的最低协议版本
+pub const MIN_PROTOCOL_VERSION: u32 = 2;
+
+/// 网络标识，不同网络的节点互不连接
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Network {
+    #[default]
+    Mainnet,
+    Testnet,
+    Devnet,
+}
 
-    // 订阅主题
-    let topic = IdentTopic::new("hancoin-topic-v2"); // 使用版本化主题
-    gossipsub.subscribe(&topic).expect("Failed to subscribe to topic");
+impl Network {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Network::Mainnet => "mainnet",
+            Network::Testnet => "testnet",
+            Network::Devnet => "devnet",
+        }
+    }
+    
+    /// 通过identify公布的协议版本，如`/hancoin/mainnet/2`
+    pub fn protocol_version(&self) -> String {
+        format!("/hancoin/{}/{}", self.as_str(), PROTOCOL_VERSION)
+    }
+    
+    /// 带网络命名空间的gossipsub主题名
+    ///
+    /// 主题名不含协议版本，`MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION`内的节点共用同一组主题；
+    /// 不兼容的节点由`check_compatible`在identify阶段拒绝
+    pub fn topic(&self, name: &str) -> String {
+        format!("hancoin/{}/{}", self.as_str(), name)
+    }
     
-    // 添加消息验证回调
-    gossipsub.set_message_validator(|_, message| {
-        // 检查消息大小
-        if message.data.len() > config.max_message_size {
-            warn!("Rejected oversized message: {} bytes", message.data.len());
-            return false;
+    /// 本网络的Kademlia协议名，避免不同网络共用路由表
+    fn kad_protocol(&self) -> StreamProtocol {
+        match self {
+            Network::Mainnet => StreamProtocol::new("/hancoin/mainnet/kad/1.0.0"),
+            Network::Testnet => StreamProtocol::new("/hancoin/testnet/kad/1.0.0"),
+            Network::Devnet => StreamProtocol::new("/hancoin/devnet/kad/1.0.0"),
         }
-        
-        // 检查消息速率
-        if rate_limiter.check().is_err() {
-            warn!("Message rate limit exceeded");
-            return false;
+    }
+    
+    /// 本网络的账本同步协议名，不同网络的节点无法互相请求快照
+    fn sync_protocol(&self) -> StreamProtocol {
+        match self {
+            Network::Mainnet => StreamProtocol::new("/hancoin/mainnet/sync/1.0.0"),
+            Network::Testnet => StreamProtocol::new("/hancoin/testnet/sync/1.0.0"),
+            Network::Devnet => StreamProtocol::new("/hancoin/devnet/sync/1.0.0"),
         }
-        
-        true
-    });
+    }
+    
+    /// 检查对方公布的协议版本是否与本节点兼容
+    pub fn check_compatible(&self, protocol_version: &str) -> Result<(), String> {
+        let mut parts = protocol_version.strip_prefix("/hancoin/")
+            .ok_or_else(|| format!("not a hancoin node ({})", protocol_version))?
+            .split('/');
+        let network = parts.next().unwrap_or_default();
+        if network != self.as_str() {
+            return Err(format!("network {} does not match local network {}", network, self.as_str()));
+        }
+        let version: u32 = parts.next()
+            .and_then(|v| v.parse().ok())
+            .ok_or_else(|| format!("malformed protocol version {}", protocol_version))?;
+        if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version) {
+            return Err(format!(
+                "protocol version {} not supported (supported {}..={})",
+                version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+            ));
+        }
+        Ok(())
+    }
+}
 
-    // 5. 构建优化的Swarm
-    let mut swarm = {
-        let behaviour = gossipsub;
-        let swarm_config = libp2p::swarm::Config::with_tokio_executor()
-            .with_idle_connection_timeout(config.peer_timeout)
-            .with_max_established_incoming_connections(config.max_connections)
-            .with_max_established_outgoing_connections(config.max_connections)
-            .with_dial_concurrency_factor(4)  // 增加并发拨号数
-            .with_notification_buffer_size(32)  // 增加通知缓冲区大小
-            .with_pending_connection_timeout(Duration::from_secs(10))  // 减少挂起连接的超时时间
-            .with_max_negotiating_inbound_streams(8)  // 增加最大协商入站流
-            .with_max_negotiating_outbound_streams(8)  // 增加最大协商出站流
-            .with_connection_event_buffer_size(64);  // 增加连接事件缓冲区大小
-        
-        SwarmBuilder::with_tokio_executor(transport, behaviour, peer_id)
-            .config(swarm_config)
-            .build()
-    };
+impl std::fmt::Display for Network {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
 
-    // 监听多个地址
-    swarm.listen_on("/ip4/0.0.0.0/tcp/4001".parse()?)?;
-    swarm.listen_on("/ip6/::/tcp/4001".parse()?)?;  // 添加IPv6支持
+impl std::str::FromStr for Network {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "mainnet" => Ok(Network::Mainnet),
+            "testnet" => Ok(Network::Testnet),
+            "devnet" => Ok(Network::Devnet),
+            other => Err(format!("unknown network: {}", other)),
+        }
+    }
+}
 
-    // 6. 优化的事件循环
-    tokio::spawn(async move {
-        let state_clone = state.clone();
-        
-        loop {
-            match swarm.next().await {
-                Some(SwarmEvent::Behaviour(GossipsubEvent::Message { 
-                    propagation_source: _,
-                    message_id: _,
-                    message,
-                })) => {
-                    // 更新状态
-                    let mut state = state_clone.lock();
-                    state.message_count += 1;
-                    state.last_message_time = Some(Instant::now());
-                    
-                    // 处理消息
-                    if let Ok(msg) = deserialize::<P2PMessage>(&message.data) {
-                        debug!("Received valid P2P message: {:?}", msg);
-                        // 这里添加消息处理逻辑
-                    } else {
-                        warn!("Received invalid P2P message");
-                    }
-                },
-                Some(SwarmEvent::NewListenAddr { address, .. }) => {
-                    info!("Listening on {:?}", address);
-                },
-                Some(SwarmEvent::ConnectionEstablished { peer_id, .. }) => {
-                    info!("Connected to peer: {:?}", peer_id);
-                    state_clone.lock().active_peers.insert(peer_id, Instant::now());
-                },
-                Some(SwarmEvent::ConnectionClosed { peer_id, cause, .. }) => {
-                    info!("Disconnected from peer: {:?}, cause: {:?}", peer_id, cause);
-                    state_clone.lock().active_peers.remove(&peer_id);
-                },
-                Some(SwarmEvent::OutgoingConnectionError { peer_id, error, .. }) => {
-                    warn!("Failed to connect to peer {:?}: {:?}", peer_id, error);
-                },
-                Some(SwarmEvent::IncomingConnectionError { error, .. }) => {
-                    warn!("Incoming connection error: {:?}", error);
-                },
-                Some(_) => {},
-                None => {
-                    // 处理None情况，可能是连接已关闭
-                    warn!("Swarm stream returned None, connection may be closed");
-                }
-            }
+/// 消息类别，每类使用独立的gossipsub主题
+///
+/// 账本没有区块，转账逐笔广播并记账，因此没有区块主题；引入区块后在此增加对应类别
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TopicClass {
+    /// 转账交易
+    Transactions,
+    /// 动态消息（社交）
+    Moments,
+    /// CoinJoin协调消息
+    CoinJoin,
+}
+
+/// 单个主题的限制
+#[derive(Clone, Debug)]
+pub struct TopicLimits {
+    /// 单条消息最大字节数
+    pub max_message_size: usize,
+    /// 每个节点每秒最多消息数
+    pub rate_limit: u32,
+}
+
+impl TopicClass {
+    pub const ALL: [TopicClass; 3] = [
+        TopicClass::Transactions,
+        TopicClass::Moments,
+        TopicClass::CoinJoin,
+    ];
+    
+    pub fn name(&self) -> &'static str {
+        match self {
+            TopicClass::Transactions => "transactions",
+            TopicClass::Moments => "moments",
+            TopicClass::CoinJoin => "coinjoin",
         }
-    });
+    }
     
-    // 添加定期清理任务
-    tokio::spawn(async move {
-        let state = state.clone();
-        let mut interval = tokio::time::interval(Duration::from_secs(60));
-        
-        loop {
-            interval.tick().await;
-            
-            // 清理不活跃的对等节点
-            let mut state = state.lock();
-            let now = Instant::now();
-            state.active_peers.retain(|_, last_seen| {
-                now.duration_since(*last_seen) < config.peer_timeout
-            });
-            
-            debug!("Active peers: {}, Total messages: {}", 
-                  state.active_peers.len(), state.message_count);
+    /// 社交类主题可以选择不订阅
+    pub fn is_social(&self) -> bool {
+        matches!(self, TopicClass::Moments)
+    }
+    
+    /// 优先级：排队待广播的消息按优先级从高到低发送；同时作为gossipsub评分中的主题权重
+    pub fn priority(&self) -> f64 {
+        match self {
+            TopicClass::Transactions => 0.8,
+            TopicClass::CoinJoin => 0.5,
+            TopicClass::Moments => 0.2,
         }
-    });
+    }
+    
+    pub fn default_limits(&self) -> TopicLimits {
+        match self {
+            TopicClass::Transactions => TopicLimits { max_message_size: 4 * 1024, rate_limit: 20 },
+            TopicClass::Moments => TopicLimits { max_message_size: 2 * 1024, rate_limit: 5 },
+            TopicClass::CoinJoin => TopicLimits { max_message_size: 8 * 1024, rate_limit: 10 },
+        }
+    }
+}
 
-    Ok(())
+/// 已订阅的主题及其限制
+struct TopicRoute {
+    topic: IdentTopic,
+    limits: TopicLimits,
+    limiter: DefaultKeyedRateLimiter<PeerId>,
 }
 
-/// 优化的P2P消息结构
-#[derive(Serialize, Deserialize, Debug)]
-pub struct P2PMessage {
-    pub version: u8,
-    pub timestamp: u64,
-    pub payload: Vec<u8>,
-    pub signature: Vec<u8>,
+/// 组合的网络行为：gossipsub消息传播、Kademlia和mDNS节点发现、identify与ping
+#[derive(NetworkBehaviour)]
+struct HancoinBehaviour {
+    limits: connection_limits::Behaviour,
+    gossipsub: Gossipsub,
+    kademlia: Toggle<kad::Behaviour<kad::store::MemoryStore>>,
+    mdns: Toggle<mdns::tokio::Behaviour>,
+    identify: identify::Behaviour,
+    ping: ping::Behaviour,
+    sync: request_response::cbor::Behaviour<SyncRequest, SyncResponse>,
 }
 
-impl P2PMessage {
-    pub fn new(payload: Vec<u8>) -> Self {
+/// 引导节点拨号状态
+struct BootstrapPeer {
+    addr: Multiaddr,
+    peer_id: Option<PeerId>,
+    attempts: u32,
+    next_attempt: Instant,
+    connected: bool,
+    /// 对方不兼容，不再拨号
+    incompatible: bool,
+}
+
+/// 引导节点拨号管理
+///
+/// 连接数不足时拨号未连接的引导节点，失败后按重连策略退避
+struct Bootstrapper {
+    policy: ReconnectPolicy,
+    peers: Vec<BootstrapPeer>,
+    pending: HashMap<ConnectionId, usize>,
+}
+
+impl Bootstrapper {
+    fn new(addrs: &[Multiaddr], policy: ReconnectPolicy, tor: &TorConfig, now: Instant) -> Self {
+        let peers = addrs.iter()
+            .filter(|addr| {
+                if is_onion_multiaddr(addr) && !tor.enabled {
+                    warn!("Skipping onion bootstrap peer {} because Tor is disabled", addr);
+                    return false;
+                }
+                if !is_onion_multiaddr(addr) && tor.is_strict() {
+                    error!("Skipping clearnet bootstrap peer {} because only onion peers are allowed", addr);
+                    return false;
+                }
+                true
+            })
+            .map(|addr| BootstrapPeer {
+                addr: addr.clone(),
+                peer_id: multiaddr_peer_id(addr),
+                attempts: 0,
+                next_attempt: now,
+                connected: false,
+                incompatible: false,
+            })
+            .collect();
+            
         Self {
-            version: 1,
-            timestamp: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs(),
-            payload,
-            signature: Vec::new(),
+            policy,
+            peers,
+            pending: HashMap::new(),
         }
     }
     
-    pub fn sign(&mut self, keypair: &Keypair) -> Result<(), Box<dyn Error>> {
-        let mut data = serialize(&self.payload)?;
-        data.extend(self.timestamp.to_be_bytes());
-        
-        // 使用libp2p内置方法进行签名
-        let signature = keypair.sign(&data);
-        self.signature = signature;
-        Ok(())
+    /// 需要拨号的引导节点
+    fn due(&self, now: Instant) -> Vec<(usize, Multiaddr)> {
+        self.peers.iter()
+            .enumerate()
+            .filter(|(index, peer)| {
+                !peer.connected
+                    && !peer.incompatible
+                    && peer.next_attempt <= now
+                    && !self.pending.values().any(|i| i == index)
+            })
+            .map(|(index, peer)| (index, peer.addr.clone()))
+            .collect()
     }
     
-    pub fn verify(&self, public_key: &PublicKey) -> Result<(), Box<dyn Error>> {
-        let mut data = serialize(&self.payload)?;
-        data.extend(self.timestamp.to_be_bytes());
-        
-        if !public_key.verify(&data, &self.signature) {
-            return Err("Signature verification failed".into());
+    /// 记录发起的拨号
+    fn dialing(&mut self, index: usize, connection_id: ConnectionId) {
+        self.pending.insert(connection_id, index);
+    }
+    
+    /// 连接建立
+    fn on_connected(&mut self, connection_id: ConnectionId, peer_id: PeerId) {
+        let index = self.pending.remove(&connection_id)
+            .or_else(|| self.peers.iter().position(|p| p.peer_id == Some(peer_id)));
+        if let Some(peer) = index.and_then(|i| self.peers.get_mut(i)) {
+            peer.peer_id = Some(peer_id);
+            peer.connected = true;
+            peer.attempts = 0;
         }
-        Ok(())
     }
-}
\ No newline at end of file
+    
+    /// 拨号失败
+    fn on_failed(&mut self, connection_id: ConnectionId, now: Instant) {
+        if let Some(index) = self.pending.remove(&connection_id) {
+            self.back_off(index, now);
+        }
+    }
+    
+    /// 拨号未能发起（如地址被传输层拒绝），与拨号失败一样退避
+    fn on_dial_error(&mut self, index: usize, now: Instant) {
+        self.back_off(index, now);
+    }
+    
+    fn back_off(&mut self, index: usize, now: Instant) {
+        if let Some(peer) = self.peers.get_mut(index) {
+            peer.attempts += 1;
+            let backoff = self.policy.backoff(peer.attempts);
+            peer.next_attempt = now + backoff;
+            debug!("Bootstrap peer {} unreachable, retrying in {:?}", peer.addr, backoff);
+        }
+    }
+    
+    /// Tor恢复后立即重试所有未连接的引导节点
+    fn resume(&mut self, now: Instant) {
+        for peer in self.peers.iter_mut().filter(|p| !p.connected) {
+            peer.attempts = 0;
+            peer.next_attempt = now;
+        }
+    }
+    
+    /// 与节点的所有连接均已断开
+    fn on_disconnected(&mut self, peer_id: PeerId, now: Instant) {
+        for peer in self.peers.iter_mut().filter(|p| p.peer_id == Some(peer_id)) {
+            peer.connected = false;
+            peer.next_attempt = now + self.policy.initial_backoff;
+        }
+    }
+    
+    /// 不再拨号该节点（属于其他网络或协议版本不兼容）
+    fn forget(&mut self, peer_id: PeerId) {
+        for peer in self.peers.iter_mut().filter(|p| p.peer_id == Some(peer_id)) {
+            peer.incompatible = true;
+        }
+    }
+}
+
+
+/// 默认的节点密钥文件名
+pub const NODE_KEY_FILE: &str = "node_key";
+
+/// 由运营者账户密钥派生节点身份时使用的域分隔上下文
+const NODE_IDENTITY_CONTEXT: &str = "hancoin 2024 p2p node identity v1";
+
+/// 加载节点身份
+///
+/// 配置了运营者账户密钥时由其派生，否则从密钥文件读取，文件不存在时生成一次
+pub fn load_node_identity(config: &P2PConfig) -> Result<Keypair, Box<dyn Error>> {
+    match &config.operator_key {
+        Some(secret) => identity_from_operator_key(secret),
+        None => load_or_generate_identity(&config.key_file),
+    }
+}
+
+/// 从运营者账户私钥派生节点身份
+///
+/// 使用独立的域分隔上下文，节点私钥泄露不会暴露账户私钥
+pub fn identity_from_operator_key(secret: &[u8; 32]) -> Result<Keypair, Box<dyn Error>> {
+    let derived = Zeroizing::new(blake3::derive_key(NODE_IDENTITY_CONTEXT, secret));
+    let mut bytes = *derived;
+    Ok(Keypair::ed25519_from_bytes(&mut bytes)?)
+}
+
+/// 从密钥文件读取节点身份，文件不存在时返回`None`
+pub fn load_identity(path: &Path) -> Result<Option<Keypair>, Box<dyn Error>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    check_key_permissions(path);
+    let bytes = Zeroizing::new(fs::read(path)?);
+    let keypair = Keypair::from_protobuf_encoding(&bytes)
+        .map_err(|e| format!("Invalid node key file {}: {}", path.display(), e))?;
+    debug!("Loaded node identity from {}", path.display());
+    Ok(Some(keypair))
+}
+
+/// 从密钥文件读取节点身份，文件不存在时生成新身份并保存
+pub fn load_or_generate_identity(path: &Path) -> Result<Keypair, Box<dyn Error>> {
+    if let Some(keypair) = load_identity(path)? {
+        return Ok(keypair);
+    }
+    
+    let keypair = identity::Keypair::generate_ed25519();
+    write_identity(path, &keypair)?;
+    info!("Generated new node identity {} at {}", keypair.public().to_peer_id(), path.display());
+    Ok(keypair)
+}
+
+/// 轮换节点身份
+///
+/// 旧密钥文件重命名备份，返回新身份
+pub fn rotate_identity(path: &Path) -> Result<Keypair, Box<dyn Error>> {
+    if path.exists() {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let backup = path.with_extension(format!("{}.bak", now));
+        fs::rename(path, &backup)?;
+        info!("Previous node key moved to {}", backup.display());
+    }
+    
+    let keypair = identity::Keypair::generate_ed25519();
+    write_identity(path, &keypair)?;
+    info!("Rotated node identity, new PeerId: {}", keypair.public().to_peer_id());
+    Ok(keypair)
+}
+
+/// 保存节点身份（先写临时文件再重命名，仅所有者可读写）
+fn write_identity(path: &Path, keypair: &Keypair) -> Result<(), Box<dyn Error>> {
+    let bytes = Zeroizing::new(keypair.to_protobuf_encoding()?);
+    crate::crypto::write_private_file(path, &bytes)?;
+    Ok(())
+}
+
+/// 检查密钥文件权限，其他用户可访问时发出警告
+fn check_key_permissions(path: &Path) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if let Ok(meta) = fs::metadata(path) {
+            if meta.permissions().mode() & 0o077 != 0 {
+                warn!("Node key file {} is accessible by other users, run `chmod 600` on it", path.display());
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+}
+
+/// 优化的P2P网络状态
+#[derive(Default)]
+struct P2PState {
+    active_peers: HashMap<PeerId, PeerRecord>,
+    connected_peers: HashSet<PeerId>,
+    message_count: usize,
+    last_message_time: Option<Instant>,
+    /// 按主题统计的消息计数
+    topics: BTreeMap<&'static str, TopicMetrics>,
+    /// 当前监听的地址
+    listen_addrs: Vec<Multiaddr>,
+}
+
+/// 单个主题的消息统计
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct TopicMetrics {
+    pub received: u64,
+    pub received_bytes: u64,
+    pub accepted: u64,
+    pub published: u64,
+    pub published_bytes: u64,
+    /// 按原因统计的被拒绝消息数
+    pub rejected: BTreeMap<&'static str, u64>,
+}
+
+/// 网络统计快照
+#[derive(Clone, Debug, Serialize)]
+pub struct NetworkMetrics {
+    pub local_peer_id: String,
+    pub network: Network,
+    pub protocol_version: u32,
+    pub connected_peers: usize,
+    pub known_peers: usize,
+    pub banned_peers: usize,
+    pub messages_total: usize,
+    /// 距最后一条消息的秒数
+    pub last_message_secs: Option<u64>,
+    pub topics: BTreeMap<&'static str, TopicMetrics>,
+}
+
+impl NetworkMetrics {
+    /// 以Prometheus文本格式输出
+    pub fn to_prometheus(&self) -> String {
+        use std::fmt::Write;
+        
+        let mut out = String::new();
+        let gauges = [
+            ("hancoin_p2p_connected_peers", "Number of connected peers.", self.connected_peers),
+            ("hancoin_p2p_known_peers", "Number of peers with a score record.", self.known_peers),
+            ("hancoin_p2p_banned_peers", "Number of currently banned peers.", self.banned_peers),
+        ];
+        for (name, help, value) in gauges {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} gauge", name, help, name);
+            let _ = writeln!(out, "{}{{network=\"{}\"}} {}", name, self.network, value);
+        }
+        
+        type Counter = (&'static str, &'static str, fn(&TopicMetrics) -> u64);
+        let counters: [Counter; 5] = [
+            ("hancoin_p2p_messages_received_total", "Gossip messages received per topic.", |t| t.received),
+            ("hancoin_p2p_bytes_received_total", "Gossip bytes received per topic.", |t| t.received_bytes),
+            ("hancoin_p2p_messages_accepted_total", "Gossip messages accepted per topic.", |t| t.accepted),
+            ("hancoin_p2p_messages_published_total", "Gossip messages published per topic.", |t| t.published),
+            ("hancoin_p2p_bytes_published_total", "Gossip bytes published per topic.", |t| t.published_bytes),
+        ];
+        for (name, help, value) in counters {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter", name, help, name);
+            for (topic, metrics) in &self.topics {
+                let _ = writeln!(out, "{}{{network=\"{}\",topic=\"{}\"}} {}", name, self.network, topic, value(metrics));
+            }
+        }
+        
+        let name = "hancoin_p2p_messages_rejected_total";
+        let _ = writeln!(out, "# HELP {} Gossip messages rejected per topic and reason.\n# TYPE {} counter", name, name);
+        for (topic, metrics) in &self.topics {
+            for (reason, count) in &metrics.rejected {
+                let _ = writeln!(
+                    out, "{}{{network=\"{}\",topic=\"{}\",reason=\"{}\"}} {}",
+                    name, self.network, topic, reason, count
+                );
+            }
+        }
+        out
+    }
+}
+
+/// 从PeerId中恢复公钥（ed25519等短公钥直接内嵌在PeerId中）
+fn peer_public_key(peer: &PeerId) -> Option<PublicKey> {
+    let multihash = peer.as_ref();
+    // identity multihash
+    if multihash.code() != 0 {
+        return None;
+    }
+    PublicKey::try_decode_protobuf(multihash.digest()).ok()
+}
+
+/// 收到消息的签名、时效和防重放检查
+///
+/// 已见过的消息按签名记录，只需保留时效窗口内的记录：更早的消息会先被时效检查拒绝
+struct MessageVerifier {
+    network: Network,
+    max_skew: u64,
+    capacity: usize,
+    seen: HashSet<[u8; 32]>,
+    order: VecDeque<(u64, [u8; 32])>,
+}
+
+impl MessageVerifier {
+    fn new(network: Network, max_skew: Duration, capacity: usize) -> Self {
+        Self {
+            network,
+            max_skew: max_skew.as_secs(),
+            capacity: capacity.max(1),
+            seen: HashSet::new(),
+            order: VecDeque::new(),
+        }
+    }
+    
+    /// 校验消息并返回载荷，`now`为当前Unix时间（秒）
+    fn verify(&mut self, author: &PeerId, message: &P2PMessage, now: u64) -> Result<P2PPayload, P2PError> {
+        if now.abs_diff(message.timestamp) > self.max_skew {
+            return Err(P2PError::StaleMessage { timestamp: message.timestamp, now });
+        }
+        
+        let public_key = peer_public_key(author).ok_or(P2PError::InvalidSignature(*author))?;
+        message.verify(&public_key, self.network).map_err(|_| P2PError::InvalidSignature(*author))?;
+        
+        let id = *blake3::hash(&message.signature).as_bytes();
+        if self.seen.contains(&id) {
+            return Err(P2PError::Replay);
+        }
+        let payload = message.payload()?;
+        
+        self.expire(now);
+        while self.order.len() >= self.capacity {
+            if let Some((_, oldest)) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        self.seen.insert(id);
+        self.order.push_back((message.timestamp, id));
+        Ok(payload)
+    }
+    
+    /// 移除时效窗口之外的记录
+    fn expire(&mut self, now: u64) {
+        let cutoff = now.saturating_sub(self.max_skew);
+        // 时间戳大致有序，从队首清理到第一个未过期的记录为止
+        while let Some((timestamp, id)) = self.order.front().copied() {
+            if timestamp >= cutoff {
+                break;
+            }
+            self.order.pop_front();
+            self.seen.remove(&id);
+        }
+    }
+}
+
+/// 单个节点的活动、评分和封禁记录
+#[derive(Clone, Debug)]
+struct PeerRecord {
+    last_seen: Instant,
+    /// 应用层评分
+    score: f64,
+    /// gossipsub综合评分（由维护周期同步）
+    gossipsub_score: Option<f64>,
+    accepted: u64,
+    rejected: u64,
+    throttled: u64,
+    banned_until: Option<Instant>,
+    /// 当前连接的远端地址
+    addresses: Vec<Multiaddr>,
+    /// ping往返时间
+    latency: Option<Duration>,
+    /// identify公布的协议版本和客户端版本
+    protocol_version: Option<String>,
+    agent_version: Option<String>,
+    /// identify确认的不兼容原因；不兼容的节点被拒绝连接但不计入封禁
+    incompatible: Option<String>,
+    /// 连接是否经过Tor
+    via_tor: bool,
+}
+
+impl PeerRecord {
+    fn new(now: Instant) -> Self {
+        Self {
+            last_seen: now,
+            score: 0.0,
+            gossipsub_score: None,
+            accepted: 0,
+            rejected: 0,
+            throttled: 0,
+            banned_until: None,
+            addresses: Vec::new(),
+            latency: None,
+            protocol_version: None,
+            agent_version: None,
+            incompatible: None,
+            via_tor: false,
+        }
+    }
+    
+    fn is_banned(&self, now: Instant) -> bool {
+        self.banned_until.is_some_and(|until| until > now)
+    }
+    
+    /// identify已确认对方与本节点兼容
+    fn is_compatible(&self) -> bool {
+        self.protocol_version.is_some() && self.incompatible.is_none()
+    }
+}
+
+/// 消息被拒绝的原因
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Rejection {
+    Oversized,
+    RateLimited,
+    UnknownTopic,
+    WrongTopic,
+    InvalidSignature,
+    Stale,
+    Replay,
+    Malformed,
+    InvalidPayload,
+}
+
+impl Rejection {
+    fn from_error(error: &P2PError) -> Self {
+        match error {
+            P2PError::InvalidSignature(_) => Rejection::InvalidSignature,
+            P2PError::StaleMessage { .. } => Rejection::Stale,
+            P2PError::Replay => Rejection::Replay,
+            _ => Rejection::Malformed,
+        }
+    }
+    
+    fn as_str(&self) -> &'static str {
+        match self {
+            Rejection::Oversized => "oversized",
+            Rejection::RateLimited => "rate_limited",
+            Rejection::UnknownTopic => "unknown_topic",
+            Rejection::WrongTopic => "wrong_topic",
+            Rejection::InvalidSignature => "invalid_signature",
+            Rejection::Stale => "stale",
+            Rejection::Replay => "replay",
+            Rejection::Malformed => "malformed",
+            Rejection::InvalidPayload => "invalid_payload",
+        }
+    }
+    
+    fn outcome(&self) -> ValidationOutcome {
+        match self {
+            Rejection::RateLimited => ValidationOutcome::Throttle,
+            _ => ValidationOutcome::Reject,
+        }
+    }
+}
+
+/// 消息验证结果
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ValidationOutcome {
+    /// 有效消息
+    Accept,
+    /// 无效消息（过大、无法解析或签名错误）
+    Reject,
+    /// 超出该节点的速率限制
+    Throttle,
+}
+
+/// 节点状态（管理视图）
+#[derive(Clone, Debug, Serialize)]
+pub struct PeerStatus {
+    pub peer_id: String,
+    pub connected: bool,
+    /// 应用层评分
+    pub score: f64,
+    /// gossipsub综合评分
+    pub gossipsub_score: Option<f64>,
+    pub accepted: u64,
+    pub rejected: u64,
+    pub throttled: u64,
+    /// 距上次活动的秒数
+    pub idle_secs: u64,
+    /// 剩余封禁秒数，未封禁时为`None`
+    pub banned_secs: Option<u64>,
+    /// 当前连接的远端地址
+    pub addresses: Vec<String>,
+    /// ping往返时间（毫秒）
+    pub latency_ms: Option<u64>,
+    pub protocol_version: Option<String>,
+    pub agent_version: Option<String>,
+    /// 不兼容原因（其他网络或不支持的协议版本）
+    pub incompatible: Option<String>,
+    /// 连接方式：`tor`或`direct`
+    pub transport: &'static str,
+}
+
+impl P2PState {
+    fn touch(&mut self, peer: PeerId, now: Instant) -> &mut PeerRecord {
+        let record = self.active_peers.entry(peer).or_insert_with(|| PeerRecord::new(now));
+        record.last_seen = now;
+        record
+    }
+    
+    fn is_banned(&self, peer: &PeerId, now: Instant) -> bool {
+        self.active_peers.get(peer).is_some_and(|record| record.is_banned(now))
+    }
+    
+    fn is_incompatible(&self, peer: &PeerId) -> bool {
+        self.active_peers.get(peer).is_some_and(|record| record.incompatible.is_some())
+    }
+    
+    fn is_compatible(&self, peer: &PeerId) -> bool {
+        self.active_peers.get(peer).is_some_and(PeerRecord::is_compatible)
+    }
+    
+    /// 根据验证结果调整评分，返回新的评分以及是否因此被封禁
+    fn record_validation(
+        &mut self,
+        peer: PeerId,
+        outcome: ValidationOutcome,
+        config: &PeerScoreConfig,
+        now: Instant,
+    ) -> (f64, bool) {
+        let record = self.touch(peer, now);
+        match outcome {
+            ValidationOutcome::Accept => {
+                record.accepted += 1;
+                record.score = (record.score + config.accept_reward).min(config.max_score);
+            }
+            ValidationOutcome::Reject => {
+                record.rejected += 1;
+                record.score -= config.reject_penalty;
+            }
+            ValidationOutcome::Throttle => {
+                record.throttled += 1;
+                record.score -= config.throttle_penalty;
+            }
+        }
+        
+        let newly_banned = record.score <= config.ban_threshold && !record.is_banned(now);
+        if newly_banned {
+            record.banned_until = Some(now + config.ban_duration);
+        }
+        (record.score, newly_banned)
+    }
+    
+    fn ban(&mut self, peer: PeerId, duration: Duration, now: Instant) {
+        self.touch(peer, now).banned_until = Some(now + duration);
+    }
+    
+    /// 解除封禁并清零评分
+    fn unban(&mut self, peer: &PeerId) -> bool {
+        match self.active_peers.get_mut(peer) {
+            Some(record) if record.banned_until.is_some() => {
+                record.banned_until = None;
+                record.score = 0.0;
+                true
+            }
+            _ => false,
+        }
+    }
+    
+    /// 评分衰减并返回封禁到期的节点
+    fn maintain(&mut self, config: &PeerScoreConfig, now: Instant) -> Vec<PeerId> {
+        let mut expired = Vec::new();
+        for (peer, record) in self.active_peers.iter_mut() {
+            if record.banned_until.is_some_and(|until| until <= now) {
+                record.banned_until = None;
+                record.score = 0.0;
+                expired.push(*peer);
+            }
+            if !record.is_banned(now) {
+                record.score *= config.decay;
+            }
+        }
+        expired
+    }
+    
+    /// 清理长时间不活跃的节点
+    ///
+    /// 被封禁的节点保留到封禁到期；评分为负的节点在保留期内保留，防止断线重连重置评分
+    fn prune(&mut self, timeout: Duration, config: &PeerScoreConfig, now: Instant) {
+        let connected = &self.connected_peers;
+        self.active_peers.retain(|peer, record| {
+            let idle = now.saturating_duration_since(record.last_seen);
+            connected.contains(peer)
+                || record.is_banned(now)
+                || (record.score < 0.0 && idle < config.negative_score_retention)
+                || idle < timeout
+        });
+    }
+    
+    /// 记录收到的消息，`topic`为主题类别名
+    fn record_received(&mut self, topic: &'static str, bytes: usize, rejection: Option<Rejection>) {
+        let metrics = self.topics.entry(topic).or_default();
+        metrics.received += 1;
+        metrics.received_bytes += bytes as u64;
+        match rejection {
+            Some(reason) => *metrics.rejected.entry(reason.as_str()).or_default() += 1,
+            None => metrics.accepted += 1,
+        }
+    }
+    
+    fn record_published(&mut self, topic: &'static str, bytes: usize) {
+        let metrics = self.topics.entry(topic).or_default();
+        metrics.published += 1;
+        metrics.published_bytes += bytes as u64;
+    }
+    
+    fn peer_status(&self, now: Instant) -> Vec<PeerStatus> {
+        let mut peers: Vec<PeerStatus> = self.active_peers.iter()
+            .map(|(peer, record)| PeerStatus {
+                peer_id: peer.to_string(),
+                connected: self.connected_peers.contains(peer),
+                score: record.score,
+                gossipsub_score: record.gossipsub_score,
+                accepted: record.accepted,
+                rejected: record.rejected,
+                throttled: record.throttled,
+                idle_secs: now.saturating_duration_since(record.last_seen).as_secs(),
+                banned_secs: record.banned_until
+                    .filter(|until| *until > now)
+                    .map(|until| (until - now).as_secs()),
+                addresses: record.addresses.iter().map(|a| a.to_string()).collect(),
+                latency_ms: record.latency.map(|l| l.as_millis() as u64),
+                protocol_version: record.protocol_version.clone(),
+                agent_version: record.agent_version.clone(),
+                incompatible: record.incompatible.clone(),
+                transport: if record.via_tor { "tor" } else { "direct" },
+            })
+            .collect();
+        peers.sort_by(|a, b| a.score.total_cmp(&b.score));
+        peers
+    }
+}
+
+/// P2P网络错误
+#[derive(Error, Debug)]
+pub enum P2PError {
+    #[error("P2P service stopped")]
+    ServiceStopped,
+    #[error("Message encoding failed: {0}")]
+    Encoding(String),
+    #[error("Invalid multiaddr: {0}")]
+    InvalidAddress(String),
+    #[error("Ledger sync failed: {0}")]
+    Sync(String),
+    #[error("Invalid message signature from {0}")]
+    InvalidSignature(PeerId),
+    #[error("Message timestamp {timestamp} outside allowed window (now {now})")]
+    StaleMessage { timestamp: u64, now: u64 },
+    #[error("Replayed message")]
+    Replay,
+    #[error("Dial failed: {0}")]
+    Dial(String),
+}
+
+/// 网络消息载荷
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum P2PPayload {
+    /// CoinJoin协调消息
+    CoinJoin(CoinJoinMessage),
+    /// 带发送方签名的转账交易
+    Transaction(SignedTransfer),
+    /// 带作者签名的动态消息
+    Moment(SignedMoment),
+}
+
+impl P2PPayload {
+    /// 载荷所属的主题类别
+    pub fn class(&self) -> TopicClass {
+        match self {
+            P2PPayload::CoinJoin(_) => TopicClass::CoinJoin,
+            P2PPayload::Transaction(_) => TopicClass::Transactions,
+            P2PPayload::Moment(_) => TopicClass::Moments,
+        }
+    }
+    
+    /// 按类别检查载荷内容：交易检查字段和发送方签名，动态检查长度和作者签名
+    pub fn validate(&self) -> Result<(), HancoinError> {
+        match self {
+            P2PPayload::CoinJoin(_) => Ok(()),
+            P2PPayload::Transaction(transfer) => transfer.verify(),
+            P2PPayload::Moment(moment) => moment.verify(),
+        }
+    }
+}
+
+/// 从网络接收到的事件
+#[derive(Debug, Clone)]
+pub enum P2PEvent {
+    /// 收到消息
+    Message {
+        /// 消息作者
+        source: PeerId,
+        /// 消息载荷
+        payload: P2PPayload,
+    },
+    /// 收到账本同步请求，需通过`P2PHandle::respond_sync`回复
+    SyncRequest {
+        /// 请求方
+        peer: PeerId,
+        /// 请求编号
+        id: u64,
+        /// 请求内容
+        request: SyncRequest,
+    },
+    /// 网络服务已停止，之后不会再有事件
+    Stopped,
+}
+
+/// 发往网络事件循环的命令
+enum P2PCommand {
+    /// 发送账本同步请求
+    SyncRequest {
+        peer: PeerId,
+        request: SyncRequest,
+        reply: oneshot::Sender<Result<SyncResponse, P2PError>>,
+    },
+    /// 回复账本同步请求
+    SyncRespond {
+        id: u64,
+        response: SyncResponse,
+    },
+    /// 封禁节点
+    Ban {
+        peer: PeerId,
+        duration: Duration,
+    },
+    /// 解除封禁
+    Unban(PeerId),
+    /// 拨号指定地址
+    Dial {
+        addr: Multiaddr,
+        reply: oneshot::Sender<Result<(), P2PError>>,
+    },
+    /// 断开与节点的所有连接
+    Disconnect {
+        peer: PeerId,
+        reply: oneshot::Sender<bool>,
+    },
+    /// 公布外部地址（如onion服务地址）
+    AddExternalAddress(Multiaddr),
+    /// 停止网络服务
+    Shutdown {
+        reply: oneshot::Sender<()>,
+    },
+}
+
+/// P2P网络句柄
+///
+/// 用于广播消息、订阅收到的消息、管理连接以及停止网络服务。
+/// 所有句柄都被丢弃或调用`shutdown`后网络服务停止
+#[derive(Clone)]
+pub struct P2PHandle {
+    local_peer_id: PeerId,
+    network: Network,
+    commands: mpsc::Sender<P2PCommand>,
+    /// 待广播的载荷，事件循环按主题优先级发送
+    outbound: mpsc::Sender<P2PPayload>,
+    events: broadcast::Sender<P2PEvent>,
+    state: Arc<Mutex<P2PState>>,
+    /// Tor健康状况，未启用Tor时为`None`
+    tor_health: Option<watch::Receiver<TorHealth>>,
+}
+
+impl P2PHandle {
+    /// 本节点的PeerId
+    pub fn local_peer_id(&self) -> PeerId {
+        self.local_peer_id
+    }
+    
+    /// 网络服务是否仍在运行
+    pub fn is_running(&self) -> bool {
+        !self.commands.is_closed()
+    }
+    
+    /// 拨号指定地址（可包含`/p2p/<PeerId>`），拨号发起后返回，连接结果通过节点列表查看
+    pub async fn dial(&self, addr: Multiaddr) -> Result<(), P2PError> {
+        let (reply, rx) = oneshot::channel();
+        self.commands.send(P2PCommand::Dial { addr, reply }).await
+            .map_err(|_| P2PError::ServiceStopped)?;
+        rx.await.map_err(|_| P2PError::ServiceStopped)?
+    }
+    
+    /// 断开与节点的连接，未连接时返回`false`
+    pub async fn disconnect(&self, peer: PeerId) -> Result<bool, P2PError> {
+        let (reply, rx) = oneshot::channel();
+        self.commands.send(P2PCommand::Disconnect { peer, reply }).await
+            .map_err(|_| P2PError::ServiceStopped)?;
+        rx.await.map_err(|_| P2PError::ServiceStopped)
+    }
+    
+    /// 公布外部可达地址，通过identify告知其他节点
+    pub async fn add_external_address(&self, addr: Multiaddr) -> Result<(), P2PError> {
+        self.commands.send(P2PCommand::AddExternalAddress(addr)).await
+            .map_err(|_| P2PError::ServiceStopped)
+    }
+    
+    /// 停止网络服务：停止监听、断开所有连接，完成后返回
+    pub async fn shutdown(&self) -> Result<(), P2PError> {
+        let (reply, rx) = oneshot::channel();
+        self.commands.send(P2PCommand::Shutdown { reply }).await
+            .map_err(|_| P2PError::ServiceStopped)?;
+        rx.await.map_err(|_| P2PError::ServiceStopped)
+    }
+    
+    /// 广播消息
+    pub async fn publish(&self, payload: P2PPayload) -> Result<(), P2PError> {
+        self.outbound.send(payload).await
+            .map_err(|_| P2PError::ServiceStopped)
+    }
+    
+    /// 订阅收到的消息
+    pub fn subscribe(&self) -> broadcast::Receiver<P2PEvent> {
+        self.events.subscribe()
+    }
+    
+    /// 向节点发送账本同步请求并等待回复
+    pub async fn request_sync(&self, peer: PeerId, request: SyncRequest) -> Result<SyncResponse, P2PError> {
+        let (reply, rx) = oneshot::channel();
+        self.commands.send(P2PCommand::SyncRequest { peer, request, reply }).await
+            .map_err(|_| P2PError::ServiceStopped)?;
+        rx.await.map_err(|_| P2PError::ServiceStopped)?
+    }
+    
+    /// 回复收到的账本同步请求
+    pub async fn respond_sync(&self, id: u64, response: SyncResponse) -> Result<(), P2PError> {
+        self.commands.send(P2PCommand::SyncRespond { id, response }).await
+            .map_err(|_| P2PError::ServiceStopped)
+    }
+    
+    /// 当前已连接的节点
+    pub fn connected_peers(&self) -> Vec<PeerId> {
+        self.state.lock().connected_peers.iter().copied().collect()
+    }
+    
+    /// 已连接且经identify确认兼容的节点，账本同步只向这些节点请求
+    pub fn compatible_peers(&self) -> Vec<PeerId> {
+        let state = self.state.lock();
+        state.connected_peers.iter()
+            .filter(|peer| state.is_compatible(peer))
+            .copied()
+            .collect()
+    }
+    
+    /// Tor连接健康状况，未启用Tor时为`None`
+    pub fn tor_health(&self) -> Option<TorHealth> {
+        self.tor_health.as_ref().map(|health| health.borrow().clone())
+    }
+    
+    /// 当前监听的地址
+    pub fn listen_addrs(&self) -> Vec<Multiaddr> {
+        self.state.lock().listen_addrs.clone()
+    }
+    
+    /// 所有已知节点的地址、延迟、评分和版本，按评分从低到高排序
+    pub fn peers(&self) -> Vec<PeerStatus> {
+        self.state.lock().peer_status(Instant::now())
+    }
+    
+    /// 当前被封禁的节点
+    pub fn banned_peers(&self) -> Vec<PeerStatus> {
+        self.peers().into_iter().filter(|p| p.banned_secs.is_some()).collect()
+    }
+    
+    /// 网络统计
+    pub fn metrics(&self) -> NetworkMetrics {
+        let state = self.state.lock();
+        let now = Instant::now();
+        NetworkMetrics {
+            local_peer_id: self.local_peer_id.to_string(),
+            network: self.network,
+            protocol_version: PROTOCOL_VERSION,
+            connected_peers: state.connected_peers.len(),
+            known_peers: state.active_peers.len(),
+            banned_peers: state.active_peers.values().filter(|r| r.is_banned(now)).count(),
+            messages_total: state.message_count,
+            last_message_secs: state.last_message_time.map(|t| now.saturating_duration_since(t).as_secs()),
+            topics: state.topics.clone(),
+        }
+    }
+    
+    /// 封禁节点并断开连接
+    pub async fn ban_peer(&self, peer: PeerId, duration: Duration) -> Result<(), P2PError> {
+        self.commands.send(P2PCommand::Ban { peer, duration }).await
+            .map_err(|_| P2PError::ServiceStopped)
+    }
+    
+    /// 解除节点封禁
+    pub async fn unban_peer(&self, peer: PeerId) -> Result<(), P2PError> {
+        self.commands.send(P2PCommand::Unban(peer)).await
+            .map_err(|_| P2PError::ServiceStopped)
+    }
+}
+
+/// gossipsub节点评分参数
+///
+/// 只使用无效消息和应用层评分两项，mesh投递相关的惩罚在小网络中容易误伤正常节点
+fn peer_score_params(routes: &HashMap<TopicClass, TopicRoute>) -> gossipsub::PeerScoreParams {
+    let mut params = gossipsub::PeerScoreParams {
+        app_specific_weight: 1.0,
+        ..Default::default()
+    };
+    for (class, route) in routes {
+        params.topics.insert(route.topic.hash(), gossipsub::TopicScoreParams {
+            topic_weight: class.priority(),
+            first_message_deliveries_weight: 0.0,
+            mesh_message_deliveries_weight: 0.0,
+            mesh_failure_penalty_weight: 0.0,
+            invalid_message_deliveries_weight: -10.0,
+            invalid_message_deliveries_decay: 0.9,
+            ..Default::default()
+        });
+    }
+    params
+}
+
+/// 序列化网络消息
+fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, P2PError> {
+    bincode::serde::encode_to_vec(value, bincode::config::standard())
+        .map_err(|e| P2PError::Encoding(e.to_string()))
+}
+
+/// 反序列化网络消息
+fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, P2PError> {
+    bincode::serde::decode_from_slice(bytes, bincode::config::standard())
+        .map(|(value, _)| value)
+        .map_err(|e| P2PError::Encoding(e.to_string()))
+}
+
+/// 启动优化的P2P网络
+pub async fn start_p2p(config: Option<P2PConfig>) -> Result<P2PHandle, Box<dyn Error>> {
+    let config = config.unwrap_or_default();
+    
+    // 1. 加载本地密钥和PeerId
+    let id_keys = load_node_identity(&config)?;
+    let peer_id = PeerId::from(id_keys.public());
+    info!("Starting P2P node with ID: {:?}", peer_id);
+    
+
+    // 2. 生成Noise握手配置（由节点身份认证）
+    let noise_config = NoiseConfig::new(&id_keys)
+        .map_err(|e| format!("Failed to generate Noise keys: {:?}", e))?;
+    
+    // 初始化P2P状态
+    let state = Arc::new(Mutex::new(P2PState::default()));
+
+    // 3. 构建优化的传输层，支持Tor
+    let tor_connector = match (&config.tor_connector, config.tor_config.enabled) {
+        (Some(connector), true) => Some(connector.clone()),
+        (None, true) => Some(TorConnector::from_config(config.tor_config.clone())?),
+        (_, false) => None,
+    };
+    if tor_connector.is_some() {
+        info!("启用Tor网络连接: {:?}", config.tor_config.backend);
+    }
+    let transport = {
+        // WebSocket叠加在同样的基础传输上，处理带/ws的地址，其余地址走TCP
+        let websocket = libp2p::websocket::Config::new(base_transport(tor_connector.as_ref()));
+        let transports = websocket.or_transport(base_transport(tor_connector.as_ref()));
+        
+        transports.upgrade(upgrade::Version::V1)
+            .authenticate(noise_config) // Noise握手验证远程PeerId
+            .multiplex(YamuxConfig::default())
+            .timeout(Duration::from_secs(10)) // 添加超时
+            .boxed()
+    };
+
+    // 4. 配置优化的gossipsub
+    let gossipsub_config = GossipsubConfigBuilder::default()
+        .max_transmit_size(config.max_message_size)
+        .validation_mode(gossipsub::ValidationMode::Strict) // 使用Strict验证模式
+        .validate_messages() // 由事件循环验证后再转发
+        .flood_publish(true)
+        .message_id_fn(|message| {
+            // 使用更安全的消息ID生成
+            let mut hasher = blake3::Hasher::new();
+            hasher.update(&message.source.as_ref().map(|p| p.to_bytes()).unwrap_or_default());
+            hasher.update(&message.data);
+            hasher.update(&message.sequence_number.unwrap_or_default().to_be_bytes());
+            gossipsub::MessageId::from(hasher.finalize().as_bytes()[..32].to_vec())
+        })
+        .build()
+        .expect("Failed to build Gossipsub config");
+
+    let mut gossipsub: Gossipsub = Gossipsub::new(
+        MessageAuthenticity::Signed(id_keys.clone()),
+        gossipsub_config,
+    )
+    .expect("Failed to create Gossipsub");
+
+    // 订阅各类主题，主题名按网络和版本区分
+    let network = config.network;
+    let kad_protocol = network.kad_protocol();
+    let mut routes: HashMap<TopicClass, TopicRoute> = HashMap::new();
+    for class in TopicClass::ALL {
+        if class.is_social() && !config.enable_social {
+            info!("Social topics disabled, not subscribing to {}", class.name());
+            continue;
+        }
+        let limits = config.topic_limits.get(&class).cloned().unwrap_or_else(|| class.default_limits());
+        let rate_limit = NonZeroU32::new(limits.rate_limit).unwrap_or(nonzero!(1u32));
+        let topic = IdentTopic::new(network.topic(class.name()));
+        gossipsub.subscribe(&topic).expect("Failed to subscribe to topic");
+        routes.insert(class, TopicRoute {
+            topic,
+            limits,
+            limiter: RateLimiter::keyed(Quota::per_second(rate_limit)),
+        });
+    }
+    let classes: HashMap<gossipsub::TopicHash, TopicClass> = routes.iter()
+        .map(|(class, route)| (route.topic.hash(), *class))
+        .collect();
+    
+    // 启用节点评分：无效消息降低评分，应用层评分由验证结果决定
+    gossipsub.with_peer_score(peer_score_params(&routes), gossipsub::PeerScoreThresholds::default())
+        .map_err(|e| format!("Invalid peer score params: {}", e))?;
+
+    // 节点发现
+    let kademlia = if config.enable_kademlia {
+        let mut kad_config = kad::Config::new(kad_protocol.clone());
+        kad_config.set_query_timeout(Duration::from_secs(30));
+        let mut kademlia = kad::Behaviour::with_config(
+            peer_id,
+            kad::store::MemoryStore::new(peer_id),
+            kad_config,
+        );
+        kademlia.set_mode(Some(kad::Mode::Server));
+        for addr in &config.bootstrap_peers {
+            if let Some(bootstrap_id) = multiaddr_peer_id(addr) {
+                kademlia.add_address(&bootstrap_id, addr.clone());
+            }
+        }
+        Some(kademlia)
+    } else {
+        None
+    };
+    
+    let mdns_enabled = config.enable_mdns && !config.tor_config.enabled;
+    let mdns = if mdns_enabled {
+        Some(mdns::tokio::Behaviour::new(mdns::Config::default(), peer_id)?)
+    } else {
+        if config.enable_mdns {
+            info!("Tor已启用，关闭mDNS局域网节点发现");
+        }
+        None
+    };
+    
+    let identify = identify::Behaviour::new(
+        identify::Config::new(network.protocol_version(), id_keys.public())
+            .with_agent_version(format!("hancoin/{}", env!("CARGO_PKG_VERSION"))),
+    );
+    let ping = ping::Behaviour::new(ping::Config::new());
+    let sync = request_response::cbor::Behaviour::new(
+        [(network.sync_protocol(), ProtocolSupport::Full)],
+        request_response::Config::default().with_request_timeout(SYNC_REQUEST_TIMEOUT),
+    );
+
+    // 5. 构建优化的Swarm
+    let mut swarm = {
+        let limits = ConnectionLimits::default()
+            .with_max_established_incoming(Some(config.max_connections))
+            .with_max_established_outgoing(Some(config.max_connections));
+        let behaviour = HancoinBehaviour {
+            limits: connection_limits::Behaviour::new(limits),
+            gossipsub,
+            kademlia: Toggle::from(kademlia),
+            mdns: Toggle::from(mdns),
+            identify,
+            ping,
+            sync,
+        };
+        let swarm_config = libp2p::swarm::Config::with_tokio_executor()
+            .with_idle_connection_timeout(config.peer_timeout)
+            .with_dial_concurrency_factor(nonzero!(4u8))  // 增加并发拨号数
+            .with_notify_handler_buffer_size(nonzero!(32usize))  // 增加通知缓冲区大小
+            .with_max_negotiating_inbound_streams(8)  // 增加最大协商入站流
+            .with_per_connection_event_buffer_size(64);  // 增加连接事件缓冲区大小
+        
+        Swarm::new(transport, behaviour, peer_id, swarm_config)
+    };
+
+    // 监听配置的地址，严格Tor模式下只监听本机，入站连接只能经由onion服务转发
+    let strict_tor = config.tor_config.is_strict();
+    let onion_target = config.onion_service_target();
+    let allow_private_addrs = config.allow_private_addrs || mdns_enabled;
+    let mut listeners = Vec::with_capacity(config.listen_addrs.len());
+    for addr in &config.listen_addrs {
+        let addr = if strict_tor { loopback_listen_addr(addr) } else { addr.clone() };
+        listeners.push(swarm.listen_on(addr)?);
+    }
+    
+    // 引导节点
+    let mut bootstrapper = Bootstrapper::new(
+        &config.bootstrap_peers,
+        config.reconnect.clone(),
+        &config.tor_config,
+        Instant::now(),
+    );
+    let mut dial_check = tokio::time::interval(config.reconnect.check_interval);
+    let mut discovery = tokio::time::interval(config.discovery_interval);
+    let min_peers = config.min_peers;
+    let mut verifier = MessageVerifier::new(network, config.max_clock_skew, config.replay_cache_size);
+    let scoring = config.peer_scoring.clone();
+    let tor_enabled = config.tor_config.enabled;
+    let mut maintenance = tokio::time::interval(scoring.maintenance_interval);
+    let mut cleanup = tokio::time::interval(Duration::from_secs(60));
+    let peer_timeout = config.peer_timeout;
+    let mut shutdown_reply: Option<oneshot::Sender<()>> = None;
+    
+    // 进行中的同步请求
+    let mut pending_sync: HashMap<OutboundRequestId, oneshot::Sender<Result<SyncResponse, P2PError>>> = HashMap::new();
+    // 同步服务未在超时前回复的请求在清理时丢弃，请求方届时也已超时
+    let mut inbound_sync: HashMap<u64, (Instant, ResponseChannel<SyncResponse>)> = HashMap::new();
+    let mut next_inbound_id: u64 = 0;
+
+    // 6. 优化的事件循环
+    let (command_tx, mut command_rx) = mpsc::channel::<P2PCommand>(256);
+    let (outbound_tx, mut outbound_rx) = mpsc::channel::<P2PPayload>(256);
+    let (event_tx, _) = broadcast::channel::<P2PEvent>(1024);
+    // Tor不可用时暂停拨号，恢复后继续
+    let mut tor_watch = tor_connector.map(spawn_tor_monitor);
+    let mut dials_paused = tor_watch.as_ref().is_some_and(|w| w.borrow().status != TorNetworkStatus::Connected);
+    
+    let handle = P2PHandle {
+        local_peer_id: peer_id,
+        network,
+        commands: command_tx,
+        outbound: outbound_tx,
+        events: event_tx.clone(),
+        state: state.clone(),
+        tor_health: tor_watch.clone(),
+    };
+    
+    let state_clone = state.clone();
+    tokio::spawn(async move {
+        loop {
+            let event = tokio::select! {
+                event = swarm.next() => event,
+                Ok(()) = async {
+                    match tor_watch.as_mut() {
+                        Some(watch) => watch.changed().await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    let status = tor_watch.as_ref().map_or(TorNetworkStatus::Connected, |w| w.borrow().status);
+                    let paused = status != TorNetworkStatus::Connected;
+                    if paused && !dials_paused {
+                        warn!("Tor is {:?}, pausing outbound dials", status);
+                    } else if !paused && dials_paused {
+                        info!("Tor is available again, resuming outbound dials");
+                        bootstrapper.resume(Instant::now());
+                    }
+                    dials_paused = paused;
+                    continue;
+                }
+                _ = dial_check.tick() => {
+                    // 连接数不足时拨号引导节点，Tor不可用时跳过
+                    if !dials_paused && swarm.connected_peers().count() < min_peers {
+                        for (index, addr) in bootstrapper.due(Instant::now()) {
+                            let opts = match multiaddr_peer_id(&addr) {
+                                Some(peer_id) => DialOpts::peer_id(peer_id).addresses(vec![addr.clone()]).build(),
+                                None => DialOpts::unknown_peer_id().address(addr.clone()).build(),
+                            };
+                            let connection_id = opts.connection_id();
+                            match swarm.dial(opts) {
+                                Ok(()) => {
+                                    debug!("Dialing bootstrap peer {}", addr);
+                                    bootstrapper.dialing(index, connection_id);
+                                }
+                                Err(e) => {
+                                    debug!("Failed to dial bootstrap peer {}: {}", addr, e);
+                                    bootstrapper.on_dial_error(index, Instant::now());
+                                }
+                            }
+                        }
+                    }
+                    continue;
+                }
+                _ = discovery.tick() => {
+                    // Kademlia随机游走，发现更多节点；Tor不可用时跳过，避免经失效的代理反复拨号
+                    if dials_paused {
+                        continue;
+                    }
+                    let below_target = swarm.connected_peers().count() < min_peers;
+                    if let Some(kademlia) = swarm.behaviour_mut().kademlia.as_mut() {
+                        if below_target {
+                            kademlia.get_closest_peers(PeerId::random());
+                        }
+                        let _ = kademlia.bootstrap();
+                    }
+                    continue;
+                }
+                _ = maintenance.tick() => {
+                    // 评分衰减、同步gossipsub评分、解除到期的封禁
+                    let now = Instant::now();
+                    let expired = {
+                        let mut state = state_clone.lock();
+                        let expired = state.maintain(&scoring, now);
+                        for (peer, record) in state.active_peers.iter_mut() {
+                            record.gossipsub_score = swarm.behaviour().gossipsub.peer_score(peer);
+                            swarm.behaviour_mut().gossipsub.set_application_score(peer, record.score);
+                        }
+                        expired
+                    };
+                    for peer in expired {
+                        info!("Ban expired for peer {}", peer);
+                        swarm.behaviour_mut().gossipsub.remove_blacklisted_peer(&peer);
+                    }
+                    for route in routes.values() {
+                        route.limiter.retain_recent();
+                    }
+                    continue;
+                }
+                _ = cleanup.tick() => {
+                    // 清理不活跃的对等节点和未回复的同步请求
+                    inbound_sync.retain(|_, (received, _)| received.elapsed() < SYNC_REQUEST_TIMEOUT);
+                    let mut state = state_clone.lock();
+                    state.prune(peer_timeout, &scoring, Instant::now());
+                    debug!("Active peers: {}, Total messages: {}", 
+                          state.active_peers.len(), state.message_count);
+                    continue;
+                }
+                Some(payload) = outbound_rx.recv() => {
+                    // 取出已排队的载荷，按主题优先级从高到低发送
+                    let mut batch = vec![payload];
+                    while batch.len() < 64 {
+                        match outbound_rx.try_recv() {
+                            Ok(payload) => batch.push(payload),
+                            Err(_) => break,
+                        }
+                    }
+                    batch.sort_by(|a, b| b.class().priority().total_cmp(&a.class().priority()));
+                    for payload in batch {
+                        let Some(route) = routes.get(&payload.class()) else {
+                            debug!("Not publishing {} message: topic disabled", payload.class().name());
+                            continue;
+                        };
+                        let data = P2PMessage::from_payload(&payload, network, &id_keys)
+                            .and_then(|msg| encode(&msg));
+                        match data {
+                            Ok(data) if data.len() > route.limits.max_message_size => {
+                                warn!("Not publishing {} message: {} bytes exceeds topic limit", payload.class().name(), data.len());
+                            }
+                            Ok(data) => {
+                                let len = data.len();
+                                match swarm.behaviour_mut().gossipsub.publish(route.topic.clone(), data) {
+                                    Ok(_) => state_clone.lock().record_published(payload.class().name(), len),
+                                    Err(e) => warn!("Failed to publish P2P message: {:?}", e),
+                                }
+                            }
+                            Err(e) => warn!("Failed to encode P2P message: {}", e),
+                        }
+                    }
+                    continue;
+                }
+                command = command_rx.recv() => {
+                    match command {
+                        Some(P2PCommand::SyncRequest { peer, request, reply }) => {
+                            let request_id = swarm.behaviour_mut().sync.send_request(&peer, request);
+                            pending_sync.insert(request_id, reply);
+                        }
+                        Some(P2PCommand::SyncRespond { id, response }) => {
+                            match inbound_sync.remove(&id) {
+                                Some((_, channel)) => {
+                                    if swarm.behaviour_mut().sync.send_response(channel, response).is_err() {
+                                        debug!("Sync requester disconnected before response {}", id);
+                                    }
+                                }
+                                None => debug!("Unknown sync request {}", id),
+                            }
+                        }
+                        Some(P2PCommand::Ban { peer, duration }) => {
+                            warn!("Banning peer {} for {:?}", peer, duration);
+                            state_clone.lock().ban(peer, duration, Instant::now());
+                            swarm.behaviour_mut().gossipsub.blacklist_peer(&peer);
+                            let _ = swarm.disconnect_peer_id(peer);
+                        }
+                        Some(P2PCommand::Unban(peer)) => {
+                            if state_clone.lock().unban(&peer) {
+                                info!("Unbanned peer {}", peer);
+                                swarm.behaviour_mut().gossipsub.remove_blacklisted_peer(&peer);
+                                swarm.behaviour_mut().gossipsub.set_application_score(&peer, 0.0);
+                            }
+                        }
+                        Some(P2PCommand::Dial { addr, reply }) => {
+                            let opts = match multiaddr_peer_id(&addr) {
+                                Some(peer) => DialOpts::peer_id(peer).addresses(vec![addr.clone()]).build(),
+                                None => DialOpts::unknown_peer_id().address(addr.clone()).build(),
+                            };
+                            let result = if dials_paused {
+                                Err(P2PError::Dial("Tor is unavailable".to_string()))
+                            } else if multiaddr_peer_id(&addr).is_some_and(|p| state_clone.lock().is_banned(&p, Instant::now())) {
+                                Err(P2PError::Dial("peer is banned".to_string()))
+                            } else {
+                                swarm.dial(opts).map_err(|e| P2PError::Dial(e.to_string()))
+                            };
+                            let _ = reply.send(result);
+                        }
+                        Some(P2PCommand::Disconnect { peer, reply }) => {
+                            let _ = reply.send(swarm.disconnect_peer_id(peer).is_ok());
+                        }
+                        Some(P2PCommand::AddExternalAddress(addr)) => {
+                            info!("Announcing external address {}", addr);
+                            swarm.add_external_address(addr);
+                        }
+                        Some(P2PCommand::Shutdown { reply }) => {
+                            info!("Shutting down P2P network");
+                            shutdown_reply = Some(reply);
+                            break;
+                        }
+                        None => {
+                            info!("All P2P handles dropped, stopping network loop");
+                            break;
+                        }
+                    }
+                    continue;
+                }
+            };
+            
+            match event {
+                Some(SwarmEvent::Behaviour(HancoinBehaviourEvent::Gossipsub(GossipsubEvent::Message { 
+                    propagation_source,
+                    message_id,
+                    message,
+                }))) => {
+                    // 验证消息：主题、大小、该节点在该主题的速率、作者签名、时效和重放
+                    let source = message.source.unwrap_or(propagation_source);
+                    let class = classes.get(&message.topic).copied();
+                    let route = class.and_then(|class| routes.get(&class));
+                    let result = if let Some(route) = route.filter(|r| message.data.len() > r.limits.max_message_size) {
+                        warn!("Rejected oversized {} message from {}: {} bytes", route.topic, propagation_source, message.data.len());
+                        Err(Rejection::Oversized)
+                    } else if let Some(route) = route.filter(|r| r.limiter.check_key(&propagation_source).is_err()) {
+                        debug!("Rate limit on {} exceeded by {}", route.topic, propagation_source);
+                        Err(Rejection::RateLimited)
+                    } else if route.is_none() {
+                        debug!("Message from {} on unknown topic {}", propagation_source, message.topic);
+                        Err(Rejection::UnknownTopic)
+                    } else {
+                        let now_secs = SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap_or_default()
+                            .as_secs();
+                        match decode::<P2PMessage>(&message.data).and_then(|msg| verifier.verify(&source, &msg, now_secs)) {
+                            Ok(payload) if Some(payload.class()) != class => {
+                                warn!("Received {} message from {} on the wrong topic", payload.class().name(), propagation_source);
+                                Err(Rejection::WrongTopic)
+                            }
+                            Ok(payload) => match payload.validate() {
+                                Ok(()) => Ok(payload),
+                                Err(e) => {
+                                    warn!("Received invalid {} payload from {}: {}", payload.class().name(), propagation_source, e);
+                                    Err(Rejection::InvalidPayload)
+                                }
+                            },
+                            Err(e) => {
+                                warn!("Received invalid P2P message from {}: {}", propagation_source, e);
+                                Err(Rejection::from_error(&e))
+                            }
+                        }
+                    };
+                    let outcome = match &result {
+                        Ok(_) => ValidationOutcome::Accept,
+                        Err(rejection) => rejection.outcome(),
+                    };
+                    
+                    // 更新状态、统计和评分
+                    let now = Instant::now();
+                    let (score, newly_banned) = {
+                        let mut state = state_clone.lock();
+                        state.message_count += 1;
+                        state.last_message_time = Some(now);
+                        let topic = class.map_or("unknown", |c| c.name());
+                        state.record_received(topic, message.data.len(), result.as_ref().err().copied());
+                        state.record_validation(propagation_source, outcome, &scoring, now)
+                    };
+                    
+                    let gossipsub = &mut swarm.behaviour_mut().gossipsub;
+                    let acceptance = match outcome {
+                        ValidationOutcome::Accept => gossipsub::MessageAcceptance::Accept,
+                        ValidationOutcome::Reject => gossipsub::MessageAcceptance::Reject,
+                        // 超速的消息不转发，但不计为无效消息
+                        ValidationOutcome::Throttle => gossipsub::MessageAcceptance::Ignore,
+                    };
+                    gossipsub.report_message_validation_result(&message_id, &propagation_source, acceptance);
+                    gossipsub.set_application_score(&propagation_source, score);
+                    
+                    if newly_banned {
+                        warn!("Banning peer {} for {:?} (score {:.1})", propagation_source, scoring.ban_duration, score);
+                        gossipsub.blacklist_peer(&propagation_source);
+                        let _ = swarm.disconnect_peer_id(propagation_source);
+                    }
+                    
+                    if let Ok(payload) = result {
+                        debug!("Received valid P2P message from {:?}: {:?}", source, payload);
+                        // 没有订阅者时忽略
+                        let _ = event_tx.send(P2PEvent::Message { source, payload });
+                    }
+                },
+                Some(SwarmEvent::Behaviour(HancoinBehaviourEvent::Mdns(mdns::Event::Discovered(peers)))) => {
+                    // 局域网发现的节点加入路由表并建立连接，gossipsub会将其纳入mesh
+                    for (peer, addr) in peers {
+                        debug!("mDNS discovered peer {} at {}", peer, addr);
+                        if let Some(kademlia) = swarm.behaviour_mut().kademlia.as_mut() {
+                            kademlia.add_address(&peer, addr.clone());
+                        }
+                        if !swarm.is_connected(&peer) {
+                            let opts = DialOpts::peer_id(peer).addresses(vec![addr]).build();
+                            if let Err(e) = swarm.dial(opts) {
+                                debug!("Failed to dial mDNS peer {}: {}", peer, e);
+                            }
+                        }
+                    }
+                },
+                Some(SwarmEvent::Behaviour(HancoinBehaviourEvent::Mdns(mdns::Event::Expired(peers)))) => {
+                    for (peer, addr) in peers {
+                        debug!("mDNS peer expired: {} at {}", peer, addr);
+                        if let Some(kademlia) = swarm.behaviour_mut().kademlia.as_mut() {
+                            kademlia.remove_address(&peer, &addr);
+                        }
+                    }
+                },
+                Some(SwarmEvent::Behaviour(HancoinBehaviourEvent::Identify(identify::Event::Received { peer_id, info, .. }))) => {
+                    // 拒绝其他网络或不兼容版本的节点：只断开连接，不影响评分和封禁
+                    let compatible = network.check_compatible(&info.protocol_version);
+                    {
+                        let mut state = state_clone.lock();
+                        let record = state.touch(peer_id, Instant::now());
+                        record.protocol_version = Some(info.protocol_version.clone());
+                        record.agent_version = Some(info.agent_version.clone());
+                        record.incompatible = compatible.as_ref().err().cloned();
+                    }
+                    if let Err(reason) = compatible {
+                        warn!("Refusing incompatible peer {} ({}): {}", peer_id, info.agent_version, reason);
+                        bootstrapper.forget(peer_id);
+                        if let Some(kademlia) = swarm.behaviour_mut().kademlia.as_mut() {
+                            kademlia.remove_peer(&peer_id);
+                        }
+                        let _ = swarm.disconnect_peer_id(peer_id);
+                        continue;
+                    }
+                    
+                    // 对方支持Kademlia时将其监听地址加入路由表
+                    if info.protocols.contains(&kad_protocol) {
+                        if let Some(kademlia) = swarm.behaviour_mut().kademlia.as_mut() {
+                            for addr in info.listen_addrs {
+                                // 严格Tor模式下不传播明网地址
+                                if strict_tor && !is_onion_multiaddr(&addr) {
+                                    continue;
+                                }
+                                // 非本地模式下不传播回环和内网地址
+                                if !allow_private_addrs && !is_global_multiaddr(&addr) {
+                                    continue;
+                                }
+                                kademlia.add_address(&peer_id, addr);
+                            }
+                        }
+                    }
+                },
+                Some(SwarmEvent::Behaviour(HancoinBehaviourEvent::Kademlia(kad::Event::OutboundQueryProgressed {
+                    result: kad::QueryResult::GetClosestPeers(Ok(result)),
+                    ..
+                }))) => {
+                    // 连接数不足时拨号Kademlia发现的节点
+                    let missing = min_peers.saturating_sub(swarm.connected_peers().count());
+                    let missing = if dials_paused { 0 } else { missing };
+                    for peer in result.peers.into_iter().filter(|p| p.peer_id != peer_id).take(missing) {
+                        if !swarm.is_connected(&peer.peer_id) {
+                            let opts = DialOpts::peer_id(peer.peer_id).addresses(peer.addrs).build();
+                            if let Err(e) = swarm.dial(opts) {
+                                debug!("Failed to dial discovered peer {}: {}", peer.peer_id, e);
+                            }
+                        }
+                    }
+                },
+                Some(SwarmEvent::Behaviour(HancoinBehaviourEvent::Kademlia(kad::Event::RoutingUpdated { peer, .. }))) => {
+                    debug!("Kademlia routing table updated with {}", peer);
+                },
+                Some(SwarmEvent::Behaviour(HancoinBehaviourEvent::Sync(request_response::Event::Message { peer, message, .. }))) => {
+                    match message {
+                        request_response::Message::Request { request, channel, .. } => {
+                            // identify确认兼容之前不提供同步数据，丢弃通道后请求方会收到错误
+                            if !state_clone.lock().is_compatible(&peer) {
+                                debug!("Ignoring sync request from unidentified or incompatible peer {}", peer);
+                                continue;
+                            }
+                            let id = next_inbound_id;
+                            next_inbound_id += 1;
+                            // 没有同步服务时直接丢弃，请求方会收到错误
+                            if event_tx.send(P2PEvent::SyncRequest { peer, id, request }).is_ok() {
+                                inbound_sync.insert(id, (Instant::now(), channel));
+                            }
+                        }
+                        request_response::Message::Response { request_id, response } => {
+                            if let Some(reply) = pending_sync.remove(&request_id) {
+                                let _ = reply.send(Ok(response));
+                            }
+                        }
+                    }
+                },
+                Some(SwarmEvent::Behaviour(HancoinBehaviourEvent::Sync(request_response::Event::OutboundFailure { peer, request_id, error, .. }))) => {
+                    debug!("Sync request to {} failed: {}", peer, error);
+                    if let Some(reply) = pending_sync.remove(&request_id) {
+                        let _ = reply.send(Err(P2PError::Sync(error.to_string())));
+                    }
+                },
+                Some(SwarmEvent::Behaviour(HancoinBehaviourEvent::Sync(request_response::Event::InboundFailure { peer, error, .. }))) => {
+                    debug!("Sync request from {} failed: {}", peer, error);
+                },
+                Some(SwarmEvent::Behaviour(HancoinBehaviourEvent::Ping(ping::Event { peer, result: Ok(rtt), .. }))) => {
+                    if let Some(record) = state_clone.lock().active_peers.get_mut(&peer) {
+                        record.latency = Some(rtt);
+                    }
+                },
+                Some(SwarmEvent::NewListenAddr { address, .. }) => {
+                    info!("Listening on {:?}", address);
+                    state_clone.lock().listen_addrs.push(address);
+                },
+                Some(SwarmEvent::ExpiredListenAddr { address, .. }) => {
+                    state_clone.lock().listen_addrs.retain(|a| a != &address);
+                },
+                Some(SwarmEvent::ConnectionEstablished { peer_id, connection_id, endpoint, .. }) => {
+                    let now = Instant::now();
+                    if state_clone.lock().is_banned(&peer_id, now) {
+                        debug!("Dropping connection from banned peer {}", peer_id);
+                        let _ = swarm.disconnect_peer_id(peer_id);
+                        continue;
+                    }
+                    if state_clone.lock().is_incompatible(&peer_id) {
+                        debug!("Dropping connection from incompatible peer {}", peer_id);
+                        let _ = swarm.disconnect_peer_id(peer_id);
+                        continue;
+                    }
+                    if strict_tor && !is_tor_endpoint(&endpoint, onion_target) {
+                        error!("Refusing clearnet connection {:?} with {} in onion-only mode", endpoint, peer_id);
+                        let _ = swarm.disconnect_peer_id(peer_id);
+                        continue;
+                    }
+                    info!("Connected to peer: {:?}", peer_id);
+                    bootstrapper.on_connected(connection_id, peer_id);
+                    let mut state = state_clone.lock();
+                    let record = state.touch(peer_id, now);
+                    let address = endpoint.get_remote_address().clone();
+                    record.via_tor = is_onion_multiaddr(&address) || (tor_enabled && endpoint.is_dialer());
+                    if !record.addresses.contains(&address) {
+                        record.addresses.push(address);
+                    }
+                    state.connected_peers.insert(peer_id);
+                },
+                Some(SwarmEvent::ConnectionClosed { peer_id, cause, num_established, endpoint, .. }) => {
+                    info!("Disconnected from peer: {:?}, cause: {:?}", peer_id, cause);
+                    let mut state = state_clone.lock();
+                    if let Some(record) = state.active_peers.get_mut(&peer_id) {
+                        record.addresses.retain(|a| a != endpoint.get_remote_address());
+                    }
+                    if num_established == 0 {
+                        bootstrapper.on_disconnected(peer_id, Instant::now());
+                        state.connected_peers.remove(&peer_id);
+                    }
+                },
+                Some(SwarmEvent::OutgoingConnectionError { peer_id, connection_id, error }) => {
+                    warn!("Failed to connect to peer {:?}: {:?}", peer_id, error);
+                    bootstrapper.on_failed(connection_id, Instant::now());
+                },
+                Some(SwarmEvent::IncomingConnectionError { error, .. }) => {
+                    warn!("Incoming connection error: {:?}", error);
+                },
+                Some(_) => {},
+                None => {
+                    // 处理None情况，可能是连接已关闭
+                    warn!("Swarm stream returned None, connection may be closed");
+                    break;
+                }
+            }
+        }
+        
+        // 停止监听并断开所有连接，等待连接关闭后再退出
+        for listener in listeners {
+            swarm.remove_listener(listener);
+        }
+        let peers: Vec<PeerId> = swarm.connected_peers().copied().collect();
+        for peer in peers {
+            let _ = swarm.disconnect_peer_id(peer);
+        }
+        let _ = tokio::time::timeout(Duration::from_secs(5), async {
+            while swarm.connected_peers().next().is_some() {
+                if swarm.next().await.is_none() {
+                    break;
+                }
+            }
+        }).await;
+        
+        // 未完成的同步请求随通道关闭返回ServiceStopped
+        pending_sync.clear();
+        inbound_sync.clear();
+        state_clone.lock().connected_peers.clear();
+        command_rx.close();
+        outbound_rx.close();
+        let _ = event_tx.send(P2PEvent::Stopped);
+        info!("P2P network stopped");
+        if let Some(reply) = shutdown_reply {
+            let _ = reply.send(());
+        }
+    });
+
+    Ok(handle)
+}
+
+/// 优化的P2P消息结构
+#[derive(Serialize, Deserialize, Debug)]
+pub struct P2PMessage {
+    pub version: u8,
+    pub timestamp: u64,
+    pub payload: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+impl P2PMessage {
+    pub fn new(payload: Vec<u8>) -> Self {
+        Self {
+            version: 1,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            payload,
+            signature: Vec::new(),
+        }
+    }
+    
+    /// 由类型化载荷创建并签名消息
+    pub fn from_payload(payload: &P2PPayload, network: Network, keypair: &Keypair) -> Result<Self, P2PError> {
+        let mut msg = Self::new(encode(payload)?);
+        msg.sign(keypair, network).map_err(|e| P2PError::Encoding(e.to_string()))?;
+        Ok(msg)
+    }
+    
+    /// 解析消息载荷
+    pub fn payload(&self) -> Result<P2PPayload, P2PError> {
+        decode(&self.payload)
+    }
+    
+    /// 待签名数据；包含网络标识，签名不能在其他网络重放
+    fn signing_bytes(&self, network: Network) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut data = network.as_str().as_bytes().to_vec();
+        data.push(0);
+        data.extend(encode(&self.payload)?);
+        data.extend(self.timestamp.to_be_bytes());
+        Ok(data)
+    }
+    
+    pub fn sign(&mut self, keypair: &Keypair, network: Network) -> Result<(), Box<dyn Error>> {
+        let data = self.signing_bytes(network)?;
+        
+        // 使用libp2p内置方法进行签名
+        let signature = keypair.sign(&data)?;
+        self.signature = signature;
+        Ok(())
+    }
+    
+    pub fn verify(&self, public_key: &PublicKey, network: Network) -> Result<(), Box<dyn Error>> {
+        let data = self.signing_bytes(network)?;
+        
+        if !public_key.verify(&data, &self.signature) {
+            return Err("Signature verification failed".into());
+        }
+        Ok(())
+    }
+}
+
+/// 账本同步请求
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum SyncRequest {
+    /// 获取最新快照清单
+    Manifest,
+    /// 获取指定状态根下的快照分块（账户、交易和动态都在快照中）
+    Chunk { state_root: String, index: u32 },
+    /// 获取(时间戳, ID)在给定位置之后的带签名转账，用于快照之后的追赶
+    Transfers { after_timestamp: u64, after_id: String, limit: u32 },
+}
+
+/// 账本同步回复
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum SyncResponse {
+    Manifest(SnapshotManifest),
+    Chunk(SnapshotChunk),
+    /// 按(时间戳, ID)排序的带签名转账，接收方逐笔重新验证后记账
+    Transfers(Vec<SignedTransfer>),
+    /// 超出对方的带宽限制，稍后重试或换一个节点
+    RateLimited,
+    /// 请求无法满足（未知状态根、分块序号越界等）
+    Error(String),
+}
+
+/// 账本同步配置
+#[derive(Clone, Debug)]
+pub struct SyncConfig {
+    /// 每个快照分块包含的账户、交易或动态数
+    pub chunk_size: usize,
+    /// 快照重新生成间隔
+    pub snapshot_interval: Duration,
+    /// 向单个节点提供数据的带宽上限（字节/秒）
+    pub max_bytes_per_sec: u64,
+    /// 可信状态根，设置后只接受该状态根的快照
+    pub trusted_state_root: Option<String>,
+    /// 可信节点，未设置可信状态根时只采用这些节点公布的状态根（检查点），
+    /// 两者都未配置时不同步，避免接受女巫节点伪造的多数
+    pub trusted_peers: HashSet<PeerId>,
+    /// 已校验分块的保存目录，用于断点续传
+    pub progress_dir: PathBuf,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        Self {
+            chunk_size: 500,
+            snapshot_interval: Duration::from_secs(60),
+            max_bytes_per_sec: 1024 * 1024,
+            trusted_state_root: None,
+            trusted_peers: HashSet::new(),
+            progress_dir: PathBuf::from("data").join("sync"),
+        }
+    }
+}
+
+/// 同步请求超时时间
+const SYNC_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// 超过该等待时间的请求直接拒绝，避免请求方超时
+const MAX_SYNC_DELAY: Duration = Duration::from_secs(20);
+
+/// 同一节点连续回复限速的次数上限，超过后不再向其请求
+const MAX_RATE_LIMITED_ATTEMPTS: u32 = 5;
+
+/// 追赶转账的起点早于快照生成时间的秒数，覆盖时间戳早于快照、但快照之后才到达的转账
+const CATCH_UP_OVERLAP: u64 = 600;
+
+/// 按节点限制同步带宽（漏桶）
+struct BandwidthLimiter {
+    max_bytes_per_sec: u64,
+    next_free: HashMap<PeerId, Instant>,
+}
+
+impl BandwidthLimiter {
+    fn new(max_bytes_per_sec: u64) -> Self {
+        Self {
+            max_bytes_per_sec: max_bytes_per_sec.max(1),
+            next_free: HashMap::new(),
+        }
+    }
+    
+    /// 为发送`bytes`字节预留带宽，返回需要等待的时间；超过上限时返回`None`
+    fn reserve(&mut self, peer: PeerId, bytes: usize, now: Instant) -> Option<Duration> {
+        self.next_free.retain(|_, free| *free > now);
+        let start = self.next_free.get(&peer).copied().unwrap_or(now).max(now);
+        let delay = start - now;
+        if delay > MAX_SYNC_DELAY {
+            return None;
+        }
+        let cost = Duration::from_secs_f64(bytes as f64 / self.max_bytes_per_sec as f64);
+        self.next_free.insert(peer, start + cost);
+        Some(delay)
+    }
+}
+
+/// 最近生成的快照，保留上一个快照以便正在下载的节点完成传输
+struct SnapshotCache {
+    current: Option<(Instant, Arc<LedgerSnapshot>)>,
+    previous: Option<Arc<LedgerSnapshot>>,
+}
+
+impl SnapshotCache {
+    fn latest(&mut self, ledger: &Ledger, config: &SyncConfig) -> Arc<LedgerSnapshot> {
+        let fresh = self.current.as_ref()
+            .filter(|(created, _)| created.elapsed() < config.snapshot_interval)
+            .map(|(_, snapshot)| snapshot.clone());
+        if let Some(snapshot) = fresh {
+            return snapshot;
+        }
+        
+        let snapshot = Arc::new(ledger.snapshot(config.chunk_size));
+        if let Some((_, old)) = self.current.take() {
+            if old.manifest.state_root != snapshot.manifest.state_root {
+                self.previous = Some(old);
+            }
+        }
+        self.current = Some((Instant::now(), snapshot.clone()));
+        snapshot
+    }
+    
+    fn find(&self, state_root: &str) -> Option<Arc<LedgerSnapshot>> {
+        self.current.iter().map(|(_, snapshot)| snapshot)
+            .chain(self.previous.iter())
+            .find(|snapshot| snapshot.manifest.state_root == state_root)
+            .cloned()
+    }
+}
+
+fn serve_sync_request(
+    request: SyncRequest,
+    ledger: &Ledger,
+    cache: &mut SnapshotCache,
+    config: &SyncConfig,
+) -> SyncResponse {
+    match request {
+        SyncRequest::Manifest => SyncResponse::Manifest(cache.latest(ledger, config).manifest.clone()),
+        SyncRequest::Chunk { state_root, index } => {
+            match cache.find(&state_root) {
+                Some(snapshot) => match snapshot.chunks.get(index as usize) {
+                    Some(chunk) => SyncResponse::Chunk(chunk.clone()),
+                    None => SyncResponse::Error(format!("chunk {} out of range", index)),
+                },
+                None => SyncResponse::Error(format!("unknown state root {}", state_root)),
+            }
+        }
+        SyncRequest::Transfers { after_timestamp, after_id, limit } => {
+            let limit = (limit as usize).min(config.chunk_size.max(1));
+            SyncResponse::Transfers(ledger.transfers_after(&(after_timestamp, after_id), limit))
+        }
+    }
+}
+
+/// 启动账本同步服务，响应其他节点的快照清单、分块和转账区间请求
+///
+/// 转账区间只包含本节点验证过签名的转账，请求方逐笔重新验证，
+/// 用于在快照之后追赶；更早的状态仍通过快照同步
+pub fn spawn_sync_server(handle: P2PHandle, ledger: Arc<Ledger>, config: SyncConfig) {
+    tokio::spawn(async move {
+        let mut events = handle.subscribe();
+        let mut cache = SnapshotCache { current: None, previous: None };
+        let mut limiter = BandwidthLimiter::new(config.max_bytes_per_sec);
+        
+        loop {
+            match events.recv().await {
+                Ok(P2PEvent::SyncRequest { peer, id, request }) => {
+                    let response = serve_sync_request(request, &ledger, &mut cache, &config);
+                    let size = encode(&response).map(|bytes| bytes.len()).unwrap_or(0);
+                    let (delay, response) = match limiter.reserve(peer, size, Instant::now()) {
+                        Some(delay) => (delay, response),
+                        None => (Duration::ZERO, SyncResponse::RateLimited),
+                    };
+                    
+                    let handle = handle.clone();
+                    tokio::spawn(async move {
+                        tokio::time::sleep(delay).await;
+                        if let Err(e) = handle.respond_sync(id, response).await {
+                            debug!("Failed to answer sync request from {}: {}", peer, e);
+                        }
+                    });
+                }
+                Ok(P2PEvent::Stopped) => break,
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Sync server lagged, dropped {} requests", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+/// 账本同步结果
+#[derive(Debug, Clone)]
+pub struct SyncReport {
+    /// 采用的状态根
+    pub state_root: String,
+    /// 同步的账户数
+    pub accounts: u64,
+    /// 从上次中断处恢复的分块数
+    pub resumed_chunks: usize,
+    /// 同步的交易数
+    pub transactions: u64,
+    /// 同步的动态数
+    pub moments: u64,
+    /// 快照之后按区间补齐的转账数
+    pub caught_up: u64,
+}
+
+/// 提供快照的节点，轮流请求；回复无效的节点立即移除，连续限速过多的节点也被移除
+struct SyncSources {
+    peers: Vec<PeerId>,
+    next: usize,
+    rate_limited: HashMap<PeerId, u32>,
+}
+
+impl SyncSources {
+    fn new(peers: Vec<PeerId>) -> Self {
+        Self { peers, next: 0, rate_limited: HashMap::new() }
+    }
+    
+    /// 下一个请求的节点，没有可用节点时返回`None`
+    fn current(&self) -> Option<PeerId> {
+        (!self.peers.is_empty()).then(|| self.peers[self.next % self.peers.len()])
+    }
+    
+    /// 请求成功，换下一个节点分担负载
+    fn advance(&mut self, peer: PeerId) {
+        self.rate_limited.remove(&peer);
+        self.next += 1;
+    }
+    
+    /// 记录一次限速并换下一个节点，连续限速达到上限时移除该节点
+    fn throttled(&mut self, peer: PeerId) {
+        let attempts = self.rate_limited.entry(peer).or_default();
+        *attempts += 1;
+        if *attempts >= MAX_RATE_LIMITED_ATTEMPTS {
+            warn!("Dropping sync source {} after {} rate-limited attempts", peer, attempts);
+            self.remove(peer);
+        } else {
+            self.next += 1;
+        }
+    }
+    
+    fn remove(&mut self, peer: PeerId) {
+        self.peers.retain(|p| *p != peer);
+        self.rate_limited.remove(&peer);
+    }
+}
+
+/// 选择要同步的快照清单
+///
+/// 配置了可信状态根时只接受匹配的清单；否则只采用可信节点公布的状态根，
+/// 可信节点之间不一致时选择公布者较多、其次较新的快照。
+/// 其他节点只作为已选状态根的分块来源（分块逐一校验）。返回清单和可提供该快照的节点
+fn choose_manifest(
+    manifests: Vec<(PeerId, SnapshotManifest)>,
+    trusted_root: Option<&str>,
+    trusted_peers: &HashSet<PeerId>,
+) -> Option<(SnapshotManifest, Vec<PeerId>)> {
+    let mut by_root: HashMap<String, (SnapshotManifest, Vec<PeerId>, usize)> = HashMap::new();
+    for (peer, manifest) in manifests {
+        if !manifest.verify() {
+            continue;
+        }
+        if trusted_root.is_some_and(|root| root != manifest.state_root) {
+            continue;
+        }
+        let entry = by_root.entry(manifest.state_root.clone())
+            .or_insert_with(|| (manifest, Vec::new(), 0));
+        entry.1.push(peer);
+        if trusted_peers.contains(&peer) {
+            entry.2 += 1;
+        }
+    }
+    
+    by_root.into_values()
+        .filter(|(_, _, trusted)| trusted_root.is_some() || *trusted > 0)
+        .max_by(|(a, _, a_trusted), (b, _, b_trusted)| {
+            a_trusted.cmp(b_trusted)
+                .then(a.created_at.cmp(&b.created_at))
+                .then(a.state_root.cmp(&b.state_root))
+        })
+        .map(|(manifest, peers, _)| (manifest, peers))
+}
+
+/// 断点续传进度：已校验的分块保存在`progress_dir`中
+struct SyncProgress {
+    dir: PathBuf,
+}
+
+impl SyncProgress {
+    const MANIFEST_FILE: &'static str = "manifest.json";
+    
+    /// 打开进度目录，状态根与之前不同时清除旧进度
+    fn open(dir: &Path, manifest: &SnapshotManifest) -> Result<Self, P2PError> {
+        let io_err = |e: std::io::Error| P2PError::Sync(format!("{}: {}", dir.display(), e));
+        let manifest_path = dir.join(Self::MANIFEST_FILE);
+        let previous: Option<SnapshotManifest> = fs::read(&manifest_path).ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok());
+        
+        if previous.as_ref().map(|m| &m.state_root) != Some(&manifest.state_root) {
+            if dir.exists() {
+                fs::remove_dir_all(dir).map_err(io_err)?;
+            }
+            fs::create_dir_all(dir).map_err(io_err)?;
+            let bytes = serde_json::to_vec(manifest).map_err(|e| P2PError::Encoding(e.to_string()))?;
+            fs::write(&manifest_path, bytes).map_err(io_err)?;
+        }
+        Ok(Self { dir: dir.to_path_buf() })
+    }
+    
+    fn chunk_path(&self, index: u32) -> PathBuf {
+        self.dir.join(format!("chunk-{}.json", index))
+    }
+    
+    /// 读取已保存且校验通过的分块
+    fn load(&self, manifest: &SnapshotManifest, index: u32) -> Option<SnapshotChunk> {
+        let bytes = fs::read(self.chunk_path(index)).ok()?;
+        let chunk: SnapshotChunk = serde_json::from_slice(&bytes).ok()?;
+        (chunk.index == index && manifest.verify_chunk(&chunk)).then_some(chunk)
+    }
+    
+    fn save(&self, chunk: &SnapshotChunk) -> Result<(), P2PError> {
+        let bytes = serde_json::to_vec(chunk).map_err(|e| P2PError::Encoding(e.to_string()))?;
+        fs::write(self.chunk_path(chunk.index), bytes)
+            .map_err(|e| P2PError::Sync(format!("failed to save chunk {}: {}", chunk.index, e)))
+    }
+    
+    fn finish(self) {
+        if let Err(e) = fs::remove_dir_all(&self.dir) {
+            warn!("Failed to remove sync progress {}: {}", self.dir.display(), e);
+        }
+    }
+}
+
+/// 从已连接节点同步账本
+///
+/// 按可信状态根或可信节点选定快照清单，逐块校验后用快照替换本地账本（账户、交易和动态），
+/// 再从同一批节点按区间补齐快照前后到达的转账。
+/// 已校验的分块保存在磁盘上，中断后再次调用会从断点继续
+pub async fn sync_ledger(handle: &P2PHandle, ledger: &Ledger, config: &SyncConfig) -> Result<SyncReport, P2PError> {
+    if config.trusted_state_root.is_none() && config.trusted_peers.is_empty() {
+        return Err(P2PError::Sync("no trusted state root or trusted peers configured".to_string()));
+    }
+    let peers = handle.compatible_peers();
+    if peers.is_empty() {
+        return Err(P2PError::Sync("no compatible peers".to_string()));
+    }
+    
+    // 1. 收集各节点公布的快照清单
+    let mut manifests = Vec::new();
+    for peer in peers {
+        match handle.request_sync(peer, SyncRequest::Manifest).await {
+            Ok(SyncResponse::Manifest(manifest)) => manifests.push((peer, manifest)),
+            Ok(other) => debug!("Unexpected manifest response from {}: {:?}", peer, other),
+            Err(e) => debug!("Manifest request to {} failed: {}", peer, e),
+        }
+    }
+    let (manifest, sources) = choose_manifest(manifests, config.trusted_state_root.as_deref(), &config.trusted_peers)
+        .ok_or_else(|| P2PError::Sync("no peer offers an acceptable state root".to_string()))?;
+    info!("Syncing ledger state {} from {} peers", manifest.state_root, sources.len());
+    
+    // 2. 下载并校验分块，多个节点轮流提供
+    let progress = SyncProgress::open(&config.progress_dir, &manifest)?;
+    let mut sources = SyncSources::new(sources);
+    let mut chunks = Vec::with_capacity(manifest.chunk_hashes.len());
+    let mut resumed_chunks = 0;
+    for index in 0..manifest.chunk_hashes.len() as u32 {
+        if let Some(chunk) = progress.load(&manifest, index) {
+            resumed_chunks += 1;
+            chunks.push(chunk);
+            continue;
+        }
+        
+        let chunk = loop {
+            let Some(peer) = sources.current() else {
+                return Err(P2PError::Sync(format!("no peer could provide chunk {}", index)));
+            };
+            let request = SyncRequest::Chunk { state_root: manifest.state_root.clone(), index };
+            match handle.request_sync(peer, request).await {
+                Ok(SyncResponse::Chunk(chunk)) if chunk.index == index && manifest.verify_chunk(&chunk) => {
+                    sources.advance(peer);
+                    break chunk;
+                }
+                Ok(SyncResponse::RateLimited) => {
+                    // 对方限速，换下一个节点
+                    sources.throttled(peer);
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+                other => {
+                    warn!("Dropping sync source {} for chunk {}: {:?}", peer, index, other.map(|_| ()));
+                    sources.remove(peer);
+                }
+            }
+        };
+        progress.save(&chunk)?;
+        chunks.push(chunk);
+    }
+    
+    // 3. 用校验过的快照替换本地账本
+    let counts = ledger.restore_snapshot(&manifest, chunks)
+        .map_err(|e| P2PError::Sync(e.to_string()))?;
+    progress.finish();
+    
+    // 4. 按区间补齐快照生成前后到达的转账
+    let since = manifest.created_at.saturating_sub(CATCH_UP_OVERLAP);
+    let caught_up = catch_up_transfers(handle, ledger, &mut sources, since, config.chunk_size.max(1)).await;
+    
+    Ok(SyncReport {
+        state_root: manifest.state_root,
+        accounts: counts.accounts,
+        resumed_chunks,
+        transactions: counts.transactions,
+        moments: counts.moments,
+        caught_up,
+    })
+}
+
+/// 从时间戳`since`起分页请求带签名的转账，逐笔重新验证后记账，返回记账的转账数
+///
+/// 同一发送方的转账须按nonce顺序记账，暂时无法记账的转账留到下一页之后重试；
+/// 回复乱序或超出请求数量的节点被移除。追赶失败不影响已恢复的快照
+async fn catch_up_transfers(
+    handle: &P2PHandle,
+    ledger: &Ledger,
+    sources: &mut SyncSources,
+    since: u64,
+    page_size: usize,
+) -> u64 {
+    let mut cursor = (since, String::new());
+    let mut deferred: Vec<SignedTransfer> = Vec::new();
+    let mut applied = 0;
+    
+    while let Some(peer) = sources.current() {
+        let request = SyncRequest::Transfers {
+            after_timestamp: cursor.0,
+            after_id: cursor.1.clone(),
+            limit: page_size as u32,
+        };
+        let page = match handle.request_sync(peer, request).await {
+            Ok(SyncResponse::Transfers(page))
+                if page.len() <= page_size
+                    && page.first().is_none_or(|t| (t.tx.timestamp, &t.tx.id) > (cursor.0, &cursor.1))
+                    && page.windows(2).all(|w| (w[0].tx.timestamp, &w[0].tx.id) < (w[1].tx.timestamp, &w[1].tx.id)) =>
+            {
+                sources.advance(peer);
+                page
+            }
+            Ok(SyncResponse::RateLimited) => {
+                sources.throttled(peer);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+            other => {
+                warn!("Dropping sync source {} for transfer catch-up: {:?}", peer, other.map(|_| ()));
+                sources.remove(peer);
+                continue;
+            }
+        };
+        
+        let last_page = page.len() < page_size;
+        if let Some(last) = page.last() {
+            cursor = (last.tx.timestamp, last.tx.id.clone());
+        }
+        deferred.extend(page);
+        
+        // 反复尝试直到没有新的转账可以记账
+        loop {
+            let before = deferred.len();
+            deferred.retain(|transfer| match ledger.apply_transfer(transfer) {
+                Ok(_) => {
+                    applied += 1;
+                    false
+                }
+                Err(_) => !ledger.transactions.contains_key(&transfer.tx.id),
+            });
+            if deferred.len() == before {
+                break;
+            }
+        }
+        deferred.truncate(page_size);
+        
+        if last_page {
+            break;
+        }
+    }
+    
+    if !deferred.is_empty() {
+        debug!("Skipped {} transfers that could not be applied during catch-up", deferred.len());
+    }
+    applied
+}
+
+/// 将网络上收到的交易和动态写入本地账本
+///
+/// 转账在验证发送方签名、nonce和余额后记账，动态在验证作者签名和长度后保存
+pub fn spawn_ledger_bridge(handle: P2PHandle, ledger: Arc<Ledger>) {
+    tokio::spawn(async move {
+        let mut events = handle.subscribe();
+        loop {
+            match events.recv().await {
+                Ok(P2PEvent::Message { source, payload: P2PPayload::Transaction(transfer) }) => {
+                    // 重新验证签名、nonce和余额后才记账
+                    if let Err(e) = ledger.apply_transfer(&transfer) {
+                        debug!("Ignoring transfer {} from {}: {}", transfer.tx.id, source, e);
+                    }
+                }
+                Ok(P2PEvent::Message { source, payload: P2PPayload::Moment(moment) }) => {
+                    // 作者时间戳超出窗口的动态不再接收，转发节点不能重放旧动态
+                    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+                    if let Err(e) = ledger.add_moment(&moment, now) {
+                        debug!("Ignoring moment {} from {}: {}", moment.moment.id, source, e);
+                    }
+                }
+                Ok(P2PEvent::Stopped) => break,
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Ledger bridge lagged, dropped {} messages", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+/// 后台同步账本，直到成功一次为止；未配置可信状态根或可信节点时不同步
+pub fn spawn_initial_sync(handle: P2PHandle, ledger: Arc<Ledger>, config: SyncConfig) {
+    if config.trusted_state_root.is_none() && config.trusted_peers.is_empty() {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut retry = tokio::time::interval(Duration::from_secs(10));
+        loop {
+            retry.tick().await;
+            if handle.compatible_peers().is_empty() {
+                continue;
+            }
+            match sync_ledger(&handle, &ledger, &config).await {
+                Ok(report) => {
+                    info!(
+                        "Ledger synced to {}: {} accounts ({} chunks resumed), {} transactions, {} moments, {} transfers caught up",
+                        report.state_root, report.accounts, report.resumed_chunks,
+                        report.transactions, report.moments, report.caught_up
+                    );
+                    break;
+                }
+                Err(P2PError::ServiceStopped) => break,
+                Err(e) => warn!("Ledger sync failed, retrying: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::Ordering;
+    use crate::types::{Moment, Tx};
+    use ed25519_dalek::Signer;
+    
+    fn temp_key_file() -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("hancoin-test-{}", uuid::Uuid::new_v4()))
+            .join(NODE_KEY_FILE)
+    }
+    
+    #[test]
+    fn test_identity_persists() {
+        let path = temp_key_file();
+        // 只读取时不会生成密钥
+        assert!(load_identity(&path).unwrap().is_none());
+        assert!(!path.exists());
+        
+        let first = load_or_generate_identity(&path).unwrap();
+        let second = load_or_generate_identity(&path).unwrap();
+        assert_eq!(first.public().to_peer_id(), second.public().to_peer_id());
+        
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o077, 0);
+        }
+        
+        let rotated = rotate_identity(&path).unwrap();
+        assert_ne!(first.public().to_peer_id(), rotated.public().to_peer_id());
+        let reloaded = load_identity(&path).unwrap().unwrap();
+        assert_eq!(rotated.public().to_peer_id(), reloaded.public().to_peer_id());
+        
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+    
+    #[test]
+    fn test_identity_from_operator_key() {
+        let a = identity_from_operator_key(&[7u8; 32]).unwrap();
+        let b = identity_from_operator_key(&[7u8; 32]).unwrap();
+        let c = identity_from_operator_key(&[8u8; 32]).unwrap();
+        assert_eq!(a.public().to_peer_id(), b.public().to_peer_id());
+        assert_ne!(a.public().to_peer_id(), c.public().to_peer_id());
+    }
+    
+    #[test]
+    fn test_parse_multiaddrs() {
+        let onion = "/onion3/vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd:4001";
+        let list = format!("/ip4/127.0.0.1/tcp/4001, {}\n# comment\n\n", onion);
+        let addrs = parse_multiaddrs(&list).unwrap();
+        assert_eq!(addrs.len(), 2);
+        assert!(!is_onion_multiaddr(&addrs[0]));
+        assert!(is_onion_multiaddr(&addrs[1]));
+        assert!(parse_multiaddrs("not-an-addr").is_err());
+        
+        assert!(is_global_multiaddr(&addrs[1]));
+        for addr in ["/ip4/8.8.8.8/tcp/4001", "/ip6/2001:4860::8888/tcp/4001", "/dns/seed.hancoin.org/tcp/4001"] {
+            assert!(is_global_multiaddr(&addr.parse().unwrap()), "{}", addr);
+        }
+        for addr in [
+            "/ip4/127.0.0.1/tcp/4001", "/ip4/10.1.2.3/tcp/4001", "/ip4/192.168.1.5/tcp/4001",
+            "/ip4/169.254.0.1/tcp/4001", "/ip4/100.64.0.1/tcp/4001", "/ip4/0.0.0.0/tcp/4001",
+            "/ip6/::1/tcp/4001", "/ip6/fd00::1/tcp/4001", "/ip6/fe80::1/tcp/4001",
+        ] {
+            assert!(!is_global_multiaddr(&addr.parse().unwrap()), "{}", addr);
+        }
+    }
+    
+    #[test]
+    fn test_bootstrap_backoff() {
+        let policy = ReconnectPolicy {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(5),
+            multiplier: 2,
+            check_interval: Duration::from_secs(1),
+        };
+        assert_eq!(policy.backoff(1), Duration::from_secs(1));
+        assert_eq!(policy.backoff(3), Duration::from_secs(4));
+        assert_eq!(policy.backoff(10), Duration::from_secs(5));
+        
+        let addrs = parse_multiaddrs(
+            "/ip4/127.0.0.1/tcp/4001,/onion3/vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd:4001",
+        ).unwrap();
+        let now = Instant::now();
+        let mut bootstrapper = Bootstrapper::new(&addrs, policy, &TorConfig::default(), now);
+        
+        // Tor未启用时跳过.onion引导节点
+        let due = bootstrapper.due(now);
+        assert_eq!(due.len(), 1);
+        
+        let connection_id = ConnectionId::new_unchecked(1);
+        bootstrapper.dialing(due[0].0, connection_id);
+        assert!(bootstrapper.due(now).is_empty());
+        
+        bootstrapper.on_failed(connection_id, now);
+        assert!(bootstrapper.due(now).is_empty());
+        assert_eq!(bootstrapper.due(now + Duration::from_secs(1)).len(), 1);
+        
+        // 拨号未能发起时同样退避
+        bootstrapper.on_dial_error(0, now + Duration::from_secs(1));
+        assert!(bootstrapper.due(now + Duration::from_secs(2)).is_empty());
+        assert_eq!(bootstrapper.due(now + Duration::from_secs(3)).len(), 1);
+        
+        let connection_id = ConnectionId::new_unchecked(2);
+        let peer_id = PeerId::random();
+        bootstrapper.dialing(0, connection_id);
+        bootstrapper.on_connected(connection_id, peer_id);
+        assert!(bootstrapper.due(now + Duration::from_secs(60)).is_empty());
+        
+        bootstrapper.on_disconnected(peer_id, now);
+        assert_eq!(bootstrapper.due(now + Duration::from_secs(1)).len(), 1);
+    }
+    
+    #[test]
+    fn test_peer_scoring_and_bans() {
+        let config = PeerScoreConfig {
+            reject_penalty: 10.0,
+            ban_threshold: -25.0,
+            ban_duration: Duration::from_secs(60),
+            ..Default::default()
+        };
+        let mut state = P2PState::default();
+        let (spammer, honest) = (PeerId::random(), PeerId::random());
+        let now = Instant::now();
+        
+        for _ in 0..5 {
+            state.record_validation(honest, ValidationOutcome::Accept, &config, now);
+        }
+        assert!(state.record_validation(spammer, ValidationOutcome::Throttle, &config, now).0 < 0.0);
+        assert!(!state.record_validation(spammer, ValidationOutcome::Reject, &config, now).1);
+        assert!(!state.record_validation(spammer, ValidationOutcome::Reject, &config, now).1);
+        let (score, banned) = state.record_validation(spammer, ValidationOutcome::Reject, &config, now);
+        assert!(banned && score < config.ban_threshold);
+        assert!(state.is_banned(&spammer, now));
+        assert!(!state.is_banned(&honest, now));
+        
+        // 已封禁的节点不会重复触发封禁
+        assert!(!state.record_validation(spammer, ValidationOutcome::Reject, &config, now).1);
+        
+        // 管理视图按评分排序，封禁节点在前
+        let status = state.peer_status(now);
+        assert_eq!(status[0].peer_id, spammer.to_string());
+        assert_eq!(status[0].rejected, 4);
+        assert_eq!(status[0].throttled, 1);
+        assert_eq!(status[0].banned_secs, Some(60));
+        assert_eq!(status[1].accepted, 5);
+        
+        // 断线后封禁记录和保留期内的负评分仍然保留，保留期过后清除
+        let config = PeerScoreConfig { negative_score_retention: Duration::from_secs(120), ..config };
+        let throttled = PeerId::random();
+        state.record_validation(throttled, ValidationOutcome::Throttle, &config, now);
+        state.prune(Duration::from_secs(1), &config, now + Duration::from_secs(30));
+        assert!(state.is_banned(&spammer, now + Duration::from_secs(30)));
+        assert!(state.active_peers.contains_key(&throttled));
+        assert!(!state.active_peers.contains_key(&honest));
+        
+        // 封禁到期后评分清零
+        let later = now + Duration::from_secs(61);
+        assert_eq!(state.maintain(&config, later), vec![spammer]);
+        assert!(!state.is_banned(&spammer, later));
+        assert_eq!(state.active_peers[&spammer].score, 0.0);
+        
+        state.ban(honest, Duration::from_secs(10), later);
+        assert!(state.is_banned(&honest, later));
+        assert!(state.unban(&honest));
+        assert!(!state.unban(&honest));
+        
+        state.prune(Duration::from_secs(1), &config, now + Duration::from_secs(121));
+        assert!(!state.active_peers.contains_key(&throttled));
+    }
+    
+    #[test]
+    fn test_network_compatibility() {
+        let mainnet = Network::Mainnet;
+        assert_eq!(mainnet.protocol_version(), format!("/hancoin/mainnet/{}", PROTOCOL_VERSION));
+        assert!(mainnet.check_compatible(&mainnet.protocol_version()).is_ok());
+        assert!(mainnet.check_compatible(&Network::Testnet.protocol_version()).is_err());
+        assert!(mainnet.check_compatible(&format!("/hancoin/mainnet/{}", PROTOCOL_VERSION + 1)).is_err());
+        assert!(mainnet.check_compatible("/hancoin/mainnet/abc").is_err());
+        assert!(mainnet.check_compatible("/ipfs/0.1.0").is_err());
+        
+        assert_ne!(mainnet.topic("main"), Network::Devnet.topic("main"));
+        assert_eq!("Testnet".parse::<Network>().unwrap(), Network::Testnet);
+        assert!("moonnet".parse::<Network>().is_err());
+    }
+    
+    #[test]
+    fn test_topic_classes() {
+        let names: HashSet<String> = TopicClass::ALL.iter()
+            .map(|class| Network::Mainnet.topic(class.name()))
+            .collect();
+        assert_eq!(names.len(), TopicClass::ALL.len());
+        assert!(names.contains("hancoin/mainnet/moments"));
+        
+        let config = P2PConfig::default();
+        for class in TopicClass::ALL {
+            assert!(config.topic_limits[&class].max_message_size <= config.max_message_size);
+        }
+        assert_eq!(TopicClass::ALL.iter().filter(|c| c.is_social()).count(), 1);
+        
+        let closed = P2PPayload::CoinJoin(CoinJoinMessage::Closed {
+            session_id: "s1".to_string(),
+            status: crate::coinjoin::CoinJoinStatus::Failed,
+            transcript: None,
+        });
+        assert_eq!(closed.class(), TopicClass::CoinJoin);
+        assert!(TopicClass::Transactions.priority() > TopicClass::CoinJoin.priority());
+        assert!(TopicClass::CoinJoin.priority() > TopicClass::Moments.priority());
+    }
+    
+    #[test]
+    fn test_payload_validation() {
+        let keypair = crate::crypto::generate_keypair();
+        let tx = Tx {
+            id: "tx-0".to_string(),
+            from: hex::encode(keypair.verifying_key().to_bytes()),
+            to: "bob".to_string(),
+            amount: 10,
+            timestamp: 1000,
+            fee: 0,
+            memo: None,
+            status: crate::types::TxStatus::Completed,
+        };
+        let signature = keypair.sign(SignedTransfer::signing_message(&tx, 0).as_bytes());
+        let mut transfer = SignedTransfer {
+            tx,
+            nonce: 0,
+            signature: hex::encode(signature.to_bytes()),
+        };
+        assert!(P2PPayload::Transaction(transfer.clone()).validate().is_ok());
+        transfer.tx.amount = 1000;
+        assert!(P2PPayload::Transaction(transfer).validate().is_err());
+        
+        let content = "长".repeat(crate::types::MAX_MOMENT_LENGTH + 1);
+        let message = SignedMoment::signing_message(1000, &content);
+        let signature = keypair.sign(message.as_bytes());
+        let author = hex::encode(keypair.verifying_key().to_bytes());
+        let moment = SignedMoment {
+            moment: Moment {
+                id: SignedMoment::moment_id(&author, 1000, &content),
+                author,
+                content,
+                timestamp: 1000,
+                likes: 0,
+                reposts: 0,
+                comments: Vec::new(),
+            },
+            signature: hex::encode(signature.to_bytes()),
+        };
+        assert!(P2PPayload::Moment(moment).validate().is_err());
+    }
+    
+    #[test]
+    fn test_topic_metrics() {
+        let mut state = P2PState::default();
+        state.record_received("transactions", 100, None);
+        state.record_received("transactions", 50, Some(Rejection::Replay));
+        state.record_received("transactions", 10, Some(Rejection::Replay));
+        state.record_received("unknown", 10, Some(Rejection::UnknownTopic));
+        state.record_published("coinjoin", 300);
+        
+        let tx = &state.topics["transactions"];
+        assert_eq!((tx.received, tx.received_bytes, tx.accepted), (3, 160, 1));
+        assert_eq!(tx.rejected["replay"], 2);
+        assert_eq!(state.topics["coinjoin"].published_bytes, 300);
+        
+        let metrics = NetworkMetrics {
+            local_peer_id: PeerId::random().to_string(),
+            network: Network::Testnet,
+            protocol_version: PROTOCOL_VERSION,
+            connected_peers: 2,
+            known_peers: 3,
+            banned_peers: 1,
+            messages_total: state.message_count,
+            last_message_secs: None,
+            topics: state.topics.clone(),
+        };
+        let text = metrics.to_prometheus();
+        assert!(text.contains("hancoin_p2p_connected_peers{network=\"testnet\"} 2"));
+        assert!(text.contains("hancoin_p2p_bytes_received_total{network=\"testnet\",topic=\"transactions\"} 160"));
+        assert!(text.contains(
+            "hancoin_p2p_messages_rejected_total{network=\"testnet\",topic=\"transactions\",reason=\"replay\"} 2"
+        ));
+        assert!(text.contains("# TYPE hancoin_p2p_messages_published_total counter"));
+    }
+    
+    fn signed_message(keypair: &Keypair, timestamp: u64) -> P2PMessage {
+        let payload = P2PPayload::CoinJoin(CoinJoinMessage::Closed {
+            session_id: "s1".to_string(),
+            status: crate::coinjoin::CoinJoinStatus::Completed,
+            transcript: None,
+        });
+        let mut msg = P2PMessage::new(encode(&payload).unwrap());
+        msg.timestamp = timestamp;
+        msg.sign(keypair, Network::Mainnet).unwrap();
+        msg
+    }
+    
+    #[test]
+    fn test_message_freshness_and_replay() {
+        let author = Keypair::generate_ed25519();
+        let author_id = author.public().to_peer_id();
+        let mut verifier = MessageVerifier::new(Network::Mainnet, Duration::from_secs(60), 2);
+        let now = 1_000_000;
+        
+        let msg = signed_message(&author, now);
+        assert!(verifier.verify(&author_id, &msg, now).is_ok());
+        assert!(matches!(verifier.verify(&author_id, &msg, now), Err(P2PError::Replay)));
+        
+        // 超出时间窗口
+        let old = signed_message(&author, now - 61);
+        assert!(matches!(verifier.verify(&author_id, &old, now), Err(P2PError::StaleMessage { .. })));
+        let future = signed_message(&author, now + 61);
+        assert!(matches!(verifier.verify(&author_id, &future, now), Err(P2PError::StaleMessage { .. })));
+        
+        // 签名必须来自声称的作者
+        let other = Keypair::generate_ed25519().public().to_peer_id();
+        let fresh = signed_message(&author, now + 1);
+        assert!(matches!(verifier.verify(&other, &fresh, now), Err(P2PError::InvalidSignature(_))));
+        let mut tampered = signed_message(&author, now + 2);
+        tampered.timestamp += 1;
+        assert!(matches!(verifier.verify(&author_id, &tampered, now), Err(P2PError::InvalidSignature(_))));
+        
+        // 记录有上限，过期记录会被清理
+        assert!(verifier.verify(&author_id, &fresh, now).is_ok());
+        assert!(verifier.verify(&author_id, &signed_message(&author, now + 3), now).is_ok());
+        assert!(verifier.order.len() <= 2);
+        
+        // 其他网络的签名不能重放到本网络
+        let mut testnet = MessageVerifier::new(Network::Testnet, Duration::from_secs(60), 2);
+        let replayed = signed_message(&author, now + 4);
+        assert!(matches!(testnet.verify(&author_id, &replayed, now), Err(P2PError::InvalidSignature(_))));
+        verifier.expire(now + 200);
+        assert!(verifier.seen.is_empty());
+    }
+    
+    fn free_port() -> u16 {
+        std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+    }
+    
+    fn loopback_addr(port: u16) -> Multiaddr {
+        format!("/ip4/127.0.0.1/tcp/{}", port).parse().unwrap()
+    }
+    
+    fn loopback_config(port: u16, bootstrap_peers: Vec<Multiaddr>) -> P2PConfig {
+        P2PConfig {
+            key_file: temp_key_file(),
+            listen_addrs: vec![loopback_addr(port)],
+            bootstrap_peers,
+            reconnect: ReconnectPolicy {
+                initial_backoff: Duration::from_millis(100),
+                max_backoff: Duration::from_secs(1),
+                multiplier: 2,
+                check_interval: Duration::from_millis(100),
+            },
+            ..Default::default()
+        }
+    }
+    
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_three_nodes_find_each_other() {
+        // 关闭mDNS，c只知道a，只能经a的Kademlia路由表发现b
+        let config = |port, bootstrap| P2PConfig {
+            enable_mdns: false,
+            allow_private_addrs: true,
+            discovery_interval: Duration::from_millis(200),
+            ..loopback_config(port, bootstrap)
+        };
+        let (port_a, port_b, port_c) = (free_port(), free_port(), free_port());
+        let a = start_p2p(Some(config(port_a, vec![]))).await.unwrap();
+        let b = start_p2p(Some(config(port_b, vec![loopback_addr(port_a)]))).await.unwrap();
+        let c = start_p2p(Some(config(port_c, vec![loopback_addr(port_a)]))).await.unwrap();
+        
+        let deadline = Instant::now() + Duration::from_secs(15);
+        loop {
+            let counts = [a.connected_peers().len(), b.connected_peers().len(), c.connected_peers().len()];
+            if counts.iter().all(|&n| n >= 2) {
+                break;
+            }
+            assert!(Instant::now() < deadline, "nodes did not find each other: {:?}", counts);
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        
+        assert!(a.connected_peers().contains(&b.local_peer_id()));
+        assert!(a.connected_peers().contains(&c.local_peer_id()));
+        assert!(b.connected_peers().contains(&c.local_peer_id()));
+    }
+
+    fn test_ledger(accounts: u64) -> Ledger {
+        let ledger = Ledger::new();
+        for i in 0..accounts {
+            ledger.accounts.insert(format!("account-{:04}", i), crate::types::Account {
+                balance: i * 10,
+                ..Default::default()
+            });
+        }
+        ledger.issued.store(accounts * 10, Ordering::SeqCst);
+        ledger
+    }
+    
+    #[test]
+    fn test_choose_manifest() {
+        let honest = test_ledger(10).snapshot(3).manifest;
+        let forged = test_ledger(11).snapshot(3).manifest;
+        let mut tampered = honest.clone();
+        tampered.issued += 1;
+        let (a, b, c) = (PeerId::random(), PeerId::random(), PeerId::random());
+        
+        let manifests = vec![(a, honest.clone()), (b, honest.clone()), (c, forged.clone()), (c, tampered)];
+        let no_peers = HashSet::new();
+        
+        // 没有可信节点时多数节点公布的状态根也不被采用
+        assert!(choose_manifest(manifests.clone(), None, &no_peers).is_none());
+        
+        // 只采用可信节点公布的状态根，其他公布同一状态根的节点作为分块来源
+        let (chosen, sources) = choose_manifest(manifests.clone(), None, &HashSet::from([a])).unwrap();
+        assert_eq!(chosen.state_root, honest.state_root);
+        assert_eq!(sources, vec![a, b]);
+        let (chosen, sources) = choose_manifest(manifests.clone(), None, &HashSet::from([c])).unwrap();
+        assert_eq!(chosen.state_root, forged.state_root);
+        assert_eq!(sources, vec![c]);
+        
+        // 可信状态根优先于可信节点
+        let (chosen, sources) = choose_manifest(manifests.clone(), Some(&forged.state_root), &HashSet::from([a, b])).unwrap();
+        assert_eq!(chosen.state_root, forged.state_root);
+        assert_eq!(sources, vec![c]);
+        
+        assert!(choose_manifest(manifests, Some("unknown"), &no_peers).is_none());
+    }
+    
+    #[test]
+    fn test_bandwidth_limiter() {
+        let mut limiter = BandwidthLimiter::new(1000);
+        let (peer, other) = (PeerId::random(), PeerId::random());
+        let now = Instant::now();
+        
+        assert_eq!(limiter.reserve(peer, 2000, now), Some(Duration::ZERO));
+        assert_eq!(limiter.reserve(peer, 1000, now), Some(Duration::from_secs(2)));
+        assert_eq!(limiter.reserve(other, 1000, now), Some(Duration::ZERO));
+        assert_eq!(limiter.reserve(peer, 1000, now + Duration::from_secs(10)), Some(Duration::ZERO));
+        
+        // 积压超过上限时拒绝
+        assert!(limiter.reserve(peer, 100_000, now + Duration::from_secs(10)).is_some());
+        assert!(limiter.reserve(peer, 1, now + Duration::from_secs(10)).is_none());
+    }
+    
+    #[test]
+    fn test_sync_sources_drop_throttling_peers() {
+        let (a, b) = (PeerId::random(), PeerId::random());
+        let mut sources = SyncSources::new(vec![a, b]);
+        
+        // 限速时换下一个节点，成功的请求清零计数
+        sources.throttled(a);
+        assert_eq!(sources.current(), Some(b));
+        sources.advance(b);
+        assert_eq!(sources.current(), Some(a));
+        sources.advance(a);
+        sources.advance(b);
+        for _ in 0..MAX_RATE_LIMITED_ATTEMPTS - 1 {
+            assert_eq!(sources.current(), Some(a));
+            sources.throttled(a);
+            sources.advance(b);
+        }
+        assert_eq!(sources.peers, vec![a, b]);
+        
+        // 连续限速达到上限的节点被移除
+        sources.throttled(a);
+        assert_eq!(sources.peers, vec![b]);
+        for _ in 0..MAX_RATE_LIMITED_ATTEMPTS {
+            sources.throttled(b);
+        }
+        assert_eq!(sources.current(), None);
+    }
+    
+    #[test]
+    fn test_sync_progress_resume() {
+        let dir = temp_key_file().with_file_name("sync");
+        let snapshot = test_ledger(10).snapshot(3);
+        
+        let progress = SyncProgress::open(&dir, &snapshot.manifest).unwrap();
+        progress.save(&snapshot.chunks[0]).unwrap();
+        let mut corrupted = snapshot.chunks[1].clone();
+        corrupted.accounts[0].1.balance += 1;
+        progress.save(&corrupted).unwrap();
+        
+        // 相同状态根继续使用已校验的分块，损坏的分块重新下载
+        let progress = SyncProgress::open(&dir, &snapshot.manifest).unwrap();
+        assert!(progress.load(&snapshot.manifest, 0).is_some());
+        assert!(progress.load(&snapshot.manifest, 1).is_none());
+        
+        // 状态根变化时清除旧进度
+        let newer = test_ledger(11).snapshot(3).manifest;
+        let progress = SyncProgress::open(&dir, &newer).unwrap();
+        assert!(progress.load(&snapshot.manifest, 0).is_none());
+        progress.finish();
+        assert!(!dir.exists());
+        
+        let _ = fs::remove_dir_all(dir.parent().unwrap());
+    }
+    
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_handle_dial_disconnect_shutdown() {
+        let (port_a, port_b) = (free_port(), free_port());
+        let a = start_p2p(Some(loopback_config(port_a, vec![]))).await.unwrap();
+        let b = start_p2p(Some(loopback_config(port_b, vec![]))).await.unwrap();
+        let mut events = b.subscribe();
+        
+        let addr = loopback_addr(port_a).with(Protocol::P2p(a.local_peer_id()));
+        b.dial(addr).await.unwrap();
+        let deadline = Instant::now() + Duration::from_secs(15);
+        while !b.connected_peers().contains(&a.local_peer_id()) {
+            assert!(Instant::now() < deadline, "dial did not connect");
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        let status = b.peers();
+        let peer = status.iter().find(|p| p.peer_id == a.local_peer_id().to_string()).unwrap();
+        assert_eq!(peer.transport, "direct");
+        assert!(!peer.addresses.is_empty());
+        
+        assert!(b.disconnect(a.local_peer_id()).await.unwrap());
+        while b.connected_peers().contains(&a.local_peer_id()) {
+            assert!(Instant::now() < deadline, "disconnect did not close the connection");
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        
+        b.shutdown().await.unwrap();
+        assert!(!b.is_running());
+        assert!(matches!(b.dial(loopback_addr(port_a)).await, Err(P2PError::ServiceStopped)));
+        loop {
+            match events.recv().await {
+                Ok(P2PEvent::Stopped) => break,
+                Ok(_) => continue,
+                Err(e) => panic!("missing stop event: {}", e),
+            }
+        }
+        a.shutdown().await.unwrap();
+    }
+    
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_strict_tor_mode() {
+        let port = free_port();
+        let onion: Multiaddr = "/onion3/vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd:4001".parse().unwrap();
+        let tor_config = TorConfig {
+            enabled: true,
+            only_onion: true,
+            // 不可达的代理，任何拨号都无法真正建立连接
+            proxy_addr: "127.0.0.1:9".to_string(),
+            ..TorConfig::default()
+        };
+        
+        // 明网引导节点被跳过
+        let bootstrapper = Bootstrapper::new(
+            &[loopback_addr(port), onion.clone()],
+            ReconnectPolicy::default(),
+            &tor_config,
+            Instant::now(),
+        );
+        assert_eq!(bootstrapper.peers.len(), 1);
+        assert_eq!(bootstrapper.peers[0].addr, onion);
+        
+        assert_eq!(
+            loopback_listen_addr(&"/ip4/0.0.0.0/tcp/4001".parse().unwrap()),
+            "/ip4/127.0.0.1/tcp/4001".parse::<Multiaddr>().unwrap(),
+        );
+        assert_eq!(
+            loopback_listen_addr(&"/ip6/::/tcp/4001".parse().unwrap()),
+            "/ip6/::1/tcp/4001".parse::<Multiaddr>().unwrap(),
+        );
+        
+        // 只有本机Tor转发到onion服务目标端口的入站连接算作Tor连接
+        let config = P2PConfig { listen_addrs: vec![loopback_addr(port)], ..P2PConfig::default() };
+        let target = config.onion_service_target();
+        assert_eq!(target, SocketAddr::from(([127, 0, 0, 1], port)));
+        let inbound = |local: Multiaddr, remote: &str| libp2p::core::ConnectedPoint::Listener {
+            local_addr: local,
+            send_back_addr: remote.parse().unwrap(),
+        };
+        assert!(is_tor_endpoint(&inbound(loopback_addr(port), "/ip4/127.0.0.1/tcp/50000"), target));
+        assert!(!is_tor_endpoint(&inbound(loopback_addr(port ^ 1), "/ip4/127.0.0.1/tcp/50000"), target));
+        assert!(!is_tor_endpoint(&inbound(loopback_addr(port), "/ip4/192.0.2.1/tcp/50000"), target));
+        assert!(!is_tor_endpoint(&inbound(format!("/ip6/::1/tcp/{}", port).parse().unwrap(), "/ip6/::1/tcp/50000"), target));
+        
+        // 明网监听器：严格Tor模式下节点不应直接连接它
+        let clearnet = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let clearnet_addr: Multiaddr = format!("/ip4/127.0.0.1/tcp/{}", clearnet.local_addr().unwrap().port())
+            .parse().unwrap();
+        
+        // 通配地址只在本机监听
+        let handle = start_p2p(Some(P2PConfig {
+            tor_config,
+            enable_mdns: true,
+            listen_addrs: vec![format!("/ip4/0.0.0.0/tcp/{}", port).parse().unwrap()],
+            ..loopback_config(port, vec![clearnet_addr.clone()])
+        })).await.unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while handle.listen_addrs().is_empty() {
+            assert!(Instant::now() < deadline, "node did not start listening");
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert_eq!(handle.listen_addrs(), vec![loopback_addr(port)]);
+        
+        // 明网拨号被传输层拒绝，明网监听器收不到任何连接
+        let _ = handle.dial(clearnet_addr.with(Protocol::P2p(PeerId::random()))).await;
+        let accepted = tokio::time::timeout(Duration::from_secs(1), clearnet.accept()).await;
+        assert!(accepted.is_err(), "strict Tor node opened a clearnet connection");
+        assert!(handle.connected_peers().is_empty());
+        
+        handle.shutdown().await.unwrap();
+    }
+    
+    #[test]
+    fn test_transport_kinds() {
+        let addrs = parse_listen_addrs(
+            "/ip4/0.0.0.0/tcp/4001, ws=/ip4/0.0.0.0/tcp/4002\nwebsocket=/ip6/::/tcp/4003/ws, tcp=/ip4/0.0.0.0/tcp/4004",
+        ).unwrap();
+        let expected: Vec<Multiaddr> = [
+            "/ip4/0.0.0.0/tcp/4001",
+            "/ip4/0.0.0.0/tcp/4002/ws",
+            "/ip6/::/tcp/4003/ws",
+            "/ip4/0.0.0.0/tcp/4004",
+        ].iter().map(|a| a.parse().unwrap()).collect();
+        assert_eq!(addrs, expected);
+        assert_eq!(
+            addrs.iter().map(TransportKind::of).collect::<Vec<_>>(),
+            vec![TransportKind::Tcp, TransportKind::WebSocket, TransportKind::WebSocket, TransportKind::Tcp],
+        );
+        assert!(parse_listen_addrs("quic=/ip4/0.0.0.0/udp/4001").is_err());
+        
+        // 严格Tor模式保留传输后缀
+        assert_eq!(
+            loopback_listen_addr(&expected[1]),
+            "/ip4/127.0.0.1/tcp/4002/ws".parse::<Multiaddr>().unwrap(),
+        );
+    }
+    
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_nodes_connect_over_websocket() {
+        let (port_a, port_b) = (free_port(), free_port());
+        let ws_addr = |port| TransportKind::WebSocket.apply(loopback_addr(port));
+        let a = start_p2p(Some(P2PConfig {
+            listen_addrs: vec![ws_addr(port_a)],
+            ..loopback_config(port_a, vec![])
+        })).await.unwrap();
+        let b = start_p2p(Some(loopback_config(port_b, vec![]))).await.unwrap();
+        
+        b.dial(ws_addr(port_a).with(Protocol::P2p(a.local_peer_id()))).await.unwrap();
+        let deadline = Instant::now() + Duration::from_secs(15);
+        while !b.connected_peers().contains(&a.local_peer_id()) {
+            assert!(Instant::now() < deadline, "websocket dial did not connect");
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        let status = b.peers();
+        let peer = status.iter().find(|p| p.peer_id == a.local_peer_id().to_string()).unwrap();
+        assert!(peer.addresses.iter().any(|addr| addr.contains("/ws")));
+        
+        b.shutdown().await.unwrap();
+        a.shutdown().await.unwrap();
+    }
+    
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dials_paused_while_tor_is_down() {
+        let port = free_port();
+        let unreachable = format!("127.0.0.1:{}", free_port());
+        let handle = start_p2p(Some(P2PConfig {
+            tor_config: TorConfig {
+                enabled: true,
+                proxy_addr: unreachable.clone(),
+                control_addr: unreachable,
+                health_check_interval: Duration::from_millis(100),
+                ..TorConfig::default()
+            },
+            ..loopback_config(port, vec![])
+        })).await.unwrap();
+        
+        let deadline = Instant::now() + Duration::from_secs(15);
+        while handle.tor_health().unwrap().status != TorNetworkStatus::Disconnected {
+            assert!(Instant::now() < deadline, "Tor monitor did not report the proxy as down");
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        match handle.dial(loopback_addr(free_port())).await {
+            Err(P2PError::Dial(e)) => assert!(e.contains("Tor"), "{}", e),
+            other => panic!("dial was not paused: {:?}", other),
+        }
+        handle.shutdown().await.unwrap();
+        
+        // 未启用Tor时没有健康状况
+        let handle = start_p2p(Some(loopback_config(free_port(), vec![]))).await.unwrap();
+        assert!(handle.tor_health().is_none());
+        handle.shutdown().await.unwrap();
+    }
+    
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_incompatible_network_is_refused() {
+        let (port_a, port_b) = (free_port(), free_port());
+        let a = start_p2p(Some(loopback_config(port_a, vec![]))).await.unwrap();
+        let b = start_p2p(Some(P2PConfig {
+            network: Network::Testnet,
+            ..loopback_config(port_b, vec![loopback_addr(port_a)])
+        })).await.unwrap();
+        
+        let refused = |handle: &P2PHandle, other: &P2PHandle| handle.peers().iter().any(|p| {
+            p.peer_id == other.local_peer_id().to_string() && p.incompatible.is_some() && !p.connected
+        });
+        let deadline = Instant::now() + Duration::from_secs(15);
+        while !refused(&a, &b) || !refused(&b, &a) {
+            assert!(Instant::now() < deadline, "incompatible peers were not refused");
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        // 不兼容不等于作恶：不封禁，也不参与账本同步
+        assert!(a.banned_peers().is_empty());
+        assert!(b.banned_peers().is_empty());
+        assert!(a.compatible_peers().is_empty());
+    }
+    
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_fresh_node_syncs_ledger() {
+        let source_ledger = Arc::new(test_ledger(25));
+        let alice = crate::crypto::generate_keypair();
+        let alice_id = hex::encode(alice.verifying_key().to_bytes());
+        source_ledger.accounts.insert(alice_id.clone(), crate::types::Account { balance: 100, ..Default::default() });
+        let transfer = |nonce: u64| {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+            let tx = Tx {
+                id: format!("transfer-{}", nonce),
+                from: alice_id.clone(),
+                to: "account-0003".to_string(),
+                amount: 10,
+                timestamp: now,
+                fee: 0,
+                memo: None,
+                status: crate::types::TxStatus::Completed,
+            };
+            let signature = alice.sign(SignedTransfer::signing_message(&tx, nonce).as_bytes());
+            SignedTransfer { tx, nonce, signature: hex::encode(signature.to_bytes()) }
+        };
+        for i in 0..5u64 {
+            let id = format!("tx-{}", i);
+            source_ledger.transactions.insert(id.clone(), Tx {
+                id,
+                from: "account-0001".to_string(),
+                to: "account-0002".to_string(),
+                amount: 1,
+                timestamp: 1000 + i / 2,
+                fee: 0,
+                memo: None,
+                status: crate::types::TxStatus::Completed,
+            });
+        }
+        
+        let (port_a, port_b) = (free_port(), free_port());
+        let a = start_p2p(Some(loopback_config(port_a, vec![]))).await.unwrap();
+        let b = start_p2p(Some(loopback_config(port_b, vec![loopback_addr(port_a)]))).await.unwrap();
+        let config = SyncConfig {
+            chunk_size: 4,
+            trusted_peers: HashSet::from([a.local_peer_id()]),
+            progress_dir: temp_key_file().with_file_name("sync"),
+            ..Default::default()
+        };
+        spawn_sync_server(a.clone(), source_ledger.clone(), config.clone());
+        
+        // 等待网络标识交换完成，之前对方不算兼容节点
+        let deadline = Instant::now() + Duration::from_secs(15);
+        while b.compatible_peers().is_empty() {
+            assert!(Instant::now() < deadline, "nodes did not connect");
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        
+        // 未配置可信状态根或可信节点时拒绝同步
+        let untrusted = SyncConfig { trusted_peers: HashSet::new(), ..config.clone() };
+        assert!(sync_ledger(&b, &Ledger::new(), &untrusted).await.is_err());
+        
+        // 本地独有的账户和交易被快照替换
+        let ledger = Ledger::new();
+        ledger.accounts.insert("stale".to_string(), crate::types::Account::default());
+        let stale_tx = Tx { id: "stale-tx".to_string(), ..source_ledger.transactions.get("tx-0").unwrap().clone() };
+        ledger.transactions.insert(stale_tx.id.clone(), stale_tx);
+        
+        let report = sync_ledger(&b, &ledger, &config).await.unwrap();
+        assert_eq!(report.accounts, 26);
+        assert_eq!(report.transactions, 5);
+        assert_eq!(report.caught_up, 0);
+        assert_eq!(ledger.accounts.len(), 26);
+        assert!(!ledger.accounts.contains_key("stale"));
+        assert_eq!(ledger.transactions.len(), 5);
+        assert_eq!(ledger.issued.load(Ordering::SeqCst), 250);
+        assert_eq!(ledger.snapshot(4).manifest.state_root, report.state_root);
+        
+        // 快照之后的转账按区间补齐，接收方重新验证签名和nonce；跨页的转账按nonce顺序记账
+        for nonce in 0..6 {
+            source_ledger.apply_transfer(&transfer(nonce)).unwrap();
+        }
+        let caught_up = sync_ledger(&b, &ledger, &config).await.unwrap();
+        assert_eq!(caught_up.state_root, report.state_root);
+        assert_eq!(caught_up.caught_up, 6);
+        assert_eq!(ledger.transactions.len(), 11);
+        assert_eq!(ledger.accounts.get(&alice_id).unwrap().balance, 40);
+        assert_eq!(ledger.accounts.get(&alice_id).unwrap().nonce, 6);
+        
+        let _ = fs::remove_dir_all(config.progress_dir.parent().unwrap());
+    }
+}
@@ -4,48 +4,367 @@
 //! - Tor配置
 //! - Tor连接器
 //! - .onion地址支持
+//! - 经SOCKS5代理拨号的libp2p传输层
+//! - 通过控制端口发布onion服务
+//! - Tor连接健康监测
+//! - 可选的内嵌arti客户端（`arti`功能），无需单独运行tor进程
 
+use std::fs;
 use std::io;
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use async_trait::async_trait;
+use futures::future::{BoxFuture, FutureExt, TryFutureExt};
+use hmac::{Hmac, Mac};
+use libp2p::core::transport::{DialOpts, ListenerId, TransportError, TransportEvent};
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, Transport};
+use serde::Serialize;
+use sha2::Sha256;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::net::TcpStream;
+use tokio::sync::{watch, Mutex};
 use tokio_socks::tcp::Socks5Stream;
-use log::{debug, error, info};
+use zeroize::Zeroizing;
+use thiserror::Error;
+use log::{debug, error, info, warn};
+
+#[cfg(feature = "arti")]
+mod arti;
+#[cfg(feature = "arti")]
+pub use self::arti::EmbeddedTor;
+
+/// Tor客户端实现
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub enum TorBackend {
+    /// 外部tor进程的SOCKS5代理和控制端口
+    #[default]
+    Socks,
+    /// 进程内的arti客户端，需要启用`arti`功能
+    Embedded,
+}
+
+impl FromStr for TorBackend {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "socks" => Ok(TorBackend::Socks),
+            "embedded" | "arti" => Ok(TorBackend::Embedded),
+            other => Err(format!("unknown Tor backend: {}", other)),
+        }
+    }
+}
+
+/// Tor相关错误
+#[derive(Error, Debug)]
+pub enum TorError {
+    #[error("Invalid proxy address: {0}")]
+    InvalidProxy(String),
+    #[error("Invalid target address: {0}")]
+    InvalidTarget(String),
+    #[error("Connection to {0} refused: only onion addresses are allowed")]
+    Policy(String),
+    #[error("Timed out connecting to {0}")]
+    Timeout(String),
+    #[error("SOCKS5 proxy error: {0}")]
+    Socks(#[from] tokio_socks::Error),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+impl From<TorError> for io::Error {
+    fn from(e: TorError) -> Self {
+        let kind = match &e {
+            TorError::InvalidProxy(_) | TorError::InvalidTarget(_) => io::ErrorKind::InvalidInput,
+            TorError::Policy(_) => io::ErrorKind::PermissionDenied,
+            TorError::Timeout(_) => io::ErrorKind::TimedOut,
+            TorError::Socks(_) => io::ErrorKind::ConnectionRefused,
+            TorError::Io(e) => e.kind(),
+        };
+        match e {
+            TorError::Io(e) => e,
+            e => io::Error::new(kind, e),
+        }
+    }
+}
+
+/// SOCKS5用户名/密码认证
+#[derive(Clone)]
+pub struct ProxyAuth {
+    pub username: String,
+    pub password: Zeroizing<String>,
+    /// 在用户名后附加流隔离标签（`<username>+<tag>`），仅用于按IsolateSOCKSAuth隔离线路的Tor代理；
+    /// 普通SOCKS5代理应保持关闭，凭据原样发送
+    pub isolate_with_auth: bool,
+}
+
+impl std::fmt::Debug for ProxyAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProxyAuth")
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .field("isolate_with_auth", &self.isolate_with_auth)
+            .finish()
+    }
+}
 
 /// Tor配置
 #[derive(Clone, Debug)]
 pub struct TorConfig {
     /// 是否启用Tor
     pub enabled: bool,
-    /// Tor SOCKS5代理地址
+    /// 使用外部tor进程还是内嵌客户端
+    pub backend: TorBackend,
+    /// SOCKS5代理地址：`ip:port`、`[ipv6]:port`或`host:port`，也可以是非Tor的普通SOCKS5代理
     pub proxy_addr: String,
-    /// 是否只允许.onion地址
+    /// 代理认证，凭据原样发送；`isolate_with_auth`开启时用户名后附加流隔离标签
+    pub proxy_auth: Option<ProxyAuth>,
+    /// 经代理建立连接的超时时间
+    pub connect_timeout: Duration,
+    /// 是否只允许.onion地址：只拨号和接受onion节点，监听地址限制在本机，关闭mDNS
     pub only_onion: bool,
+    /// Tor控制端口地址
+    pub control_addr: String,
+    /// 控制端口密码（HashedControlPassword），未设置时使用SAFECOOKIE认证
+    pub control_password: Option<String>,
+    /// 控制端口cookie文件（CookieAuthentication），用于SAFECOOKIE认证；
+    /// 只读取这里配置的路径，不使用控制端口在PROTOCOLINFO中公布的COOKIEFILE
+    pub control_cookie_file: Option<PathBuf>,
+    /// onion服务私钥文件，保证重启后onion地址不变
+    pub onion_key_file: PathBuf,
+    /// 健康检查间隔
+    pub health_check_interval: Duration,
+    /// 内嵌客户端的状态和缓存目录（含onion服务密钥）
+    pub embedded_dir: PathBuf,
+    /// 网桥，格式同torrc的`Bridge`行，如`obfs4 192.0.2.1:443 <fingerprint> cert=... iat-mode=0`；
+    /// 只由内嵌客户端载入，用于屏蔽了Tor中继的网络。外部tor进程的网桥须在torrc中配置
+    /// （`UseBridges 1`和各`Bridge`行），节点不修改外部进程的配置，启动时只检查并警告
+    pub bridges: Vec<String>,
+    /// 可插拔传输插件，格式同torrc的`ClientTransportPlugin`行，如`obfs4 exec /usr/bin/obfs4proxy`；
+    /// 同样只用于内嵌客户端
+    pub transport_plugins: Vec<String>,
 }
 
 impl Default for TorConfig {
     fn default() -> Self {
         Self {
             enabled: false,
+            backend: TorBackend::Socks,
             proxy_addr: "127.0.0.1:9050".to_string(),
+            proxy_auth: None,
+            connect_timeout: Duration::from_secs(60),
             only_onion: false,
+            control_addr: "127.0.0.1:9051".to_string(),
+            control_password: None,
+            control_cookie_file: None,
+            onion_key_file: PathBuf::from("data").join("tor").join("onion_key"),
+            health_check_interval: Duration::from_secs(30),
+            embedded_dir: PathBuf::from("data").join("tor").join("arti"),
+            bridges: Vec::new(),
+            transport_plugins: Vec::new(),
+        }
+    }
+}
+
+impl TorConfig {
+    /// 是否为严格Tor模式（启用Tor且只允许.onion地址）
+    pub fn is_strict(&self) -> bool {
+        self.enabled && self.only_onion
+    }
+    
+    /// 检查网桥和传输插件行，拒绝含换行等控制字符的行
+    fn validate_bridges(&self) -> io::Result<()> {
+        for line in self.bridges.iter().chain(&self.transport_plugins) {
+            if line.chars().any(char::is_control) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("网桥配置含有控制字符: {:?}", line),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// SOCKS流隔离
+///
+/// Tor默认按SOCKS用户名/密码隔离线路（IsolateSOCKSAuth），
+/// 节点连接与CoinJoin各阶段的登记使用不同凭据：输入、输出和签名各自通过独立线路提交，
+/// 避免协调者把同一参与者的各个阶段相互关联，或与节点的P2P线路关联起来
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamIsolation {
+    /// P2P节点连接
+    Peer,
+    /// 一次直接发往HTTP接口的请求（如CoinJoin某一阶段的登记），由`TorConnector::fresh_isolation`分配，
+    /// 每次请求使用独立线路，服务端无法按线路关联同一节点的多个请求
+    WalletApi {
+        /// 请求用途，如`coinjoin-output`
+        purpose: String,
+        stream: u64,
+    },
+}
+
+impl StreamIsolation {
+    /// 是否只用于一次连接，内嵌客户端不缓存此类隔离的线路标记
+    #[cfg(feature = "arti")]
+    fn is_one_shot(&self) -> bool {
+        matches!(self, StreamIsolation::WalletApi { .. })
+    }
+    
+    /// 该用途的SOCKS用户名和密码，`nonce`在每个连接器实例中不同，重启后不复用旧线路
+    fn credentials(&self, nonce: &str) -> (String, String) {
+        match self {
+            StreamIsolation::Peer => ("hancoin-peer".to_string(), nonce.to_string()),
+            StreamIsolation::WalletApi { purpose, stream } => (
+                "hancoin-wallet".to_string(),
+                format!("{}:{}:{}", nonce, purpose, stream),
+            ),
+        }
+    }
+}
+
+/// 经Tor建立的流
+pub enum TorStream {
+    /// SOCKS5代理建立的TCP连接，或监听接受的入站连接
+    Tcp(libp2p::tcp::tokio::TcpStream),
+    /// 内嵌客户端建立的数据流
+    #[cfg(feature = "arti")]
+    Embedded(Box<arti_client::DataStream>),
+}
+
+impl futures::AsyncRead for TorStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            TorStream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(feature = "arti")]
+            TorStream::Embedded(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl futures::AsyncWrite for TorStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            TorStream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(feature = "arti")]
+            TorStream::Embedded(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+    
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            TorStream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(feature = "arti")]
+            TorStream::Embedded(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+    
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            TorStream::Tcp(stream) => Pin::new(stream).poll_close(cx),
+            #[cfg(feature = "arti")]
+            TorStream::Embedded(stream) => Pin::new(stream).poll_close(cx),
         }
     }
 }
 
 /// Tor连接器
 /// 
-/// 用于通过Tor网络建立TCP连接
+/// 用于通过Tor网络建立连接，默认经外部tor进程的SOCKS5代理，
+/// 启用`arti`功能并选择内嵌客户端时在进程内连接
 #[derive(Clone)]
 pub struct TorConnector {
     config: TorConfig,
+    /// 流隔离凭据的随机前缀
+    isolation_nonce: Arc<String>,
+    /// 已分配的独立线路数
+    fresh_streams: Arc<AtomicU64>,
+    /// 内嵌客户端
+    #[cfg(feature = "arti")]
+    embedded: Option<Arc<EmbeddedTor>>,
 }
 
 impl TorConnector {
     /// 创建新的Tor连接器
     pub fn new(config: TorConfig) -> Self {
-        Self { config }
+        Self {
+            config,
+            isolation_nonce: Arc::new(hex::encode(rand::random::<[u8; 16]>())),
+            fresh_streams: Arc::new(AtomicU64::new(0)),
+            #[cfg(feature = "arti")]
+            embedded: None,
+        }
+    }
+    
+    /// 按配置的实现创建连接器，选择内嵌客户端时在后台开始引导
+    pub fn from_config(config: TorConfig) -> io::Result<Self> {
+        config.validate_bridges()?;
+        match config.backend {
+            TorBackend::Socks => Ok(Self::new(config)),
+            #[cfg(feature = "arti")]
+            TorBackend::Embedded => {
+                let embedded = Arc::new(EmbeddedTor::new(&config)?);
+                Ok(Self { embedded: Some(embedded), ..Self::new(config) })
+            }
+            #[cfg(not(feature = "arti"))]
+            TorBackend::Embedded => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "内嵌Tor客户端需要启用arti功能编译",
+            )),
+        }
+    }
+    
+    /// 用于发布onion服务的接口：内嵌客户端自身，或外部tor进程的控制端口
+    pub fn onion_service(&self) -> Box<dyn TorNetwork + Send + Sync> {
+        #[cfg(feature = "arti")]
+        if let Some(embedded) = &self.embedded {
+            return Box::new(embedded.clone());
+        }
+        Box::new(TorController::new(self.config.clone()))
+    }
+    
+    /// 配置了网桥时检查外部tor进程是否启用了网桥，返回`false`表示会直连Tor网络
+    ///
+    /// 只通过控制端口查询，不修改外部进程的配置；内嵌客户端创建时已载入网桥，未配置网桥时不做检查
+    pub async fn check_bridges(&self) -> io::Result<bool> {
+        #[cfg(feature = "arti")]
+        if self.embedded.is_some() {
+            return Ok(true);
+        }
+        if self.config.bridges.is_empty() {
+            return Ok(true);
+        }
+        let reply = with_timeout(async {
+            let mut connection = ControlConnection::open(&self.config.control_addr).await?;
+            connection.authenticate(&self.config).await?;
+            connection.command("GETCONF UseBridges").await
+        }).await?;
+        Ok(reply.iter().any(|line| line.trim() == "UseBridges=1"))
+    }
+    
+    /// 当前的Tor健康状况
+    pub async fn health(&self) -> TorHealth {
+        #[cfg(feature = "arti")]
+        if let Some(embedded) = &self.embedded {
+            return embedded.health();
+        }
+        check_health(&self.config).await
+    }
+    
+    /// 为一次HTTP接口请求分配不与其他连接共享的新线路
+    pub fn fresh_isolation(&self, purpose: &str) -> StreamIsolation {
+        StreamIsolation::WalletApi {
+            purpose: purpose.to_string(),
+            stream: self.fresh_streams.fetch_add(1, Ordering::Relaxed),
+        }
     }
     
     /// 检查是否启用Tor
@@ -53,56 +372,202 @@ impl TorConnector {
         self.config.enabled
     }
     
-    /// 检查地址是否为.onion地址
+    /// 检查`host:port`形式的地址是否为.onion地址
     pub fn is_onion_address(addr: &str) -> bool {
-        addr.contains(".onion:")
+        parse_host_port(addr).is_ok_and(|(host, _)| host.ends_with(".onion"))
     }
     
-    /// 通过Tor网络连接到目标地址
-    pub async fn connect(&self, addr: &str) -> io::Result<TcpStream> {
-        debug!("通过Tor连接到: {}", addr);
-        
-        // 解析代理地址
-        let proxy_addr = match SocketAddr::from_str(&self.config.proxy_addr) {
-            Ok(addr) => addr,
-            Err(e) => {
-                error!("无效的Tor代理地址: {}", e);
-                return Err(io::Error::new(io::ErrorKind::InvalidInput, "无效的Tor代理地址"));
+    /// 通过Tor网络连接到`host:port`或`[ipv6]:port`形式的目标地址
+    pub async fn connect(&self, addr: &str) -> Result<TorStream, TorError> {
+        let (host, port) = parse_host_port(addr).inspect_err(|_| {
+            error!("无效的目标地址: {}", addr);
+        })?;
+        self.connect_host(&host, port).await
+    }
+    
+    /// 检查连接策略：只允许.onion地址时拒绝其他目标
+    pub fn check_policy(&self, host: &str) -> Result<(), TorError> {
+        if self.config.only_onion && !host.ends_with(".onion") {
+            error!("Tor策略拒绝连接非onion地址: {}", host);
+            return Err(TorError::Policy(host.to_string()));
+        }
+        Ok(())
+    }
+    
+    /// 通过Tor网络连接到目标主机，使用节点连接的线路
+    pub async fn connect_host(&self, host: &str, port: u16) -> Result<TorStream, TorError> {
+        self.connect_isolated(host, port, &StreamIsolation::Peer).await
+    }
+    
+    /// SOCKS认证凭据：未配置代理认证时直接使用流隔离凭据；
+    /// 配置了代理认证时原样发送，只有开启`isolate_with_auth`时才在用户名后附加流隔离标签
+    fn proxy_credentials(&self, isolation: &StreamIsolation) -> (String, Zeroizing<String>) {
+        let (username, password) = isolation.credentials(&self.isolation_nonce);
+        match &self.config.proxy_auth {
+            Some(auth) if auth.isolate_with_auth => {
+                let tag = blake3::hash(format!("{}:{}", username, password).as_bytes());
+                let tag = hex::encode(&tag.as_bytes()[..16]);
+                (format!("{}+{}", auth.username, tag), auth.password.clone())
             }
-        };
+            Some(auth) => (auth.username.clone(), auth.password.clone()),
+            None => (username, Zeroizing::new(password)),
+        }
+    }
+    
+    /// 按指定用途隔离线路连接到目标主机，域名由Tor出口节点解析
+    pub async fn connect_isolated(&self, host: &str, port: u16, isolation: &StreamIsolation) -> Result<TorStream, TorError> {
+        self.check_policy(host)?;
+        let addr = format!("{}:{}", host, port);
+        debug!("通过Tor连接到: {} ({:?})", addr, isolation);
         
-        // 解析目标地址
-        let (host, port) = match addr.rsplit_once(':') {
-            Some((host, port)) => {
-                let port = match port.parse::<u16>() {
-                    Ok(p) => p,
-                    Err(e) => {
-                        error!("无效的端口号: {}", e);
-                        return Err(io::Error::new(io::ErrorKind::InvalidInput, "无效的端口号"));
-                    }
-                };
-                (host.to_string(), port)
-            },
-            None => {
-                error!("无效的地址格式: {}", addr);
-                return Err(io::Error::new(io::ErrorKind::InvalidInput, "无效的地址格式"));
-            }
-        };
+        #[cfg(feature = "arti")]
+        if let Some(embedded) = &self.embedded {
+            let isolation = (!isolation.is_one_shot()).then(|| isolation.credentials(&self.isolation_nonce));
+            return embedded.connect_stream(host, port, isolation).await
+                .map(|stream| TorStream::Embedded(Box::new(stream)))
+                .map_err(TorError::Io);
+        }
+        
+        // 代理地址可以是IP、[IPv6]或主机名
+        parse_host_port(&self.config.proxy_addr)
+            .map_err(|_| TorError::InvalidProxy(self.config.proxy_addr.clone()))?;
+        
+        let (username, password) = self.proxy_credentials(isolation);
         
-        // 通过SOCKS5代理连接
-        match Socks5Stream::connect(proxy_addr, (host, port)).await {
-            Ok(stream) => {
+        // 通过SOCKS5代理连接，主机名原样交给代理，本地不做DNS查询
+        let connect = Socks5Stream::connect_with_password(
+            self.config.proxy_addr.as_str(),
+            (host, port),
+            &username,
+            &password,
+        );
+        match tokio::time::timeout(self.config.connect_timeout, connect).await {
+            Ok(Ok(stream)) => {
                 debug!("成功通过Tor连接到: {}", addr);
-                Ok(stream.into_inner())
-            },
-            Err(e) => {
+                Ok(TorStream::Tcp(libp2p::tcp::tokio::TcpStream(stream.into_inner())))
+            }
+            Ok(Err(e)) => {
                 error!("通过Tor连接失败: {}", e);
-                Err(io::Error::new(io::ErrorKind::ConnectionRefused, format!("通过Tor连接失败: {}", e)))
+                Err(TorError::Socks(e))
+            }
+            Err(_) => {
+                error!("通过Tor连接超时: {}", addr);
+                Err(TorError::Timeout(addr))
             }
         }
     }
 }
 
+/// 解析`host:port`，IPv6地址须写成`[addr]:port`
+pub fn parse_host_port(addr: &str) -> Result<(String, u16), TorError> {
+    let invalid = || TorError::InvalidTarget(addr.to_string());
+    let (host, port) = if let Some(rest) = addr.strip_prefix('[') {
+        let (host, port) = rest.split_once("]:").ok_or_else(invalid)?;
+        host.parse::<std::net::Ipv6Addr>().map_err(|_| invalid())?;
+        (host, port)
+    } else {
+        let (host, port) = addr.rsplit_once(':').ok_or_else(invalid)?;
+        // 未加方括号的IPv6地址有歧义
+        if host.is_empty() || host.contains(':') {
+            return Err(invalid());
+        }
+        (host, port)
+    };
+    let port = port.parse::<u16>().ok().filter(|p| *p != 0).ok_or_else(invalid)?;
+    Ok((host.to_string(), port))
+}
+
+/// 解析multiaddr中的SOCKS5拨号目标（主机, 端口）
+///
+/// 支持`/onion3/<id>:<port>`、`/dns{,4,6}/<host>/tcp/<port>`和`/ip{4,6}/<ip>/tcp/<port>`，
+/// 末尾可带`/p2p/<peer>`，其他地址返回`None`
+pub fn socks_target(addr: &Multiaddr) -> Option<(String, u16)> {
+    let mut protocols = addr.iter();
+    let (host, port) = match protocols.next()? {
+        Protocol::Onion3(onion) => {
+            let port = onion.port();
+            // Display形式为/onion3/<base32>:<port>
+            let text = Protocol::Onion3(onion).to_string();
+            let id = text.strip_prefix("/onion3/")?.rsplit_once(':')?.0.to_string();
+            (format!("{}.onion", id), port)
+        }
+        Protocol::Dns(host) | Protocol::Dns4(host) | Protocol::Dns6(host) => {
+            let Some(Protocol::Tcp(port)) = protocols.next() else { return None };
+            (host.to_string(), port)
+        }
+        Protocol::Ip4(ip) => {
+            let Some(Protocol::Tcp(port)) = protocols.next() else { return None };
+            (ip.to_string(), port)
+        }
+        Protocol::Ip6(ip) => {
+            let Some(Protocol::Tcp(port)) = protocols.next() else { return None };
+            (ip.to_string(), port)
+        }
+        _ => return None,
+    };
+    match protocols.next() {
+        None | Some(Protocol::P2p(_)) => Some((host, port)),
+        _ => None,
+    }
+}
+
+/// 经Tor拨号的libp2p传输层
+///
+/// 所有外连都通过Tor建立，域名和.onion地址由Tor远程解析；
+/// 监听由内部TCP传输完成，得到的流可直接进行noise/yamux升级
+pub struct TorTransport {
+    connector: TorConnector,
+    inner: libp2p::tcp::tokio::Transport,
+}
+
+impl TorTransport {
+    pub fn new(connector: TorConnector, inner: libp2p::tcp::tokio::Transport) -> Self {
+        Self { connector, inner }
+    }
+}
+
+impl Transport for TorTransport {
+    type Output = TorStream;
+    type Error = TorError;
+    type ListenerUpgrade = futures::future::MapOk<
+        futures::future::MapErr<
+            <libp2p::tcp::tokio::Transport as Transport>::ListenerUpgrade,
+            fn(io::Error) -> TorError,
+        >,
+        fn(libp2p::tcp::tokio::TcpStream) -> TorStream,
+    >;
+    type Dial = BoxFuture<'static, Result<Self::Output, TorError>>;
+    
+    fn listen_on(&mut self, id: ListenerId, addr: Multiaddr) -> Result<(), TransportError<Self::Error>> {
+        self.inner.listen_on(id, addr).map_err(|e| e.map(TorError::Io))
+    }
+    
+    fn remove_listener(&mut self, id: ListenerId) -> bool {
+        self.inner.remove_listener(id)
+    }
+    
+    fn dial(&mut self, addr: Multiaddr, _opts: DialOpts) -> Result<Self::Dial, TransportError<Self::Error>> {
+        let Some((host, port)) = socks_target(&addr) else {
+            return Err(TransportError::MultiaddrNotSupported(addr));
+        };
+        self.connector.check_policy(&host).map_err(TransportError::Other)?;
+        let connector = self.connector.clone();
+        Ok(async move { connector.connect_host(&host, port).await }.boxed())
+    }
+    
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<TransportEvent<Self::ListenerUpgrade, Self::Error>> {
+        Pin::new(&mut self.inner).poll(cx).map(|event| {
+            event
+                .map_upgrade(|upgrade| {
+                    upgrade
+                        .map_err(TorError::Io as fn(_) -> _)
+                        .map_ok(TorStream::Tcp as fn(_) -> _)
+                })
+                .map_err(TorError::Io)
+        })
+    }
+}
+
 /// Tor网络接口
 /// 
 /// 定义了Tor网络的基本操作
@@ -112,7 +577,12 @@ pub trait TorNetwork {
     async fn get_onion_address(&self) -> io::Result<String>;
     
     /// 发布服务到Tor网络
-    async fn publish_service(&self, port: u16) -> io::Result<String>;
+    async fn publish_service(&self, port: u16) -> io::Result<String> {
+        self.publish_services(&[(port, SocketAddr::from(([127, 0, 0, 1], port)))]).await
+    }
+    
+    /// 发布onion服务，`ports`为(onion端口, 本地目标地址)，返回`<id>.onion`
+    async fn publish_services(&self, ports: &[(u16, SocketAddr)]) -> io::Result<String>;
     
     /// 连接到Tor网络
     async fn connect(&self) -> io::Result<()>;
@@ -121,8 +591,270 @@ pub trait TorNetwork {
     async fn disconnect(&self) -> io::Result<()>;
 }
 
+/// 控制端口连接
+struct ControlConnection {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl ControlConnection {
+    async fn open(addr: &str) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr).await?;
+        let (reader, writer) = stream.into_split();
+        Ok(Self { reader: BufReader::new(reader), writer })
+    }
+    
+    /// 发送命令并读取回复，返回去掉状态码的各行；非250回复返回错误
+    async fn command(&mut self, command: &str) -> io::Result<Vec<String>> {
+        self.writer.write_all(command.as_bytes()).await?;
+        self.writer.write_all(b"\r\n").await?;
+        self.writer.flush().await?;
+        
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line).await? == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Tor控制端口连接已关闭"));
+            }
+            let line = line.trim_end_matches(['\r', '\n']);
+            if line.len() < 4 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("无效的控制端口回复: {}", line)));
+            }
+            let (code, separator, text) = (&line[..3], &line[3..4], &line[4..]);
+            if code != "250" {
+                return Err(io::Error::other(format!("Tor控制端口错误 {}: {}", code, text)));
+            }
+            match separator {
+                " " => {
+                    lines.push(text.to_string());
+                    return Ok(lines);
+                }
+                "-" => lines.push(text.to_string()),
+                "+" => {
+                    // 多行数据，以单独的"."结束
+                    let mut data = text.to_string();
+                    loop {
+                        let mut line = String::new();
+                        if self.reader.read_line(&mut line).await? == 0 {
+                            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Tor控制端口连接已关闭"));
+                        }
+                        let line = line.trim_end_matches(['\r', '\n']);
+                        if line == "." {
+                            break;
+                        }
+                        data.push('\n');
+                        data.push_str(line);
+                    }
+                    lines.push(data);
+                }
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("无效的控制端口回复: {}", line))),
+            }
+        }
+    }
+    
+    /// 按PROTOCOLINFO公布的方式认证：优先使用配置的密码，其次SAFECOOKIE，最后无认证
+    ///
+    /// 不支持明文COOKIE：它会把cookie交给监听控制端口地址的任何进程
+    async fn authenticate(&mut self, config: &TorConfig) -> io::Result<()> {
+        let info = self.command("PROTOCOLINFO 1").await?;
+        let methods: Vec<String> = info.iter()
+            .filter_map(|line| line.strip_prefix("AUTH "))
+            .flat_map(|auth| auth.split_whitespace())
+            .find_map(|field| field.strip_prefix("METHODS="))
+            .map(|methods| methods.split(',').map(str::to_string).collect())
+            .unwrap_or_default();
+        
+        let command = if let Some(password) = &config.control_password {
+            Zeroizing::new(format!("AUTHENTICATE {}", quote(password)?))
+        } else if methods.iter().any(|m| m == "SAFECOOKIE") {
+            let cookie_file = config.control_cookie_file.as_deref().ok_or_else(|| io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Tor要求cookie认证，请配置控制端口cookie文件或密码",
+            ))?;
+            let cookie = read_cookie(cookie_file)?;
+            self.safe_cookie_command(&cookie).await?
+        } else if methods.iter().any(|m| m == "NULL") {
+            Zeroizing::new("AUTHENTICATE".to_string())
+        } else {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("不支持的Tor认证方式: {:?}，请配置控制端口密码或cookie文件", methods),
+            ));
+        };
+        self.command(&command).await?;
+        Ok(())
+    }
+    
+    /// SAFECOOKIE挑战：先验证控制端口持有同一cookie，再返回带本端HMAC的AUTHENTICATE命令
+    async fn safe_cookie_command(&mut self, cookie: &[u8; COOKIE_LENGTH]) -> io::Result<Zeroizing<String>> {
+        let client_nonce: [u8; 32] = rand::random();
+        let reply = self.command(&format!("AUTHCHALLENGE SAFECOOKIE {}", hex::encode(client_nonce))).await?;
+        let field = |key: &str| reply.iter()
+            .flat_map(|line| line.split_whitespace())
+            .find_map(|field| field.strip_prefix(key))
+            .and_then(|value| hex::decode(value).ok());
+        let (Some(server_hash), Some(server_nonce)) = (field("SERVERHASH="), field("SERVERNONCE=")) else {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "无效的AUTHCHALLENGE回复"));
+        };
+        if server_nonce.len() != 32 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "无效的AUTHCHALLENGE回复"));
+        }
+        safe_cookie_mac(SAFECOOKIE_SERVER_KEY, cookie, &client_nonce, &server_nonce)
+            .verify_slice(&server_hash)
+            .map_err(|_| io::Error::new(io::ErrorKind::PermissionDenied, "控制端口未能证明持有Tor cookie"))?;
+        let client_hash = safe_cookie_mac(SAFECOOKIE_CLIENT_KEY, cookie, &client_nonce, &server_nonce)
+            .finalize()
+            .into_bytes();
+        Ok(Zeroizing::new(format!("AUTHENTICATE {}", hex::encode(client_hash))))
+    }
+}
+
+/// Tor认证cookie的长度
+const COOKIE_LENGTH: usize = 32;
+
+/// SAFECOOKIE中控制端口一方的HMAC密钥（控制协议规范）
+const SAFECOOKIE_SERVER_KEY: &[u8] = b"Tor safe cookie authentication server-to-controller hash";
+
+/// SAFECOOKIE中控制器一方的HMAC密钥
+const SAFECOOKIE_CLIENT_KEY: &[u8] = b"Tor safe cookie authentication controller-to-server hash";
+
+/// 读取配置的cookie文件，长度不是32字节时拒绝，不读入任意大小的文件
+fn read_cookie(path: &Path) -> io::Result<Zeroizing<[u8; COOKIE_LENGTH]>> {
+    let invalid = || io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}不是{}字节的Tor cookie文件", path.display(), COOKIE_LENGTH),
+    );
+    if fs::metadata(path)?.len() != COOKIE_LENGTH as u64 {
+        return Err(invalid());
+    }
+    let bytes = Zeroizing::new(fs::read(path)?);
+    let cookie = <[u8; COOKIE_LENGTH]>::try_from(bytes.as_slice()).map_err(|_| invalid())?;
+    Ok(Zeroizing::new(cookie))
+}
+
+/// HMAC-SHA256(key, cookie | client_nonce | server_nonce)
+fn safe_cookie_mac(key: &[u8], cookie: &[u8], client_nonce: &[u8], server_nonce: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(cookie);
+    mac.update(client_nonce);
+    mac.update(server_nonce);
+    mac
+}
+
+/// 控制协议的QuotedString，含控制字符的值会破坏命令行，直接拒绝
+fn quote(value: &str) -> io::Result<String> {
+    if value.chars().any(char::is_control) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "控制端口参数含有控制字符"));
+    }
+    Ok(format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"")))
+}
+
+/// 通过Tor控制端口发布onion服务
+///
+/// 使用`ADD_ONION`创建v3 onion服务，私钥保存在`onion_key_file`中以保持地址不变。
+/// 服务随控制连接存在，`disconnect`或进程退出后由Tor移除
+pub struct TorController {
+    config: TorConfig,
+    connection: Mutex<Option<ControlConnection>>,
+    service_id: parking_lot::Mutex<Option<String>>,
+}
+
+impl TorController {
+    pub fn new(config: TorConfig) -> Self {
+        Self {
+            config,
+            connection: Mutex::new(None),
+            service_id: parking_lot::Mutex::new(None),
+        }
+    }
+}
+
+#[async_trait]
+impl TorNetwork for TorController {
+    async fn get_onion_address(&self) -> io::Result<String> {
+        self.service_id.lock().as_ref()
+            .map(|id| format!("{}.onion", id))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "尚未发布onion服务"))
+    }
+    
+    async fn publish_services(&self, ports: &[(u16, SocketAddr)]) -> io::Result<String> {
+        let mut connection = self.connection.lock().await;
+        let connection = connection.as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "未连接Tor控制端口"))?;
+        
+        let previous = self.service_id.lock().take();
+        if let Some(service_id) = previous {
+            // 重新发布前移除旧服务
+            let _ = connection.command(&format!("DEL_ONION {}", service_id)).await;
+        }
+        
+        let key = load_onion_key(&self.config.onion_key_file)?;
+        let key_spec = key.as_deref().map_or("NEW:ED25519-V3", |k| k.as_str());
+        let mut command = Zeroizing::new(format!("ADD_ONION {}", key_spec));
+        for (port, target) in ports {
+            command.push_str(&format!(" Port={},{}", port, target));
+        }
+        let reply = connection.command(&command).await?;
+        
+        let service_id = reply.iter()
+            .find_map(|line| line.strip_prefix("ServiceID="))
+            .map(str::to_string)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "ADD_ONION回复中没有ServiceID"))?;
+        if key.is_none() {
+            let private_key = reply.iter()
+                .find_map(|line| line.strip_prefix("PrivateKey="))
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "ADD_ONION回复中没有PrivateKey"))?;
+            save_onion_key(&self.config.onion_key_file, private_key)?;
+        }
+        
+        info!("Onion服务已发布: {}.onion", service_id);
+        *self.service_id.lock() = Some(service_id.clone());
+        Ok(format!("{}.onion", service_id))
+    }
+    
+    async fn connect(&self) -> io::Result<()> {
+        let mut connection = ControlConnection::open(&self.config.control_addr).await?;
+        connection.authenticate(&self.config).await?;
+        debug!("已连接Tor控制端口: {}", self.config.control_addr);
+        *self.connection.lock().await = Some(connection);
+        Ok(())
+    }
+    
+    async fn disconnect(&self) -> io::Result<()> {
+        let mut connection = self.connection.lock().await;
+        let service_id = self.service_id.lock().take();
+        if let (Some(conn), Some(service_id)) = (connection.as_mut(), service_id) {
+            if let Err(e) = conn.command(&format!("DEL_ONION {}", service_id)).await {
+                warn!("移除onion服务失败: {}", e);
+            }
+        }
+        *connection = None;
+        Ok(())
+    }
+}
+
+/// 读取保存的onion服务私钥（`ED25519-V3:<base64>`）
+fn load_onion_key(path: &Path) -> io::Result<Option<Zeroizing<String>>> {
+    match fs::read_to_string(path) {
+        Ok(key) => {
+            let key = Zeroizing::new(key.trim().to_string());
+            if !key.starts_with("ED25519-V3:") {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("无效的onion私钥文件: {}", path.display())));
+            }
+            Ok(Some(key))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// 保存onion服务私钥，仅所有者可读写
+fn save_onion_key(path: &Path, key: &str) -> io::Result<()> {
+    crate::crypto::write_private_file(path, key.as_bytes())
+}
+
 /// Tor网络状态
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
 pub enum TorNetworkStatus {
     /// 已连接
     Connected,
@@ -132,4 +864,707 @@ pub enum TorNetworkStatus {
     Connecting,
     /// 连接错误
     Error,
-}
\ No newline at end of file
+}
+
+/// 单次探测的超时时间
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Tor连接健康状况
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TorHealth {
+    pub status: TorNetworkStatus,
+    /// 引导进度（0-100），控制端口不可用时未知
+    pub bootstrap_progress: Option<u8>,
+    pub proxy_reachable: bool,
+    pub control_reachable: bool,
+    /// 最近一次探测的错误
+    pub last_error: Option<String>,
+    /// 最近一次探测时间（Unix秒）
+    pub checked_at: u64,
+}
+
+impl Default for TorHealth {
+    fn default() -> Self {
+        Self {
+            status: TorNetworkStatus::Connecting,
+            bootstrap_progress: None,
+            proxy_reachable: false,
+            control_reachable: false,
+            last_error: None,
+            checked_at: 0,
+        }
+    }
+}
+
+impl TorHealth {
+    /// 除探测时间外是否相同
+    fn same_state(&self, other: &TorHealth) -> bool {
+        self.status == other.status
+            && self.bootstrap_progress == other.bootstrap_progress
+            && self.proxy_reachable == other.proxy_reachable
+            && self.control_reachable == other.control_reachable
+    }
+}
+
+/// 探测SOCKS5代理：完成方法协商即认为可用
+async fn probe_proxy(proxy_addr: &str) -> io::Result<()> {
+    let mut stream = TcpStream::connect(proxy_addr).await?;
+    stream.write_all(&[5, 1, 0]).await?;
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != 5 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "代理不是SOCKS5服务"));
+    }
+    Ok(())
+}
+
+/// 通过控制端口查询引导进度
+async fn probe_bootstrap(config: &TorConfig) -> io::Result<u8> {
+    let mut connection = ControlConnection::open(&config.control_addr).await?;
+    connection.authenticate(config).await?;
+    let reply = connection.command("GETINFO status/bootstrap-phase").await?;
+    reply.iter()
+        .flat_map(|line| line.split_whitespace())
+        .find_map(|field| field.strip_prefix("PROGRESS=")?.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "无法解析Tor引导进度"))
+}
+
+async fn with_timeout<T>(probe: impl std::future::Future<Output = io::Result<T>>) -> io::Result<T> {
+    tokio::time::timeout(PROBE_TIMEOUT, probe).await
+        .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "Tor探测超时")))
+}
+
+/// 探测SOCKS代理和控制端口，得到当前健康状况
+///
+/// 代理不可达时为`Disconnected`，不是SOCKS5服务时为`Error`；
+/// 代理可用但引导未完成时为`Connecting`，控制端口不可用时仅依据代理判断
+pub async fn check_health(config: &TorConfig) -> TorHealth {
+    let checked_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let (proxy, bootstrap) = tokio::join!(
+        with_timeout(probe_proxy(&config.proxy_addr)),
+        with_timeout(probe_bootstrap(config)),
+    );
+    
+    let mut health = TorHealth {
+        proxy_reachable: proxy.is_ok(),
+        control_reachable: bootstrap.is_ok(),
+        bootstrap_progress: bootstrap.as_ref().ok().copied(),
+        checked_at,
+        ..TorHealth::default()
+    };
+    health.status = match &proxy {
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => TorNetworkStatus::Error,
+        Err(_) => TorNetworkStatus::Disconnected,
+        Ok(()) if health.bootstrap_progress.is_some_and(|p| p < 100) => TorNetworkStatus::Connecting,
+        Ok(()) => TorNetworkStatus::Connected,
+    };
+    health.last_error = match (proxy, bootstrap) {
+        (Err(e), _) => Some(format!("SOCKS代理: {}", e)),
+        (_, Err(e)) => Some(format!("控制端口: {}", e)),
+        _ => None,
+    };
+    health
+}
+
+/// 启动后台健康监测，定期探测Tor并在状态变化时通知订阅者
+///
+/// 所有接收端都被丢弃后监测停止
+pub fn spawn_tor_monitor(connector: TorConnector) -> watch::Receiver<TorHealth> {
+    let (sender, receiver) = watch::channel(TorHealth::default());
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(connector.config.health_check_interval);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = sender.closed() => break,
+            }
+            let health = connector.health().await;
+            sender.send_if_modified(|current| {
+                let changed = !current.same_state(&health);
+                if changed {
+                    match health.status {
+                        TorNetworkStatus::Connected => info!("Tor连接正常"),
+                        TorNetworkStatus::Connecting => info!("Tor正在引导: {:?}%", health.bootstrap_progress),
+                        status => warn!("Tor不可用 ({:?}): {}", status, health.last_error.as_deref().unwrap_or_default()),
+                    }
+                }
+                *current = health;
+                changed
+            });
+        }
+    });
+    receiver
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("hancoin-tor-test-{}", uuid::Uuid::new_v4()))
+    }
+    
+    /// 模拟Tor控制端口：按顺序返回预设回复，收到的命令交给测试主体校验
+    struct FakeControlPort {
+        addr: String,
+        expected: Vec<String>,
+        commands: tokio::sync::mpsc::UnboundedReceiver<String>,
+    }
+    
+    impl FakeControlPort {
+        /// 等待连接结束，按顺序比较收到的命令前缀，返回收到的命令
+        async fn assert_commands(mut self) -> Vec<String> {
+            let mut received = Vec::new();
+            while let Some(line) = self.commands.recv().await {
+                received.push(line);
+            }
+            assert_eq!(received.len(), self.expected.len(), "commands: {:?}", received);
+            for (line, expected) in received.iter().zip(&self.expected) {
+                assert!(line.starts_with(expected.as_str()), "unexpected command: {}", line);
+            }
+            received
+        }
+    }
+    
+    /// 按收到的命令生成回复
+    type ControlReply = Box<dyn FnOnce(&str) -> String + Send>;
+    
+    async fn fake_control_port(script: Vec<(String, String)>) -> FakeControlPort {
+        fake_control_port_with(script.into_iter()
+            .map(|(command, reply)| (command, Box::new(move |_: &str| reply) as ControlReply))
+            .collect()).await
+    }
+    
+    async fn fake_control_port_with(script: Vec<(String, ControlReply)>) -> FakeControlPort {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let (sender, commands) = tokio::sync::mpsc::unbounded_channel();
+        let expected = script.iter().map(|(command, _)| command.clone()).collect();
+        tokio::spawn(async move {
+            let Ok((stream, _)) = listener.accept().await else { return };
+            let (reader, mut writer) = stream.into_split();
+            let mut reader = BufReader::new(reader);
+            for (_, reply) in script {
+                let mut line = String::new();
+                if !matches!(reader.read_line(&mut line).await, Ok(n) if n > 0) {
+                    break;
+                }
+                let reply = reply(&line);
+                let _ = sender.send(line);
+                if writer.write_all(reply.as_bytes()).await.is_err() {
+                    break;
+                }
+            }
+        });
+        FakeControlPort { addr, expected, commands }
+    }
+    
+    /// 模拟控制端口对AUTHCHALLENGE的回复，`cookie`为控制端口持有的cookie
+    fn auth_challenge_reply(cookie: [u8; COOKIE_LENGTH]) -> ControlReply {
+        Box::new(move |line: &str| {
+            let client_nonce = hex::decode(line.trim_end().rsplit(' ').next().unwrap()).unwrap();
+            let server_nonce = [0xcd; 32];
+            let server_hash = safe_cookie_mac(SAFECOOKIE_SERVER_KEY, &cookie, &client_nonce, &server_nonce);
+            format!(
+                "250 AUTHCHALLENGE SERVERHASH={} SERVERNONCE={}\r\n",
+                hex::encode(server_hash.finalize().into_bytes()),
+                hex::encode(server_nonce),
+            )
+        })
+    }
+    
+    /// PROTOCOLINFO回复，公布的cookie文件指向`cookie_file`
+    fn cookie_protocol_info(methods: &str, cookie_file: &Path) -> String {
+        format!(
+            "250-PROTOCOLINFO 1\r\n250-AUTH METHODS={} COOKIEFILE=\"{}\"\r\n250-VERSION Tor=\"0.4.8.9\"\r\n250 OK\r\n",
+            methods,
+            cookie_file.display(),
+        )
+    }
+    
+    #[tokio::test]
+    async fn test_publish_onion_with_cookie_auth() {
+        let dir = temp_dir();
+        fs::create_dir_all(&dir).unwrap();
+        let cookie_file = dir.join("control auth cookie");
+        fs::write(&cookie_file, [0xab; 32]).unwrap();
+        
+        let control = fake_control_port_with(vec![
+            ("PROTOCOLINFO 1".to_string(), Box::new(move |_: &str| cookie_protocol_info("COOKIE,SAFECOOKIE", &PathBuf::from("/ignored")))),
+            ("AUTHCHALLENGE SAFECOOKIE ".to_string(), auth_challenge_reply([0xab; 32])),
+            ("AUTHENTICATE ".to_string(), Box::new(|_: &str| "250 OK\r\n".to_string())),
+            ("ADD_ONION NEW:ED25519-V3 Port=4001,127.0.0.1:4001 Port=80,127.0.0.1:3030".to_string(),
+                Box::new(|_: &str| "250-ServiceID=exampleonionid\r\n250-PrivateKey=ED25519-V3:c2VjcmV0\r\n250 OK\r\n".to_string())),
+            ("DEL_ONION exampleonionid".to_string(), Box::new(|_: &str| "250 OK\r\n".to_string())),
+        ]).await;
+        
+        let controller = TorController::new(TorConfig {
+            enabled: true,
+            control_addr: control.addr.clone(),
+            control_cookie_file: Some(cookie_file.clone()),
+            onion_key_file: dir.join("onion_key"),
+            ..TorConfig::default()
+        });
+        assert!(controller.get_onion_address().await.is_err());
+        controller.connect().await.unwrap();
+        
+        let onion = controller.publish_services(&[
+            (4001, "127.0.0.1:4001".parse().unwrap()),
+            (80, "127.0.0.1:3030".parse().unwrap()),
+        ]).await.unwrap();
+        assert_eq!(onion, "exampleonionid.onion");
+        assert_eq!(controller.get_onion_address().await.unwrap(), onion);
+        
+        // 私钥被保存，仅所有者可读
+        assert_eq!(fs::read_to_string(dir.join("onion_key")).unwrap(), "ED25519-V3:c2VjcmV0");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(dir.join("onion_key")).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        
+        controller.disconnect().await.unwrap();
+        assert!(controller.get_onion_address().await.is_err());
+        
+        // 本端HMAC由配置的cookie和双方nonce计算，不发送cookie本身
+        let commands = control.assert_commands().await;
+        let client_nonce = hex::decode(commands[1].trim_end().rsplit(' ').next().unwrap()).unwrap();
+        let client_hash = safe_cookie_mac(SAFECOOKIE_CLIENT_KEY, &[0xab; 32], &client_nonce, &[0xcd; 32]);
+        assert_eq!(commands[2].trim_end(), format!("AUTHENTICATE {}", hex::encode(client_hash.finalize().into_bytes())));
+        assert!(!commands[2].contains(&"ab".repeat(32)));
+    }
+    
+    #[tokio::test]
+    async fn test_safe_cookie_rejections() {
+        let dir = temp_dir();
+        fs::create_dir_all(&dir).unwrap();
+        let cookie_file = dir.join("control_auth_cookie");
+        fs::write(&cookie_file, [0xab; 32]).unwrap();
+        let secret_file = dir.join("node_key");
+        fs::write(&secret_file, [0x42; 32]).unwrap();
+        let config = |control: &FakeControlPort, cookie: Option<&Path>| TorConfig {
+            control_addr: control.addr.clone(),
+            control_cookie_file: cookie.map(Path::to_path_buf),
+            ..TorConfig::default()
+        };
+        
+        // 控制端口不持有同一cookie时不发送AUTHENTICATE
+        let control = fake_control_port_with(vec![
+            ("PROTOCOLINFO 1".to_string(), Box::new(|_: &str| cookie_protocol_info("SAFECOOKIE", &PathBuf::from("/ignored")))),
+            ("AUTHCHALLENGE SAFECOOKIE ".to_string(), auth_challenge_reply([0xee; 32])),
+        ]).await;
+        let controller = TorController::new(config(&control, Some(&cookie_file)));
+        assert_eq!(controller.connect().await.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        control.assert_commands().await;
+        
+        // 控制端口公布的COOKIEFILE不会被读取；未配置cookie文件或只支持明文COOKIE时认证失败
+        for (methods, cookie) in [("COOKIE,SAFECOOKIE", None), ("COOKIE", Some(cookie_file.as_path()))] {
+            let info = cookie_protocol_info(methods, &secret_file);
+            let control = fake_control_port(vec![("PROTOCOLINFO 1".to_string(), info)]).await;
+            let controller = TorController::new(config(&control, cookie));
+            assert_eq!(controller.connect().await.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+            control.assert_commands().await;
+        }
+        
+        // 长度不是32字节的cookie文件被拒绝
+        let oversized = dir.join("oversized");
+        fs::write(&oversized, [0xab; 33]).unwrap();
+        let control = fake_control_port(vec![
+            ("PROTOCOLINFO 1".to_string(), cookie_protocol_info("SAFECOOKIE", &oversized)),
+        ]).await;
+        let controller = TorController::new(config(&control, Some(&oversized)));
+        assert_eq!(controller.connect().await.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        control.assert_commands().await;
+    }
+    
+    #[tokio::test]
+    async fn test_publish_onion_reuses_saved_key() {
+        let dir = temp_dir();
+        let key_file = dir.join("onion_key");
+        save_onion_key(&key_file, "ED25519-V3:c2VjcmV0").unwrap();
+        
+        let control = fake_control_port(vec![
+            ("PROTOCOLINFO 1".to_string(), "250-PROTOCOLINFO 1\r\n250-AUTH METHODS=HASHEDPASSWORD\r\n250 OK\r\n".to_string()),
+            ("AUTHENTICATE \"pa\\\"ss\"".to_string(), "250 OK\r\n".to_string()),
+            ("ADD_ONION ED25519-V3:c2VjcmV0 Port=4001,127.0.0.1:4001".to_string(),
+                "250-ServiceID=exampleonionid\r\n250 OK\r\n".to_string()),
+        ]).await;
+        
+        let controller = TorController::new(TorConfig {
+            enabled: true,
+            control_addr: control.addr.clone(),
+            control_password: Some("pa\"ss".to_string()),
+            onion_key_file: key_file,
+            ..TorConfig::default()
+        });
+        controller.connect().await.unwrap();
+        assert_eq!(controller.publish_service(4001).await.unwrap(), "exampleonionid.onion");
+        control.assert_commands().await;
+    }
+    
+    #[test]
+    fn test_socks_target() {
+        let onion = "/onion3/vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd:4001";
+        assert_eq!(
+            socks_target(&onion.parse().unwrap()),
+            Some(("vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd.onion".to_string(), 4001)),
+        );
+        assert_eq!(
+            socks_target(&"/dns/seed.hancoin.org/tcp/4001/p2p/12D3KooWQYV9dGMFoRzNStwpXztXaBUjtPqi6aU76ZgUriHhKust".parse().unwrap()),
+            Some(("seed.hancoin.org".to_string(), 4001)),
+        );
+        assert_eq!(socks_target(&"/ip6/::1/tcp/4001".parse().unwrap()), Some(("::1".to_string(), 4001)));
+        assert_eq!(socks_target(&"/ip4/1.2.3.4/udp/4001".parse().unwrap()), None);
+        assert_eq!(socks_target(&"/dns/seed.hancoin.org/tcp/443/ws".parse().unwrap()), None);
+        
+        assert!(TorConnector::is_onion_address("example.onion:4001"));
+        assert!(!TorConnector::is_onion_address("example.com:4001"));
+    }
+    
+    /// 模拟SOCKS5代理请求：目标地址和认证凭据
+    type ProxyRequest = ((String, u16), Option<(String, String)>);
+    
+    /// 模拟SOCKS5代理：记录请求的目标地址和凭据后回复连接成功
+    async fn fake_socks_proxy() -> (String, tokio::sync::oneshot::Receiver<ProxyRequest>) {
+        use tokio::io::AsyncReadExt;
+        
+        async fn read_string(stream: &mut TcpStream) -> String {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len).await.unwrap();
+            let mut value = vec![0u8; len[0] as usize];
+            stream.read_exact(&mut value).await.unwrap();
+            String::from_utf8(value).unwrap()
+        }
+        
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut greeting = [0u8; 2];
+            stream.read_exact(&mut greeting).await.unwrap();
+            let mut methods = vec![0u8; greeting[1] as usize];
+            stream.read_exact(&mut methods).await.unwrap();
+            
+            // 客户端提供用户名/密码认证时使用它
+            let credentials = if methods.contains(&2) {
+                stream.write_all(&[5, 2]).await.unwrap();
+                let mut version = [0u8; 1];
+                stream.read_exact(&mut version).await.unwrap();
+                let username = read_string(&mut stream).await;
+                let password = read_string(&mut stream).await;
+                stream.write_all(&[1, 0]).await.unwrap();
+                Some((username, password))
+            } else {
+                stream.write_all(&[5, 0]).await.unwrap();
+                None
+            };
+            
+            // 健康检查只做方法协商
+            let mut request = [0u8; 4];
+            if stream.read_exact(&mut request).await.is_err() {
+                return;
+            }
+            // 必须以域名形式（ATYP=3）交给代理解析
+            assert_eq!(request[3], 3, "target was resolved locally");
+            let host = read_string(&mut stream).await;
+            let mut port = [0u8; 2];
+            stream.read_exact(&mut port).await.unwrap();
+            stream.write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 0]).await.unwrap();
+            let _ = tx.send(((host, u16::from_be_bytes(port)), credentials));
+            let _ = stream.read(&mut [0u8; 1]).await;
+        });
+        (addr, rx)
+    }
+    
+    #[tokio::test]
+    async fn test_transport_dials_through_proxy() {
+        use libp2p::core::{transport::PortUse, Endpoint};
+        
+        let dial_opts = DialOpts { role: Endpoint::Dialer, port_use: PortUse::New };
+        for (addr, expected) in [
+            ("/dns/seed.hancoin.org/tcp/4001", "seed.hancoin.org"),
+            (
+                "/onion3/vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd:4001",
+                "vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd.onion",
+            ),
+        ] {
+            let (proxy_addr, target) = fake_socks_proxy().await;
+            let mut transport = TorTransport::new(
+                TorConnector::new(TorConfig { enabled: true, proxy_addr, ..TorConfig::default() }),
+                libp2p::tcp::tokio::Transport::new(libp2p::tcp::Config::default()),
+            );
+            transport.dial(addr.parse().unwrap(), dial_opts).unwrap().await.unwrap();
+            assert_eq!(target.await.unwrap().0, (expected.to_string(), 4001));
+        }
+        
+        let mut transport = TorTransport::new(
+            TorConnector::new(TorConfig::default()),
+            libp2p::tcp::tokio::Transport::new(libp2p::tcp::Config::default()),
+        );
+        assert!(matches!(
+            transport.dial("/ip4/1.2.3.4/udp/4001".parse().unwrap(), dial_opts),
+            Err(TransportError::MultiaddrNotSupported(_)),
+        ));
+    }
+    
+    #[tokio::test]
+    async fn test_only_onion_refuses_clearnet_dials() {
+        use libp2p::core::{transport::PortUse, Endpoint};
+        
+        let (proxy_addr, target) = fake_socks_proxy().await;
+        let config = TorConfig { enabled: true, only_onion: true, proxy_addr, ..TorConfig::default() };
+        assert!(config.is_strict());
+        let connector = TorConnector::new(config);
+        let mut transport = TorTransport::new(
+            connector.clone(),
+            libp2p::tcp::tokio::Transport::new(libp2p::tcp::Config::default()),
+        );
+        
+        // 明网地址在建立任何socket之前被拒绝：本机监听器收不到任何连接
+        let clearnet = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let clearnet_port = clearnet.local_addr().unwrap().port();
+        let local = format!("/ip4/127.0.0.1/tcp/{}", clearnet_port);
+        let dial_opts = DialOpts { role: Endpoint::Dialer, port_use: PortUse::New };
+        for addr in [local.as_str(), "/ip4/1.2.3.4/tcp/4001", "/dns/seed.hancoin.org/tcp/4001", "/ip6/::1/tcp/4001"] {
+            match transport.dial(addr.parse().unwrap(), dial_opts) {
+                Err(TransportError::Other(TorError::Policy(_))) => {}
+                _ => panic!("clearnet dial to {} was not refused", addr),
+            }
+        }
+        assert!(matches!(connector.connect("seed.hancoin.org:4001").await, Err(TorError::Policy(_))));
+        assert!(matches!(connector.connect(&format!("127.0.0.1:{}", clearnet_port)).await, Err(TorError::Policy(_))));
+        let accepted = tokio::time::timeout(Duration::from_millis(500), clearnet.accept()).await;
+        assert!(accepted.is_err(), "strict Tor transport opened a clearnet socket");
+        
+        // 代理收到的第一个请求就是onion地址，说明之前没有经过代理的明网连接
+        let onion = "/onion3/vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd:4001";
+        transport.dial(onion.parse().unwrap(), dial_opts).unwrap().await.unwrap();
+        let ((host, _), _) = target.await.unwrap();
+        assert!(host.ends_with(".onion"));
+    }
+    
+    #[tokio::test]
+    async fn test_stream_isolation() {
+        let connector = TorConnector::new(TorConfig { enabled: true, ..TorConfig::default() });
+        
+        let mut credentials = Vec::new();
+        for isolation in [
+            StreamIsolation::Peer,
+            connector.fresh_isolation("coinjoin-input"),
+            connector.fresh_isolation("coinjoin-output"),
+            connector.fresh_isolation("coinjoin-output"),
+            connector.fresh_isolation("coinjoin-signature"),
+        ] {
+            let (proxy_addr, target) = fake_socks_proxy().await;
+            let connector = TorConnector { config: TorConfig { proxy_addr, ..connector.config.clone() }, ..connector.clone() };
+            connector.connect_isolated("example.onion", 80, &isolation).await.unwrap();
+            credentials.push(target.await.unwrap().1.unwrap());
+        }
+        
+        // 节点连接和每个阶段的每次登记使用不同凭据，即不同线路
+        for (i, a) in credentials.iter().enumerate() {
+            for b in &credentials[i + 1..] {
+                assert_ne!(a, b);
+            }
+        }
+        // 节点连接复用凭据
+        assert_eq!(
+            StreamIsolation::Peer.credentials(&connector.isolation_nonce),
+            credentials[0],
+        );
+        // 其他连接器实例使用不同凭据
+        let other = TorConnector::new(TorConfig::default());
+        assert_ne!(StreamIsolation::Peer.credentials(&other.isolation_nonce), credentials[0]);
+    }
+    
+    #[tokio::test]
+    async fn test_health_check() {
+        // 代理不可达
+        let unused = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let unreachable = unused.local_addr().unwrap().to_string();
+        drop(unused);
+        let health = check_health(&TorConfig {
+            proxy_addr: unreachable.clone(),
+            control_addr: unreachable.clone(),
+            ..TorConfig::default()
+        }).await;
+        assert_eq!(health.status, TorNetworkStatus::Disconnected);
+        assert!(!health.proxy_reachable && !health.control_reachable);
+        assert!(health.last_error.is_some());
+        
+        for (progress, status) in [(45, TorNetworkStatus::Connecting), (100, TorNetworkStatus::Connected)] {
+            let (proxy_addr, _) = fake_socks_proxy().await;
+            let control = fake_control_port(vec![
+                ("PROTOCOLINFO 1".to_string(), "250-PROTOCOLINFO 1\r\n250-AUTH METHODS=NULL\r\n250 OK\r\n".to_string()),
+                ("AUTHENTICATE".to_string(), "250 OK\r\n".to_string()),
+                ("GETINFO status/bootstrap-phase".to_string(), format!(
+                    "250-status/bootstrap-phase=NOTICE BOOTSTRAP PROGRESS={} TAG=done SUMMARY=\"Done\"\r\n250 OK\r\n",
+                    progress,
+                )),
+            ]).await;
+            let health = check_health(&TorConfig { proxy_addr, control_addr: control.addr.clone(), ..TorConfig::default() }).await;
+            control.assert_commands().await;
+            assert_eq!(health.status, status);
+            assert_eq!(health.bootstrap_progress, Some(progress));
+            assert!(health.last_error.is_none());
+        }
+        
+        // 控制端口不可用时只依据代理判断
+        let (proxy_addr, _) = fake_socks_proxy().await;
+        let health = check_health(&TorConfig { proxy_addr, control_addr: unreachable, ..TorConfig::default() }).await;
+        assert_eq!(health.status, TorNetworkStatus::Connected);
+        assert!(health.proxy_reachable && !health.control_reachable);
+        assert_eq!(health.bootstrap_progress, None);
+    }
+    
+    #[test]
+    fn test_parse_host_port() {
+        assert_eq!(parse_host_port("127.0.0.1:9050").unwrap(), ("127.0.0.1".to_string(), 9050));
+        assert_eq!(parse_host_port("tor.local:9050").unwrap(), ("tor.local".to_string(), 9050));
+        assert_eq!(parse_host_port("[::1]:9050").unwrap(), ("::1".to_string(), 9050));
+        for invalid in ["::1:9050", "[::1]", "[not-ipv6]:9050", "host", "host:0", "host:http", ":9050"] {
+            assert!(matches!(parse_host_port(invalid), Err(TorError::InvalidTarget(_))), "{}", invalid);
+        }
+        assert!(TorConnector::is_onion_address("example.onion:80"));
+        assert!(!TorConnector::is_onion_address("[::1]:80"));
+    }
+    
+    #[tokio::test]
+    async fn test_socks_auth_hostname_proxy_and_timeout() {
+        // 主机名代理地址和配置的认证凭据，普通SOCKS5代理收到原样的用户名和密码
+        let (proxy_addr, target) = fake_socks_proxy().await;
+        let port = proxy_addr.rsplit_once(':').unwrap().1;
+        let connector = TorConnector::new(TorConfig {
+            enabled: true,
+            proxy_addr: format!("localhost:{}", port),
+            proxy_auth: Some(ProxyAuth {
+                username: "alice".to_string(),
+                password: Zeroizing::new("secret".to_string()),
+                isolate_with_auth: false,
+            }),
+            ..TorConfig::default()
+        });
+        assert!(!format!("{:?}", connector.config).contains("secret"));
+        connector.connect("seed.hancoin.org:4001").await.unwrap();
+        let ((host, port), credentials) = target.await.unwrap();
+        assert_eq!((host.as_str(), port), ("seed.hancoin.org", 4001));
+        assert_eq!(credentials.unwrap(), ("alice".to_string(), "secret".to_string()));
+        
+        let (proxy_addr, target) = fake_socks_proxy().await;
+        let plain = TorConnector { config: TorConfig { proxy_addr, ..connector.config.clone() }, ..connector.clone() };
+        let isolation = plain.fresh_isolation("coinjoin-output");
+        plain.connect_isolated("example.onion", 80, &isolation).await.unwrap();
+        assert_eq!(target.await.unwrap().1.unwrap(), ("alice".to_string(), "secret".to_string()));
+        
+        // 开启isolate_with_auth时按用途在用户名后附加隔离标签
+        let mut config = connector.config.clone();
+        if let Some(auth) = config.proxy_auth.as_mut() {
+            auth.isolate_with_auth = true;
+        }
+        let mut usernames = Vec::new();
+        for isolation in [StreamIsolation::Peer, connector.fresh_isolation("coinjoin-output")] {
+            let (proxy_addr, target) = fake_socks_proxy().await;
+            let tagged = TorConnector { config: TorConfig { proxy_addr, ..config.clone() }, ..connector.clone() };
+            tagged.connect_isolated("example.onion", 80, &isolation).await.unwrap();
+            let (username, password) = target.await.unwrap().1.unwrap();
+            assert!(username.starts_with("alice+"));
+            assert_eq!(password, "secret");
+            usernames.push(username);
+        }
+        assert_ne!(usernames[0], usernames[1]);
+        
+        // 无效的代理和目标地址
+        let connector = TorConnector::new(TorConfig { proxy_addr: "::1:9050".to_string(), ..TorConfig::default() });
+        assert!(matches!(connector.connect_host("example.onion", 80).await, Err(TorError::InvalidProxy(_))));
+        assert!(matches!(connector.connect("example.onion").await, Err(TorError::InvalidTarget(_))));
+        
+        // 代理不回应时超时
+        let silent = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let connector = TorConnector::new(TorConfig {
+            proxy_addr: silent.local_addr().unwrap().to_string(),
+            connect_timeout: Duration::from_millis(200),
+            ..TorConfig::default()
+        });
+        let e = connector.connect_host("example.onion", 80).await.err().unwrap();
+        assert!(matches!(e, TorError::Timeout(_)));
+        assert_eq!(io::Error::from(e).kind(), io::ErrorKind::TimedOut);
+    }
+    
+    #[test]
+    fn test_backend_selection() {
+        assert_eq!("socks".parse::<TorBackend>().unwrap(), TorBackend::Socks);
+        assert_eq!("embedded".parse::<TorBackend>().unwrap(), TorBackend::Embedded);
+        assert!("tor".parse::<TorBackend>().is_err());
+        assert!(TorConnector::from_config(TorConfig::default()).is_ok());
+        
+        // 未编译arti功能时不能选择内嵌客户端
+        #[cfg(not(feature = "arti"))]
+        {
+            let config = TorConfig { backend: TorBackend::Embedded, ..TorConfig::default() };
+            let e = TorConnector::from_config(config).err().unwrap();
+            assert_eq!(e.kind(), io::ErrorKind::Unsupported);
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_check_external_bridges() {
+        let bridges = vec![
+            "obfs4 192.0.2.1:443 0123456789ABCDEF0123456789ABCDEF01234567 cert=abc iat-mode=0".to_string(),
+            "192.0.2.2:9001".to_string(),
+        ];
+        
+        // 只查询外部tor的配置，不发送SETCONF
+        for (reply, enabled) in [("250 UseBridges=1\r\n", true), ("250 UseBridges=0\r\n", false)] {
+            let control = fake_control_port(vec![
+                ("PROTOCOLINFO 1".to_string(), "250-PROTOCOLINFO 1\r\n250-AUTH METHODS=NULL\r\n250 OK\r\n".to_string()),
+                ("AUTHENTICATE".to_string(), "250 OK\r\n".to_string()),
+                ("GETCONF UseBridges".to_string(), reply.to_string()),
+            ]).await;
+            let connector = TorConnector::new(TorConfig {
+                control_addr: control.addr.clone(),
+                bridges: bridges.clone(),
+                ..TorConfig::default()
+            });
+            assert_eq!(connector.check_bridges().await.unwrap(), enabled);
+            control.assert_commands().await;
+        }
+        
+        // 未配置网桥时不访问控制端口；控制端口不可用时报告错误
+        let unreachable = TorConfig { control_addr: "127.0.0.1:1".to_string(), ..TorConfig::default() };
+        assert!(TorConnector::new(unreachable.clone()).check_bridges().await.unwrap());
+        let with_bridges = TorConfig { bridges: bridges.clone(), ..unreachable };
+        assert!(TorConnector::new(with_bridges).check_bridges().await.is_err());
+        
+        // 含换行的网桥行和控制端口密码被拒绝
+        for line in ["192.0.2.2:9001\r\nSETCONF SocksPort=0", "192.0.2.2:9001\n"] {
+            let config = TorConfig { bridges: vec![line.to_string()], ..TorConfig::default() };
+            assert_eq!(TorConnector::from_config(config).err().unwrap().kind(), io::ErrorKind::InvalidInput);
+        }
+        let config = TorConfig { transport_plugins: vec!["obfs4 exec\r/bin/sh".to_string()], ..TorConfig::default() };
+        assert!(TorConnector::from_config(config).is_err());
+        assert!(quote("pa\r\nss").is_err());
+        assert_eq!(quote("pa\"ss").unwrap(), "\"pa\\\"ss\"");
+    }
+    
+    #[tokio::test]
+    async fn test_control_port_errors() {
+        let control = fake_control_port(vec![
+            ("PROTOCOLINFO 1".to_string(), "250-PROTOCOLINFO 1\r\n250-AUTH METHODS=NULL\r\n250 OK\r\n".to_string()),
+            ("AUTHENTICATE".to_string(), "515 Authentication failed\r\n".to_string()),
+        ]).await;
+        
+        let controller = TorController::new(TorConfig {
+            control_addr: control.addr.clone(),
+            ..TorConfig::default()
+        });
+        assert!(controller.connect().await.is_err());
+        control.assert_commands().await;
+        assert_eq!(
+            controller.publish_service(4001).await.unwrap_err().kind(),
+            io::ErrorKind::NotConnected,
+        );
+    }
+}
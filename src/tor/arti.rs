@@ -0,0 +1,234 @@
+//! 内嵌的arti Tor客户端
+//!
+//! 启用`arti`功能后可在进程内连接Tor网络并托管onion服务，
+//! 不再需要单独运行tor进程。状态、缓存和onion服务密钥保存在`TorConfig::embedded_dir`中
+
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use arti_client::config::pt::TransportConfigBuilder;
+use arti_client::config::{BridgeConfigBuilder, CfgPath, TorClientConfigBuilder};
+use arti_client::{DataStream, IsolationToken, StreamPrefs, TorClient};
+use async_trait::async_trait;
+use futures::{Stream, StreamExt};
+use parking_lot::Mutex;
+use safelog::DisplayRedacted;
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+use tor_cell::relaycell::msg::Connected;
+use tor_hsservice::config::OnionServiceConfigBuilder;
+use tor_hsservice::{handle_rend_requests, RunningOnionService, StreamRequest};
+use tor_proto::stream::IncomingStreamRequest;
+use tor_rtcompat::PreferredRuntime;
+use log::{debug, info, warn};
+
+use super::{TorConfig, TorHealth, TorNetwork, TorNetworkStatus};
+
+/// onion服务在arti密钥库中的名称
+const SERVICE_NICKNAME: &str = "hancoin";
+
+fn other_error(e: impl std::fmt::Display) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+/// 正在运行的onion服务及其转发任务
+struct OnionService {
+    service: Arc<RunningOnionService>,
+    forwarder: JoinHandle<()>,
+}
+
+/// 内嵌的arti客户端
+///
+/// 创建后在后台引导，引导完成前健康状态为`Connecting`
+pub struct EmbeddedTor {
+    client: TorClient<PreferredRuntime>,
+    /// 按隔离凭据分配的共享线路隔离标记，一次性隔离不在此缓存
+    isolation: Mutex<HashMap<(String, String), IsolationToken>>,
+    service: Mutex<Option<OnionService>>,
+}
+
+impl EmbeddedTor {
+    /// 创建客户端并在后台开始引导，需要在tokio运行时中调用
+    pub fn new(config: &TorConfig) -> io::Result<Self> {
+        let mut builder = TorClientConfigBuilder::from_directories(
+            config.embedded_dir.join("state"),
+            config.embedded_dir.join("cache"),
+        );
+        for bridge in &config.bridges {
+            let bridge: BridgeConfigBuilder = bridge.parse().map_err(other_error)?;
+            builder.bridges().bridges().push(bridge);
+        }
+        for plugin in &config.transport_plugins {
+            builder.bridges().transports().push(transport_plugin(plugin)?);
+        }
+        let client_config = builder.build().map_err(other_error)?;
+        let client = TorClient::builder()
+            .config(client_config)
+            .create_unbootstrapped()
+            .map_err(other_error)?;
+
+        let bootstrapping = client.clone();
+        tokio::spawn(async move {
+            match bootstrapping.bootstrap().await {
+                Ok(()) => info!("内嵌Tor客户端引导完成"),
+                Err(e) => warn!("内嵌Tor客户端引导失败: {}", e),
+            }
+        });
+
+        Ok(Self {
+            client,
+            isolation: Mutex::new(HashMap::new()),
+            service: Mutex::new(None),
+        })
+    }
+
+    /// 按隔离凭据连接目标，相同凭据共享线路；未提供凭据时使用新的线路
+    pub(super) async fn connect_stream(&self, host: &str, port: u16, isolation: Option<(String, String)>) -> io::Result<DataStream> {
+        let token = match isolation {
+            Some(isolation) => *self.isolation.lock().entry(isolation).or_insert_with(IsolationToken::new),
+            None => IsolationToken::new(),
+        };
+        let mut prefs = StreamPrefs::new();
+        prefs.set_isolation(token);
+        prefs.connect_to_onion_services(arti_client::config::BoolOrAuto::Explicit(true));
+        self.client.connect_with_prefs((host, port), &prefs).await
+            .map_err(|e| io::Error::new(io::ErrorKind::ConnectionRefused, format!("通过Tor连接失败: {}", e)))
+    }
+
+    /// 引导进度即健康状况；内嵌客户端没有独立的代理和控制端口，二者随引导完成视为可用
+    pub(super) fn health(&self) -> TorHealth {
+        let status = self.client.bootstrap_status();
+        let ready = status.ready_for_traffic();
+        let blocked = status.blocked().map(|b| b.to_string());
+        TorHealth {
+            status: match (ready, &blocked) {
+                (true, _) => TorNetworkStatus::Connected,
+                (false, Some(_)) => TorNetworkStatus::Error,
+                (false, None) => TorNetworkStatus::Connecting,
+            },
+            bootstrap_progress: Some((status.as_frac() * 100.0).round() as u8),
+            proxy_reachable: ready,
+            control_reachable: ready,
+            last_error: blocked,
+            checked_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+        }
+    }
+}
+
+/// 解析`<transport>[,<transport>...] exec <path> [args...]`形式的插件配置
+fn transport_plugin(line: &str) -> io::Result<TransportConfigBuilder> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, format!("无效的传输插件配置: {}", line));
+    let mut parts = line.split_whitespace();
+    let protocols = parts.next().ok_or_else(invalid)?
+        .split(',')
+        .map(|name| name.parse().map_err(other_error))
+        .collect::<io::Result<Vec<_>>>()?;
+    if parts.next() != Some("exec") {
+        return Err(invalid());
+    }
+    let path = parts.next().ok_or_else(invalid)?;
+
+    let mut transport = TransportConfigBuilder::default();
+    transport
+        .protocols(protocols)
+        .path(CfgPath::new(path.to_string()))
+        .arguments(parts.map(str::to_string).collect())
+        .run_on_startup(true);
+    Ok(transport)
+}
+
+/// 把onion服务收到的流按端口转发到本地地址
+async fn forward_streams(requests: impl Stream<Item = StreamRequest>, ports: HashMap<u16, SocketAddr>) {
+    futures::pin_mut!(requests);
+    while let Some(request) = requests.next().await {
+        let target = match request.request() {
+            IncomingStreamRequest::Begin(begin) => ports.get(&begin.port()).copied(),
+            _ => None,
+        };
+        let Some(target) = target else {
+            let _ = request.shutdown_circuit();
+            continue;
+        };
+        tokio::spawn(async move {
+            let mut local = match TcpStream::connect(target).await {
+                Ok(local) => local,
+                Err(e) => {
+                    warn!("无法连接onion服务的本地目标 {}: {}", target, e);
+                    return;
+                }
+            };
+            match request.accept(Connected::new_empty()).await {
+                Ok(mut stream) => {
+                    let _ = tokio::io::copy_bidirectional(&mut stream, &mut local).await;
+                }
+                Err(e) => debug!("接受onion服务连接失败: {}", e),
+            }
+        });
+    }
+}
+
+#[async_trait]
+impl TorNetwork for Arc<EmbeddedTor> {
+    async fn get_onion_address(&self) -> io::Result<String> {
+        self.service.lock().as_ref()
+            .and_then(|s| s.service.onion_address())
+            .map(|id| id.display_unredacted().to_string())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "尚未发布onion服务"))
+    }
+
+    async fn publish_services(&self, ports: &[(u16, SocketAddr)]) -> io::Result<String> {
+        self.disconnect().await?;
+
+        let config = OnionServiceConfigBuilder::default()
+            .nickname(SERVICE_NICKNAME.parse().map_err(other_error)?)
+            .build()
+            .map_err(other_error)?;
+        let (service, requests) = self.client.launch_onion_service(config)
+            .map_err(other_error)?;
+        let forwarder = tokio::spawn(forward_streams(
+            handle_rend_requests(requests),
+            ports.iter().copied().collect(),
+        ));
+
+        let onion = service.onion_address()
+            .map(|id| id.display_unredacted().to_string())
+            .ok_or_else(|| other_error("onion地址尚未生成"))?;
+        info!("Onion服务已发布: {}", onion);
+        *self.service.lock() = Some(OnionService { service, forwarder });
+        Ok(onion)
+    }
+
+    async fn connect(&self) -> io::Result<()> {
+        self.client.bootstrap().await.map_err(other_error)
+    }
+
+    async fn disconnect(&self) -> io::Result<()> {
+        // 丢弃服务句柄即停止发布
+        if let Some(service) = self.service.lock().take() {
+            service.forwarder.abort();
+            drop(service.service);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transport_plugin() {
+        assert!(transport_plugin("obfs4 exec /usr/bin/obfs4proxy").is_ok());
+        assert!(transport_plugin("obfs4,meek exec /usr/bin/lyrebird -enableLogging").is_ok());
+        assert!(transport_plugin("snowflake exec /usr/bin/snowflake-client -url https://example.com/").unwrap().build().is_ok());
+
+        // 缺少exec、程序路径或传输名称
+        assert!(transport_plugin("obfs4 /usr/bin/obfs4proxy").is_err());
+        assert!(transport_plugin("obfs4 exec").is_err());
+        assert!(transport_plugin("").is_err());
+        // 非法的传输名称
+        assert!(transport_plugin("obfs-4! exec /usr/bin/obfs4proxy").is_err());
+    }
+}
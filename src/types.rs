@@ -1,29 +1,21 @@
 use serde::{Serialize, Deserialize};
-use serde_with::{serde_as, skip_serializing_none};
-use std::sync::atomic::AtomicU64;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::collections::{VecDeque, HashMap};
-use log::{warn, info, debug, error};
+use std::num::NonZeroUsize;
 use std::time::{SystemTime, UNIX_EPOCH};
 use regex::Regex;
-use lazy_static::lazy_static;
-use sha2::{Digest, Sha256};
-use parking_lot::RwLock;
+use parking_lot::{RwLock, RwLockReadGuard};
 use once_cell::sync::Lazy;
-use std::hash::Hasher;
-use twox_hash::XxHash64;
 use dashmap::DashMap;
 use lru::LruCache;
-use serde_bytes;
+use crate::crypto::{self, CryptoError};
 
 // 使用once_cell替代lazy_static
 static ACCOUNT_ID_REGEX: Lazy<Regex> = Lazy::new(|| {
     Regex::new(r"^[1-9A-HJ-NP-Za-km-z]{32,44}$").expect("Invalid account ID regex")
 });
 
-// 优化的哈希器实例
-static HASHER: Lazy<XxHash64> = Lazy::new(|| XxHash64::default());
-
 /// 验证账户ID格式
 pub fn is_valid_account_id(account_id: &str) -> bool {
     ACCOUNT_ID_REGEX.is_match(account_id)
@@ -32,7 +24,9 @@ pub fn is_valid_account_id(account_id: &str) -> bool {
 /// 最大交易历史记录数
 const MAX_TX_HISTORY: usize = 100;
 /// 最大动态消息长度
-const MAX_MOMENT_LENGTH: usize = 280;
+pub const MAX_MOMENT_LENGTH: usize = 280;
+/// 动态时间戳与本地时间的最大偏差(秒)，超出时不再接收
+pub const MOMENT_WINDOW: u64 = 300;
 
 /// 水龙头冷却时间(秒)
 pub const FAUCET_COOLDOWN: u64 = 86400; // 24小时
@@ -108,7 +102,7 @@ impl Default for Account {
             last_claim: 0,
             transactions: VecDeque::with_capacity(100), // 保存最近100笔交易
             created_at: now,
-            last_active: now,
+            last_active: 0, // 尚无活动
             nonce: 0,
             status: AccountStatus::Active,
         }
@@ -204,22 +198,99 @@ pub struct Comment {
     pub timestamp: u64,
 }
 
+/// 带发送方签名的转账，节点之间转发时由接收方重新验证
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SignedTransfer {
+    pub tx: Tx,
+    /// 发送方账户的交易序号
+    pub nonce: u64,
+    /// 发送方对`signing_message`的hex签名
+    pub signature: String,
+}
+
+impl SignedTransfer {
+    /// 转账签名消息，覆盖节点之间转发的全部交易字段
+    ///
+    /// JSON数组`[id, from, to, amount, fee, timestamp, memo, nonce]`，字段内容中的分隔符不会产生歧义
+    pub fn signing_message(tx: &Tx, nonce: u64) -> String {
+        serde_json::json!([tx.id, tx.from, tx.to, tx.amount, tx.fee, tx.timestamp, tx.memo, nonce]).to_string()
+    }
+    
+    /// 检查字段和签名，不涉及账本状态
+    ///
+    /// 转账验证后立即记账，只接受`Completed`状态
+    pub fn verify(&self) -> Result<(), HancoinError> {
+        let tx = &self.tx;
+        if tx.amount == 0 || tx.from == tx.to || tx.to.is_empty() || tx.id.is_empty()
+            || !matches!(tx.status, TxStatus::Completed)
+        {
+            return Err(HancoinError::InvalidTransaction);
+        }
+        let message = Self::signing_message(tx, self.nonce);
+        verify_account_signature(&tx.from, &self.signature, message.as_bytes())
+    }
+}
+
+/// 带作者签名的动态
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SignedMoment {
+    pub moment: Moment,
+    /// 作者对`<timestamp>:<content>`的hex签名
+    pub signature: String,
+}
+
+impl SignedMoment {
+    /// 动态签名消息
+    pub fn signing_message(timestamp: u64, content: &str) -> String {
+        format!("{}:{}", timestamp, content)
+    }
+    
+    /// 动态ID，由作者和签名覆盖的时间戳、内容派生，转发节点无法为同一条动态换用新的ID
+    pub fn moment_id(author: &str, timestamp: u64, content: &str) -> String {
+        let data = serde_json::json!([author, timestamp, content]).to_string();
+        hex::encode(blake3::hash(data.as_bytes()).as_bytes())
+    }
+    
+    /// 检查内容长度、ID和签名
+    pub fn verify(&self) -> Result<(), HancoinError> {
+        let moment = &self.moment;
+        if moment.content.trim().is_empty() || moment.content.chars().count() > MAX_MOMENT_LENGTH
+            || moment.id != Self::moment_id(&moment.author, moment.timestamp, &moment.content)
+        {
+            return Err(HancoinError::InvalidMoment);
+        }
+        let message = Self::signing_message(moment.timestamp, &moment.content);
+        verify_account_signature(&moment.author, &self.signature, message.as_bytes())
+    }
+}
+
+/// 验证hex账户对原始消息的hex签名
+fn verify_account_signature(account_id: &str, signature: &str, message: &[u8]) -> Result<(), HancoinError> {
+    let public_key = crypto::public_key_from_hex(account_id).map_err(|e| match e {
+        CryptoError::InvalidPublicKey => HancoinError::InvalidPublicKey,
+        _ => HancoinError::InvalidAccountIdFormat,
+    })?;
+    let signature = crypto::signature_from_hex(signature)
+        .map_err(|_| HancoinError::InvalidSignatureFormat)?;
+    crypto::verify_raw(&public_key, message, &signature)
+        .map_err(|_| HancoinError::InvalidSignature)
+}
+
 /// 优化的账本结构体
-#[serde_as]
-#[derive(Serialize, Deserialize)]
 pub struct Ledger {
     pub accounts: Arc<DashMap<String, Account>>,
     pub issued: AtomicU64,
     pub transactions: Arc<DashMap<String, Tx>>,
+    /// 本节点验证过的转账的(nonce, 签名)，键为交易ID；交易区间同步时随交易发送，接收方重新验证
+    pub transfer_proofs: Arc<DashMap<String, (u64, String)>>,
     pub moments: Arc<DashMap<String, Moment>>,
     // 添加缓存优化频繁访问的数据
-    #[serde(skip)]
     pub cache: Arc<RwLock<LruCache<String, Account>>>,
     // 缓存统计
-    #[serde(skip)]
     pub cache_hits: AtomicU64,
-    #[serde(skip)]
     pub cache_misses: AtomicU64,
+    // 快照恢复持有写锁，修改账本或需要一致视图的调用方持有读锁
+    state_lock: RwLock<()>,
 }
 
 impl Default for Ledger {
@@ -228,24 +299,127 @@ impl Default for Ledger {
             accounts: Arc::new(DashMap::new()),
             issued: AtomicU64::new(0),
             transactions: Arc::new(DashMap::new()),
+            transfer_proofs: Arc::new(DashMap::new()),
             moments: Arc::new(DashMap::new()),
-            cache: Arc::new(RwLock::new(LruCache::new(1000))), // 缓存1000个账户
+            cache: Arc::new(RwLock::new(LruCache::new(NonZeroUsize::new(1000).expect("non-zero capacity")))), // 缓存1000个账户
             cache_hits: AtomicU64::new(0),
             cache_misses: AtomicU64::new(0),
+            state_lock: RwLock::new(()),
         }
     }
 }
 
 impl Ledger {
+    /// 验证并执行转账：签名、nonce和余额都通过后才记账，返回发送方的新余额
+    ///
+    /// 本地API和P2P广播的转账都经过这里；同一笔转账重复提交时nonce检查失败
+    pub fn apply_transfer(&self, transfer: &SignedTransfer) -> Result<u64, HancoinError> {
+        transfer.verify()?;
+        let _state = self.read_state();
+        let tx = &transfer.tx;
+        if self.transactions.contains_key(&tx.id) {
+            return Err(HancoinError::InvalidTransaction);
+        }
+        
+        // 扣款：nonce必须与账户当前nonce一致，防止重放
+        let balance = {
+            let mut from = self.accounts.get_mut(&tx.from).ok_or(HancoinError::AccountNotFound)?;
+            if from.nonce != transfer.nonce || from.balance < tx.amount {
+                return Err(HancoinError::InvalidTransaction);
+            }
+            from.balance -= tx.amount;
+            from.nonce += 1;
+            from.add_transaction(TxRef {
+                tx_id: tx.id.clone(),
+                timestamp: tx.timestamp,
+                amount: tx.amount,
+                is_incoming: false,
+            });
+            from.balance
+        };
+        
+        // 入账
+        {
+            let mut to = self.accounts.entry(tx.to.clone()).or_default();
+            to.balance = to.balance.saturating_add(tx.amount);
+            to.add_transaction(TxRef {
+                tx_id: tx.id.clone(),
+                timestamp: tx.timestamp,
+                amount: tx.amount,
+                is_incoming: true,
+            });
+        }
+        
+        let mut cache = self.cache.write();
+        cache.pop(&tx.from);
+        cache.pop(&tx.to);
+        drop(cache);
+        
+        self.transactions.insert(tx.id.clone(), tx.clone());
+        self.transfer_proofs.insert(tx.id.clone(), (transfer.nonce, transfer.signature.clone()));
+        Ok(balance)
+    }
+    
+    /// 按(时间戳, ID)顺序返回`after`之后的带签名转账，最多`limit`笔
+    ///
+    /// 只包含本节点验证过签名的转账，从快照恢复的交易没有签名，不在其中
+    pub fn transfers_after(&self, after: &(u64, String), limit: usize) -> Vec<SignedTransfer> {
+        let _state = self.read_state();
+        let mut transfers: Vec<SignedTransfer> = self.transfer_proofs.iter()
+            .filter_map(|proof| {
+                let tx = self.transactions.get(proof.key())?;
+                ((tx.timestamp, &tx.id) > (after.0, &after.1)).then(|| SignedTransfer {
+                    tx: tx.clone(),
+                    nonce: proof.value().0,
+                    signature: proof.value().1.clone(),
+                })
+            })
+            .collect();
+        transfers.sort_by(|a, b| (a.tx.timestamp, &a.tx.id).cmp(&(b.tx.timestamp, &b.tx.id)));
+        transfers.truncate(limit);
+        transfers
+    }
+    
+    /// 验证并保存动态，已存在时返回`false`
+    ///
+    /// 时间戳与`now`相差超过[`MOMENT_WINDOW`]时拒绝；点赞、转发计数和评论不在签名范围内，保存时清零
+    pub fn add_moment(&self, moment: &SignedMoment, now: u64) -> Result<bool, HancoinError> {
+        moment.verify()?;
+        if now.abs_diff(moment.moment.timestamp) > MOMENT_WINDOW {
+            return Err(HancoinError::RequestExpired);
+        }
+        let _state = self.read_state();
+        match self.moments.entry(moment.moment.id.clone()) {
+            dashmap::mapref::entry::Entry::Occupied(_) => Ok(false),
+            dashmap::mapref::entry::Entry::Vacant(entry) => {
+                entry.insert(Moment {
+                    likes: 0,
+                    reposts: 0,
+                    comments: Vec::new(),
+                    ..moment.moment.clone()
+                });
+                Ok(true)
+            }
+        }
+    }
+    
     /// 创建新的账本实例
     pub fn new() -> Self {
         Self::default()
     }
+    
+    /// 持有期间账本状态不会被快照恢复替换
+    ///
+    /// 直接修改`accounts`、`transactions`、`moments`或`issued`，
+    /// 以及需要在多个表之间保持一致视图的调用方应先取得该锁
+    pub fn read_state(&self) -> RwLockReadGuard<'_, ()> {
+        self.state_lock.read()
+    }
 
     /// 获取账户信息，优先使用缓存
     pub fn get_account(&self, account_id: &str) -> Option<Account> {
         let cache = self.cache.read();
-        if let Some(account) = cache.get(account_id) {
+        if let Some(account) = cache.peek(account_id) {
             self.cache_hits.fetch_add(1, Ordering::Relaxed);
             return Some(account.clone());
         }
@@ -271,7 +445,7 @@ impl Ledger {
         {
             let cache = self.cache.read();
             for id in account_ids {
-                if let Some(account) = cache.get(id) {
+                if let Some(account) = cache.peek(id) {
                     result.insert(id.clone(), account.clone());
                     self.cache_hits.fetch_add(1, Ordering::Relaxed);
                 } else {
@@ -308,11 +482,198 @@ impl Ledger {
         
         result
     }
+    
+    /// 生成账本状态快照
+    ///
+    /// 账户按ID排序、交易和动态按(时间戳, ID)排序后分块，每块单独计算哈希，
+    /// 状态根由所有分块哈希计算得出，同步节点可以逐块校验并断点续传
+    pub fn snapshot(&self, chunk_size: usize) -> LedgerSnapshot {
+        let chunk_size = chunk_size.max(1);
+        let state = self.read_state();
+        let mut accounts: Vec<(String, Account)> = self.accounts.iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect();
+        accounts.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut transactions: Vec<Tx> = self.transactions.iter().map(|tx| tx.clone()).collect();
+        transactions.sort_by(|a, b| (a.timestamp, &a.id).cmp(&(b.timestamp, &b.id)));
+        let mut moments: Vec<Moment> = self.moments.iter().map(|m| m.clone()).collect();
+        moments.sort_by(|a, b| (a.timestamp, &a.id).cmp(&(b.timestamp, &b.id)));
+        let issued = self.issued.load(Ordering::SeqCst);
+        drop(state);
+        
+        let mut chunks: Vec<SnapshotChunk> = Vec::new();
+        for part in accounts.chunks(chunk_size) {
+            chunks.push(SnapshotChunk { index: chunks.len() as u32, accounts: part.to_vec(), ..Default::default() });
+        }
+        for part in transactions.chunks(chunk_size) {
+            chunks.push(SnapshotChunk { index: chunks.len() as u32, transactions: part.to_vec(), ..Default::default() });
+        }
+        for part in moments.chunks(chunk_size) {
+            chunks.push(SnapshotChunk { index: chunks.len() as u32, moments: part.to_vec(), ..Default::default() });
+        }
+            
+        let chunk_hashes: Vec<String> = chunks.iter().map(|c| hex::encode(c.hash())).collect();
+        let manifest = SnapshotManifest {
+            state_root: SnapshotManifest::compute_root(issued, &chunk_hashes),
+            issued,
+            accounts: accounts.len() as u64,
+            transactions: transactions.len() as u64,
+            moments: moments.len() as u64,
+            chunk_hashes,
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        };
+        
+        LedgerSnapshot { manifest, chunks }
+    }
+    
+    /// 用已校验的快照替换本地账本状态
+    ///
+    /// 快照之外的账户、交易和动态被删除，发行量取自状态根覆盖的清单。
+    /// 分块必须完整且通过`SnapshotManifest::verify_chunk`校验。
+    /// 新状态先在锁外构建，再在状态写锁内一次性替换，持有`read_state`的调用方不会看到中间状态
+    pub fn restore_snapshot(&self, manifest: &SnapshotManifest, chunks: Vec<SnapshotChunk>) -> Result<SnapshotCounts, HancoinError> {
+        if !manifest.verify()
+            || chunks.len() != manifest.chunk_hashes.len()
+            || !chunks.iter().enumerate().all(|(i, c)| c.index as usize == i && manifest.verify_chunk(c))
+        {
+            return Err(HancoinError::InvalidSnapshot);
+        }
+        
+        let mut counts = SnapshotCounts::default();
+        let mut accounts = HashMap::new();
+        let mut transactions = HashMap::new();
+        let mut moments = HashMap::new();
+        for chunk in chunks {
+            for (account_id, account) in chunk.accounts {
+                accounts.insert(account_id, account);
+                counts.accounts += 1;
+            }
+            for tx in chunk.transactions {
+                transactions.insert(tx.id.clone(), tx);
+                counts.transactions += 1;
+            }
+            for moment in chunk.moments {
+                moments.insert(moment.id.clone(), moment);
+                counts.moments += 1;
+            }
+        }
+        
+        let _state = self.state_lock.write();
+        self.accounts.clear();
+        for (id, account) in accounts {
+            self.accounts.insert(id, account);
+        }
+        self.transactions.clear();
+        for (id, tx) in transactions {
+            self.transactions.insert(id, tx);
+        }
+        self.transfer_proofs.retain(|id, _| self.transactions.contains_key(id));
+        self.moments.clear();
+        for (id, moment) in moments {
+            self.moments.insert(id, moment);
+        }
+        self.issued.store(manifest.issued, Ordering::SeqCst);
+        self.cache.write().clear();
+        Ok(counts)
+    }
+}
+
+/// 从快照恢复的记录数
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SnapshotCounts {
+    pub accounts: u64,
+    pub transactions: u64,
+    pub moments: u64,
+}
+
+/// 账本快照的分块，每块只包含账户、交易或动态中的一种
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SnapshotChunk {
+    /// 分块序号
+    pub index: u32,
+    /// 按ID排序的账户
+    #[serde(default)]
+    pub accounts: Vec<(String, Account)>,
+    /// 按(时间戳, ID)排序的交易
+    #[serde(default)]
+    pub transactions: Vec<Tx>,
+    /// 按(时间戳, ID)排序的动态
+    #[serde(default)]
+    pub moments: Vec<Moment>,
+}
+
+impl SnapshotChunk {
+    /// 计算分块哈希
+    pub fn hash(&self) -> [u8; 32] {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&self.index.to_be_bytes());
+        hasher.update(&serde_json::to_vec(&(&self.accounts, &self.transactions, &self.moments)).unwrap_or_default());
+        *hasher.finalize().as_bytes()
+    }
+}
+
+/// 快照清单，包含状态根和每个分块的哈希
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotManifest {
+    /// 状态根（十六进制）
+    pub state_root: String,
+    /// 已发行总量
+    pub issued: u64,
+    /// 账户数量
+    pub accounts: u64,
+    /// 交易数量
+    #[serde(default)]
+    pub transactions: u64,
+    /// 动态数量
+    #[serde(default)]
+    pub moments: u64,
+    /// 分块哈希（十六进制）
+    pub chunk_hashes: Vec<String>,
+    /// 快照生成时间
+    pub created_at: u64,
+}
+
+impl SnapshotManifest {
+    /// 由发行量和分块哈希计算状态根
+    pub fn compute_root(issued: u64, chunk_hashes: &[String]) -> String {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(b"hancoin-state-v2");
+        hasher.update(&issued.to_be_bytes());
+        hasher.update(&(chunk_hashes.len() as u64).to_be_bytes());
+        for hash in chunk_hashes {
+            hasher.update(hash.as_bytes());
+        }
+        hex::encode(hasher.finalize().as_bytes())
+    }
+    
+    /// 校验清单内容与状态根一致
+    pub fn verify(&self) -> bool {
+        Self::compute_root(self.issued, &self.chunk_hashes) == self.state_root
+    }
+    
+    /// 校验分块内容
+    pub fn verify_chunk(&self, chunk: &SnapshotChunk) -> bool {
+        self.chunk_hashes.get(chunk.index as usize)
+            .is_some_and(|expected| *expected == hex::encode(chunk.hash()))
+    }
+}
+
+/// 账本状态快照
+#[derive(Clone, Debug)]
+pub struct LedgerSnapshot {
+    /// 快照清单
+    pub manifest: SnapshotManifest,
+    /// 按清单顺序排列的分块，依次为账户、交易和动态
+    pub chunks: Vec<SnapshotChunk>,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
     
     #[test]
     fn test_account_activity() {
@@ -337,6 +698,60 @@ mod tests {
         assert_eq!(account.transactions[0].tx_id, "test123");
     }
     
+    #[test]
+    fn test_ledger_snapshot() {
+        let ledger = Ledger::new();
+        for i in 0..5 {
+            let account = Account { balance: i * 10, ..Default::default() };
+            ledger.accounts.insert(format!("account{}", i), account);
+        }
+        ledger.issued.store(100, Ordering::SeqCst);
+        
+        let snapshot = ledger.snapshot(2);
+        assert_eq!(snapshot.chunks.len(), 3);
+        assert_eq!(snapshot.manifest.accounts, 5);
+        assert!(snapshot.manifest.verify());
+        assert!(snapshot.chunks.iter().all(|c| snapshot.manifest.verify_chunk(c)));
+        
+        // 篡改的分块无法通过校验
+        let mut tampered = snapshot.chunks[1].clone();
+        tampered.accounts[0].1.balance += 1;
+        assert!(!snapshot.manifest.verify_chunk(&tampered));
+        
+        let mut manifest = snapshot.manifest.clone();
+        manifest.issued += 1;
+        assert!(!manifest.verify());
+    }
+    
+    #[test]
+    fn test_restore_snapshot_waits_for_readers() {
+        let source = Ledger::new();
+        for i in 0..5 {
+            source.accounts.insert(format!("account{}", i), Account { balance: 10, ..Default::default() });
+        }
+        source.issued.store(50, Ordering::SeqCst);
+        let snapshot = source.snapshot(2);
+        
+        let ledger = Arc::new(Ledger::new());
+        ledger.accounts.insert("stale".to_string(), Account::default());
+        
+        // 持有读锁期间恢复不会开始，读方看不到部分替换的账本
+        let state = ledger.read_state();
+        let restore = {
+            let ledger = ledger.clone();
+            std::thread::spawn(move || ledger.restore_snapshot(&snapshot.manifest, snapshot.chunks))
+        };
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert_eq!(ledger.accounts.len(), 1);
+        assert_eq!(ledger.issued.load(Ordering::SeqCst), 0);
+        drop(state);
+        
+        assert_eq!(restore.join().unwrap().unwrap().accounts, 5);
+        assert_eq!(ledger.accounts.len(), 5);
+        assert!(!ledger.accounts.contains_key("stale"));
+        assert_eq!(ledger.issued.load(Ordering::SeqCst), 50);
+    }
+    
     #[test]
     fn test_ledger_cache() {
         let ledger = Ledger::new();
@@ -344,11 +759,138 @@ mod tests {
         let account = Account::default();
         
         ledger.accounts.insert(account_id.clone(), account.clone());
-        let batch = ledger.get_accounts_batch(&[account_id.clone()]);
+        let batch = ledger.get_accounts_batch(std::slice::from_ref(&account_id));
         
         assert_eq!(batch.len(), 1);
         assert_eq!(batch[&account_id].balance, account.balance);
     }
+    
+    fn signed_transfer(keypair: &SigningKey, to: &str, amount: u64, nonce: u64) -> SignedTransfer {
+        let tx = Tx {
+            id: format!("tx-{}", nonce),
+            from: hex::encode(keypair.verifying_key().to_bytes()),
+            to: to.to_string(),
+            amount,
+            timestamp: 1000 + nonce,
+            fee: 0,
+            memo: None,
+            status: TxStatus::Completed,
+        };
+        let signature = keypair.sign(SignedTransfer::signing_message(&tx, nonce).as_bytes());
+        SignedTransfer {
+            tx,
+            nonce,
+            signature: hex::encode(signature.to_bytes()),
+        }
+    }
+    
+    #[test]
+    fn test_transfer_signature_covers_relayed_fields() {
+        let alice = crate::crypto::generate_keypair();
+        let mut transfer = signed_transfer(&alice, "bob", 10, 0);
+        transfer.tx.memo = Some("memo".to_string());
+        let signature = alice.sign(SignedTransfer::signing_message(&transfer.tx, 0).as_bytes());
+        transfer.signature = hex::encode(signature.to_bytes());
+        assert!(transfer.verify().is_ok());
+        
+        // 转发节点改动任一字段都会使签名失效
+        let tampered: [fn(&mut Tx); 5] = [
+            |tx| tx.id = "tx-other".to_string(),
+            |tx| tx.timestamp += 1,
+            |tx| tx.fee = 1,
+            |tx| tx.memo = None,
+            |tx| tx.status = TxStatus::Failed("relay".to_string()),
+        ];
+        for tamper in tampered {
+            let mut relayed = transfer.clone();
+            tamper(&mut relayed.tx);
+            assert!(relayed.verify().is_err());
+        }
+    }
+    
+    #[test]
+    fn test_apply_transfer() {
+        let ledger = Ledger::new();
+        let alice = crate::crypto::generate_keypair();
+        ledger.accounts.insert(hex::encode(alice.verifying_key().to_bytes()), Account { balance: 100, ..Default::default() });
+        
+        let transfer = signed_transfer(&alice, "bob", 60, 0);
+        assert_eq!(ledger.apply_transfer(&transfer).unwrap(), 40);
+        assert_eq!(ledger.accounts.get("bob").unwrap().balance, 60);
+        
+        // 重放、余额不足、签名与内容不符都不记账
+        assert!(ledger.apply_transfer(&transfer).is_err());
+        assert!(ledger.apply_transfer(&signed_transfer(&alice, "bob", 60, 1)).is_err());
+        let mut forged = signed_transfer(&alice, "bob", 10, 1);
+        forged.tx.to = "mallory".to_string();
+        forged.tx.id = "tx-forged".to_string();
+        assert!(matches!(ledger.apply_transfer(&forged), Err(HancoinError::InvalidSignature)));
+        assert!(ledger.accounts.get("mallory").is_none());
+        
+        assert_eq!(ledger.apply_transfer(&signed_transfer(&alice, "bob", 40, 1)).unwrap(), 0);
+        assert_eq!(ledger.transactions.len(), 2);
+        
+        // 已记账的转账保留签名，可按区间重新发给其他节点验证
+        let transfers = ledger.transfers_after(&(0, String::new()), 10);
+        assert_eq!(transfers.iter().map(|t| t.tx.id.as_str()).collect::<Vec<_>>(), ["tx-0", "tx-1"]);
+        assert!(transfers.iter().all(|t| t.verify().is_ok()));
+        assert_eq!(ledger.transfers_after(&(1000, "tx-0".to_string()), 10).len(), 1);
+        assert_eq!(ledger.transfers_after(&(0, String::new()), 1).len(), 1);
+    }
+    
+    #[test]
+    fn test_add_moment() {
+        let ledger = Ledger::new();
+        let author = crate::crypto::generate_keypair();
+        let author_id = hex::encode(author.verifying_key().to_bytes());
+        let signed = |content: &str| {
+            let message = SignedMoment::signing_message(1000, content);
+            let signature = author.sign(message.as_bytes());
+            SignedMoment {
+                moment: Moment {
+                    id: SignedMoment::moment_id(&author_id, 1000, content),
+                    author: author_id.clone(),
+                    content: content.to_string(),
+                    timestamp: 1000,
+                    likes: 0,
+                    reposts: 0,
+                    comments: Vec::new(),
+                },
+                signature: hex::encode(signature.to_bytes()),
+            }
+        };
+        
+        assert!(matches!(
+            ledger.add_moment(&signed(&"长".repeat(MAX_MOMENT_LENGTH + 1)), 1000),
+            Err(HancoinError::InvalidMoment)
+        ));
+        let mut tampered = signed("hello");
+        tampered.moment.content = "goodbye".to_string();
+        assert!(ledger.add_moment(&tampered, 1000).is_err());
+        
+        // 转发节点换用新ID重发同一条动态被拒绝
+        let mut renamed = signed("hello");
+        renamed.moment.id = "m2".to_string();
+        assert!(matches!(ledger.add_moment(&renamed, 1000), Err(HancoinError::InvalidMoment)));
+        
+        // 超出时间窗口的动态被拒绝
+        assert!(matches!(ledger.add_moment(&signed("hello"), 1000 + MOMENT_WINDOW + 1), Err(HancoinError::RequestExpired)));
+        
+        // 伪造的计数和评论在保存时清零
+        let mut inflated = signed("hello");
+        inflated.moment.likes = 1_000_000;
+        inflated.moment.reposts = 500;
+        inflated.moment.comments.push(Comment {
+            id: "c1".to_string(),
+            author: "mallory".to_string(),
+            content: "spam".to_string(),
+            timestamp: 1000,
+        });
+        assert!(ledger.add_moment(&inflated, 1000 + MOMENT_WINDOW).unwrap());
+        let stored = ledger.moments.get(&inflated.moment.id).unwrap().clone();
+        assert_eq!((stored.likes, stored.reposts, stored.comments.len()), (0, 0, 0));
+        assert!(!ledger.add_moment(&signed("hello"), 1000).unwrap());
+    }
 }
 
 use thiserror::Error;
@@ -379,6 +921,14 @@ pub enum HancoinError {
     AccountNotFound,
     #[error("Invalid transaction")]
     InvalidTransaction,
+    #[error("Invalid moment")]
+    InvalidMoment,
     #[error("Session not found: {0}")]
     SessionNotFound(String),
-}
\ No newline at end of file
+    #[error("Request expired")]
+    RequestExpired,
+    #[error("Invalid ledger snapshot")]
+    InvalidSnapshot,
+}
+
+impl warp::reject::Reject for HancoinError {}
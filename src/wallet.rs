@@ -0,0 +1,302 @@
+//! 助记词备份与确定性密钥派生
+//!
+//! 助记词遵循BIP39，支持英文和简体中文词表；种子按SLIP-0010派生ed25519账户密钥。
+//! ed25519只支持硬化派生，路径中的每一级都须带`'`或`h`后缀，未标记硬化的路径会被拒绝。
+//!
+//! 账户路径为`m/44'/<COIN_TYPE>'/<account>'/0'`，同一个种子可派生任意多个账户，
+//! 换设备时用助记词（和可选的密码）恢复全部账户。
+
+use bip39::{Language, Mnemonic};
+use ed25519_dalek::SigningKey;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha512;
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+/// SLIP-0010 ed25519主密钥的HMAC密钥
+const ED25519_SEED_KEY: &[u8] = b"ed25519 seed";
+
+/// 硬化派生的索引偏移
+const HARDENED_OFFSET: u32 = 0x8000_0000;
+
+/// BIP44用途
+const PURPOSE: u32 = 44;
+
+/// 账户路径中的币种编号，暂未在SLIP-0044登记
+pub const COIN_TYPE: u32 = 8888;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum WalletError {
+    #[error("Invalid word count: {0}")]
+    InvalidWordCount(usize),
+    #[error("Invalid mnemonic: {0}")]
+    InvalidMnemonic(String),
+    #[error("Invalid seed length: {0}")]
+    InvalidSeedLength(usize),
+    #[error("Invalid derivation path: {0}")]
+    InvalidPath(String),
+}
+
+/// 助记词词表
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MnemonicLanguage {
+    English,
+    ChineseSimplified,
+}
+
+impl From<MnemonicLanguage> for Language {
+    fn from(language: MnemonicLanguage) -> Self {
+        match language {
+            MnemonicLanguage::English => Language::English,
+            MnemonicLanguage::ChineseSimplified => Language::SimplifiedChinese,
+        }
+    }
+}
+
+/// 生成随机助记词，`word_count`为12、15、18、21或24
+pub fn generate_mnemonic(language: MnemonicLanguage, word_count: usize) -> Result<Zeroizing<String>, WalletError> {
+    if !(12..=24).contains(&word_count) || !word_count.is_multiple_of(3) {
+        return Err(WalletError::InvalidWordCount(word_count));
+    }
+    let mut entropy = Zeroizing::new([0u8; 32]);
+    let entropy = &mut entropy[..word_count / 3 * 4];
+    rand::rngs::OsRng.fill_bytes(entropy);
+    let mnemonic = Mnemonic::from_entropy_in(language.into(), entropy)
+        .map_err(|e| WalletError::InvalidMnemonic(e.to_string()))?;
+    Ok(Zeroizing::new(mnemonic.to_string()))
+}
+
+/// 检查助记词的单词和校验和，返回所用词表
+pub fn validate_mnemonic(phrase: &str) -> Result<MnemonicLanguage, WalletError> {
+    let mnemonic = parse_mnemonic(phrase)?;
+    match mnemonic.language() {
+        Language::SimplifiedChinese => Ok(MnemonicLanguage::ChineseSimplified),
+        _ => Ok(MnemonicLanguage::English),
+    }
+}
+
+fn parse_mnemonic(phrase: &str) -> Result<Mnemonic, WalletError> {
+    Mnemonic::parse(phrase).map_err(|e| WalletError::InvalidMnemonic(e.to_string()))
+}
+
+/// 解析派生路径，如`m/44'/8888'/0'/0'`，返回硬化后的索引
+///
+/// ed25519只支持硬化派生，每段都须以`'`或`h`标记，未标记的段返回错误而不是自动硬化
+pub fn parse_path(path: &str) -> Result<Vec<u32>, WalletError> {
+    let mut parts = path.split('/');
+    if parts.next() != Some("m") {
+        return Err(WalletError::InvalidPath(path.to_string()));
+    }
+    parts
+        .map(|part| {
+            part.strip_suffix(['\'', 'h'])
+                .and_then(|index| index.parse::<u32>().ok())
+                .filter(|index| *index < HARDENED_OFFSET)
+                .map(|index| index | HARDENED_OFFSET)
+                .ok_or_else(|| WalletError::InvalidPath(path.to_string()))
+        })
+        .collect()
+}
+
+/// 第`account`个账户的派生路径
+pub fn account_path(account: u32) -> String {
+    format!("m/{}'/{}'/{}'/0'", PURPOSE, COIN_TYPE, account)
+}
+
+/// SLIP-0010扩展私钥
+pub struct ExtendedKey {
+    secret: Zeroizing<[u8; 32]>,
+    chain_code: Zeroizing<[u8; 32]>,
+}
+
+impl ExtendedKey {
+    /// 由种子生成主密钥，种子长度须在16到64字节之间
+    pub fn master(seed: &[u8]) -> Result<Self, WalletError> {
+        if !(16..=64).contains(&seed.len()) {
+            return Err(WalletError::InvalidSeedLength(seed.len()));
+        }
+        Ok(Self::from_hmac(ED25519_SEED_KEY, &[seed]))
+    }
+
+    /// 硬化派生子密钥，`index`不含硬化偏移时自动加上
+    pub fn derive_child(&self, index: u32) -> Self {
+        let index = index | HARDENED_OFFSET;
+        Self::from_hmac(self.chain_code.as_slice(), &[&[0u8], self.secret.as_slice(), &index.to_be_bytes()])
+    }
+
+    /// 按路径派生
+    pub fn derive_path(&self, path: &str) -> Result<Self, WalletError> {
+        let mut key = Self {
+            secret: self.secret.clone(),
+            chain_code: self.chain_code.clone(),
+        };
+        for index in parse_path(path)? {
+            key = key.derive_child(index);
+        }
+        Ok(key)
+    }
+
+    /// 对应的ed25519签名密钥
+    pub fn signing_key(&self) -> SigningKey {
+        SigningKey::from_bytes(&self.secret)
+    }
+
+    /// 链码
+    pub fn chain_code(&self) -> &[u8; 32] {
+        &self.chain_code
+    }
+
+    fn from_hmac(key: &[u8], parts: &[&[u8]]) -> Self {
+        let mut mac = Hmac::<Sha512>::new_from_slice(key).expect("HMAC accepts any key length");
+        for part in parts {
+            mac.update(part);
+        }
+        let output = Zeroizing::new(mac.finalize().into_bytes().to_vec());
+        let mut secret = Zeroizing::new([0u8; 32]);
+        let mut chain_code = Zeroizing::new([0u8; 32]);
+        secret.copy_from_slice(&output[..32]);
+        chain_code.copy_from_slice(&output[32..]);
+        Self { secret, chain_code }
+    }
+}
+
+/// 由助记词恢复的分层确定性钱包
+pub struct HdWallet {
+    master: ExtendedKey,
+}
+
+impl HdWallet {
+    /// 从助记词和可选密码恢复，词表自动识别
+    pub fn from_mnemonic(phrase: &str, passphrase: &str) -> Result<Self, WalletError> {
+        let seed = Zeroizing::new(parse_mnemonic(phrase)?.to_seed(passphrase));
+        Self::from_seed(seed.as_slice())
+    }
+
+    /// 从原始种子恢复
+    pub fn from_seed(seed: &[u8]) -> Result<Self, WalletError> {
+        Ok(Self {
+            master: ExtendedKey::master(seed)?,
+        })
+    }
+
+    /// 第`account`个账户的签名密钥，账户编号须小于2^31
+    pub fn account_key(&self, account: u32) -> Result<SigningKey, WalletError> {
+        Ok(self.master.derive_path(&account_path(account))?.signing_key())
+    }
+
+    /// 按任意路径派生签名密钥
+    pub fn derive_path(&self, path: &str) -> Result<SigningKey, WalletError> {
+        Ok(self.master.derive_path(path)?.signing_key())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slip10_ed25519_vector_1() {
+        // SLIP-0010 ed25519 测试向量1
+        let seed = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        let master = ExtendedKey::master(&seed).unwrap();
+        let vectors = [
+            (
+                "m",
+                "90046a93de5380a72b5e45010748567d5ea02bbf6522f979e05c0d8d8ca9fffb",
+                "2b4be7f19ee27bbf30c667b642d5f4aa69fd169872f8fc3059c08ebae2eb19e7",
+                "a4b2856bfec510abab89753fac1ac0e1112364e7d250545963f135f2a33188ed",
+            ),
+            (
+                "m/0'",
+                "8b59aa11380b624e81507a27fedda59fea6d0b779a778918a2fd3590e16e9c69",
+                "68e0fe46dfb67e368c75379acec591dad19df3cde26e63b93a8e704f1dade7a3",
+                "8c8a13df77a28f3445213a0f432fde644acaa215fc72dcdf300d5efaa85d350c",
+            ),
+            (
+                "m/0'/1'",
+                "a320425f77d1b5c2505a6b1b27382b37368ee640e3557c315416801243552f14",
+                "b1d0bad404bf35da785a64ca1ac54b2617211d2777696fbffaf208f746ae84f2",
+                "1932a5270f335bed617d5b935c80aedb1a35bd9fc1e31acafd5372c30f5c1187",
+            ),
+            (
+                "m/0'/1'/2'",
+                "2e69929e00b5ab250f49c3fb1c12f252de4fed2c1db88387094a0f8c4c9ccd6c",
+                "92a5b23c0b8a99e37d07df3fb9966917f5d06e02ddbd909c7e184371463e9fc9",
+                "ae98736566d30ed0e9d2f4486a64bc95740d89c7db33f52121f8ea8f76ff0fc1",
+            ),
+            (
+                "m/0'/1'/2'/2'",
+                "8f6d87f93d750e0efccda017d662a1b31a266e4a6f5993b15f5c1f07f74dd5cc",
+                "30d1dc7e5fc04c31219ab25a27ae00b50f6fd66622f6e9c913253d6511d1e662",
+                "8abae2d66361c879b900d204ad2cc4984fa2aa344dd7ddc46007329ac76c429c",
+            ),
+            (
+                "m/0'/1'/2'/2'/1000000000'",
+                "68789923a0cac2cd5a29172a475fe9e0fb14cd6adb5ad98a3fa70333e7afa230",
+                "8f94d394a8e8fd6b1bc2f3f49f5c47e385281d5c17e65324b0f62483e37e8793",
+                "3c24da049451555d51a7014a37337aa4e12d41e485abccfa46b47dfb2af54b7a",
+            ),
+        ];
+        for (path, chain_code, secret, public) in vectors {
+            let key = master.derive_path(path).unwrap();
+            assert_eq!(hex::encode(key.chain_code()), chain_code, "{}", path);
+            assert_eq!(hex::encode(key.signing_key().as_bytes()), secret, "{}", path);
+            assert_eq!(hex::encode(key.signing_key().verifying_key().as_bytes()), public, "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_mnemonic_seed_vectors() {
+        // BIP39 英文测试向量，密码为"TREZOR"
+        let phrase = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+        let seed = parse_mnemonic(phrase).unwrap().to_seed("TREZOR");
+        assert_eq!(
+            hex::encode(seed),
+            "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04"
+        );
+
+        // 全零熵的简体中文助记词。BIP39没有官方的中文测试向量，
+        // 以下种子是本实现的回归值，用于发现词表或规范化处理的意外改动
+        let phrase = "的 的 的 的 的 的 的 的 的 的 的 在";
+        assert_eq!(validate_mnemonic(phrase), Ok(MnemonicLanguage::ChineseSimplified));
+        assert_eq!(
+            hex::encode(parse_mnemonic(phrase).unwrap().to_seed("")),
+            "c015b86e4b208402bb0bdd0febb746708b869bb6e433cb227fd66d444f3ccdc360fee9ca9271014c2a684df380fcc40bd80a37eaa41a8061a52a18d319cdd899"
+        );
+    }
+
+    #[test]
+    fn test_generate_and_restore_accounts() {
+        for language in [MnemonicLanguage::English, MnemonicLanguage::ChineseSimplified] {
+            let phrase = generate_mnemonic(language, 24).unwrap();
+            assert_eq!(phrase.split_whitespace().count(), 24);
+            assert_eq!(validate_mnemonic(&phrase), Ok(language));
+
+            // 同一助记词恢复出相同账户，不同账户和不同密码得到不同密钥
+            let wallet = HdWallet::from_mnemonic(&phrase, "").unwrap();
+            let restored = HdWallet::from_mnemonic(&phrase, "").unwrap();
+            let first = wallet.account_key(0).unwrap();
+            assert_eq!(restored.account_key(0).unwrap().as_bytes(), first.as_bytes());
+            assert_ne!(wallet.account_key(1).unwrap().as_bytes(), first.as_bytes());
+            let protected = HdWallet::from_mnemonic(&phrase, "passphrase").unwrap();
+            assert_ne!(protected.account_key(0).unwrap().as_bytes(), first.as_bytes());
+            assert_eq!(wallet.derive_path(&account_path(0)).unwrap().as_bytes(), first.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_rejects_invalid_input() {
+        assert_eq!(generate_mnemonic(MnemonicLanguage::English, 13).err(), Some(WalletError::InvalidWordCount(13)));
+        assert!(validate_mnemonic("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon").is_err());
+        assert!(validate_mnemonic("hancoin").is_err());
+        assert_eq!(HdWallet::from_seed(&[0u8; 8]).err(), Some(WalletError::InvalidSeedLength(8)));
+
+        assert_eq!(parse_path("m/44'/0h/1'").unwrap(), vec![44 | HARDENED_OFFSET, HARDENED_OFFSET, 1 | HARDENED_OFFSET]);
+        assert_eq!(parse_path("m/44'/0h/1"), Err(WalletError::InvalidPath("m/44'/0h/1".to_string())));
+        assert!(parse_path("44'/0'").is_err());
+        assert!(parse_path("m/2147483648'").is_err());
+        assert!(parse_path("m/x'").is_err());
+        assert!(HdWallet::from_seed(&[7u8; 32]).unwrap().account_key(HARDENED_OFFSET).is_err());
+    }
+}
@@ -1,17 +1,21 @@
 use dashmap::DashSet;
-use warp::Filter;
+use warp::{Filter, Reply};
 use futures::{SinkExt, StreamExt};
-use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation, TokenData};
+use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
-use log::{info, warn, error, debug};
+use log::{info, warn, debug};
 use parking_lot::Mutex;
 use std::sync::Arc;
 use std::time::{Instant, Duration};
-use governor::{Quota, RateLimiter};
+use governor::{DefaultDirectRateLimiter, Quota, RateLimiter};
 use nonzero_ext::nonzero;
 use tokio::time::interval;
+use tokio::sync::broadcast::{self, error::RecvError};
 use once_cell::sync::Lazy;
+use rand::RngCore;
+use crate::coinjoin::{CoinJoinEvent, CoinJoinManager, CoinJoinStatus};
+use crate::crypto;
 
 /// WebSocket连接状态
 #[derive(Default)]
@@ -21,10 +25,52 @@ struct WsState {
     message_count: usize,
 }
 
+/// 客户端发送的控制消息
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum WsClientMessage {
+    /// 订阅CoinJoin会话通知
+    ///
+    /// 不带签名时服务端返回挑战；参与者用自己的私钥（参与者ID即公钥）
+    /// 对挑战签名后再次发送，验证通过才订阅
+    SubscribeCoinjoin {
+        session_id: String,
+        participant_id: String,
+        #[serde(default)]
+        signature: Option<String>,
+    },
+    /// 取消订阅CoinJoin会话通知
+    UnsubscribeCoinjoin {
+        session_id: String,
+    },
+}
+
+/// 服务端推送的消息
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum WsServerMessage<'a> {
+    /// CoinJoin会话事件
+    Coinjoin {
+        event: &'a CoinJoinEvent,
+    },
+    /// 订阅挑战，需由参与者按`coinjoin-subscribe`用途域签名
+    Challenge {
+        session_id: &'a str,
+        challenge: &'a str,
+    },
+    /// 订阅结果
+    Subscribed {
+        session_id: &'a str,
+        ok: bool,
+    },
+}
+
 /// WebSocket路由配置
-pub fn chat_routes() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+pub fn chat_routes(
+    coinjoin: Arc<CoinJoinManager>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     let state = Arc::new(Mutex::new(WsState::default()));
-    let rate_limiter = Arc::new(RateLimiter::direct(Quota::per_second(nonzero!(10)))); // 10 msg/s
+    let rate_limiter = Arc::new(RateLimiter::direct(Quota::per_second(nonzero!(10u32)))); // 10 msg/s
     
     warp::path("ws")
         .and(warp::query::<HashMap<String, String>>())
@@ -32,24 +78,25 @@ pub fn chat_routes() -> impl Filter<Extract = impl warp::Reply, Error = warp::Re
         .map(move |params: HashMap<String, String>, ws: warp::ws::Ws| {
             let state = state.clone();
             let rate_limiter = rate_limiter.clone();
+            let coinjoin = coinjoin.clone();
             
             // 验证token
             if let Some(token) = params.get("token") {
                 if validate_token(token) {
                     return ws.on_upgrade(move |socket| {
-                        handle_ws(socket, state, rate_limiter)
-                    });
+                        handle_ws(socket, state, rate_limiter, coinjoin)
+                    }).into_response();
                 }
             }
             
             // 如果没有token或验证失败，返回未授权状态
-            ws.on_upgrade(move |socket| {
+            ws.on_upgrade(move |socket: warp::ws::WebSocket| {
                 async move {
                     let mut socket = socket;
                     let _ = socket.send(warp::ws::Message::text("Unauthorized")).await;
                     // 关闭连接
                 }
-            })
+            }).into_response()
         })
 }
 
@@ -127,13 +174,10 @@ impl JwtValidator {
 
     /// 实际验证逻辑
     fn try_validate(&self, token: &str, secret: &str) -> bool {
-        let validation = Validation {
-            algorithms: vec![Algorithm::HS256],
-            validate_exp: true,
-            validate_nbf: true,
-            iss: None, // 禁用自动iss验证，改为手动验证
-            ..Default::default()
-        };
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.validate_exp = true;
+        validation.validate_nbf = true;
+        validation.iss = None; // 禁用自动iss验证，改为手动验证
 
         match decode::<Claims>(
             token,
@@ -164,11 +208,13 @@ impl JwtValidator {
     }
 
     /// 撤销令牌
+    #[allow(dead_code)]
     fn revoke_token(&self, token: &str) {
         self.revoked_tokens.insert(token.to_string());
     }
     
     /// 清理过期的撤销令牌
+    #[allow(dead_code)]
     fn cleanup_revoked(&self) {
         // 实际实现中，我们应该解析令牌并检查过期时间
         // 这里简化处理，假设所有撤销令牌在24小时后可以从集合中移除
@@ -178,7 +224,7 @@ impl JwtValidator {
 }
 
 // 使用once_cell替代lazy_static
-static JWT_VALIDATOR: Lazy<JwtValidator> = Lazy::new(|| JwtValidator::new());
+static JWT_VALIDATOR: Lazy<JwtValidator> = Lazy::new(JwtValidator::new);
 
 /// 增强的JWT验证入口
 fn validate_token(token: &str) -> bool {
@@ -188,23 +234,68 @@ fn validate_token(token: &str) -> bool {
 /// 优化的WebSocket消息处理
 const MAX_MESSAGE_SIZE: usize = 1024;
 const MAX_CONNECTIONS: usize = 1000;
+/// 每个连接最多同时等待签名的订阅挑战数
+const MAX_PENDING_CHALLENGES: usize = 16;
+
+/// 订阅挑战签名的用途域，见[`crypto::sign`]
+pub const SUBSCRIPTION_SIGNATURE_DOMAIN: &str = "coinjoin-subscribe";
+
+/// 订阅挑战的签名内容，按`SUBSCRIPTION_SIGNATURE_DOMAIN`用途域签名
+fn subscription_message(session_id: &str, participant_id: &str, challenge: &str) -> String {
+    format!("subscribe:{}:{}:{}", session_id, participant_id, challenge)
+}
+
+/// 检查参与者对订阅挑战的签名，挑战只能使用一次
+fn verify_subscription(
+    pending: &mut HashMap<String, (String, String)>,
+    session_id: &str,
+    participant_id: &str,
+    signature: &str,
+) -> bool {
+    let Some((expected_participant, challenge)) = pending.remove(session_id) else {
+        return false;
+    };
+    if expected_participant != participant_id {
+        return false;
+    }
+    let (Ok(public_key), Ok(signature)) = (crypto::public_key_from_hex(participant_id), crypto::signature_from_hex(signature)) else {
+        return false;
+    };
+    let message = subscription_message(session_id, participant_id, &challenge);
+    crypto::verify(&public_key, SUBSCRIPTION_SIGNATURE_DOMAIN, message.as_bytes(), &signature).is_ok()
+}
+
+/// 接收CoinJoin事件，没有订阅时不接收
+async fn next_coinjoin_event(
+    events: &mut Option<broadcast::Receiver<CoinJoinEvent>>,
+) -> Result<CoinJoinEvent, RecvError> {
+    match events {
+        Some(events) => events.recv().await,
+        None => std::future::pending().await,
+    }
+}
 
 async fn handle_ws(
     ws: warp::ws::WebSocket,
     state: Arc<Mutex<WsState>>,
-    rate_limiter: Arc<RateLimiter>,
+    rate_limiter: Arc<DefaultDirectRateLimiter>,
+    coinjoin: Arc<CoinJoinManager>,
 ) {
     // 检查连接限制
-    {
+    let accepted = {
         let mut state = state.lock();
-        if state.active_connections >= MAX_CONNECTIONS {
-            drop(state); // 提前释放锁
-            let mut ws = ws;
-            let _ = ws.send(warp::ws::Message::text("Error: Too many connections")).await;
-            return;
+        if state.active_connections < MAX_CONNECTIONS {
+            state.active_connections += 1;
+            state.last_connection_time = Some(Instant::now());
+            true
+        } else {
+            false
         }
-        state.active_connections += 1;
-        state.last_connection_time = Some(Instant::now());
+    };
+    if !accepted {
+        let mut ws = ws;
+        let _ = ws.send(warp::ws::Message::text("Error: Too many connections")).await;
+        return;
     }
     
     info!("New WebSocket connection established");
@@ -213,17 +304,79 @@ async fn handle_ws(
     let (mut ws_sink, mut ws_stream) = ws.split();
     let mut heartbeat = interval(Duration::from_secs(30));
     
-    // 使用固定大小的缓冲区
-    let mut buf = [0u8; MAX_MESSAGE_SIZE];
+    // CoinJoin会话订阅：有订阅时才接收事件，避免积压订阅前的旧事件
+    let mut coinjoin_events: Option<broadcast::Receiver<CoinJoinEvent>> = None;
+    let mut subscriptions: HashSet<String> = HashSet::new();
+    // 会话ID -> (参与者ID, 挑战)
+    let mut challenges: HashMap<String, (String, String)> = HashMap::new();
     
     loop {
         tokio::select! {
             _ = heartbeat.tick() => {
-                if let Err(e) = ws_sink.send(warp::ws::Message::ping(&[])).await {
+                if let Err(e) = ws_sink.send(warp::ws::Message::ping([])).await {
                     warn!("Heartbeat failed: {:?}", e);
                     break;
                 }
             },
+            event = next_coinjoin_event(&mut coinjoin_events) => {
+                let events = match event {
+                    Ok(event) => vec![event],
+                    Err(RecvError::Lagged(skipped)) => {
+                        // 跳过的事件可能包含阶段切换，重新推送每个订阅会话的当前状态
+                        warn!("CoinJoin event stream lagged, skipped {} events, resending session status", skipped);
+                        let mut current = Vec::new();
+                        for session_id in subscriptions.clone() {
+                            let events = coinjoin.session_events(&session_id);
+                            if events.is_empty() {
+                                subscriptions.remove(&session_id);
+                            }
+                            current.extend(events);
+                        }
+                        if subscriptions.is_empty() {
+                            coinjoin_events = None;
+                        }
+                        current
+                    },
+                    Err(RecvError::Closed) => {
+                        subscriptions.clear();
+                        coinjoin_events = None;
+                        continue;
+                    },
+                };
+                
+                let mut failed = false;
+                for event in events {
+                    if !subscriptions.contains(event.session_id()) {
+                        continue;
+                    }
+                    
+                    // 会话结束后自动取消订阅（已完成的会话在推送最终记录后取消）
+                    let finished = match &event {
+                        CoinJoinEvent::StatusChanged { status, .. } => {
+                            matches!(status, CoinJoinStatus::Failed | CoinJoinStatus::TimedOut)
+                        },
+                        CoinJoinEvent::Transcript(_) => true,
+                    };
+                    
+                    let payload = serde_json::to_string(&WsServerMessage::Coinjoin { event: &event })
+                        .unwrap_or_default();
+                    if let Err(e) = ws_sink.send(warp::ws::Message::text(payload)).await {
+                        warn!("Failed to send CoinJoin event: {:?}", e);
+                        failed = true;
+                        break;
+                    }
+                    
+                    if finished {
+                        subscriptions.remove(event.session_id());
+                        if subscriptions.is_empty() {
+                            coinjoin_events = None;
+                        }
+                    }
+                }
+                if failed {
+                    break;
+                }
+            },
             result = ws_stream.next() => {
                 match result {
                     Some(Ok(msg)) => {
@@ -246,6 +399,47 @@ async fn handle_ws(
                             // 更新状态
                             state.lock().message_count += 1;
                             
+                            // 处理控制消息
+                            if let Ok(control) = serde_json::from_str::<WsClientMessage>(text) {
+                                let payload = match &control {
+                                    WsClientMessage::SubscribeCoinjoin { session_id, participant_id, signature: None } => {
+                                        if challenges.len() >= MAX_PENDING_CHALLENGES && !challenges.contains_key(session_id) {
+                                            serde_json::to_string(&WsServerMessage::Subscribed { session_id, ok: false })
+                                        } else {
+                                            let mut bytes = [0u8; 32];
+                                            rand::rngs::OsRng.fill_bytes(&mut bytes);
+                                            let challenge = hex::encode(bytes);
+                                            let reply = serde_json::to_string(&WsServerMessage::Challenge { session_id, challenge: &challenge });
+                                            challenges.insert(session_id.clone(), (participant_id.clone(), challenge));
+                                            reply
+                                        }
+                                    },
+                                    WsClientMessage::SubscribeCoinjoin { session_id, participant_id, signature: Some(signature) } => {
+                                        let ok = verify_subscription(&mut challenges, session_id, participant_id, signature)
+                                            && coinjoin.is_participant(session_id, participant_id);
+                                        if ok {
+                                            if coinjoin_events.is_none() {
+                                                coinjoin_events = Some(coinjoin.subscribe());
+                                            }
+                                            subscriptions.insert(session_id.clone());
+                                        }
+                                        serde_json::to_string(&WsServerMessage::Subscribed { session_id, ok })
+                                    },
+                                    WsClientMessage::UnsubscribeCoinjoin { session_id } => {
+                                        subscriptions.remove(session_id);
+                                        if subscriptions.is_empty() {
+                                            coinjoin_events = None;
+                                        }
+                                        serde_json::to_string(&WsServerMessage::Subscribed { session_id, ok: false })
+                                    },
+                                }.unwrap_or_default();
+                                if let Err(e) = ws_sink.send(warp::ws::Message::text(payload)).await {
+                                    warn!("Failed to send WebSocket message: {:?}", e);
+                                    break;
+                                }
+                                continue;
+                            }
+                            
                             // 构造响应
                             let response = format!("echo: {}", text);
                             if let Err(e) = ws_sink.send(warp::ws::Message::text(response)).await {
@@ -278,6 +472,7 @@ async fn handle_ws(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use ed25519_dalek::Signer;
     
     #[test]
     fn test_jwt_validator() {
@@ -290,4 +485,37 @@ mod tests {
         validator.revoke_token("some.token.here");
         assert!(!validator.validate_token("some.token.here"));
     }
+    
+    #[test]
+    fn test_subscription_challenge() {
+        let participant = crypto::generate_keypair();
+        let participant_id = hex::encode(participant.verifying_key().to_bytes());
+        let sign = |challenge: &str| {
+            let message = subscription_message("session", &participant_id, challenge);
+            hex::encode(crypto::sign(&participant, SUBSCRIPTION_SIGNATURE_DOMAIN, message.as_bytes()).to_bytes())
+        };
+        let pending = || HashMap::from([
+            ("session".to_string(), (participant_id.clone(), "challenge".to_string())),
+        ]);
+        
+        let mut challenges = pending();
+        assert!(verify_subscription(&mut challenges, "session", &participant_id, &sign("challenge")));
+        // 挑战只能使用一次
+        assert!(!verify_subscription(&mut challenges, "session", &participant_id, &sign("challenge")));
+        
+        // 签错挑战或冒用他人的参与者ID都不能订阅
+        assert!(!verify_subscription(&mut pending(), "session", &participant_id, &sign("other")));
+        let other = hex::encode(crypto::generate_keypair().verifying_key().to_bytes());
+        let mut challenges = HashMap::from([
+            ("session".to_string(), (other.clone(), "challenge".to_string())),
+        ]);
+        assert!(!verify_subscription(&mut challenges, "session", &other, &sign("challenge")));
+        
+        // 不带用途域的签名和CoinJoin参与者签名都不能用于订阅
+        let message = subscription_message("session", &participant_id, "challenge");
+        let raw = hex::encode(participant.sign(message.as_bytes()).to_bytes());
+        assert!(!verify_subscription(&mut pending(), "session", &participant_id, &raw));
+        let participant_signed = crypto::sign(&participant, crate::coinjoin::PARTICIPANT_SIGNATURE_DOMAIN, message.as_bytes());
+        assert!(!verify_subscription(&mut pending(), "session", &participant_id, &hex::encode(participant_signed.to_bytes())));
+    }
 }
\ No newline at end of file
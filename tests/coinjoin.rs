@@ -0,0 +1,804 @@
+use hancoin::coinjoin::*;
+use hancoin::blind;
+use hancoin::crypto::generate_keypair;
+use ed25519_dalek::{Signer, SigningKey};
+
+fn test_input(txid: &str) -> TxInput {
+    TxInput {
+        txid: txid.to_string(),
+        vout: 0,
+        amount: 1000,
+        script: String::new(),
+        pubkey: String::new(),
+    }
+}
+
+fn test_output(address: &str) -> TxOutput {
+    TxOutput {
+        address: address.to_string(),
+        amount: 1000,
+    }
+}
+
+#[test]
+fn test_max_participants() {
+    let mut session = CoinJoinSession::new(2, 2, 1000, 1, 60);
+
+    assert!(session.add_participant("alice"));
+    assert!(session.add_participant("bob"));
+    assert!(session.is_full());
+    assert!(!session.add_participant("carol"));
+    assert_eq!(session.participants.len(), 2);
+}
+
+#[test]
+fn test_lobby_stays_open_until_full_or_deadline() {
+    // 达到最少人数后仍可加入，满员时立即开始
+    let mut session = CoinJoinSession::new(2, 3, 1000, 1, 60);
+    session.lobby_deadline = session.created_at + 30;
+    assert!(session.add_participant("alice"));
+    assert!(session.add_participant("bob"));
+    assert_eq!(session.status, CoinJoinStatus::Waiting);
+    assert!(!session.close_lobby(session.created_at));
+    assert!(session.add_participant("carol"));
+    assert_eq!(session.status, CoinJoinStatus::CollectingInputs);
+
+    // 截止时间后达到最少人数的会话开始，之后不再接受加入
+    let mut session = CoinJoinSession::new(2, 3, 1000, 1, 60);
+    let deadline = session.created_at + 30;
+    session.lobby_deadline = deadline;
+    assert!(session.add_participant("alice"));
+    assert!(!session.close_lobby(deadline));
+    assert!(session.add_participant("bob"));
+    assert!(!session.close_lobby(deadline - 1));
+    assert!(session.close_lobby(deadline));
+    assert_eq!(session.status, CoinJoinStatus::CollectingInputs);
+    assert!(!session.add_participant("carol"));
+    assert_eq!(session.participants.len(), 2);
+}
+
+#[test]
+fn test_unsigned_participants_timeout() {
+    let mut session = CoinJoinSession::new(2, 2, 1000, 1, 60);
+    session.add_participant("alice");
+    session.add_participant("bob");
+    session.add_input("alice", test_input("a"));
+    session.add_input("bob", test_input("b"));
+    session.add_output(test_output("x"));
+    session.add_output(test_output("y"));
+    assert_eq!(session.status, CoinJoinStatus::CollectingSignatures);
+
+    session.add_signature(TxSignature {
+        input_index: 0,
+        signature: "sig".to_string(),
+        pubkey: String::new(),
+    });
+
+    let unsigned = session.unsigned_participants();
+    assert_eq!(unsigned.len(), 1);
+    assert!(unsigned.contains("bob"));
+
+    // 模拟长时间无活动
+    session.last_active = 0;
+    assert!(session.check_timeout());
+    assert_eq!(session.status, CoinJoinStatus::TimedOut);
+    assert!(session.is_terminal());
+    assert!(!session.check_timeout());
+}
+
+#[test]
+fn test_blame_tracker_ban() {
+    let tracker = BlameTracker::new(BlameConfig {
+        max_strikes: 2,
+        strike_window: 100,
+        ban_duration: 50,
+    });
+
+    assert!(!tracker.record_strike("mallory", 1000));
+    assert!(!tracker.is_banned("mallory", 1000));
+    assert!(tracker.record_strike("mallory", 1010));
+    assert!(tracker.is_banned("mallory", 1020));
+    assert_eq!(tracker.banned(1020).len(), 1);
+
+    // 封禁到期
+    assert!(!tracker.is_banned("mallory", 1060));
+    tracker.prune(1200);
+    assert!(tracker.banned(1200).is_empty());
+
+    // 有效期外的拖延重新计数
+    assert!(!tracker.record_strike("eve", 1000));
+    assert!(!tracker.record_strike("eve", 1200));
+
+    // 运行时调整配置
+    assert!(tracker.set_config(BlameConfig { max_strikes: 0, strike_window: 100, ban_duration: 50 }).is_err());
+    assert!(tracker.set_config(BlameConfig { max_strikes: 1, strike_window: 100, ban_duration: u64::MAX }).is_err());
+    tracker.set_config(BlameConfig { max_strikes: 1, strike_window: 100, ban_duration: 50 }).unwrap();
+    assert!(tracker.record_strike("trudy", 2000));
+    assert!(tracker.is_banned("trudy", 2010));
+
+    // 封禁截止时间不会溢出
+    assert!(tracker.record_strike("trent", u64::MAX - 10));
+    assert!(tracker.is_banned("trent", u64::MAX - 1));
+}
+
+#[tokio::test]
+async fn test_manager_join_limits() {
+    let manager = CoinJoinManager::new(60);
+    let info = manager.create_session(&create_request(&generate_keypair(), Some(2), Some(2), None), "http:creator").unwrap();
+
+    let (bob, carol) = (generate_keypair(), generate_keypair());
+    assert!(manager.join_session(&info.id, &join_request(&info.id, &bob), &http_source(&bob)).is_ok());
+    assert!(manager.join_session(&info.id, &join_request(&info.id, &carol), &http_source(&carol)).is_err());
+    assert!(manager.join_session("missing", &join_request("missing", &carol), &http_source(&carol)).is_err());
+    assert_eq!(manager.cleanup(), 0);
+    assert!(manager.banned_participants().is_empty());
+
+    // 最少人数大于最多人数的会话无法创建
+    assert!(manager.create_session(&create_request(&generate_keypair(), Some(3), Some(2), None), "http:creator").is_err());
+
+    // 超时时间不能超过上限
+    let long = manager.create_session(&create_request(&generate_keypair(), Some(2), Some(2), Some(u64::MAX)), "http:other").unwrap();
+    assert_eq!(long.timeout, CoinJoinConfig::default().max_session_timeout);
+
+    // 最多人数不能超过固定上限
+    let large = manager.create_session(&create_request(&generate_keypair(), Some(3), Some(usize::MAX), None), "http:creator").unwrap();
+    assert_eq!(large.max_participants, MAX_PARTICIPANTS);
+
+    // 同一来源同时开启的会话数量受限
+    assert!(manager.create_session(&create_request(&generate_keypair(), Some(2), Some(2), None), "http:creator").is_err());
+
+    // 创建请求须带有创建者的有效签名，且时间戳在允许范围内
+    let creator = generate_keypair();
+    let mut forged = create_request(&creator, Some(2), Some(2), None);
+    forged.target_amount += 1;
+    assert!(manager.create_session(&forged, "http:forger").is_err());
+    let mut unsigned = create_request(&creator, Some(2), Some(2), None);
+    unsigned.participant_signature = String::new();
+    assert!(manager.create_session(&unsigned, "http:forger").is_err());
+    let mut stale = create_request(&creator, Some(2), Some(2), None);
+    stale.timestamp -= 2 * CREATE_REQUEST_WINDOW;
+    let stale = stale.sign(&creator).unwrap();
+    assert!(manager.create_session(&stale, "http:forger").is_err());
+    assert!(manager.create_session(&create_request(&creator, Some(2), Some(2), None), "http:forger").is_ok());
+
+    // 同一HTTP客户端地址只能加入一个参与者
+    let (dave, erin) = (generate_keypair(), generate_keypair());
+    manager.join_session(&large.id, &join_request(&large.id, &dave), "http:10.0.0.1").unwrap();
+    assert!(manager.join_session(&large.id, &join_request(&large.id, &erin), "http:10.0.0.1").is_err());
+    assert!(manager.join_session(&large.id, &join_request(&large.id, &erin), "http:creator").is_err());
+    manager.join_session(&large.id, &join_request(&large.id, &erin), "http:10.0.0.2").unwrap();
+}
+
+#[tokio::test]
+async fn test_manager_closes_lobby_after_deadline() {
+    let manager = CoinJoinManager::with_config(CoinJoinConfig {
+        lobby_timeout: 1,
+        ..Default::default()
+    });
+    let info = manager.create_session(&create_request(&generate_keypair(), Some(2), Some(3), None), "http:creator").unwrap();
+    let (bob, carol) = (generate_keypair(), generate_keypair());
+    manager.join_session(&info.id, &join_request(&info.id, &bob), &http_source(&bob)).unwrap();
+    assert_eq!(manager.get_session(&info.id).unwrap().status, CoinJoinStatus::Waiting);
+    assert_eq!(manager.open_rounds(Some(1000)).len(), 1);
+
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+    assert_eq!(manager.cleanup(), 0);
+    assert_eq!(manager.get_session(&info.id).unwrap().status, CoinJoinStatus::CollectingInputs);
+    assert!(manager.open_rounds(Some(1000)).is_empty());
+    assert!(manager.join_session(&info.id, &join_request(&info.id, &carol), &http_source(&carol)).is_err());
+}
+
+/// 两人会话走到签名阶段后只有`signer`签名，会话超时时间为0
+fn stalled_round(manager: &CoinJoinManager, signer: &SigningKey, staller: &SigningKey) -> String {
+    let info = manager.create_session(&create_request(signer, Some(2), Some(2), Some(0)), "http:creator").unwrap();
+
+    let nonces: Vec<String> = [signer, staller].into_iter()
+        .map(|participant| manager.join_session(&info.id, &join_request(&info.id, participant), &http_source(participant)).unwrap().nonce.unwrap())
+        .collect();
+    let mut credentials = Vec::new();
+    for (index, (participant, nonce)) in [signer, staller].into_iter().zip(nonces).enumerate() {
+        let output = test_output(&format!("{}-mixed", index));
+        let (blinded_output, unblinder) = blind::blind(
+            &info_key(manager, &info.id),
+            &nonce,
+            output.credential_message(&info.id).as_bytes(),
+        ).unwrap();
+        let request = InputRequest {
+            participant_id: account_id(participant),
+            input: test_input(&format!("{}-{}", info.id, index)),
+            blinded_output,
+            participant_signature: String::new(),
+        }
+        .sign(&info.id, participant)
+        .unwrap();
+        let reply = manager.add_input(&info.id, &request).unwrap();
+        credentials.push((output, unblinder.unblind(&reply.blind_signature.unwrap()).unwrap()));
+    }
+    for (output, credential) in credentials {
+        manager.register_blinded_output(&info.id, &BlindedOutputRequest { output, credential }).unwrap();
+    }
+
+    let request = SignatureRequest {
+        participant_id: account_id(signer),
+        signature: TxSignature { input_index: 0, signature: "sig".to_string(), pubkey: account_id(signer) },
+        participant_signature: String::new(),
+    };
+    manager.add_signature(&info.id, &request.sign(&info.id, signer).unwrap()).unwrap();
+    assert_eq!(manager.get_session(&info.id).unwrap().status, CoinJoinStatus::CollectingSignatures);
+    info.id
+}
+
+#[tokio::test]
+async fn test_expiry_sweep_strikes_and_bans_stalling_signer() {
+    let manager = CoinJoinManager::with_config(CoinJoinConfig {
+        blame: BlameConfig { max_strikes: 2, strike_window: 3600, ban_duration: 3600 },
+        ..Default::default()
+    });
+    let (alice, bob) = (generate_keypair(), generate_keypair());
+
+    // 第一次拖延只记录一次，不封禁
+    let first = stalled_round(&manager, &alice, &bob);
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+    assert_eq!(manager.cleanup(), 1);
+    assert!(manager.get_session(&first).is_none());
+    assert!(manager.banned_participants().is_empty());
+
+    // 第二次拖延达到上限，未签名的参与者被封禁，已签名的参与者不受影响
+    stalled_round(&manager, &alice, &bob);
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+    assert_eq!(manager.cleanup(), 1);
+    let banned = manager.banned_participants();
+    assert_eq!(banned.len(), 1);
+    assert_eq!(banned[0].participant_id, account_id(&bob));
+
+    // 被封禁的参与者不能加入新会话
+    let info = manager.create_session(&create_request(&alice, Some(2), Some(2), None), "http:creator").unwrap();
+    assert!(manager.join_session(&info.id, &join_request(&info.id, &bob), &http_source(&bob)).is_err());
+    assert!(manager.unban_participant(&account_id(&bob)));
+    assert!(manager.join_session(&info.id, &join_request(&info.id, &bob), &http_source(&bob)).is_ok());
+}
+
+#[tokio::test]
+async fn test_status_events() {
+    let manager = CoinJoinManager::new(60);
+    let mut events = manager.subscribe();
+    let info = manager.create_session(&create_request(&generate_keypair(), Some(2), Some(2), None), "http:creator").unwrap();
+    let bob = generate_keypair();
+    manager.join_session(&info.id, &join_request(&info.id, &bob), &http_source(&bob)).unwrap();
+
+    match events.try_recv().unwrap() {
+        CoinJoinEvent::StatusChanged { session_id, status, deadline } => {
+            assert_eq!(session_id, info.id);
+            assert_eq!(status, CoinJoinStatus::CollectingInputs);
+            assert!(deadline > info.created_at);
+        }
+        other => panic!("unexpected event: {:?}", other),
+    }
+    assert!(manager.is_participant(&info.id, &account_id(&bob)));
+    assert!(!manager.is_participant(&info.id, "carol"));
+
+    // 错过事件的订阅者可以取得会话当前状态
+    match manager.session_events(&info.id).as_slice() {
+        [CoinJoinEvent::StatusChanged { status, deadline, .. }] => {
+            assert_eq!(*status, CoinJoinStatus::CollectingInputs);
+            assert!(*deadline > info.created_at);
+        }
+        other => panic!("unexpected events: {:?}", other),
+    }
+    assert!(manager.session_events("missing").is_empty());
+}
+
+/// 创建者签名的会话创建请求，目标金额为1000
+fn create_request(creator: &SigningKey, min_participants: Option<usize>, max_participants: Option<usize>, timeout: Option<u64>) -> CoinJoinRequest {
+    CoinJoinRequest {
+        min_participants,
+        max_participants,
+        target_amount: 1000,
+        fee_rate: None,
+        timeout,
+        participant_id: account_id(creator),
+        timestamp: std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs(),
+        participant_signature: String::new(),
+    }
+    .sign(creator)
+    .unwrap()
+}
+
+fn join_request(session_id: &str, participant: &SigningKey) -> JoinRequest {
+    JoinRequest {
+        participant_id: account_id(participant),
+        participant_signature: String::new(),
+    }
+    .sign(session_id, participant)
+    .unwrap()
+}
+
+/// 参与者各自的HTTP客户端地址
+fn http_source(participant: &SigningKey) -> String {
+    format!("http:{}", account_id(participant))
+}
+
+/// 参与者ID为公钥的hex编码
+fn account_id(keypair: &SigningKey) -> String {
+    hex::encode(keypair.verifying_key().to_bytes())
+}
+
+fn signed_join(session_id: &str, participant: &SigningKey) -> CoinJoinMessage {
+    CoinJoinMessage::Join {
+        session_id: session_id.to_string(),
+        participant_id: account_id(participant),
+        participant_signature: String::new(),
+    }
+    .sign(participant)
+    .unwrap()
+}
+
+fn signed_input(session_id: &str, participant: &SigningKey, txid: &str, blinded_output: String) -> CoinJoinMessage {
+    CoinJoinMessage::Input {
+        session_id: session_id.to_string(),
+        participant_id: account_id(participant),
+        input: test_input(txid),
+        blinded_output,
+        participant_signature: String::new(),
+    }
+    .sign(participant)
+    .unwrap()
+}
+
+/// 将一个节点待发往P2P网络的消息全部交给另一个节点
+fn relay(from: &mut tokio::sync::broadcast::Receiver<CoinJoinMessage>, source: &str, to: &CoinJoinManager) {
+    while let Ok(msg) = from.try_recv() {
+        to.handle_network_message(source, msg);
+    }
+}
+
+#[tokio::test]
+async fn test_network_coordination() {
+    let coordinator = CoinJoinManager::new(60);
+    coordinator.set_node_id("peer-a".to_string());
+    let mut coordinator_out = coordinator.subscribe_outbound();
+
+    let remote = CoinJoinManager::new(60);
+    remote.set_node_id("peer-b".to_string());
+    let mut remote_out = remote.subscribe_outbound();
+
+    let info = coordinator.create_session(&create_request(&generate_keypair(), Some(3), Some(5), None), "http:creator").unwrap();
+
+    let announce = coordinator_out.try_recv().unwrap();
+    assert!(matches!(announce, CoinJoinMessage::Announce(_)));
+
+    // 非协调节点转发的公告被忽略
+    remote.handle_network_message("peer-c", announce.clone());
+    assert!(remote.open_rounds(Some(1000)).is_empty());
+
+    remote.handle_network_message("peer-a", announce);
+    assert_eq!(remote.open_rounds(Some(1000)).len(), 1);
+    assert!(remote.open_rounds(Some(2000)).is_empty());
+
+    // 远程参与者签名的加入请求被转发给协调节点
+    let bob = generate_keypair();
+    remote.submit(signed_join(&info.id, &bob)).unwrap();
+    let join = remote_out.try_recv().unwrap();
+    coordinator.handle_network_message("peer-b", join);
+    assert!(coordinator.is_participant(&info.id, &account_id(&bob)));
+
+    // 签名与参与者ID不符的加入请求被拒绝
+    let carol = generate_keypair();
+    let forged = match signed_join(&info.id, &carol) {
+        CoinJoinMessage::Join { session_id, participant_signature, .. } => CoinJoinMessage::Join {
+            session_id,
+            participant_id: account_id(&generate_keypair()),
+            participant_signature,
+        },
+        _ => unreachable!(),
+    };
+    assert!(remote.submit(forged.clone()).is_err());
+    coordinator.handle_network_message("peer-c", forged);
+    assert_eq!(coordinator.get_session(&info.id).unwrap().participants.len(), 2);
+
+    // 不带用途域、直接对消息内容的签名被拒绝
+    let mut undomained = signed_join(&info.id, &carol);
+    let raw = hex::encode(carol.sign(undomained.signing_message().unwrap().as_bytes()).to_bytes());
+    if let CoinJoinMessage::Join { participant_signature, .. } = &mut undomained {
+        *participant_signature = raw;
+    }
+    assert!(undomained.verify().is_err());
+    assert!(remote.submit(undomained).is_err());
+
+    // 同一节点不能代理超过上限的参与者
+    coordinator.handle_network_message("peer-b", signed_join(&info.id, &carol));
+    assert!(!coordinator.is_participant(&info.id, &account_id(&carol)));
+    coordinator.handle_network_message("peer-c", signed_join(&info.id, &carol));
+    assert!(coordinator.is_participant(&info.id, &account_id(&carol)));
+
+    assert!(remote.submit(signed_join("missing", &bob)).is_err());
+}
+
+#[tokio::test]
+async fn test_blinded_output_registration() {
+    let coordinator = CoinJoinManager::with_config(CoinJoinConfig {
+        session_timeout: 60,
+        registration_endpoint: Some("127.0.0.1:3030".to_string()),
+        ..Default::default()
+    });
+    coordinator.set_node_id("peer-a".to_string());
+    let mut coordinator_out = coordinator.subscribe_outbound();
+
+    let remote = CoinJoinManager::new(60);
+    remote.set_node_id("peer-b".to_string());
+    let mut remote_out = remote.subscribe_outbound();
+    let mut registrations = remote.subscribe_registrations();
+
+    let alice = generate_keypair();
+    let info = coordinator.create_session(&create_request(&alice, Some(2), Some(2), None), "http:creator").unwrap();
+    relay(&mut coordinator_out, "peer-a", &remote);
+
+    // 本地参与者经HTTP接口取得nonce
+    let alice_nonce = coordinator.join_session(&info.id, &join_request(&info.id, &alice), &http_source(&alice)).unwrap().nonce.unwrap();
+
+    // 远程参与者经P2P网络加入后收到nonce，盲化输出并随输入一起提交
+    let bob = generate_keypair();
+    remote.submit(signed_join(&info.id, &bob)).unwrap();
+    relay(&mut remote_out, "peer-b", &coordinator);
+    relay(&mut coordinator_out, "peer-a", &remote);
+
+    // 输入不经P2P广播，通过输入阶段的独立连接提交到登记地址
+    let blinded = remote.prepare_output(&info.id, &account_id(&bob), test_output("bob-mixed")).unwrap();
+    remote.submit(signed_input(&info.id, &bob, "b", blinded)).unwrap();
+    assert!(remote_out.try_recv().is_err());
+    let input_registration = registrations.try_recv().unwrap();
+    assert_eq!(input_registration.endpoint, "127.0.0.1:3030");
+    assert_eq!(input_registration.request.phase(), "coinjoin-input");
+    let RegistrationRequest::Input(request) = &input_registration.request else {
+        panic!("expected input registration");
+    };
+    let bob_reply = coordinator.add_input(&info.id, request).unwrap();
+
+    // 协调节点的回复附带盲签输出凭证
+    assert!(remote.complete_registration(&input_registration, &serde_json::json!({})).is_err());
+    remote.complete_registration(
+        &input_registration,
+        &serde_json::json!({ "blind_signature": bob_reply.blind_signature }),
+    ).unwrap();
+
+    // 同一参与者不能再次领取凭证
+    coordinator.handle_network_message("peer-b", signed_input(&info.id, &bob, "b2", "00".repeat(32)));
+    assert_eq!(coordinator.get_session(&info.id).unwrap().inputs.len(), 1);
+
+    // 本地参与者通过HTTP接口注册输入，会话进入输出阶段
+    let (alice_blinded, alice_unblinder) = blind::blind(
+        &info_key(&coordinator, &info.id),
+        &alice_nonce,
+        test_output("alice-mixed").credential_message(&info.id).as_bytes(),
+    ).unwrap();
+    let alice_input = |blinded_output: String| InputRequest {
+        participant_id: account_id(&alice),
+        input: test_input("a"),
+        blinded_output,
+        participant_signature: String::new(),
+    }
+    .sign(&info.id, &alice)
+    .unwrap();
+
+    // 未签名或签名与参与者不符的请求被拒绝
+    let mut forged = alice_input(alice_blinded.clone());
+    forged.participant_id = account_id(&generate_keypair());
+    assert!(coordinator.add_input(&info.id, &forged).is_err());
+
+    // 盲化输出无效时输入不被登记，参与者可以重试
+    assert!(coordinator.add_input(&info.id, &alice_input("zz".to_string())).is_err());
+    assert_eq!(coordinator.get_session(&info.id).unwrap().inputs.len(), 1);
+
+    let reply = coordinator.add_input(&info.id, &alice_input(alice_blinded)).unwrap();
+    assert_eq!(reply.session.status, CoinJoinStatus::CollectingOutputs);
+    let alice_credential = alice_unblinder.unblind(&reply.blind_signature.unwrap()).unwrap();
+
+    // 凭证和新公告（网络桥接任务在状态变化时广播）到达后，输出通过独立连接登记，不经P2P广播
+    coordinator.announce(&info.id);
+    relay(&mut coordinator_out, "peer-a", &remote);
+    let registration = registrations.try_recv().unwrap();
+    assert_eq!(registration.endpoint, "127.0.0.1:3030");
+    assert_eq!(registration.request.phase(), "coinjoin-output");
+    assert!(!std::iter::from_fn(|| remote_out.try_recv().ok())
+        .any(|msg| matches!(msg, CoinJoinMessage::Output { .. })));
+    let RegistrationRequest::Output(request) = &registration.request else {
+        panic!("expected output registration");
+    };
+
+    // 凭证与输出绑定，不能挪用到其他输出，也不能重复使用
+    let mut stolen = request.clone();
+    stolen.output.address = "mallory".to_string();
+    assert!(coordinator.register_blinded_output(&info.id, &stolen).is_err());
+    coordinator.register_blinded_output(&info.id, request).unwrap();
+    assert!(coordinator.register_blinded_output(&info.id, request).is_err());
+
+    coordinator.register_blinded_output(&info.id, &BlindedOutputRequest {
+        output: test_output("alice-mixed"),
+        credential: alice_credential,
+    }).unwrap();
+
+    let session = coordinator.get_session(&info.id).unwrap();
+    assert_eq!(session.outputs.len(), 2);
+    assert_eq!(session.outputs[0].address, "bob-mixed");
+    assert_eq!(session.status, CoinJoinStatus::CollectingSignatures);
+
+    // 签名和完成请求同样需要参与者签名
+    for (input_index, owner) in [(0, &bob), (1, &alice)] {
+        let request = SignatureRequest {
+            participant_id: account_id(owner),
+            signature: TxSignature { input_index, signature: "sig".to_string(), pubkey: account_id(owner) },
+            participant_signature: String::new(),
+        };
+        assert!(coordinator.add_signature(&info.id, &request.clone()).is_err());
+        if input_index == 1 {
+            coordinator.add_signature(&info.id, &request.sign(&info.id, owner).unwrap()).unwrap();
+            continue;
+        }
+
+        // 远程参与者的签名同样不经P2P广播，通过签名阶段的独立连接提交
+        let msg = CoinJoinMessage::Signature {
+            session_id: info.id.clone(),
+            participant_id: request.participant_id,
+            signature: request.signature,
+            participant_signature: String::new(),
+        };
+        remote.submit(msg.sign(owner).unwrap()).unwrap();
+        assert!(remote_out.try_recv().is_err());
+        let registration = registrations.try_recv().unwrap();
+        assert_eq!(registration.request.phase(), "coinjoin-signature");
+        let RegistrationRequest::Signature(request) = &registration.request else {
+            panic!("expected signature registration");
+        };
+        coordinator.add_signature(&info.id, request).unwrap();
+    }
+    let finalize = FinalizeRequest { participant_id: account_id(&alice), participant_signature: String::new() };
+    assert!(coordinator.finalize_session(&info.id, &finalize.clone().sign(&info.id, &bob).unwrap()).is_err());
+    let completed = coordinator.finalize_session(&info.id, &finalize.sign(&info.id, &alice).unwrap()).unwrap();
+    assert_eq!(completed.status, CoinJoinStatus::Completed);
+    // 协调节点只统计自己为参与者登记的输出，HTTP客户端自行登记的输出不计入
+    assert_eq!(coordinator.privacy_report("alice-mixed").privacy.mixes, 0);
+
+    // 参与节点从结束消息附带的最终记录统计隐私，未参与的节点忽略
+    let observer = CoinJoinManager::new(60);
+    let closed = std::iter::from_fn(|| coordinator_out.try_recv().ok())
+        .find(|msg| matches!(msg, CoinJoinMessage::Closed { .. }))
+        .unwrap();
+    observer.handle_network_message("peer-a", closed.clone());
+    assert_eq!(observer.privacy_report("bob-mixed").privacy.mixes, 0);
+    remote.handle_network_message("peer-c", closed.clone());
+    assert_eq!(remote.privacy_report("bob-mixed").privacy.mixes, 0);
+
+    // 缺少本节点登记输出的最终记录不被统计
+    let CoinJoinMessage::Closed { session_id, status, transcript: Some(transcript) } = closed.clone() else {
+        unreachable!()
+    };
+    let mut tampered = transcript.clone();
+    tampered.outputs.retain(|output| output.address != "bob-mixed");
+    remote.handle_network_message("peer-a", CoinJoinMessage::Closed {
+        session_id,
+        status,
+        transcript: Some(tampered),
+    });
+    assert_eq!(remote.privacy_report("bob-mixed").privacy.mixes, 0);
+
+    // 只统计本节点登记的输出，最终记录中的其他输出不计入
+    remote.handle_network_message("peer-a", closed);
+    let bob_privacy = remote.privacy_report("bob-mixed").privacy;
+    assert_eq!(bob_privacy.mixes, 1);
+    assert_eq!(bob_privacy.anonymity_score, 2);
+    assert_eq!(remote.privacy_report("alice-mixed").privacy.mixes, 0);
+
+    // 保留期内的已完成会话会重发结束消息，同一会话只统计一次
+    coordinator.announce_sessions();
+    relay(&mut coordinator_out, "peer-a", &remote);
+    assert_eq!(remote.privacy_report("bob-mixed").privacy.mixes, 1);
+}
+
+fn info_key(manager: &CoinJoinManager, session_id: &str) -> String {
+    manager.get_session(session_id).unwrap().credential_key().unwrap()
+}
+
+/// 构造处于指定阶段的会话
+fn session_in_phase(status: CoinJoinStatus) -> CoinJoinSession {
+    let mut session = CoinJoinSession::new(2, 2, 1000, 1, 600);
+    if status == CoinJoinStatus::Waiting {
+        session.add_participant("alice");
+        return session;
+    }
+
+    session.add_participant("alice");
+    session.add_participant("bob");
+    if status == CoinJoinStatus::CollectingInputs {
+        session.add_input("alice", test_input("a"));
+        return session;
+    }
+
+    session.add_input("alice", test_input("a"));
+    session.add_input("bob", test_input("b"));
+    if status == CoinJoinStatus::CollectingOutputs {
+        return session;
+    }
+
+    session.add_output(test_output("x"));
+    session.add_output(test_output("y"));
+    if status == CoinJoinStatus::CollectingSignatures {
+        return session;
+    }
+
+    for input_index in 0..2 {
+        session.add_signature(TxSignature {
+            input_index,
+            signature: "sig".to_string(),
+            pubkey: String::new(),
+        });
+    }
+    match status {
+        CoinJoinStatus::Broadcasting => {}
+        CoinJoinStatus::Completed => {
+            session.complete("final");
+        }
+        CoinJoinStatus::Failed => session.fail(),
+        CoinJoinStatus::TimedOut => {
+            session.last_active = 0;
+            session.check_timeout();
+        }
+        _ => unreachable!(),
+    }
+    session
+}
+
+#[tokio::test]
+async fn test_restore_after_crash_in_each_phase() {
+    let phases = [
+        (CoinJoinStatus::Waiting, true, 0),
+        (CoinJoinStatus::CollectingInputs, true, 0),
+        (CoinJoinStatus::CollectingOutputs, true, 0),
+        (CoinJoinStatus::CollectingSignatures, true, 0),
+        (CoinJoinStatus::Broadcasting, true, 0),
+        (CoinJoinStatus::Completed, true, 0),
+        (CoinJoinStatus::Failed, false, 2),
+        (CoinJoinStatus::TimedOut, false, 2),
+    ];
+
+    for (status, resumed, refunds) in phases {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let session = session_in_phase(status.clone());
+        assert_eq!(session.status, status);
+        CoinJoinStore::from_db(&db).unwrap().save(&session).unwrap();
+
+        // 模拟协调节点崩溃后重启
+        let manager = CoinJoinManager::new(600);
+        let report = manager.attach_store(CoinJoinStore::from_db(&db).unwrap()).unwrap();
+
+        assert_eq!(report.resumed.contains(&session.id), resumed, "{:?}", status);
+        assert_eq!(report.failed.contains(&session.id), !resumed, "{:?}", status);
+        assert_eq!(report.refunds.len(), refunds, "{:?}", status);
+        assert_eq!(manager.pending_refunds().len(), refunds, "{:?}", status);
+
+        match manager.get_session(&session.id) {
+            Some(restored) => {
+                assert_eq!(restored.status, status);
+                assert_eq!(restored.inputs.len(), session.inputs.len());
+                assert_eq!(restored.signatures.len(), session.signatures.len());
+            }
+            None => assert!(!resumed),
+        }
+
+        if refunds > 0 {
+            // 每个已注册的输入都有退款记录，确认后不再出现
+            let pending = manager.pending_refunds();
+            let mut owners: Vec<&str> = pending.iter().map(|refund| refund.participant_id.as_str()).collect();
+            owners.sort();
+            assert_eq!(owners, ["alice", "bob"]);
+            assert!(manager.acknowledge_refunds(&session.id).unwrap());
+            assert!(manager.pending_refunds().is_empty());
+            assert!(!manager.acknowledge_refunds(&session.id).unwrap());
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_expired_round_releases_registered_inputs() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let manager = CoinJoinManager::new(600);
+    manager.attach_store(CoinJoinStore::from_db(&db).unwrap()).unwrap();
+    let (alice, bob) = (generate_keypair(), generate_keypair());
+    let session_id = stalled_round(&manager, &alice, &bob);
+
+    // 锁外写入的是会话的最新状态
+    let stored = CoinJoinStore::from_db(&db).unwrap().load_all().unwrap();
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].status, CoinJoinStatus::CollectingSignatures);
+    assert_eq!(stored[0].signatures.len(), 1);
+
+    // 超时后会话从存储中删除，两个输入都生成退款记录
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+    assert_eq!(manager.cleanup(), 1);
+    assert!(CoinJoinStore::from_db(&db).unwrap().load_all().unwrap().is_empty());
+    let refunds = manager.pending_refunds();
+    assert_eq!(refunds.len(), 2);
+    assert!(refunds.iter().all(|refund| refund.session_id == session_id));
+    assert!(refunds.iter().any(|refund| refund.participant_id == account_id(&bob)));
+}
+
+#[tokio::test]
+async fn test_restore_fails_sessions_expired_during_downtime() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let mut session = session_in_phase(CoinJoinStatus::CollectingOutputs);
+    session.last_active = 0;
+    CoinJoinStore::from_db(&db).unwrap().save(&session).unwrap();
+
+    let manager = CoinJoinManager::new(600);
+    let report = manager.attach_store(CoinJoinStore::from_db(&db).unwrap()).unwrap();
+
+    assert_eq!(report.failed, vec![session.id.clone()]);
+    assert_eq!(report.refunds.len(), 2);
+    assert!(manager.get_session(&session.id).is_none());
+    assert!(CoinJoinStore::from_db(&db).unwrap().load_all().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_session_changes_are_persisted() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let manager = CoinJoinManager::new(600);
+    manager.attach_store(CoinJoinStore::from_db(&db).unwrap()).unwrap();
+
+    let info = manager.create_session(&create_request(&generate_keypair(), Some(2), Some(2), None), "http:creator").unwrap();
+    let bob = generate_keypair();
+    manager.join_session(&info.id, &join_request(&info.id, &bob), &http_source(&bob)).unwrap();
+
+    let stored = CoinJoinStore::from_db(&db).unwrap().load_all().unwrap();
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].participants.len(), 2);
+    assert_eq!(stored[0].status, CoinJoinStatus::CollectingInputs);
+}
+
+#[test]
+fn test_anonymity_sets_and_scores() {
+    let tracker = PrivacyTracker::new(5);
+    let transcript = CoinJoinTranscript {
+        session_id: "s1".to_string(),
+        inputs: Vec::new(),
+        outputs: vec![
+            test_output("alice"),
+            test_output("bob"),
+            test_output("carol"),
+            TxOutput { address: "alice-change".to_string(), amount: 37 },
+        ],
+        final_txid: Some("tx1".to_string()),
+        participants_count: 3,
+    };
+
+    let sets = transcript.anonymity_sets();
+    assert_eq!(sets[&1000], 3);
+    assert_eq!(sets[&37], 1);
+
+    // 只统计本节点登记的输出
+    let own = vec![test_output("alice"), TxOutput { address: "alice-change".to_string(), amount: 37 }];
+    let updated = tracker.record(&transcript, &own, 100).unwrap();
+    assert_eq!(updated.len(), 2);
+    assert_eq!(tracker.report("bob").privacy.mixes, 0);
+
+    // 最终记录缺少本节点登记的输出时不统计
+    assert!(tracker.record(&transcript, &[test_output("dave")], 100).is_err());
+    assert!(tracker.record(&transcript, &[test_output("alice"), test_output("alice")], 100).is_err());
+    assert_eq!(tracker.report("alice").privacy.mixes, 1);
+
+    let alice = tracker.report("alice");
+    assert_eq!(alice.privacy.anonymity_score, 3);
+    assert_eq!(alice.privacy.mixes, 1);
+    assert!(alice.remix_recommended);
+
+    // 找零输出没有匿名性
+    assert_eq!(tracker.report("alice-change").privacy.anonymity_score, 1);
+
+    // 再次混币后分数累加
+    tracker.record(&CoinJoinTranscript { session_id: "s2".to_string(), ..transcript }, &own, 200).unwrap();
+    let alice = tracker.report("alice");
+    assert_eq!(alice.privacy.anonymity_score, 5);
+    assert_eq!(alice.privacy.outputs.len(), 2);
+    assert!(!alice.remix_recommended);
+
+    let unknown = tracker.report("dave");
+    assert_eq!(unknown.privacy.mixes, 0);
+}